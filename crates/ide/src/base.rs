@@ -57,6 +57,25 @@ impl VfsPath {
         Some(())
     }
 
+    /// Resolve `.` and `..` segments.
+    /// Returns `None` if the path escapes above the root.
+    pub fn normalize(&self) -> Option<Self> {
+        let mut ret = Self::root();
+        for seg in self.0.split('/').skip(1) {
+            match seg {
+                "." => {}
+                ".." => ret.pop()?,
+                _ => ret.push_segment(seg)?,
+            }
+        }
+        Some(ret)
+    }
+
+    /// Whether this path is strictly under the directory `dir`.
+    pub fn is_under(&self, dir: &Self) -> bool {
+        matches!(self.0.strip_prefix(&*dir.0), Some(rest) if rest.starts_with('/'))
+    }
+
     pub fn as_str(&self) -> &str {
         &self.0
     }
@@ -106,6 +125,13 @@ impl FileSet {
     pub fn iter(&self) -> impl Iterator<Item = (FileId, &'_ VfsPath)> + '_ {
         self.paths.iter().map(|(&file, path)| (file, path))
     }
+
+    /// All files under the directory `dir` recursively, in arbitrary order.
+    /// This is a linear scan.
+    pub fn files_under(&self, dir: &VfsPath) -> impl Iterator<Item = (FileId, &'_ VfsPath)> + '_ {
+        let dir = dir.clone();
+        self.iter().filter(move |(_, path)| path.is_under(&dir))
+    }
 }
 
 impl fmt::Debug for FileSet {
@@ -138,6 +164,10 @@ impl SourceRoot {
         self.file_set.iter()
     }
 
+    pub fn files_under(&self, dir: &VfsPath) -> impl Iterator<Item = (FileId, &'_ VfsPath)> + '_ {
+        self.file_set.files_under(dir)
+    }
+
     pub fn entry(&self) -> Option<FileId> {
         self.entry
     }
@@ -252,3 +282,80 @@ impl fmt::Debug for Change {
             .finish_non_exhaustive()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{FileId, FileSet, VfsPath};
+
+    #[test]
+    fn normalize() {
+        let norm = |s: &str| VfsPath::new(s).unwrap().normalize();
+        assert_eq!(norm("/a/./b"), VfsPath::new("/a/b"));
+        assert_eq!(norm("/a/../b"), VfsPath::new("/b"));
+        assert_eq!(norm("/a/b/../../c/./d"), VfsPath::new("/c/d"));
+        assert_eq!(norm("/a/.."), Some(VfsPath::root()));
+        assert_eq!(norm("/./."), Some(VfsPath::root()));
+        assert_eq!(norm(""), Some(VfsPath::root()));
+        assert_eq!(norm("/.."), None);
+        assert_eq!(norm("/../a"), None);
+        assert_eq!(norm("/a/../../b"), None);
+    }
+
+    #[test]
+    fn push() {
+        let mut path = VfsPath::new("/a").unwrap();
+        path.push(&VfsPath::new("/../b").unwrap());
+        assert_eq!(path.as_str(), "/a/../b");
+        assert_eq!(path.normalize(), VfsPath::new("/b"));
+
+        path.push_segment("..").unwrap();
+        assert_eq!(path.as_str(), "/a/../b/..");
+        assert_eq!(path.push_segment("c/d"), None);
+    }
+
+    #[test]
+    fn files_under() {
+        let mut set = FileSet::default();
+        for (i, path) in [
+            "/default.nix",
+            "/lib/default.nix",
+            "/lib/strings.nix",
+            "/lib/attrsets/default.nix",
+            "/lib/attrsets/deep/foo.nix",
+            "/library.nix",
+            "/pkgs/lib/foo.nix",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            set.insert(FileId(i as u32), VfsPath::new(path).unwrap());
+        }
+
+        let under = |dir: &str| {
+            let dir = VfsPath::new(dir).unwrap();
+            let mut ret = set
+                .files_under(&dir)
+                .map(|(_, path)| path.as_str())
+                .collect::<Vec<_>>();
+            ret.sort();
+            ret
+        };
+        assert_eq!(
+            under("/lib"),
+            [
+                "/lib/attrsets/deep/foo.nix",
+                "/lib/attrsets/default.nix",
+                "/lib/default.nix",
+                "/lib/strings.nix",
+            ]
+        );
+        assert_eq!(
+            under("/lib/attrsets"),
+            ["/lib/attrsets/deep/foo.nix", "/lib/attrsets/default.nix"]
+        );
+        assert_eq!(under("/lib/attrsets/deep"), ["/lib/attrsets/deep/foo.nix"]);
+        assert_eq!(under("/lib/strings.nix"), <[&str; 0]>::default());
+        assert_eq!(under("/nonexist"), <[&str; 0]>::default());
+        assert_eq!(under("/").len(), 7);
+    }
+}
@@ -14,6 +14,7 @@
 //!
 //! We now identifies,
 //! - Unused `let` bindings.
+//! - Unused names inherited from `import`s by `let`.
 //! - Unused `with` expressions.
 //! - Unnecessary `rec` attrsets.
 use super::{BindingValue, DefDatabase, Expr, ExprId, NameId, ResolveResult};
@@ -21,18 +22,24 @@ use crate::{Diagnostic, DiagnosticKind, FileId};
 use la_arena::ArenaMap;
 use rowan::ast::AstNode;
 use rowan::TextRange;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use syntax::ast;
 
 #[derive(Default, Debug, Clone, PartialEq, Eq)]
 pub struct LivenessCheckResult {
     names: Box<[NameId]>,
+    inherited_names: Box<[NameId]>,
     withs: Box<[ExprId]>,
     rec_attrsets: Box<[ExprId]>,
 }
 
 impl LivenessCheckResult {
+    /// Whether a `let` binding, or a name inherited from `import`, is unused.
+    pub fn is_unused_name(&self, name: NameId) -> bool {
+        self.names.contains(&name) || self.inherited_names.contains(&name)
+    }
+
     pub fn to_diagnostics<'a>(
         &'a self,
         db: &dyn DefDatabase,
@@ -47,6 +54,12 @@ impl LivenessCheckResult {
                 .flat_map(|&def| source_map.nodes_for_name(def))
                 .map(|ptr| Diagnostic::new(ptr.text_range(), DiagnosticKind::UnusedBinding)),
         );
+        diags.extend(
+            self.inherited_names
+                .iter()
+                .flat_map(|&def| source_map.nodes_for_name(def))
+                .map(|ptr| Diagnostic::new(ptr.text_range(), DiagnosticKind::UnusedInheritedName)),
+        );
         diags.extend(self.withs.iter().map(|&expr| {
             let ptr = source_map.node_for_expr(expr).unwrap();
             let node = ast::With::cast(ptr.to_node(&root)).unwrap();
@@ -160,8 +173,35 @@ pub(crate) fn liveness_check_query(
         }
     }
 
+    // Split out let-bindings inherited from `import`s, which are reported separately.
+    // Either `import ./x.nix` or `import ./x.nix { }`.
+    let is_import_ref = |e: ExprId| name_res.get(e) == Some(&ResolveResult::Builtin("import"));
+    let is_import = |e: ExprId| match module[e] {
+        Expr::Apply(func, _) => {
+            is_import_ref(func)
+                || matches!(module[func], Expr::Apply(inner, _) if is_import_ref(inner))
+        }
+        _ => false,
+    };
+    let import_inherits = module
+        .exprs()
+        .filter_map(|(_, kind)| match kind {
+            Expr::LetIn(bindings, _) => Some(bindings),
+            _ => None,
+        })
+        .flat_map(|bindings| bindings.statics.iter())
+        .filter_map(|&(name, value)| match value {
+            BindingValue::InheritFrom(from) if is_import(from) => Some(name),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+    let (unused_inherits, unused_defs): (Vec<_>, Vec<_>) = unused_defs
+        .into_iter()
+        .partition(|name| import_inherits.contains(name));
+
     Arc::new(LivenessCheckResult {
         names: unused_defs.into(),
+        inherited_names: unused_inherits.into(),
         withs: unused_withs.into(),
         rec_attrsets: unused_recs.into(),
     })
@@ -244,6 +284,17 @@ mod tests {
         check("let $0a = 1; $1b = let $2c = a; in 1; in 1");
     }
 
+    #[test]
+    fn inherit_from_import() {
+        check("let inherit (import ./x.nix) a $0b; in a");
+        check("let inherit (import ./x.nix) $0a $1b; in 1");
+        check("let inherit (import ./x.nix) a b; in a b");
+        check("let inherit (import ./x.nix { }) a $0b; in a");
+        check("let inherit (import ./x.nix { inherit pkgs; }) $0a; in 1");
+        // Not an import.
+        check("let inherit ({ a = 1; b = 1; }) a $0b; in a");
+    }
+
     #[test]
     fn with_used_by_unused_let() {
         check("with 1; let $0a = from_with; in 1");
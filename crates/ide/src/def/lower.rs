@@ -282,13 +282,13 @@ impl LowerCtx<'_> {
     }
 }
 
-enum AttrKind {
+pub(crate) enum AttrKind {
     Static(SmolStr),
     Dynamic(Option<ast::Expr>),
 }
 
 impl AttrKind {
-    fn classify(attr: ast::Attr) -> Self {
+    pub(crate) fn classify(attr: ast::Attr) -> Self {
         let string_node = match attr {
             ast::Attr::String(s) => s,
             ast::Attr::Name(n) => {
@@ -1344,6 +1344,37 @@ mod tests {
         );
     }
 
+    #[test]
+    fn attrset_quoted_duplicated_error() {
+        // Quoted and unquoted keys are the same.
+        check_error(
+            r#"{ foo = 1; "foo" = 2; ${"foo"} = 3; }"#,
+            expect![[r#"
+                11..16: Duplicated name definition
+                  2..5: Previously defined here
+                22..30: Duplicated name definition
+                  2..5: Previously defined here
+            "#]],
+        );
+        check_error(
+            r#"let "a b".c = 1; ${("a b")} = 2; in 1"#,
+            expect![[r#"
+                17..27: Duplicated name definition
+                  4..9: Previously defined here
+            "#]],
+        );
+        // Unknown escapes are the escaped characters themselves.
+        check_error(
+            r#"{ inherit foo; "fo\o" = 1; }"#,
+            expect![[r#"
+                15..21: Duplicated name definition
+                  10..13: Previously defined here
+            "#]],
+        );
+        // Different keys.
+        check_error(r#"{ foo = 1; "foo bar" = 2; ${"foo "} = 3; }"#, expect![""]);
+    }
+
     #[test]
     fn attrset_no_duplicated_duplicated_error() {
         check_error(
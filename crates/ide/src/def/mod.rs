@@ -17,6 +17,7 @@ use std::sync::Arc;
 use syntax::Parse;
 
 pub use self::liveness::LivenessCheckResult;
+pub(crate) use self::lower::AttrKind;
 pub use self::nameres::{
     ModuleScopes, NameReference, NameResolution, ResolveResult, ScopeData, ScopeId,
 };
@@ -135,6 +136,23 @@ impl Module {
         self.names.iter()
     }
 
+    /// The expression of the whole file.
+    pub fn entry_expr(&self) -> ExprId {
+        self.entry_expr
+    }
+
+    /// Find the value bound to a name in `let` or attrsets.
+    /// This is a linear scan.
+    pub(crate) fn binding_value(&self, name: NameId) -> Option<BindingValue> {
+        self.exprs().find_map(|(_, kind)| {
+            let bindings = kind.bindings()?;
+            bindings
+                .statics
+                .iter()
+                .find_map(|&(n, value)| (n == name).then_some(value))
+        })
+    }
+
     pub(crate) fn module_references_query(
         db: &dyn DefDatabase,
         file_id: FileId,
@@ -208,6 +226,17 @@ pub enum Expr {
 }
 
 impl Expr {
+    /// The bindings of `let`s and attrsets.
+    pub(crate) fn bindings(&self) -> Option<&Bindings> {
+        match self {
+            Self::LetIn(bindings, _)
+            | Self::Attrset(bindings)
+            | Self::LetAttrset(bindings)
+            | Self::RecAttrset(bindings) => Some(bindings),
+            _ => None,
+        }
+    }
+
     pub(crate) fn walk_child_exprs(&self, mut f: impl FnMut(ExprId)) {
         match self {
             Self::Missing | Self::Reference(_) | Self::Literal(_) => {}
@@ -1,6 +1,7 @@
 use super::DefDatabase;
-use crate::{FileId, VfsPath};
+use crate::{FileId, SourceRoot, VfsPath};
 use smol_str::SmolStr;
+use std::sync::Arc;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Path(salsa::InternId);
@@ -16,7 +17,18 @@ impl salsa::InternKey for Path {
 
 impl Path {
     pub(crate) fn resolve_path_query(db: &dyn DefDatabase, path: Path) -> Option<FileId> {
-        let data = path.data(db);
+        let (root, mut vpath) = path.to_vfs_path(db)?;
+        // Importing a directory loads the `default.nix` inside it.
+        root.file_for_path(&vpath).or_else(|| {
+            vpath.push_segment("default.nix").unwrap();
+            root.file_for_path(&vpath)
+        })
+    }
+
+    /// The target `VfsPath` in the source root of the anchor file.
+    /// Returns `None` if it is not relative, or it goes beyond the root.
+    pub(crate) fn to_vfs_path(self, db: &dyn DefDatabase) -> Option<(Arc<SourceRoot>, VfsPath)> {
+        let data = self.data(db);
         let file = match &data.anchor {
             &PathAnchor::Relative(file) => file,
             // TODO
@@ -29,10 +41,18 @@ impl Path {
             vpath.pop()?;
         }
         vpath.push(&data.relative);
-        root.file_for_path(&vpath).or_else(|| {
-            vpath.push_segment("default.nix").unwrap();
-            root.file_for_path(&vpath)
-        })
+        Some((root, vpath))
+    }
+
+    /// Whether this path points to an existing directory without `default.nix` inside.
+    pub fn is_dir_without_default_nix(self, db: &dyn DefDatabase) -> bool {
+        if self.resolve(db).is_some() {
+            return false;
+        }
+        match self.to_vfs_path(db) {
+            Some((root, vpath)) => root.files_under(&vpath).next().is_some(),
+            None => false,
+        }
     }
 
     pub fn data(self, db: &dyn DefDatabase) -> PathData {
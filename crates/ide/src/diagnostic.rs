@@ -6,10 +6,12 @@ use syntax::{ErrorKind as SynErrorKind, TextRange};
 pub struct Diagnostic {
     pub range: TextRange,
     pub kind: DiagnosticKind,
+    /// A specific message replacing the generic one of `kind`.
+    pub message: Option<String>,
     pub notes: Vec<(FileRange, String)>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum DiagnosticKind {
     // Syntax.
     SyntaxError(SynErrorKind),
@@ -27,47 +29,160 @@ pub enum DiagnosticKind {
     // Name resolution.
     UndefinedName,
 
+    // Paths.
+    UnresolvedImport,
+    MissingDefaultNix,
+    ConflictingImportedOption,
+
+    // Lints.
+    ConstantCondition,
+    NotCallable,
+    TrivialInfiniteRecursion,
+    AttrsetAsFunction,
+    MixedIndentString,
+    ReplaceableWith,
+    BroadWithScope,
+    UnknownBuiltin,
+    DuplicateListElement,
+    RedundantMerge,
+    TrailingWhitespace,
+    LineTooLong,
+
     // Liveness.
     UnusedBinding,
+    UnusedInheritedName,
     UnusedWith,
     UnusedRec,
 }
 
+impl DiagnosticKind {
+    /// The stable identifier, used in suppression comments like `# nil: disable=unused_binding`.
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::SyntaxError(_) => "syntax_error",
+
+            Self::InvalidDynamic => "invalid_dynamic",
+            Self::DuplicatedKey => "duplicated_key",
+            Self::EmptyInherit => "empty_inherit",
+            Self::EmptyLetIn => "empty_let_in",
+            Self::LetAttrset => "let_attrset",
+            Self::UriLiteral => "uri_literal",
+            Self::MergePlainRecAttrset => "merge_plain_rec_attrset",
+            Self::MergeRecAttrset => "merge_rec_attrset",
+
+            Self::UndefinedName => "undefined_name",
+
+            Self::UnresolvedImport => "unresolved_import",
+            Self::MissingDefaultNix => "missing_default_nix",
+            Self::ConflictingImportedOption => "conflicting_imported_option",
+
+            Self::ConstantCondition => "constant_condition",
+            Self::NotCallable => "not_callable",
+            Self::TrivialInfiniteRecursion => "trivial_infinite_recursion",
+            Self::AttrsetAsFunction => "attrset_as_function",
+            Self::MixedIndentString => "mixed_indent_string",
+            Self::ReplaceableWith => "replaceable_with",
+            Self::BroadWithScope => "broad_with_scope",
+            Self::UnknownBuiltin => "unknown_builtin",
+            Self::DuplicateListElement => "duplicate_list_element",
+            Self::RedundantMerge => "redundant_merge",
+            Self::TrailingWhitespace => "trailing_whitespace",
+            Self::LineTooLong => "line_too_long",
+
+            Self::UnusedBinding => "unused_binding",
+            Self::UnusedInheritedName => "unused_inherited_name",
+            Self::UnusedWith => "unused_with",
+            Self::UnusedRec => "unused_rec",
+        }
+    }
+
+    /// Whether this reports unused code, which is expected in generated files.
+    pub fn is_unused(self) -> bool {
+        matches!(
+            self,
+            Self::UnusedBinding | Self::UnusedInheritedName | Self::UnusedWith | Self::UnusedRec
+        )
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Severity {
     Error,
     Warning,
+    Hint,
     IncompleteSyntax,
 }
 
+impl Severity {
+    /// Whether this is at least as severe as `min`.
+    /// Incomplete syntax is as severe as errors.
+    pub fn is_at_least(self, min: Severity) -> bool {
+        self.level() <= min.level()
+    }
+
+    fn level(self) -> u8 {
+        match self {
+            Self::Error | Self::IncompleteSyntax => 0,
+            Self::Warning => 1,
+            Self::Hint => 2,
+        }
+    }
+}
+
 impl Diagnostic {
     pub fn new(range: TextRange, kind: DiagnosticKind) -> Self {
         Self {
             range,
             kind,
+            message: None,
             notes: Vec::new(),
         }
     }
 
+    pub fn with_message(mut self, message: impl Into<String>) -> Self {
+        self.message = Some(message.into());
+        self
+    }
+
     pub fn with_note(mut self, frange: FileRange, message: impl Into<String>) -> Self {
         self.notes.push((frange, message.into()));
         self
     }
 
+    pub fn code(&self) -> &'static str {
+        self.kind.code()
+    }
+
     pub fn severity(&self) -> Severity {
         match self.kind {
             DiagnosticKind::InvalidDynamic
             | DiagnosticKind::DuplicatedKey
-            | DiagnosticKind::UndefinedName => Severity::Error,
+            | DiagnosticKind::UndefinedName
+            | DiagnosticKind::NotCallable
+            | DiagnosticKind::TrivialInfiniteRecursion => Severity::Error,
             DiagnosticKind::EmptyInherit
             | DiagnosticKind::EmptyLetIn
             | DiagnosticKind::LetAttrset
             | DiagnosticKind::UriLiteral
             | DiagnosticKind::MergePlainRecAttrset
             | DiagnosticKind::MergeRecAttrset
+            | DiagnosticKind::UnresolvedImport
+            | DiagnosticKind::ConflictingImportedOption
             | DiagnosticKind::UnusedBinding
+            | DiagnosticKind::UnusedInheritedName
             | DiagnosticKind::UnusedWith
-            | DiagnosticKind::UnusedRec => Severity::Warning,
+            | DiagnosticKind::UnusedRec
+            | DiagnosticKind::AttrsetAsFunction
+            | DiagnosticKind::UnknownBuiltin => Severity::Warning,
+            DiagnosticKind::MissingDefaultNix
+            | DiagnosticKind::ConstantCondition
+            | DiagnosticKind::MixedIndentString
+            | DiagnosticKind::ReplaceableWith
+            | DiagnosticKind::BroadWithScope
+            | DiagnosticKind::DuplicateListElement
+            | DiagnosticKind::RedundantMerge
+            | DiagnosticKind::TrailingWhitespace
+            | DiagnosticKind::LineTooLong => Severity::Hint,
             DiagnosticKind::SyntaxError(kind) => match kind {
                 SynErrorKind::MultipleRoots
                 | SynErrorKind::PathTrailingSlash
@@ -85,6 +200,9 @@ impl Diagnostic {
     }
 
     pub fn message(&self) -> String {
+        if let Some(message) = &self.message {
+            return message.clone();
+        }
         match self.kind {
             DiagnosticKind::SyntaxError(kind) => return kind.to_string(),
 
@@ -107,7 +225,35 @@ impl Diagnostic {
 
             DiagnosticKind::UndefinedName => "Undefined name",
 
+            DiagnosticKind::UnresolvedImport => "Imported file does not exist",
+            DiagnosticKind::MissingDefaultNix => "Imported directory has no `default.nix`",
+            DiagnosticKind::ConflictingImportedOption => {
+                "Option is set to different values by multiple imported modules"
+            }
+
+            DiagnosticKind::ConstantCondition => "Condition is constant",
+            DiagnosticKind::NotCallable => "Calling a non-function value",
+            DiagnosticKind::TrivialInfiniteRecursion => {
+                "Infinite recursion. The binding is defined as itself"
+            }
+            DiagnosticKind::AttrsetAsFunction => "Attrset is used where a function is expected",
+            DiagnosticKind::MixedIndentString => {
+                "Indented string mixes tabs and spaces in indentation. Only spaces are stripped"
+            }
+            DiagnosticKind::ReplaceableWith => {
+                "`with` provides only a few names, which can be referenced explicitly"
+            }
+            DiagnosticKind::BroadWithScope => {
+                "`with` brings all names of a large attrset into scope. Consider `inherit`ing used names explicitly"
+            }
+            DiagnosticKind::UnknownBuiltin => "Unknown builtin",
+            DiagnosticKind::DuplicateListElement => "Duplicated list element",
+            DiagnosticKind::RedundantMerge => "Merging with an empty attrset has no effect",
+            DiagnosticKind::TrailingWhitespace => "Trailing whitespace",
+            DiagnosticKind::LineTooLong => "Line is too long",
+
             DiagnosticKind::UnusedBinding => "Unused binding",
+            DiagnosticKind::UnusedInheritedName => "Unused name inherited from import",
             DiagnosticKind::UnusedWith => "Unused `with`",
             DiagnosticKind::UnusedRec => "Unused `rec`",
         }
@@ -115,13 +261,11 @@ impl Diagnostic {
     }
 
     pub fn is_unnecessary(&self) -> bool {
-        matches!(
-            self.kind,
-            DiagnosticKind::EmptyInherit
-                | DiagnosticKind::UnusedBinding
-                | DiagnosticKind::UnusedWith
-                | DiagnosticKind::UnusedRec
-        )
+        self.kind.is_unused()
+            || matches!(
+                self.kind,
+                DiagnosticKind::EmptyInherit | DiagnosticKind::RedundantMerge
+            )
     }
 
     pub fn is_deprecated(&self) -> bool {
@@ -137,8 +281,7 @@ impl Diagnostic {
             fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
                 write!(f, "{:?}: {}", self.0.range, self.0.message(),)?;
                 for (frange, msg) in &self.0.notes {
-                    // Currently all related information is in the same file.
-                    // Ignore the FileId here.
+                    // Ignore the FileId here. Notes in other files are rare.
                     write!(f, "\n  {:?}: {}", frange.range, msg)?;
                 }
                 Ok(())
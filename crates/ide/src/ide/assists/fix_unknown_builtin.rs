@@ -0,0 +1,65 @@
+//! Replace a misspelled builtin with the closest available one.
+//!
+//! ```nix
+//! builtins.toJSNO
+//! ```
+//! =>
+//! ```nix
+//! builtins.toJSON
+//! ```
+use super::{AssistKind, AssistsCtx};
+use crate::ide::diagnostics::{closest_builtin, unknown_builtin_diagnostics};
+use crate::TextEdit;
+
+pub(super) fn fix_unknown_builtin(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let file = ctx.frange.file_id;
+    let src = ctx.db.file_content(file);
+    let diag = unknown_builtin_diagnostics(ctx.db, file, &ctx.config.builtins)
+        .into_iter()
+        .find(|diag| diag.range.intersect(ctx.frange.range).is_some())?;
+    // The name may be quoted, like `builtins."toJSNO"`.
+    let name = src[diag.range].trim_matches('"');
+    let replacement = closest_builtin(name, &ctx.config.builtins)?;
+    ctx.add(
+        "fix_unknown_builtin",
+        format!("Replace with `{replacement}`"),
+        AssistKind::QuickFix,
+        vec![TextEdit {
+            delete: diag.range,
+            insert: replacement.into(),
+        }],
+    );
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    define_check_assist!(super::fix_unknown_builtin);
+
+    #[test]
+    fn typo() {
+        check(
+            "builtins.toJSNO$0 { }",
+            expect![[r#"
+                builtins.toJSON { }
+            "#]],
+        );
+        check(
+            "builtins.$0attrNmes",
+            expect![[r#"
+                builtins.attrNames
+            "#]],
+        );
+        // Nothing close enough.
+        check_no("builtins.$0fooBarBaz");
+        check_no("builtins.$0toJSON");
+    }
+
+    #[test]
+    fn shadowed() {
+        check_no("let builtins = { }; in builtins.$0toJSNO");
+        check_no("builtins: builtins.$0toJSNO");
+    }
+}
@@ -0,0 +1,186 @@
+//! Assists are code actions available at a cursor or selection.
+//! It includes quick fixes of diagnostics and refactoring rewrites.
+//!
+//! Each handler checks if it is applicable in the given `AssistsCtx`,
+//! and adds zero or more `Assist`s to it.
+
+#[cfg(test)]
+macro_rules! define_check_assist {
+    ($handler:expr) => {
+        define_check_assist!($handler, crate::ide::assists::AssistsConfig::default());
+    };
+    ($handler:expr, $config:expr) => {
+        #[track_caller]
+        fn check(fixture: &str, expect: expect_test::Expect) {
+            let (db, file, frange) = crate::ide::assists::tests::fixture_frange(fixture);
+            let config = $config;
+            let mut ctx = crate::ide::assists::AssistsCtx::new(&db, &config, frange);
+            $handler(&mut ctx);
+            let mut got = ctx
+                .assists
+                .into_iter()
+                .map(|assist| crate::ide::assists::tests::apply(&db, file, assist))
+                .collect::<Vec<_>>()
+                .join("\n");
+            if !got.is_empty() {
+                got.push('\n');
+            }
+            expect.assert_eq(&got);
+        }
+
+        #[track_caller]
+        #[allow(dead_code)]
+        fn check_no(fixture: &str) {
+            let (db, _, frange) = crate::ide::assists::tests::fixture_frange(fixture);
+            let config = $config;
+            let mut ctx = crate::ide::assists::AssistsCtx::new(&db, &config, frange);
+            $handler(&mut ctx);
+            assert_eq!(ctx.assists, Vec::new());
+        }
+    };
+}
+
+mod fix_unknown_builtin;
+mod remove_redundant_merge;
+mod remove_unused_inherited_name;
+mod rename_case;
+mod replace_with;
+mod simplify_constant_condition;
+mod toggle_inherit;
+mod trim_trailing_whitespace;
+mod wrap_in_module;
+
+use crate::{BuiltinSet, DefDatabase, FileRange, TextEdit};
+use smol_str::SmolStr;
+use syntax::ast;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssistsConfig {
+    /// Parameters of the lambda added by "wrap in module", before the trailing `...`.
+    pub module_params: Vec<SmolStr>,
+    /// Builtins available in the user's Nix, to suggest for misspelled ones.
+    pub builtins: BuiltinSet,
+    /// Offer removing trailing whitespaces, along with their diagnostics.
+    pub trailing_whitespace: bool,
+}
+
+impl Default for AssistsConfig {
+    fn default() -> Self {
+        Self {
+            module_params: vec!["config".into(), "lib".into(), "pkgs".into()],
+            builtins: BuiltinSet::default(),
+            trailing_whitespace: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assist {
+    pub id: &'static str,
+    pub label: String,
+    pub kind: AssistKind,
+    pub edits: Vec<TextEdit>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssistKind {
+    QuickFix,
+    RefactorRewrite,
+}
+
+pub(crate) fn assists(
+    db: &dyn DefDatabase,
+    config: &AssistsConfig,
+    frange: FileRange,
+) -> Vec<Assist> {
+    let handlers = [
+        fix_unknown_builtin::fix_unknown_builtin,
+        remove_redundant_merge::remove_redundant_merge,
+        remove_unused_inherited_name::remove_unused_inherited_name,
+        rename_case::rename_case,
+        replace_with::replace_with,
+        simplify_constant_condition::simplify_constant_condition,
+        toggle_inherit::toggle_inherit,
+        trim_trailing_whitespace::trim_trailing_whitespace,
+        wrap_in_module::wrap_in_module,
+    ];
+
+    let mut ctx = AssistsCtx::new(db, config, frange);
+    for handler in handlers {
+        handler(&mut ctx);
+    }
+    ctx.assists
+}
+
+pub(crate) struct AssistsCtx<'a> {
+    assists: Vec<Assist>,
+
+    pub(crate) db: &'a dyn DefDatabase,
+    pub(crate) config: &'a AssistsConfig,
+    pub(crate) frange: FileRange,
+    pub(crate) ast: ast::SourceFile,
+}
+
+impl<'a> AssistsCtx<'a> {
+    pub(crate) fn new(
+        db: &'a dyn DefDatabase,
+        config: &'a AssistsConfig,
+        frange: FileRange,
+    ) -> Self {
+        let ast = db.parse(frange.file_id).root();
+        Self {
+            assists: Vec::new(),
+            db,
+            config,
+            frange,
+            ast,
+        }
+    }
+
+    pub(crate) fn add(
+        &mut self,
+        id: &'static str,
+        label: impl Into<String>,
+        kind: AssistKind,
+        edits: Vec<TextEdit>,
+    ) {
+        self.assists.push(Assist {
+            id,
+            label: label.into(),
+            kind,
+            edits,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Assist;
+    use crate::base::SourceDatabase;
+    use crate::tests::TestDB;
+    use crate::{FileId, FileRange};
+    use rowan::TextRange;
+
+    pub(crate) fn fixture_frange(fixture: &str) -> (TestDB, FileId, FileRange) {
+        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        let frange = match f.markers() {
+            [fpos] => FileRange::new(fpos.file_id, TextRange::empty(fpos.pos)),
+            [lpos, rpos] => {
+                assert_eq!(lpos.file_id, rpos.file_id);
+                FileRange::new(lpos.file_id, TextRange::new(lpos.pos, rpos.pos))
+            }
+            _ => panic!("Expecting 1 or 2 markers"),
+        };
+        (db, frange.file_id, frange)
+    }
+
+    pub(crate) fn apply(db: &TestDB, file: FileId, assist: Assist) -> String {
+        let mut src = db.file_content(file).to_string();
+        let mut edits = assist.edits;
+        edits.sort_by_key(|edit| edit.delete.start());
+        for edit in edits.iter().rev() {
+            edit.apply(&mut src);
+        }
+        src
+    }
+}
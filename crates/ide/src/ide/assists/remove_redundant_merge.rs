@@ -0,0 +1,81 @@
+//! Remove `//` with an empty attrset literal.
+//!
+//! ```nix
+//! x // { }
+//! ```
+//! =>
+//! ```nix
+//! x
+//! ```
+use super::{AssistKind, AssistsCtx};
+use crate::ide::diagnostics::redundant_merge;
+use crate::TextEdit;
+use rowan::ast::AstNode;
+use syntax::ast;
+
+pub(super) fn remove_redundant_merge(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let (node, kept) = ctx
+        .ast
+        .syntax()
+        .covering_element(ctx.frange.range)
+        .ancestors()
+        .filter_map(ast::BinaryOp::cast)
+        .find_map(|node| {
+            let (kept, _) = redundant_merge(&node)?;
+            Some((node, kept))
+        })?;
+
+    ctx.add(
+        "remove_redundant_merge",
+        "Remove the merge with an empty attrset",
+        AssistKind::QuickFix,
+        vec![TextEdit {
+            delete: node.syntax().text_range(),
+            insert: kept.syntax().to_string().into(),
+        }],
+    );
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    define_check_assist!(super::remove_redundant_merge);
+
+    #[test]
+    fn simple() {
+        check(
+            "x: x $0// { }",
+            expect![[r#"
+                x: x
+            "#]],
+        );
+        check(
+            "x: { $0} // x",
+            expect![[r#"
+                x: x
+            "#]],
+        );
+        check(
+            "x: f (x $0// {})",
+            expect![[r#"
+                x: f (x)
+            "#]],
+        );
+        check(
+            "a: b: a // (b $0// { })",
+            expect![[r#"
+                a: b: a // (b)
+            "#]],
+        );
+    }
+
+    #[test]
+    fn not_applicable() {
+        check_no("x: x $0// { a = 1; }");
+        check_no("x: { inherit x; } $0// x");
+        check_no("x: x $0// (x: x)");
+        check_no("x: x $0+ { }");
+    }
+}
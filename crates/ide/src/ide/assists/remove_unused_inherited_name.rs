@@ -0,0 +1,161 @@
+//! Remove an unused name inherited from an `import`.
+//!
+//! ```nix
+//! let inherit (import ./lib.nix) foo bar; in foo
+//! ```
+//! =>
+//! ```nix
+//! let inherit (import ./lib.nix) foo; in foo
+//! ```
+//!
+//! The whole `inherit` is removed if it is the only name,
+//! and the whole `let ... in` if it is the only binding.
+use super::{AssistKind, AssistsCtx};
+use crate::{DiagnosticKind, TextEdit};
+use rowan::ast::AstNode;
+use rowan::TextRange;
+use syntax::ast::HasBindings;
+use syntax::{ast, SyntaxKind};
+
+pub(super) fn remove_unused_inherited_name(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let file = ctx.frange.file_id;
+    let src = ctx.db.file_content(file);
+    let liveness = ctx.db.liveness_check(file);
+    let mut ranges = liveness
+        .to_diagnostics(ctx.db, file)
+        .filter(|diag| {
+            diag.kind == DiagnosticKind::UnusedInheritedName
+                && diag.range.intersect(ctx.frange.range).is_some()
+        })
+        .map(|diag| diag.range)
+        .collect::<Vec<_>>();
+    ranges.sort_by_key(|range| range.start());
+
+    for range in ranges {
+        let root = ctx.ast.syntax();
+        // Leaving an empty `inherit` or `let` would be reported again.
+        let inherit = root
+            .covering_element(range)
+            .ancestors()
+            .find_map(ast::Inherit::cast)
+            .filter(|inherit| inherit.attrs().count() == 1);
+        let let_in = inherit
+            .as_ref()
+            .and_then(|inherit| ast::LetIn::cast(inherit.syntax().parent()?))
+            .filter(|let_in| let_in.bindings().count() == 1);
+        let delete = match (let_in, inherit) {
+            // Keep only the body of `let ... in body`.
+            (Some(let_in), _) => TextRange::new(
+                let_in.syntax().text_range().start(),
+                let_in.body()?.syntax().text_range().start(),
+            ),
+            (None, inherit) => {
+                let mut delete = inherit.map_or(range, |inherit| inherit.syntax().text_range());
+                // Also remove the whitespace before it.
+                if let Some(tok) = root.token_at_offset(delete.start()).left_biased() {
+                    if tok.kind() == SyntaxKind::SPACE {
+                        delete = tok.text_range().cover(delete);
+                    }
+                }
+                delete
+            }
+        };
+        ctx.add(
+            "remove_unused_inherited_name",
+            format!("Remove unused name `{}`", &src[range]),
+            AssistKind::QuickFix,
+            vec![TextEdit {
+                delete,
+                insert: "".into(),
+            }],
+        );
+    }
+
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    define_check_assist!(super::remove_unused_inherited_name);
+
+    #[test]
+    fn partially_unused() {
+        check(
+            "let inherit (import ./x.nix) a $0b; in a",
+            expect![[r#"
+                let inherit (import ./x.nix) a; in a
+            "#]],
+        );
+        check(
+            "let inherit (import ./x.nix) $0a b; in b",
+            expect![[r#"
+                let inherit (import ./x.nix) b; in b
+            "#]],
+        );
+        check(
+            "
+let
+  inherit (import ./x.nix)
+    a
+    b$0
+    c;
+in a + c
+            ",
+            expect![[r#"
+                let
+                  inherit (import ./x.nix)
+                    a
+                    c;
+                in a + c
+            "#]],
+        );
+    }
+
+    #[test]
+    fn fully_unused() {
+        check(
+            "let inherit (import ./x.nix) $0a b$1; in 1",
+            expect![[r#"
+                let inherit (import ./x.nix) b; in 1
+                let inherit (import ./x.nix) a; in 1
+            "#]],
+        );
+        check(
+            "let inherit (import ./x.nix) $0a; in 1",
+            expect![[r#"
+                1
+            "#]],
+        );
+        check(
+            "{ x = let\n  inherit (import ./x.nix { }) $0a;\nin\n  1; }",
+            expect![[r#"
+                { x = 1; }
+            "#]],
+        );
+        check(
+            "let b = 1; inherit (import ./x.nix) $0a; in b",
+            expect![[r#"
+                let b = 1; in b
+            "#]],
+        );
+    }
+
+    #[test]
+    fn import_with_args() {
+        check(
+            "let inherit (import ./x.nix { }) a $0b; in a",
+            expect![[r#"
+                let inherit (import ./x.nix { }) a; in a
+            "#]],
+        );
+    }
+
+    #[test]
+    fn used_or_not_import() {
+        check_no("let inherit (import ./x.nix) $0a b; in a b");
+        check_no("let inherit ({ a = 1; }) $0a; in 1");
+        check_no("let $0a = import ./x.nix; in 1");
+    }
+}
@@ -0,0 +1,254 @@
+//! Rename a binding to snake_case or camelCase, including all its references.
+//!
+//! ```nix
+//! let fooBar = 1; in fooBar
+//! ```
+//! =>
+//! ```nix
+//! let foo_bar = 1; in foo_bar
+//! ```
+//!
+//! Only `let` bindings and plain lambda parameters are renamed. Attribute names and pattern
+//! fields are part of the interface of the file, like NixOS options or function arguments.
+use super::{AssistKind, AssistsCtx};
+use crate::def::{Expr, NameId, NameKind};
+use crate::ide::rename::{find_name, is_valid_ident, rename};
+use crate::{DefDatabase, FileId, FilePos};
+
+pub(super) fn rename_case(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let file = ctx.frange.file_id;
+    let fpos = FilePos::new(file, ctx.frange.range.start());
+    let (_, name) = find_name(ctx.db, fpos)?;
+    let module = ctx.db.module(file);
+    if !matches!(module[name].kind, NameKind::LetIn | NameKind::Param) {
+        return None;
+    }
+    let old = module[name].text.clone();
+    if !is_valid_ident(&old) {
+        return None;
+    }
+
+    for (id, style, new) in [
+        ("rename_to_snake_case", "snake_case", to_snake_case(&old)),
+        ("rename_to_camel_case", "camelCase", to_camel_case(&old)),
+    ] {
+        if new == old || !is_valid_ident(&new) || has_collision(ctx.db, file, name, &new) {
+            continue;
+        }
+        let mut edit = match rename(ctx.db, fpos, &new) {
+            Ok(edit) => edit,
+            Err(_) => continue,
+        };
+        let edits = match edit.content_edits.remove(&file) {
+            Some(edits) if edit.content_edits.is_empty() => edits,
+            _ => continue,
+        };
+        ctx.add(
+            id,
+            format!("Rename to {style} `{new}`"),
+            AssistKind::RefactorRewrite,
+            edits,
+        );
+    }
+    Some(())
+}
+
+/// Whether renaming `name` to `new` would conflict with other names,
+/// or change what any reference resolves to.
+fn has_collision(db: &dyn DefDatabase, file: FileId, name: NameId, new: &str) -> bool {
+    let module = db.module(file);
+    let scopes = db.scopes(file);
+    let old = &module[name].text;
+
+    // Names defined together, like attributes of the same attrset or fields of the same pattern.
+    let is_sibling = module.exprs().any(|(_, kind)| {
+        let siblings = match kind {
+            Expr::Lambda(param, pat, _) => param
+                .iter()
+                .copied()
+                .chain(
+                    pat.iter()
+                        .flat_map(|pat| pat.fields.iter().filter_map(|f| f.0)),
+                )
+                .collect::<Vec<_>>(),
+            _ => match kind.bindings() {
+                Some(bindings) => bindings.statics.iter().map(|&(n, _)| n).collect(),
+                None => return false,
+            },
+        };
+        siblings.contains(&name) && siblings.iter().any(|&n| module[n].text == new)
+    });
+    if is_sibling {
+        return true;
+    }
+
+    // Whether the innermost definition of `old` or `new` visible from `expr` is `name`.
+    let sees_name_first = |expr, shadow: &str| {
+        let scope = match scopes.scope_for_expr(expr) {
+            Some(scope) => scope,
+            None => return false,
+        };
+        for defs in scopes.ancestors(scope).filter_map(|s| s.as_definitions()) {
+            if defs.get(old) == Some(&name) {
+                return true;
+            }
+            if defs.contains_key(shadow) {
+                return false;
+            }
+        }
+        false
+    };
+
+    // References of `name` must not be captured by inner definitions of `new`.
+    let name_refs = db.name_reference(file);
+    let refs = name_refs.name_references(name).unwrap_or_default();
+    if refs.iter().any(|&expr| !sees_name_first(expr, new)) {
+        return true;
+    }
+
+    // Existing references of `new` must not be captured by the renamed name.
+    let is_capturing = module.exprs().any(|(expr, kind)| {
+        matches!(kind, Expr::Reference(text) if text == new) && sees_name_first(expr, new)
+    });
+    is_capturing
+}
+
+/// Split an identifier into its words, keeping leading underscores and trailing primes.
+/// Words are separated by `_`, `-` and lowercase-to-uppercase boundaries.
+/// Digits belong to the preceding word, like `foo2` in `foo2Bar`.
+fn split_words(name: &str) -> (&str, Vec<String>, &str) {
+    let body_start = name.len() - name.trim_start_matches('_').len();
+    let body_end = name.trim_end_matches('\'').len().max(body_start);
+    let body = &name[body_start..body_end];
+
+    let mut words = Vec::new();
+    for part in body.split(['_', '-']) {
+        let chars = part.chars().collect::<Vec<_>>();
+        let mut word = String::new();
+        for (i, &c) in chars.iter().enumerate() {
+            let starts_word = c.is_ascii_uppercase()
+                && i != 0
+                && (!chars[i - 1].is_ascii_uppercase()
+                    || matches!(chars.get(i + 1), Some(next) if next.is_ascii_lowercase()));
+            if starts_word && !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+            word.push(c);
+        }
+        if !word.is_empty() {
+            words.push(word);
+        }
+    }
+    (&name[..body_start], words, &name[body_end..])
+}
+
+fn to_snake_case(name: &str) -> String {
+    let (prefix, words, suffix) = split_words(name);
+    let words = words
+        .iter()
+        .map(|word| word.to_ascii_lowercase())
+        .collect::<Vec<_>>();
+    format!("{prefix}{}{suffix}", words.join("_"))
+}
+
+fn to_camel_case(name: &str) -> String {
+    let (prefix, words, suffix) = split_words(name);
+    let mut ret = prefix.to_owned();
+    for (i, word) in words.iter().enumerate() {
+        let word = word.to_ascii_lowercase();
+        if i == 0 {
+            ret += &word;
+        } else {
+            ret += &word[..1].to_ascii_uppercase();
+            ret += &word[1..];
+        }
+    }
+    ret + suffix
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    define_check_assist!(super::rename_case);
+
+    #[test]
+    fn convert() {
+        use super::{to_camel_case, to_snake_case};
+
+        assert_eq!(to_snake_case("fooBar"), "foo_bar");
+        assert_eq!(to_snake_case("fooHTTPServer"), "foo_http_server");
+        assert_eq!(to_snake_case("foo-bar"), "foo_bar");
+        assert_eq!(to_snake_case("foo2Bar"), "foo2_bar");
+        assert_eq!(to_snake_case("_fooBar'"), "_foo_bar'");
+        assert_eq!(to_snake_case("foo_bar"), "foo_bar");
+
+        assert_eq!(to_camel_case("foo_bar"), "fooBar");
+        assert_eq!(to_camel_case("FOO_BAR"), "fooBar");
+        assert_eq!(to_camel_case("foo-bar-2"), "fooBar2");
+        assert_eq!(to_camel_case("foo_2bar"), "foo2bar");
+        assert_eq!(to_camel_case("__foo_bar"), "__fooBar");
+        assert_eq!(to_camel_case("fooBar"), "fooBar");
+        assert_eq!(to_camel_case("_"), "_");
+    }
+
+    #[test]
+    fn rename() {
+        check(
+            "let $0fooBar = 1; in fooBar",
+            expect![[r#"
+                let foo_bar = 1; in foo_bar
+            "#]],
+        );
+        check(
+            "foo_bar: $0foo_bar",
+            expect![[r#"
+                fooBar: fooBar
+            "#]],
+        );
+        check(
+            "let $0foo-bar = 1; baz = foo-bar; in baz",
+            expect![[r#"
+                let foo_bar = 1; baz = foo_bar; in baz
+                let fooBar = 1; baz = fooBar; in baz
+            "#]],
+        );
+        check(
+            "let $0_foo2_bar = 1; in _foo2_bar",
+            expect![[r#"
+                let _foo2Bar = 1; in _foo2Bar
+            "#]],
+        );
+        // Already in both styles.
+        check_no("let $0foo = 1; in foo");
+        check_no("let $0\"foo bar\" = 1; in 1");
+    }
+
+    #[test]
+    fn interface() {
+        // Attribute names, like NixOS options.
+        check_no("{ environment.$0systemPackages = [ ]; }");
+        check_no("rec { $0fooBar = 1; baz = fooBar; }");
+        // Pattern fields, passed by callers.
+        check_no("{ foo_bar }: $0foo_bar");
+        check_no("{ $0fooBar ? 1 }@args: fooBar");
+    }
+
+    #[test]
+    fn collision() {
+        // Siblings.
+        check_no("let $0fooBar = 1; foo_bar = 2; in fooBar");
+        check_no("fooBar@{ foo_bar }: $0fooBar");
+        // Captured by an inner definition.
+        check_no("let $0fooBar = 1; in let foo_bar = 2; in fooBar");
+        // Capturing an outer definition.
+        check_no("let foo_bar = 1; in let $0fooBar = 2; in foo_bar + fooBar");
+        // Unrelated definitions are fine.
+        check(
+            "let a = let foo_bar = 1; in foo_bar; in let $0fooBar = 2; in fooBar",
+            expect![[r#"
+                let a = let foo_bar = 1; in foo_bar; in let foo_bar = 2; in foo_bar
+            "#]],
+        );
+    }
+}
@@ -0,0 +1,119 @@
+//! Replace `with` by explicit selections on its environment.
+//!
+//! ```nix
+//! with pkgs; [ hello jq ]
+//! ```
+//! =>
+//! ```nix
+//! [ pkgs.hello pkgs.jq ]
+//! ```
+use super::{AssistKind, AssistsCtx};
+use crate::def::{AstPtr, Expr};
+use crate::ide::diagnostics::replaceable_with_uses;
+use crate::TextEdit;
+use rowan::ast::AstNode;
+use rowan::TextRange;
+use syntax::ast;
+
+pub(super) fn replace_with(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let file = ctx.frange.file_id;
+    let module = ctx.db.module(file);
+    let source_map = ctx.db.source_map(file);
+
+    // The innermost `with` whose header `with env;` is under the cursor.
+    // The cursor may touch the start of the `with` token, so search from the root.
+    let node = ctx
+        .ast
+        .syntax()
+        .descendants()
+        .filter_map(ast::With::cast)
+        .filter(|node| {
+            let header_end = match node.semicolon_token() {
+                Some(semi) => semi.text_range().end(),
+                None => return false,
+            };
+            TextRange::new(node.syntax().text_range().start(), header_end)
+                .contains_range(ctx.frange.range)
+        })
+        .last()?;
+    let with_expr = source_map.expr_for_node(AstPtr::new(node.syntax()))?;
+    let env = node.environment()?.syntax().to_string();
+    let body = node.body()?;
+    let uses = replaceable_with_uses(ctx.db, file, with_expr)?;
+
+    let mut edits = vec![TextEdit {
+        delete: TextRange::new(
+            node.syntax().text_range().start(),
+            body.syntax().text_range().start(),
+        ),
+        insert: "".into(),
+    }];
+    for expr in uses {
+        let name = match &module[expr] {
+            Expr::Reference(name) => name,
+            _ => return None,
+        };
+        edits.push(TextEdit {
+            delete: source_map.node_for_expr(expr)?.text_range(),
+            insert: format!("{env}.{name}").into(),
+        });
+    }
+
+    ctx.add(
+        "replace_with",
+        format!("Reference names from `{env}` explicitly"),
+        AssistKind::RefactorRewrite,
+        edits,
+    );
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    define_check_assist!(super::replace_with);
+
+    #[test]
+    fn simple() {
+        check(
+            "$0with pkgs; [ hello jq hello ]",
+            expect![[r#"
+                [ pkgs.hello pkgs.jq pkgs.hello ]
+            "#]],
+        );
+        check(
+            "{ pkgs }: with pkgs.$0lib;\nfoo (bar 1)",
+            expect![[r#"
+                { pkgs }: pkgs.lib.foo (pkgs.lib.bar 1)
+            "#]],
+        );
+    }
+
+    #[test]
+    fn not_applicable() {
+        // Not in the header.
+        check_no("with pkgs; [ $0hello ]");
+        // Unused.
+        check_no("$0with pkgs; 1");
+        // Complex environment.
+        check_no("$0with import ./pkgs.nix; [ hello ]");
+        check_no("$0with pkgs.${x}; [ hello ]");
+        // Inherited.
+        check_no("$0with pkgs; { inherit hello; }");
+        // Shadowed environment.
+        check_no("$0with pkgs; let pkgs = 1; in hello");
+    }
+
+    #[test]
+    fn nested() {
+        // `lib` is also the fallback of names not in `pkgs`.
+        check_no("$0with lib; with pkgs; [ a ]");
+        check(
+            "with lib; $0with pkgs; [ a ]",
+            expect![[r#"
+                with lib; [ pkgs.a ]
+            "#]],
+        );
+    }
+}
@@ -0,0 +1,121 @@
+//! Simplify `if` and `assert` with constant conditions.
+//!
+//! ```nix
+//! if true then a else b
+//! ```
+//! =>
+//! ```nix
+//! a
+//! ```
+use super::{AssistKind, AssistsCtx};
+use crate::def::AstPtr;
+use crate::ide::diagnostics::constant_bool;
+use crate::TextEdit;
+use rowan::ast::AstNode;
+use rowan::TextRange;
+use syntax::{ast, match_ast, SyntaxNode};
+
+pub(super) fn simplify_constant_condition(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let file = ctx.frange.file_id;
+    let module = ctx.db.module(file);
+    let source_map = ctx.db.source_map(file);
+    let name_res = ctx.db.name_resolution(file);
+
+    // The innermost `if` or `assert` whose condition is under the cursor.
+    let (node, cond, branches) = ctx
+        .ast
+        .syntax()
+        .covering_element(ctx.frange.range)
+        .ancestors()
+        .find_map(|node| {
+            let (cond, branches) = match_ast! {
+                match (&node) {
+                    ast::IfThenElse(n) => (n.condition()?, Some((n.then_body()?, n.else_body()?))),
+                    ast::Assert(n) => (n.condition()?, None),
+                    _ => return None,
+                }
+            };
+            cond.syntax()
+                .text_range()
+                .contains_range(ctx.frange.range)
+                .then_some((node, cond, branches))
+        })?;
+    let cond_expr = source_map.expr_for_node(AstPtr::new(cond.syntax()))?;
+    let value = constant_bool(&module, &name_res, cond_expr)?;
+
+    let (label, replacement) = match (branches, value) {
+        (Some((then_body, _)), true) => ("Simplify to the `then` branch", then_body),
+        (Some((_, else_body)), false) => ("Simplify to the `else` branch", else_body),
+        (None, true) => {
+            let body = ast::Assert::cast(node.clone())?.body()?;
+            ("Remove the always-true assertion", body)
+        }
+        // An always-failing assertion cannot be simplified.
+        (None, false) => return None,
+    };
+
+    ctx.add(
+        "simplify_constant_condition",
+        label,
+        AssistKind::QuickFix,
+        vec![TextEdit {
+            delete: trimmed_range(&node),
+            insert: replacement.syntax().to_string().trim_end().into(),
+        }],
+    );
+    Some(())
+}
+
+/// The range of a node without trailing whitespaces.
+fn trimmed_range(node: &SyntaxNode) -> TextRange {
+    let text = node.to_string();
+    let start = node.text_range().start();
+    TextRange::at(start, (text.trim_end().len() as u32).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    define_check_assist!(super::simplify_constant_condition);
+
+    #[test]
+    fn if_then_else() {
+        check(
+            "[ (if $0true then a + 1 else b) ]",
+            expect![[r#"
+                [ (a + 1) ]
+            "#]],
+        );
+        check(
+            "[ (if !tr$0ue then a else { b = 1; }) ]",
+            expect![[r#"
+                [ ({ b = 1; }) ]
+            "#]],
+        );
+        check(
+            "if true && $0false then a else if true then b else c",
+            expect![[r#"
+                if true then b else c
+            "#]],
+        );
+    }
+
+    #[test]
+    fn assert() {
+        check(
+            "assert $0true; a",
+            expect![[r#"
+                a
+            "#]],
+        );
+        check_no("assert $0false; a");
+    }
+
+    #[test]
+    fn not_constant() {
+        check_no("a: if $0a then 1 else 2");
+        check_no("let true = false; in if $0true then 1 else 2");
+        check_no("if true then $01 else 2");
+    }
+}
@@ -0,0 +1,202 @@
+//! Convert between `inherit` and explicit bindings.
+//!
+//! ```nix
+//! { inherit foo; inherit (lib) bar; }
+//! ```
+//! <=>
+//! ```nix
+//! { foo = foo; bar = lib.bar; }
+//! ```
+use super::{AssistKind, AssistsCtx};
+use crate::TextEdit;
+use rowan::ast::AstNode;
+use rowan::TextRange;
+use syntax::{ast, SyntaxNode};
+
+pub(super) fn toggle_inherit(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let src = ctx.db.file_content(ctx.frange.file_id);
+    let binding = ctx
+        .ast
+        .syntax()
+        .covering_element(ctx.frange.range)
+        .ancestors()
+        .find_map(ast::Binding::cast)?;
+
+    // In `let` and `rec` sets, `foo = foo;` refers to itself instead of the outer `foo`.
+    let recursive = match binding.syntax().parent().and_then(ast::AttrSet::cast) {
+        Some(set) => set.rec_token().is_some() || set.let_token().is_some(),
+        None => true,
+    };
+
+    let (label, insert) = match &binding {
+        ast::Binding::Inherit(i) => {
+            let from = match i.from_expr() {
+                Some(paren) => Some(select_base(paren.syntax(), &paren.expr()?)),
+                None if recursive => return None,
+                None => None,
+            };
+            let names = i
+                .attrs()
+                .map(|attr| match attr {
+                    ast::Attr::Name(name) => Some(name.token()?.text().to_owned()),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()?;
+            if names.is_empty() {
+                return None;
+            }
+            let insert = names
+                .iter()
+                .map(|name| match &from {
+                    Some(from) => format!("{name} = {from}.{name};"),
+                    None => format!("{name} = {name};"),
+                })
+                .collect::<Vec<_>>()
+                .join(" ");
+            ("Convert to explicit bindings", insert)
+        }
+        ast::Binding::AttrpathValue(b) => {
+            let name = single_name(&b.attrpath()?)?;
+            let insert = match b.value()? {
+                ast::Expr::Ref(r) if !recursive && r.token()?.text() == name => {
+                    format!("inherit {name};")
+                }
+                ast::Expr::Select(sel) if sel.or_token().is_none() => {
+                    let set = sel.set()?;
+                    let attrs = sel.attrpath()?.attrs().collect::<Vec<_>>();
+                    match attrs.last()? {
+                        ast::Attr::Name(last) if last.token()?.text() == name => {}
+                        _ => return None,
+                    }
+                    let base_end = match &attrs[..attrs.len() - 1] {
+                        [.., prev] => prev.syntax().text_range().end(),
+                        [] => set.syntax().text_range().end(),
+                    };
+                    let range = TextRange::new(set.syntax().text_range().start(), base_end);
+                    let base = &src[range];
+                    match set {
+                        ast::Expr::Paren(_) if attrs.len() == 1 => {
+                            format!("inherit {base} {name};")
+                        }
+                        _ => format!("inherit ({base}) {name};"),
+                    }
+                }
+                _ => return None,
+            };
+            ("Convert to `inherit`", insert)
+        }
+    };
+
+    ctx.add(
+        "toggle_inherit",
+        label,
+        AssistKind::RefactorRewrite,
+        vec![TextEdit {
+            delete: binding.syntax().text_range(),
+            insert: insert.into(),
+        }],
+    );
+    Some(())
+}
+
+/// The text of the only static name in an attrpath.
+fn single_name(path: &ast::Attrpath) -> Option<String> {
+    let mut attrs = path.attrs();
+    let name = match attrs.next()? {
+        ast::Attr::Name(name) => name.token()?.text().to_owned(),
+        _ => return None,
+    };
+    attrs.next().is_none().then_some(name)
+}
+
+/// The text to select names from the expression `inner` of parenthesis `paren`.
+/// Parenthesis are kept unless they are unnecessary.
+fn select_base(paren: &SyntaxNode, inner: &ast::Expr) -> String {
+    match inner {
+        ast::Expr::Ref(_) | ast::Expr::AttrSet(_) => inner.syntax().to_string(),
+        ast::Expr::Select(sel) if sel.or_token().is_none() => inner.syntax().to_string(),
+        _ => paren.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    define_check_assist!(super::toggle_inherit);
+
+    #[test]
+    fn inherit() {
+        check(
+            "{ inherit $0foo; }",
+            expect![[r#"
+                { foo = foo; }
+            "#]],
+        );
+        check(
+            "{ inh$0erit foo bar; }",
+            expect![[r#"
+                { foo = foo; bar = bar; }
+            "#]],
+        );
+        check(
+            "{ foo = $0foo; }",
+            expect![[r#"
+                { inherit foo; }
+            "#]],
+        );
+        check_no("{ foo = $0bar; }");
+        check_no("{ foo.bar = $0foo; }");
+        check_no("{ inherit $0\"foo\"; }");
+    }
+
+    #[test]
+    fn inherit_from() {
+        check(
+            "{ inherit (lib.$0strings) foo bar; }",
+            expect![[r#"
+                { foo = lib.strings.foo; bar = lib.strings.bar; }
+            "#]],
+        );
+        check(
+            "{ inherit (import ./lib.nix) $0foo; }",
+            expect![[r#"
+                { foo = (import ./lib.nix).foo; }
+            "#]],
+        );
+        check(
+            "{ foo $0= lib.strings.foo; }",
+            expect![[r#"
+                { inherit (lib.strings) foo; }
+            "#]],
+        );
+        check(
+            "{ foo = (import ./lib.nix).$0foo; }",
+            expect![[r#"
+                { inherit (import ./lib.nix) foo; }
+            "#]],
+        );
+        check_no("{ foo = $0lib.foo or null; }");
+        check_no("{ foo = $0lib.bar; }");
+    }
+
+    #[test]
+    fn recursive() {
+        // These would refer to the binding itself.
+        check_no("let inherit $0foo; in foo");
+        check_no("rec { inherit $0foo; }");
+        check_no("rec { foo = $0foo; }");
+        check(
+            "let inherit (lib) $0foo; in foo",
+            expect![[r#"
+                let foo = lib.foo; in foo
+            "#]],
+        );
+        check(
+            "rec { foo = lib.$0foo; }",
+            expect![[r#"
+                rec { inherit (lib) foo; }
+            "#]],
+        );
+    }
+}
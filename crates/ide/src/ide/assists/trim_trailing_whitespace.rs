@@ -0,0 +1,112 @@
+//! Remove whitespaces at the end of lines.
+//!
+//! ```nix
+//! { foo = 1; }␣␣
+//! ```
+//! =>
+//! ```nix
+//! { foo = 1; }
+//! ```
+//!
+//! Only offered if trailing whitespaces are reported, see `AssistsConfig::trailing_whitespace`.
+use super::{AssistKind, AssistsCtx};
+use crate::ide::diagnostics::trailing_whitespaces;
+use crate::TextEdit;
+use rowan::ast::AstNode;
+
+pub(super) fn trim_trailing_whitespace(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    if !ctx.config.trailing_whitespace {
+        return None;
+    }
+    let src = ctx.db.file_content(ctx.frange.file_id);
+    let ranges = trailing_whitespaces(ctx.ast.syntax(), &src);
+    let range = *ranges
+        .iter()
+        .find(|range| range.intersect(ctx.frange.range).is_some())?;
+
+    ctx.add(
+        "trim_trailing_whitespace",
+        "Remove trailing whitespace",
+        AssistKind::QuickFix,
+        vec![TextEdit {
+            delete: range,
+            insert: "".into(),
+        }],
+    );
+    if ranges.len() > 1 {
+        ctx.add(
+            "trim_all_trailing_whitespace",
+            "Remove all trailing whitespaces in the file",
+            AssistKind::QuickFix,
+            ranges
+                .into_iter()
+                .map(|delete| TextEdit {
+                    delete,
+                    insert: "".into(),
+                })
+                .collect(),
+        );
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ide::assists::{tests::fixture_frange, AssistsConfig, AssistsCtx};
+    use expect_test::expect;
+
+    define_check_assist!(
+        super::trim_trailing_whitespace,
+        AssistsConfig {
+            trailing_whitespace: true,
+            ..AssistsConfig::default()
+        }
+    );
+
+    #[test]
+    fn simple() {
+        check(
+            "{\n  foo = 1;  $0\n}",
+            expect![[r#"
+                {
+                  foo = 1;
+                }
+            "#]],
+        );
+        check(
+            "# comment $0\n1",
+            expect![[r#"
+                # comment
+                1
+            "#]],
+        );
+        check(
+            "let a = 1; $0\nin  \n  a",
+            expect![[r#"
+                let a = 1;
+                in  
+                  a
+                let a = 1;
+                in
+                  a
+            "#]],
+        );
+    }
+
+    #[test]
+    fn not_applicable() {
+        check_no("{\n  $0foo = 1;  \n}");
+        // Part of the string content.
+        check_no("''\n  a  $0\n''");
+        check_no("\"a  $0\n\"");
+    }
+
+    #[test]
+    fn disabled() {
+        let (db, _, frange) = fixture_frange("{\n  foo = 1;  $0\n}");
+        let config = AssistsConfig::default();
+        let mut ctx = AssistsCtx::new(&db, &config, frange);
+        super::trim_trailing_whitespace(&mut ctx);
+        assert_eq!(ctx.assists, Vec::new());
+    }
+}
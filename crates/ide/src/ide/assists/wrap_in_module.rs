@@ -0,0 +1,158 @@
+//! Wrap a top-level attrset in a NixOS module lambda.
+//!
+//! ```nix
+//! {
+//!   foo = 1;
+//! }
+//! ```
+//! =>
+//! ```nix
+//! { config, lib, pkgs, ... }:
+//!   {
+//!     foo = 1;
+//!   }
+//! ```
+use super::{AssistKind, AssistsCtx};
+use crate::TextEdit;
+use rowan::ast::AstNode;
+use rowan::{TextRange, TextSize};
+use syntax::{ast, SyntaxKind};
+
+const INDENT: &str = "  ";
+
+pub(super) fn wrap_in_module(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let set = match ctx.ast.expr()? {
+        ast::Expr::AttrSet(set) if set.let_token().is_none() => set,
+        _ => return None,
+    };
+    // Only offer it directly inside the top-level attrset, not in nested ones.
+    let innermost = ctx
+        .ast
+        .syntax()
+        .covering_element(ctx.frange.range)
+        .ancestors()
+        .find_map(ast::AttrSet::cast)?;
+    if innermost != set {
+        return None;
+    }
+
+    let params = ctx
+        .config
+        .module_params
+        .iter()
+        .map(|param| format!("{param}, "))
+        .collect::<String>();
+    let start = set.syntax().text_range().start();
+    let mut edits = vec![TextEdit {
+        delete: TextRange::empty(start),
+        insert: format!("{{ {params}... }}:\n{INDENT}").into(),
+    }];
+
+    // Indent following lines, except those continuing a string literal,
+    // whose content would be changed.
+    let text = set.syntax().text().to_string();
+    for (i, _) in text.match_indices('\n') {
+        let pos = start + TextSize::from(i as u32);
+        let line_start = pos + TextSize::from(1);
+        if matches!(text[i + 1..].chars().next(), None | Some('\n' | '\r')) {
+            continue;
+        }
+        let tok = match set.syntax().token_at_offset(pos).right_biased() {
+            Some(tok) => tok,
+            None => continue,
+        };
+        if matches!(tok.kind(), SyntaxKind::SPACE | SyntaxKind::COMMENT) {
+            edits.push(TextEdit {
+                delete: TextRange::empty(line_start),
+                insert: INDENT.into(),
+            });
+        }
+    }
+
+    ctx.add(
+        "wrap_in_module",
+        "Wrap in a module function",
+        AssistKind::RefactorRewrite,
+        edits,
+    );
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    define_check_assist!(super::wrap_in_module);
+
+    #[test]
+    fn simple() {
+        check(
+            "{ $0foo = 1; }",
+            expect![[r#"
+                { config, lib, pkgs, ... }:
+                  { foo = 1; }
+            "#]],
+        );
+        check(
+            r#"
+# A comment.
+{
+  $0foo = {
+    bar = 1;
+  };
+
+  /* block
+  comment */
+  baz = 2;
+}
+"#,
+            expect![[r#"
+                # A comment.
+                { config, lib, pkgs, ... }:
+                  {
+                    foo = {
+                      bar = 1;
+                    };
+
+                    /* block
+                    comment */
+                    baz = 2;
+                  }
+            "#]],
+        );
+    }
+
+    #[test]
+    fn preserve_strings() {
+        check(
+            r#"{
+  $0a = ''
+    line
+  '';
+  b = "x
+y";
+}"#,
+            expect![[r#"
+                { config, lib, pkgs, ... }:
+                  {
+                    a = ''
+                    line
+                  '';
+                    b = "x
+                y";
+                  }
+            "#]],
+        );
+    }
+
+    #[test]
+    fn not_applicable() {
+        // Already a lambda.
+        check_no("{ config, ... }: { $0foo = 1; }");
+        check_no("args: $0{ foo = 1; }");
+        // Not a top-level attrset.
+        check_no("{ foo = { $0bar = 1; }; }");
+        check_no("let $0a = 1; in { }");
+        check_no("let { $0body = 1; }");
+    }
+}
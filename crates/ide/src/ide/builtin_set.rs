@@ -0,0 +1,127 @@
+use builtin::ALL_BUILTINS;
+use smol_str::SmolStr;
+use std::fmt;
+
+/// Builtins introduced after Nix 2.3, with the version introducing them.
+/// Builtins not listed here are assumed to be available in all supported versions.
+#[rustfmt::skip]
+const BUILTIN_SINCE: &[(&str, NixVersion)] = &[
+    ("ceil",                     NixVersion::new(2, 4)),
+    ("fetchTree",                NixVersion::new(2, 4)),
+    ("floor",                    NixVersion::new(2, 4)),
+    ("getFlake",                 NixVersion::new(2, 4)),
+    ("groupBy",                  NixVersion::new(2, 5)),
+    ("zipAttrsWith",             NixVersion::new(2, 6)),
+    ("fetchClosure",             NixVersion::new(2, 8)),
+    ("traceVerbose",             NixVersion::new(2, 10)),
+    ("readFileType",             NixVersion::new(2, 14)),
+    ("flakeRefToString",         NixVersion::new(2, 18)),
+    ("parseFlakeRef",            NixVersion::new(2, 18)),
+    ("addDrvOutputDependencies", NixVersion::new(2, 19)),
+    ("convertHash",              NixVersion::new(2, 19)),
+    ("warn",                     NixVersion::new(2, 23)),
+];
+
+/// Whether `name` is a builtin of some Nix version.
+/// Builtins gated by experimental features, like `getFlake`, may be missing in the generated
+/// list, so those known to be introduced in some version are also included.
+pub(crate) fn is_known_builtin(name: &str) -> bool {
+    ALL_BUILTINS.contains_key(name) || BUILTIN_SINCE.iter().any(|(builtin, _)| *builtin == name)
+}
+
+/// A Nix version, ignoring the patch number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NixVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl NixVersion {
+    pub const fn new(major: u32, minor: u32) -> Self {
+        Self { major, minor }
+    }
+
+    /// Parse versions like `2.18` or `2.18.1`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut parts = s.split('.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next()?.parse().ok()?;
+        if let Some(patch) = parts.next() {
+            patch.parse::<u32>().ok()?;
+        }
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Self { major, minor })
+    }
+}
+
+impl fmt::Display for NixVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// The builtins available in the user's Nix.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum BuiltinSet {
+    /// All known builtins.
+    #[default]
+    Latest,
+    /// Builtins available in the given Nix version.
+    Version(NixVersion),
+    /// Exactly these builtins.
+    Allowlist(Vec<SmolStr>),
+}
+
+impl BuiltinSet {
+    pub fn contains(&self, name: &str) -> bool {
+        match self {
+            Self::Latest => true,
+            Self::Version(version) => BUILTIN_SINCE
+                .iter()
+                .all(|(builtin, since)| *builtin != name || since <= version),
+            Self::Allowlist(names) => names.iter().any(|allowed| allowed == name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BuiltinSet, NixVersion};
+
+    #[test]
+    fn parse_version() {
+        assert_eq!(NixVersion::parse("2.18"), Some(NixVersion::new(2, 18)));
+        assert_eq!(NixVersion::parse("2.3.16"), Some(NixVersion::new(2, 3)));
+        assert_eq!(NixVersion::parse("2"), None);
+        assert_eq!(NixVersion::parse("2.x"), None);
+        assert_eq!(NixVersion::parse("2.18.1.0"), None);
+        assert_eq!(NixVersion::parse("2.18pre"), None);
+        assert_eq!(NixVersion::new(2, 18).to_string(), "2.18");
+    }
+
+    #[test]
+    fn version() {
+        let set = BuiltinSet::Version(NixVersion::new(2, 5));
+        assert!(set.contains("map"));
+        assert!(set.contains("floor"));
+        assert!(set.contains("groupBy"));
+        assert!(!set.contains("zipAttrsWith"));
+        assert!(!set.contains("warn"));
+
+        let set = BuiltinSet::Version(NixVersion::new(2, 3));
+        assert!(set.contains("map"));
+        assert!(!set.contains("floor"));
+
+        assert!(BuiltinSet::Latest.contains("warn"));
+    }
+
+    #[test]
+    fn allowlist() {
+        let set = BuiltinSet::Allowlist(vec!["map".into(), "true".into()]);
+        assert!(set.contains("map"));
+        assert!(set.contains("true"));
+        assert!(!set.contains("toString"));
+    }
+}
@@ -1,10 +1,18 @@
-use crate::def::{AstPtr, NameKind};
-use crate::{DefDatabase, FileId, FilePos};
+use super::goto_definition::merged_attrsets;
+use crate::def::{
+    AstPtr, AttrKind, BindingValue, Expr, ModuleScopes, ModuleSourceMap, NameId, NameKind,
+    ResolveResult, ScopeId,
+};
+use crate::{BuiltinSet, DefDatabase, FileId, FilePos, Module, TextEdit};
 use builtin::{BuiltinKind, ALL_BUILTINS};
 use either::Either::{Left, Right};
 use rowan::ast::AstNode;
 use smol_str::SmolStr;
-use syntax::{ast, best_token_at_offset, match_ast, SyntaxKind, TextRange, T};
+use std::collections::HashSet;
+use syntax::ast::HasBindings;
+use syntax::{
+    ast, best_token_at_offset, match_ast, SyntaxKind, SyntaxNode, SyntaxToken, TextRange, T,
+};
 
 #[rustfmt::skip]
 const EXPR_POS_KEYWORDS: &[&str] = &[
@@ -21,6 +29,43 @@ const EXPR_POS_KEYWORDS: &[&str] = &[
 ];
 const ATTR_POS_KEYWORDS: &[&str] = &["inherit"];
 
+/// Top-level attributes of NixOS modules.
+const MODULE_ATTRS: &[&str] = &["config", "disabledModules", "imports", "meta", "options"];
+/// Parameters indicating a lambda is a NixOS module, like `{ config, lib, pkgs, ... }: { }`.
+const MODULE_PARAMS: &[&str] = &["config", "lib", "pkgs"];
+
+/// Commonly used attributes of nixpkgs `lib`, with their signatures.
+const LIB_ATTRS: &[(&str, &str)] = &[
+    ("concatMapStringsSep", "lib.concatMapStringsSep sep f list"),
+    ("concatStringsSep", "lib.concatStringsSep sep list"),
+    ("mapAttrs", "lib.mapAttrs f attrs"),
+    ("mapAttrsToList", "lib.mapAttrsToList f attrs"),
+    ("mkDefault", "lib.mkDefault value"),
+    ("mkEnableOption", "lib.mkEnableOption name"),
+    ("mkForce", "lib.mkForce value"),
+    ("mkIf", "lib.mkIf cond content"),
+    ("mkMerge", "lib.mkMerge [ defs ]"),
+    (
+        "mkOption",
+        "lib.mkOption { type, default, description, ... }",
+    ),
+    ("optional", "lib.optional cond elem"),
+    ("optionalAttrs", "lib.optionalAttrs cond attrs"),
+    ("optionalString", "lib.optionalString cond str"),
+    ("optionals", "lib.optionals cond list"),
+    ("types", "lib.types"),
+];
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CompletionConfig {
+    /// Offer heuristic completions for conventions of nixpkgs, like attributes of NixOS modules.
+    pub nixpkgs_hints: bool,
+    /// Only offer builtins available in the user's Nix.
+    pub builtins: BuiltinSet,
+    /// Whether the client accepts snippets, to place the cursor after inserted text.
+    pub snippet_support: bool,
+}
+
 /// A single completion variant in the editor pop-up.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CompletionItem {
@@ -36,6 +81,12 @@ pub struct CompletionItem {
     pub brief: Option<String>,
     /// The detailed documentation.
     pub doc: Option<String>,
+    /// Whether `replace` is a snippet rather than plain text.
+    pub is_snippet: bool,
+    /// Other edits to apply when this item is selected, like adding a binding it requires.
+    pub additional_edits: Vec<TextEdit>,
+    /// Whether it is bound by deprecated syntax, like `let { ... }`.
+    pub deprecated: bool,
 }
 
 /// The type of the completion item.
@@ -45,6 +96,8 @@ pub enum CompletionItemKind {
     Param,
     LetBinding,
     Field,
+    /// A binding whose value is a lambda.
+    Function,
     BuiltinConst,
     BuiltinFunction,
     BuiltinAttrset,
@@ -74,17 +127,47 @@ impl TryFrom<NameKind> for CompletionItemKind {
 
 pub(crate) fn completions(
     db: &dyn DefDatabase,
+    config: &CompletionConfig,
     FilePos { file_id, pos }: FilePos,
 ) -> Option<Vec<CompletionItem>> {
     let parse = db.parse(file_id);
 
     let tok = best_token_at_offset(&parse.syntax_node(), pos)?;
+
+    // Empty interpolation `"${|}"` has no identifier to complete, but it is still an expression.
+    if let Some(dynamic) = tok.parent().and_then(ast::Dynamic::cast) {
+        let in_braces = match tok.kind() {
+            T!["${"] => tok.text_range().end() <= pos,
+            T!['}'] => pos <= tok.text_range().start(),
+            _ => false,
+        };
+        if in_braces && dynamic.expr().is_none() {
+            let string_node = dynamic.syntax().parent()?;
+            if !matches!(
+                string_node.kind(),
+                SyntaxKind::STRING | SyntaxKind::INDENT_STRING
+            ) {
+                return None;
+            }
+            let source_range = TextRange::empty(pos);
+            let mut items = complete_expr(db, config, file_id, source_range, &string_node, "")?;
+            close_interpolation(config, &mut items, &dynamic);
+            return Some(items);
+        }
+    }
+
     let source_range = match tok.kind() {
         T![.] => TextRange::empty(pos),
         SyntaxKind::IDENT => tok.text_range(),
         _ => return None,
     };
 
+    if config.nixpkgs_hints {
+        if let Some(items) = complete_lib_attr(db, file_id, &tok, source_range) {
+            return Some(items);
+        }
+    }
+
     let node = tok.parent_ancestors().find_map(|node| {
         match_ast! {
             match node {
@@ -96,31 +179,51 @@ pub(crate) fn completions(
     })?;
 
     match node {
-        Left(ref_node) => complete_expr(db, file_id, source_range, ref_node),
+        Left(ref_node) => {
+            let prefix = ref_node.token()?;
+            let mut items = complete_expr(
+                db,
+                config,
+                file_id,
+                source_range,
+                ref_node.syntax(),
+                prefix.text(),
+            )?;
+            if let Some(dynamic) = ref_node.syntax().ancestors().find_map(ast::Dynamic::cast) {
+                close_interpolation(config, &mut items, &dynamic);
+            }
+            Some(items)
+        }
         Right(name_node) => {
+            if ast::Inherit::cast(name_node.syntax().parent()?).is_some() {
+                return complete_inherit(db, file_id, source_range, name_node, tok.text());
+            }
             let path_node = ast::Attrpath::cast(name_node.syntax().parent()?)?;
             let _entry_node = ast::AttrpathValue::cast(path_node.syntax().parent()?)?;
-            complete_attrpath_def(db, file_id, source_range, path_node, name_node)
+            complete_attrpath_def(db, config, file_id, source_range, path_node, name_node)
         }
     }
 }
 
+/// Complete an expression at `expr_node`, which is either the `Ref` being typed,
+/// or the string containing an empty interpolation.
 fn complete_expr(
     db: &dyn DefDatabase,
+    config: &CompletionConfig,
     file_id: FileId,
     source_range: TextRange,
-    ref_node: ast::Ref,
+    expr_node: &SyntaxNode,
+    prefix: &str,
 ) -> Option<Vec<CompletionItem>> {
     let module = db.module(file_id);
     let source_map = db.source_map(file_id);
-    let expr_id = source_map.expr_for_node(AstPtr::new(ref_node.syntax()))?;
+    let expr_id = source_map.expr_for_node(AstPtr::new(expr_node))?;
     let scopes = db.scopes(file_id);
     let scope_id = scopes.scope_for_expr(expr_id)?;
 
-    let prefix = SmolStr::from(ref_node.token()?.text());
     let mut items = Vec::new();
     let mut feed = |compe: CompletionItem| {
-        if can_complete(&prefix, &compe.replace) {
+        if can_complete(prefix, &compe.replace) {
             items.push(compe);
         }
     };
@@ -132,8 +235,7 @@ fn complete_expr(
         .for_each(&mut feed);
 
     // Contectual keywords.
-    if ref_node
-        .syntax()
+    if expr_node
         .ancestors()
         .find_map(ast::IfThenElse::cast)
         .is_some()
@@ -141,37 +243,31 @@ fn complete_expr(
         feed(keyword_to_completion("then", source_range));
         feed(keyword_to_completion("else", source_range));
     }
-    if ref_node
-        .syntax()
-        .ancestors()
-        .find_map(ast::LetIn::cast)
-        .is_some()
-    {
+    if expr_node.ancestors().find_map(ast::LetIn::cast).is_some() {
         feed(keyword_to_completion("in", source_range));
     }
 
     // Names in current scopes.
-    scopes
-        .ancestors(scope_id)
-        .filter_map(|scope| scope.as_definitions())
-        .flatten()
-        .map(|(text, name)| CompletionItem {
-            label: text.clone(),
+    scope_names(&module, &scopes, scope_id, source_range).for_each(&mut feed);
+
+    // `lib` from `pkgs`, which is a common convention in nixpkgs.
+    if config.nixpkgs_hints {
+        if let Some(item) = complete_inherited_lib(
+            &module,
+            &source_map,
+            &scopes,
+            scope_id,
+            expr_node,
             source_range,
-            replace: text.clone(),
-            kind: module[*name]
-                .kind
-                .try_into()
-                .expect("NonRecAttrset names are not definitions"),
-            brief: None,
-            doc: None,
-        })
-        .for_each(&mut feed);
+        ) {
+            feed(item);
+        }
+    }
 
     // Global builtins.
     ALL_BUILTINS
         .entries()
-        .filter(|(_, b)| b.is_global)
+        .filter(|(name, b)| b.is_global && config.builtins.contains(name))
         .map(|(name, b)| CompletionItem {
             label: name.into(),
             source_range,
@@ -179,6 +275,9 @@ fn complete_expr(
             kind: b.kind.into(),
             brief: b.summary.map(|s| s.to_owned()),
             doc: b.doc.map(|s| s.to_owned()),
+            is_snippet: false,
+            additional_edits: Vec::new(),
+            deprecated: false,
         })
         .for_each(&mut feed);
 
@@ -189,8 +288,262 @@ fn complete_expr(
     Some(items)
 }
 
+/// Names defined in `scope_id` and its ancestors.
+fn scope_names<'a>(
+    module: &'a Module,
+    scopes: &'a ModuleScopes,
+    scope_id: ScopeId,
+    source_range: TextRange,
+) -> impl Iterator<Item = CompletionItem> + 'a {
+    let let_attrset_names = module
+        .exprs()
+        .filter_map(|(_, kind)| match kind {
+            Expr::LetAttrset(bindings) => Some(bindings.statics.iter().map(|&(name, _)| name)),
+            _ => None,
+        })
+        .flatten()
+        .collect::<HashSet<_>>();
+    scopes
+        .ancestors(scope_id)
+        .filter_map(|scope| scope.as_definitions())
+        .flatten()
+        .map(move |(text, name)| CompletionItem {
+            label: text.clone(),
+            source_range,
+            replace: text.clone(),
+            kind: match module.binding_value(*name) {
+                Some(BindingValue::Expr(value)) if matches!(module[value], Expr::Lambda(..)) => {
+                    CompletionItemKind::Function
+                }
+                _ => module[*name]
+                    .kind
+                    .try_into()
+                    .expect("NonRecAttrset names are not definitions"),
+            },
+            brief: None,
+            doc: None,
+            is_snippet: false,
+            additional_edits: Vec::new(),
+            deprecated: let_attrset_names.contains(name),
+        })
+}
+
+/// Complete the name of `inherit <here>;` with names in scope, and fields of enclosing `with`
+/// whose environments are statically known attrsets.
+fn complete_inherit(
+    db: &dyn DefDatabase,
+    file_id: FileId,
+    source_range: TextRange,
+    name_node: ast::Name,
+    prefix: &str,
+) -> Option<Vec<CompletionItem>> {
+    let module = db.module(file_id);
+    let source_map = db.source_map(file_id);
+    // `inherit (from) <here>` has no reference expression.
+    let expr_id = source_map.expr_for_node(AstPtr::new(name_node.syntax()))?;
+    let scopes = db.scopes(file_id);
+    let scope_id = scopes.scope_for_expr(expr_id)?;
+    let name_res = db.name_resolution(file_id);
+    let root = db.parse(file_id).syntax_node();
+
+    // Lexical names shadow names from `with`, so they come first and win the dedup below.
+    let mut items = scope_names(&module, &scopes, scope_id, source_range).collect::<Vec<_>>();
+    for with_expr in scopes
+        .ancestors(scope_id)
+        .filter_map(|scope| scope.as_with())
+    {
+        let env = match &module[with_expr] {
+            &Expr::With(env, _) => env,
+            _ => continue,
+        };
+        let env_text = match source_map
+            .node_for_expr(with_expr)
+            .and_then(|ptr| ast::With::cast(ptr.to_node(&root))?.environment())
+        {
+            Some(env_node) => env_node.syntax().to_string(),
+            None => continue,
+        };
+        let mut sets = Vec::new();
+        merged_attrsets(&module, &name_res, env, 0, &mut sets);
+        items.extend(
+            sets.iter()
+                .filter_map(|&set| module[set].bindings())
+                .flat_map(|bindings| bindings.statics.iter())
+                .map(|&(field, value)| CompletionItem {
+                    label: module[field].text.clone(),
+                    source_range,
+                    replace: module[field].text.clone(),
+                    kind: match value {
+                        BindingValue::Expr(value) if matches!(module[value], Expr::Lambda(..)) => {
+                            CompletionItemKind::Function
+                        }
+                        _ => CompletionItemKind::Field,
+                    },
+                    brief: Some(format!("From `with {env_text}`")),
+                    doc: None,
+                    is_snippet: false,
+                    additional_edits: Vec::new(),
+                    deprecated: false,
+                }),
+        );
+    }
+
+    items.retain(|item| can_complete(prefix, &item.replace));
+    items.sort_by(|lhs, rhs| lhs.label.cmp(&rhs.label));
+    items.dedup_by(|lhs, rhs| lhs.label == rhs.label);
+    Some(items)
+}
+
+/// Complete `lib` when it is not in scope but `pkgs` is,
+/// with an additional edit inserting `inherit (pkgs) lib;` in the nearest possible place.
+fn complete_inherited_lib(
+    module: &Module,
+    source_map: &ModuleSourceMap,
+    scopes: &ModuleScopes,
+    scope_id: ScopeId,
+    expr_node: &SyntaxNode,
+    source_range: TextRange,
+) -> Option<CompletionItem> {
+    let lookup = |scope_id: ScopeId, name: &str| -> Option<NameId> {
+        scopes
+            .ancestors(scope_id)
+            .find_map(|scope| scope.as_definitions()?.get(name).copied())
+    };
+    if lookup(scope_id, "lib").is_some() {
+        return None;
+    }
+    let pkgs = lookup(scope_id, "pkgs")?;
+
+    // The innermost enclosing `let` where the same `pkgs` is visible.
+    let let_edit = expr_node
+        .ancestors()
+        .filter_map(ast::LetIn::cast)
+        .find_map(|let_in| {
+            let body = match &module[source_map.expr_for_node(AstPtr::new(let_in.syntax()))?] {
+                &Expr::LetIn(_, body) => body,
+                _ => return None,
+            };
+            if lookup(scopes.scope_for_expr(body)?, "pkgs")? != pkgs {
+                return None;
+            }
+            Some(TextEdit {
+                delete: TextRange::empty(let_in.let_token()?.text_range().end()),
+                insert: " inherit (pkgs) lib;".into(),
+            })
+        });
+    // Otherwise, wrap the body of the lambda introducing `pkgs`.
+    let edit = let_edit.or_else(|| {
+        let root = expr_node.ancestors().last()?;
+        let name_node = source_map.nodes_for_name(pkgs).next()?.to_node(&root);
+        let body = name_node.ancestors().find_map(ast::Lambda::cast)?.body()?;
+        if !body
+            .syntax()
+            .text_range()
+            .contains_range(expr_node.text_range())
+        {
+            return None;
+        }
+        Some(TextEdit {
+            delete: TextRange::empty(body.syntax().text_range().start()),
+            insert: "let inherit (pkgs) lib; in ".into(),
+        })
+    })?;
+
+    Some(CompletionItem {
+        label: "lib".into(),
+        source_range,
+        replace: "lib".into(),
+        kind: CompletionItemKind::LetBinding,
+        brief: Some("Inherit from `pkgs`".into()),
+        doc: None,
+        is_snippet: false,
+        additional_edits: vec![edit],
+        deprecated: false,
+    })
+}
+
+/// Complete the first attribute of `lib.<here>`, where `lib` is a parameter or a let binding.
+/// Since `lib` cannot be evaluated, a curated list of common attributes is offered.
+fn complete_lib_attr(
+    db: &dyn DefDatabase,
+    file_id: FileId,
+    tok: &SyntaxToken,
+    source_range: TextRange,
+) -> Option<Vec<CompletionItem>> {
+    let (select, prefix) = match tok.kind() {
+        // `lib.<here>`, but not `lib.foo.<here>`.
+        T![.] => (ast::Select::cast(tok.parent()?)?, ""),
+        _ => {
+            let name = ast::Name::cast(tok.parent()?)?;
+            let path = ast::Attrpath::cast(name.syntax().parent()?)?;
+            if path.attrs().next()?.syntax() != name.syntax() {
+                return None;
+            }
+            (ast::Select::cast(path.syntax().parent()?)?, tok.text())
+        }
+    };
+    let set = match select.set()? {
+        ast::Expr::Ref(set) => set,
+        _ => return None,
+    };
+    if set.token()?.text() != "lib" {
+        return None;
+    }
+
+    let module = db.module(file_id);
+    let source_map = db.source_map(file_id);
+    let name_res = db.name_resolution(file_id);
+    let expr = source_map.expr_for_node(AstPtr::new(set.syntax()))?;
+    match name_res.get(expr)? {
+        &ResolveResult::Definition(name)
+            if matches!(
+                module[name].kind,
+                NameKind::Param | NameKind::PatField | NameKind::LetIn
+            ) => {}
+        _ => return None,
+    }
+
+    let items = LIB_ATTRS
+        .iter()
+        .filter(|(attr, _)| can_complete(prefix, attr))
+        .map(|&(attr, signature)| CompletionItem {
+            label: attr.into(),
+            source_range,
+            replace: attr.into(),
+            kind: CompletionItemKind::Field,
+            brief: Some(signature.into()),
+            doc: None,
+            is_snippet: false,
+            additional_edits: Vec::new(),
+            deprecated: false,
+        })
+        .collect();
+    Some(items)
+}
+
+/// Append the closing `}` if the user just typed `${` and the interpolation is unclosed.
+/// With snippets, the cursor is placed after it.
+fn close_interpolation(
+    config: &CompletionConfig,
+    items: &mut [CompletionItem],
+    dynamic: &ast::Dynamic,
+) {
+    if dynamic.r_curly_token().is_some() {
+        return;
+    }
+    for item in items {
+        if config.snippet_support {
+            item.replace = format!("{}}}$0", item.replace).into();
+            item.is_snippet = true;
+        } else {
+            item.replace = format!("{}}}", item.replace).into();
+        }
+    }
+}
+
 fn complete_attrpath_def(
     _db: &dyn DefDatabase,
+    config: &CompletionConfig,
     _file_id: FileId,
     source_range: TextRange,
     path_node: ast::Attrpath,
@@ -204,14 +557,84 @@ fn complete_attrpath_def(
         .ancestors()
         .find_map(ast::LetIn::cast)
         .is_some();
-    Some(
-        ATTR_POS_KEYWORDS
-            .iter()
-            .copied()
-            .chain(in_let.then_some("in"))
-            .map(|kw| keyword_to_completion(kw, source_range))
-            .collect(),
-    )
+    let mut items = ATTR_POS_KEYWORDS
+        .iter()
+        .copied()
+        .chain(in_let.then_some("in"))
+        .map(|kw| keyword_to_completion(kw, source_range))
+        .collect::<Vec<_>>();
+
+    if config.nixpkgs_hints && is_module_body(&path_node) {
+        // Keys already set would be duplicated.
+        let bound = sibling_keys(&path_node);
+        items.extend(
+            MODULE_ATTRS
+                .iter()
+                .filter(|&&attr| !bound.contains(attr))
+                .map(|&attr| CompletionItem {
+                    label: attr.into(),
+                    source_range,
+                    replace: attr.into(),
+                    kind: CompletionItemKind::Field,
+                    brief: Some("NixOS module attribute".into()),
+                    doc: None,
+                    is_snippet: false,
+                    additional_edits: Vec::new(),
+                    deprecated: false,
+                }),
+        );
+    }
+
+    Some(items)
+}
+
+/// Static keys of other bindings in the attrset containing `path_node`,
+/// including the first segments of attrpaths and names of `inherit`.
+fn sibling_keys(path_node: &ast::Attrpath) -> HashSet<SmolStr> {
+    let (entry, set) = match path_node
+        .syntax()
+        .parent()
+        .and_then(|entry| Some((entry.clone(), ast::AttrSet::cast(entry.parent()?)?)))
+    {
+        Some(ret) => ret,
+        None => return HashSet::new(),
+    };
+    set.bindings()
+        .filter(|binding| *binding.syntax() != entry)
+        .flat_map(|binding| match binding {
+            ast::Binding::AttrpathValue(entry) => entry
+                .attrpath()
+                .and_then(|path| path.attrs().next())
+                .into_iter()
+                .collect::<Vec<_>>(),
+            ast::Binding::Inherit(inherit) => inherit.attrs().collect(),
+        })
+        .filter_map(|attr| match AttrKind::classify(attr) {
+            AttrKind::Static(name) => Some(name),
+            AttrKind::Dynamic(_) => None,
+        })
+        .collect()
+}
+
+/// Whether the attrpath is a top-level binding in the body of a NixOS module,
+/// recognized syntactically by its parameters: `{ config, lib, pkgs, ... }: { <here> }`.
+fn is_module_body(path_node: &ast::Attrpath) -> bool {
+    (|| {
+        let set = ast::AttrSet::cast(path_node.syntax().parent()?.parent()?)?;
+        if set.rec_token().is_some() || set.let_token().is_some() {
+            return None;
+        }
+        let lambda = ast::Lambda::cast(set.syntax().parent()?)?;
+        let pat = lambda.param()?.pat()?;
+        let is_module = pat.fields().any(|field| {
+            matches!(
+                field.name().and_then(|name| name.token()),
+                Some(tok) if MODULE_PARAMS.contains(&tok.text())
+            )
+        });
+        Some(is_module)
+    })()
+    .unwrap_or(false)
 }
 
 fn keyword_to_completion(kw: &str, source_range: TextRange) -> CompletionItem {
@@ -222,6 +645,9 @@ fn keyword_to_completion(kw: &str, source_range: TextRange) -> CompletionItem {
         kind: CompletionItemKind::Keyword,
         brief: None,
         doc: None,
+        is_snippet: false,
+        additional_edits: Vec::new(),
+        deprecated: false,
     }
 }
 
@@ -244,14 +670,27 @@ fn can_complete(prefix: &str, replace: &str) -> bool {
 
 #[cfg(test)]
 mod tests {
+    use super::CompletionConfig;
     use crate::base::SourceDatabase;
     use crate::tests::TestDB;
+    use crate::{BuiltinSet, NixVersion, TextEdit};
     use expect_test::{expect, Expect};
 
+    const CONFIG: CompletionConfig = CompletionConfig {
+        nixpkgs_hints: true,
+        builtins: BuiltinSet::Latest,
+        snippet_support: true,
+    };
+
     #[track_caller]
     fn check_no(fixture: &str, label: &str) {
+        check_no_with(&CONFIG, fixture, label);
+    }
+
+    #[track_caller]
+    fn check_no_with(config: &CompletionConfig, fixture: &str, label: &str) {
         let (db, f) = TestDB::from_fixture(fixture).unwrap();
-        if let Some(compes) = super::completions(&db, f[0]) {
+        if let Some(compes) = super::completions(&db, config, f[0]) {
             assert_eq!(compes.iter().find(|item| item.label == label), None);
         }
     }
@@ -259,16 +698,22 @@ mod tests {
     #[track_caller]
     fn check(fixture: &str, label: &str, expect: Expect) {
         let (db, f) = TestDB::from_fixture(fixture).unwrap();
-        let compes = super::completions(&db, f[0]).expect("No completion");
+        let compes = super::completions(&db, &CONFIG, f[0]).expect("No completion");
         let item = compes
             .iter()
             .find(|item| item.label == label)
             .expect("No expected completion");
 
-        let source_range =
-            usize::from(item.source_range.start())..usize::from(item.source_range.end());
+        let mut edits = item.additional_edits.clone();
+        edits.push(TextEdit {
+            delete: item.source_range,
+            insert: item.replace.clone(),
+        });
+        edits.sort_by_key(|edit| edit.delete.start());
         let mut completed = db.file_content(f[0].file_id).to_string();
-        completed.replace_range(source_range, &item.replace);
+        for edit in edits.iter().rev() {
+            edit.apply(&mut completed);
+        }
         let got = format!("({:?}) {}", item.kind, completed);
         expect.assert_eq(&got);
     }
@@ -309,6 +754,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn function_binding() {
+        check(
+            "let f = x: x; in f$0",
+            "f",
+            expect!["(Function) let f = x: x; in f"],
+        );
+        check(
+            "rec { f = { x }: x; a = f$0; }",
+            "f",
+            expect!["(Function) rec { f = { x }: x; a = f; }"],
+        );
+        check(
+            "let pkgs = { f = x: x; }; in with pkgs; { inherit f$0; }",
+            "f",
+            expect!["(Function) let pkgs = { f = x: x; }; in with pkgs; { inherit f; }"],
+        );
+        // Parameters are unknown.
+        check("f: f$0", "f", expect!["(Param) f: f"]);
+        check(
+            "let f = g; g = x: x; in f$0",
+            "f",
+            expect!["(LetBinding) let f = g; g = x: x; in f"],
+        );
+    }
+
     #[test]
     fn builtin() {
         check("toS$0", "toString", expect!["(BuiltinFunction) toString"]);
@@ -321,6 +792,40 @@ mod tests {
         check_no("attrN$0", "attrNames");
     }
 
+    #[test]
+    fn deprecated() {
+        let (db, f) = TestDB::from_fixture("let { foo = 1; body = f$0; }").unwrap();
+        let compes = super::completions(&db, &CONFIG, f[0]).expect("No completion");
+        let item = compes.iter().find(|item| item.label == "foo").unwrap();
+        assert!(item.deprecated);
+
+        let (db, f) = TestDB::from_fixture("let foo = 1; in f$0").unwrap();
+        let compes = super::completions(&db, &CONFIG, f[0]).expect("No completion");
+        let item = compes.iter().find(|item| item.label == "foo").unwrap();
+        assert!(!item.deprecated);
+    }
+
+    #[test]
+    fn builtin_set() {
+        let config = CompletionConfig {
+            builtins: BuiltinSet::Allowlist(vec!["true".into()]),
+            ..CONFIG
+        };
+        check_no_with(&config, "t$0", "toString");
+        let (db, f) = TestDB::from_fixture("t$0").unwrap();
+        let compes = super::completions(&db, &config, f[0]).unwrap();
+        assert!(compes.iter().any(|item| item.label == "true"));
+
+        // `map` is available in all versions.
+        let config = CompletionConfig {
+            builtins: BuiltinSet::Version(NixVersion::new(2, 3)),
+            ..CONFIG
+        };
+        let (db, f) = TestDB::from_fixture("m$0").unwrap();
+        let compes = super::completions(&db, &config, f[0]).unwrap();
+        assert!(compes.iter().any(|item| item.label == "map"));
+    }
+
     #[test]
     fn inherit() {
         check("{ i$0 }", "inherit", expect!["(Keyword) { inherit }"]);
@@ -329,4 +834,205 @@ mod tests {
         check_no("let a.i$0", "inherit");
         check_no("let a.${i$0", "inherit");
     }
+
+    #[test]
+    fn inherit_name() {
+        check(
+            "let foo = 1; in { inherit f$0; }",
+            "foo",
+            expect!["(LetBinding) let foo = 1; in { inherit foo; }"],
+        );
+        check(
+            "let pkgs = { hello = 1; jq = 2; }; in with pkgs; { inherit h$0; }",
+            "hello",
+            expect!["(Field) let pkgs = { hello = 1; jq = 2; }; in with pkgs; { inherit hello; }"],
+        );
+        check(
+            "with { a = 1; } // { b = 2; }; { inherit b$0; }",
+            "b",
+            expect!["(Field) with { a = 1; } // { b = 2; }; { inherit b; }"],
+        );
+        // Shadowed by lexical names.
+        check(
+            "with { a = 1; }; let a = 2; in { inherit a$0; }",
+            "a",
+            expect!["(LetBinding) with { a = 1; }; let a = 2; in { inherit a; }"],
+        );
+
+        // Unknown environment.
+        check_no("{ pkgs }: with pkgs; { inherit h$0; }", "hello");
+        // Not a keyword position.
+        check_no("{ inherit l$0; }", "let");
+        // Names of `inherit (from)` are not references.
+        check_no("let foo = 1; in { inherit (x) f$0; }", "foo");
+    }
+
+    #[test]
+    fn interpolation() {
+        check(
+            r#"let foo = 1; in "${f$0}""#,
+            "foo",
+            expect![[r#"(LetBinding) let foo = 1; in "${foo}""#]],
+        );
+        check(
+            r#"let foo = 1; in "a${$0}b""#,
+            "foo",
+            expect![[r#"(LetBinding) let foo = 1; in "a${foo}b""#]],
+        );
+        check(
+            "let foo = 1; in ''\n  ${$0}\n''",
+            "foo",
+            expect![[r#"
+                (LetBinding) let foo = 1; in ''
+                  ${foo}
+                ''"#]],
+        );
+        check(
+            "let foo = 1; in ''\n  ${f$0}\n''",
+            "foo",
+            expect![[r#"
+                (LetBinding) let foo = 1; in ''
+                  ${foo}
+                ''"#]],
+        );
+        check(
+            r#"let foo = 1; in "${$0}""#,
+            "toString",
+            expect![[r#"(BuiltinFunction) let foo = 1; in "${toString}""#]],
+        );
+    }
+
+    #[test]
+    fn interpolation_close_brace() {
+        check(
+            r#"let foo = 1; in "${f$0"#,
+            "foo",
+            expect![[r#"(LetBinding) let foo = 1; in "${foo}$0"#]],
+        );
+        check(
+            "let foo = 1; in ''${f$0",
+            "foo",
+            expect!["(LetBinding) let foo = 1; in ''${foo}$0"],
+        );
+
+        // Without snippets, the brace is inserted as plain text.
+        let config = CompletionConfig {
+            snippet_support: false,
+            ..CONFIG
+        };
+        let (db, f) = TestDB::from_fixture(r#"let foo = 1; in "${f$0"#).unwrap();
+        let compes = super::completions(&db, &config, f[0]).unwrap();
+        let item = compes.iter().find(|item| item.label == "foo").unwrap();
+        assert_eq!(item.replace, "foo}");
+        assert!(!item.is_snippet);
+    }
+
+    #[test]
+    fn module_attrs() {
+        check(
+            "{ config, lib, ... }: { i$0 }",
+            "imports",
+            expect!["(Field) { config, lib, ... }: { imports }"],
+        );
+        check(
+            "{ pkgs, ... }: { o$0 = { }; }",
+            "options",
+            expect!["(Field) { pkgs, ... }: { options = { }; }"],
+        );
+
+        // Not a module.
+        check_no("{ foo, ... }: { i$0 }", "imports");
+        check_no("{ i$0 }", "imports");
+        // Not at the top level.
+        check_no("{ config, ... }: { config = { i$0 }; }", "imports");
+        check_no("{ config, ... }: { foo.i$0 }", "imports");
+        check_no("{ config, ... }: rec { i$0 }", "imports");
+        // Already set.
+        check_no("{ config, ... }: { imports = [ ]; i$0 }", "imports");
+        check_no(
+            "{ config, ... }: { o$0 = { }; options.foo = 1; }",
+            "options",
+        );
+        check_no("{ config, ... }: { inherit imports; i$0 }", "imports");
+        check(
+            "{ config, ... }: { options = { }; c$0 }",
+            "config",
+            expect!["(Field) { config, ... }: { options = { }; config }"],
+        );
+
+        // Disabled.
+        check_no_with(
+            &CompletionConfig::default(),
+            "{ config, lib, ... }: { i$0 }",
+            "imports",
+        );
+    }
+
+    #[test]
+    fn inherit_lib() {
+        check(
+            "{ pkgs }: let a = 1; in l$0",
+            "lib",
+            expect!["(LetBinding) { pkgs }: let inherit (pkgs) lib; a = 1; in lib"],
+        );
+        check(
+            "let pkgs = import <nixpkgs> { }; in { a = l$0; }",
+            "lib",
+            expect![
+                "(LetBinding) let inherit (pkgs) lib; pkgs = import <nixpkgs> { }; in { a = lib; }"
+            ],
+        );
+        check(
+            "{ pkgs, ... }: { a = l$0; }",
+            "lib",
+            expect!["(LetBinding) { pkgs, ... }: let inherit (pkgs) lib; in { a = lib; }"],
+        );
+        // The `let` is outside the scope of `pkgs`.
+        check(
+            "let a = 1; in pkgs: [ l$0 ]",
+            "lib",
+            expect!["(LetBinding) let a = 1; in pkgs: let inherit (pkgs) lib; in [ lib ]"],
+        );
+
+        // Already in scope.
+        check(
+            "{ pkgs, lib }: l$0",
+            "lib",
+            expect!["(Param) { pkgs, lib }: lib"],
+        );
+        // No `pkgs`.
+        check_no("{ foo }: l$0", "lib");
+        // Disabled.
+        check_no_with(&CompletionConfig::default(), "{ pkgs }: l$0", "lib");
+    }
+
+    #[test]
+    fn lib_attrs() {
+        check(
+            "{ lib, ... }: lib.$0",
+            "mkIf",
+            expect!["(Field) { lib, ... }: lib.mkIf"],
+        );
+        check(
+            "lib: { a = lib.conc$0 }",
+            "concatStringsSep",
+            expect!["(Field) lib: { a = lib.concatStringsSep }"],
+        );
+        check(
+            "let lib = import ./lib.nix; in lib.mkO$0 { }",
+            "mkOption",
+            expect!["(Field) let lib = import ./lib.nix; in lib.mkOption { }"],
+        );
+        check_no("{ lib, ... }: lib.con$0", "mkIf");
+
+        // Not the first attribute.
+        check_no("{ lib, ... }: lib.types.$0", "mkIf");
+        check_no("{ lib, ... }: lib.types.m$0", "mkIf");
+        // Not in scope.
+        check_no("lib.$0", "mkIf");
+        check_no("with pkgs; lib.$0", "mkIf");
+        check_no("{ lib = { }; a = lib.$0; }", "mkIf");
+        // Disabled.
+        check_no_with(&CompletionConfig::default(), "{ lib }: lib.$0", "mkIf");
+    }
 }
@@ -0,0 +1,203 @@
+use crate::def::{BindingValue, Expr, ExprId, NameId, ResolveResult};
+use crate::{DefDatabase, FileId};
+use rowan::TextRange;
+use smol_str::SmolStr;
+use std::collections::{HashMap, HashSet};
+
+/// Definitions and uses of names in a file, as resolved by name resolution.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DefUseGraph {
+    /// Ordered by their ranges. The id of a node is its index.
+    pub nodes: Vec<DefUseNode>,
+    /// Ordered by the ranges of uses.
+    pub edges: Vec<DefUseEdge>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DefUseNode {
+    pub id: usize,
+    pub name: SmolStr,
+    pub range: TextRange,
+    pub kind: DefUseNodeKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DefUseNodeKind {
+    /// A name defined by `let`, `rec`, or lambda parameters.
+    Definition,
+    /// A name defined by `inherit` or `inherit (from)`.
+    Inherited,
+    /// The environment of a `with` expression, which introduces names dynamically.
+    /// Its name is the source text of the environment.
+    With,
+}
+
+/// A use of a name pointing to one of its possible definitions.
+/// Names introduced by `with` may point to multiple nodes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DefUseEdge {
+    pub use_range: TextRange,
+    pub def: usize,
+}
+
+pub(crate) fn def_use_graph(db: &dyn DefDatabase, file: FileId) -> DefUseGraph {
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+    let name_res = db.name_resolution(file);
+    let src = db.file_content(file);
+
+    let mut inherited = HashSet::new();
+    for (_, kind) in module.exprs() {
+        if let Some(bindings) = kind.bindings() {
+            inherited.extend(bindings.statics.iter().filter_map(|&(name, value)| {
+                matches!(
+                    value,
+                    BindingValue::Inherit(_) | BindingValue::InheritFrom(_)
+                )
+                .then_some(name)
+            }));
+        }
+    }
+
+    enum Def {
+        Name(NameId),
+        With(ExprId),
+    }
+
+    let mut nodes = Vec::new();
+    for (name, data) in module.names() {
+        if !data.kind.is_definition() {
+            continue;
+        }
+        let range = match source_map.nodes_for_name(name).next() {
+            Some(ptr) => ptr.text_range(),
+            None => continue,
+        };
+        let kind = if inherited.contains(&name) {
+            DefUseNodeKind::Inherited
+        } else {
+            DefUseNodeKind::Definition
+        };
+        nodes.push((Def::Name(name), data.text.clone(), range, kind));
+    }
+    for (expr, kind) in module.exprs() {
+        if let &Expr::With(env, _) = kind {
+            let range = match source_map.node_for_expr(env) {
+                Some(ptr) => ptr.text_range(),
+                None => continue,
+            };
+            nodes.push((
+                Def::With(expr),
+                src[range].into(),
+                range,
+                DefUseNodeKind::With,
+            ));
+        }
+    }
+    nodes.sort_by_key(|&(_, _, range, _)| (range.start(), range.end()));
+
+    let mut name_ids = HashMap::new();
+    let mut with_ids = HashMap::new();
+    let nodes = nodes
+        .into_iter()
+        .enumerate()
+        .map(|(id, (def, name, range, kind))| {
+            match def {
+                Def::Name(name) => name_ids.insert(name, id),
+                Def::With(expr) => with_ids.insert(expr, id),
+            };
+            DefUseNode {
+                id,
+                name,
+                range,
+                kind,
+            }
+        })
+        .collect();
+
+    let mut edges = Vec::new();
+    for (expr, res) in name_res.iter() {
+        let use_range = match source_map.node_for_expr(expr) {
+            Some(ptr) => ptr.text_range(),
+            None => continue,
+        };
+        match res {
+            ResolveResult::Definition(name) => {
+                edges.extend(name_ids.get(name).map(|&def| DefUseEdge { use_range, def }));
+            }
+            ResolveResult::WithExprs(withs) => {
+                edges.extend(
+                    withs
+                        .iter()
+                        .filter_map(|with| with_ids.get(with))
+                        .map(|&def| DefUseEdge { use_range, def }),
+                );
+            }
+            ResolveResult::Builtin(_) => {}
+        }
+    }
+    edges.sort_by_key(|edge| (edge.use_range.start(), edge.def));
+
+    DefUseGraph { nodes, edges }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::base::SourceDatabase;
+    use crate::tests::TestDB;
+    use expect_test::{expect, Expect};
+
+    #[track_caller]
+    fn check(fixture: &str, expect: Expect) {
+        let (db, file) = TestDB::single_file(fixture).unwrap();
+        let src = db.file_content(file);
+        let graph = super::def_use_graph(&db, file);
+        let mut got = String::new();
+        for node in &graph.nodes {
+            got += &format!(
+                "#{} {:?} {:?} {}\n",
+                node.id, node.kind, node.range, node.name,
+            );
+        }
+        for edge in &graph.edges {
+            got += &format!(
+                "{:?} {} -> #{}\n",
+                edge.use_range, &src[edge.use_range], edge.def,
+            );
+        }
+        expect.assert_eq(&got);
+    }
+
+    #[test]
+    fn let_in() {
+        check(
+            "let a = 1; b = a + a; in b",
+            expect![[r#"
+            #0 Definition 4..5 a
+            #1 Definition 11..12 b
+            15..16 a -> #0
+            19..20 a -> #0
+            25..26 b -> #1
+        "#]],
+        );
+    }
+
+    #[test]
+    fn inherit_and_with() {
+        check(
+            "x: with x; let inherit x; inherit (x) y; in [ x y z builtins.map ]",
+            expect![[r#"
+                #0 Definition 0..1 x
+                #1 With 8..9 x
+                #2 Inherited 23..24 x
+                #3 Inherited 38..39 y
+                8..9 x -> #0
+                23..24 x -> #0
+                35..36 x -> #2
+                46..47 x -> #2
+                48..49 y -> #3
+                50..51 z -> #1
+            "#]],
+        );
+    }
+}
@@ -1,11 +1,65 @@
-use crate::{DefDatabase, Diagnostic, FileId};
+use crate::def::{
+    BinaryOp, BindingValue, Bindings, Expr, ExprId, Literal, NameId, NameKind, NameResolution,
+    ResolveResult, UnaryOp,
+};
+use crate::ide::builtin_set::is_known_builtin;
+use crate::{
+    BuiltinSet, DefDatabase, Diagnostic, DiagnosticKind, FileId, FileRange, Module, Severity,
+};
+use builtin::ALL_BUILTINS;
+use rowan::ast::AstNode;
+use smol_str::SmolStr;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use syntax::ast::HasBindings;
+use syntax::{ast, ErrorKind as SynErrorKind, SyntaxKind, SyntaxNode, TextRange, TextSize, T};
 
-pub(crate) fn diagnostics(db: &dyn DefDatabase, file: FileId) -> Vec<Diagnostic> {
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DiagnosticsConfig {
+    /// Hint `with` expressions providing at most this many distinct names to their bodies,
+    /// which can be replaced by explicit selections. `None` disables it.
+    pub replaceable_with_max_names: Option<usize>,
+    /// Hint repeated literal elements in a list, like `[ 1 2 1 ]`.
+    pub duplicate_list_elements: bool,
+    /// Hint `with` of non-literal attrsets at the top of a file, like `with import <nixpkgs> { };`.
+    pub broad_with_scope: bool,
+    /// Builtins available in the user's Nix. Others selected from `builtins` are reported.
+    pub builtins: BuiltinSet,
+    /// Hint whitespaces at the end of lines.
+    pub trailing_whitespace: bool,
+    /// Hint lines longer than this many characters. `None` disables it.
+    pub max_line_length: Option<usize>,
+}
+
+pub(crate) fn diagnostics(
+    db: &dyn DefDatabase,
+    config: &DiagnosticsConfig,
+    file: FileId,
+) -> Vec<Diagnostic> {
+    diagnostics_of_severity(db, config, file, Severity::Hint)
+}
+
+/// Diagnostics at least as severe as `min`.
+/// Passes which can only report less severe diagnostics are skipped.
+pub(crate) fn diagnostics_of_severity(
+    db: &dyn DefDatabase,
+    config: &DiagnosticsConfig,
+    file: FileId,
+    min: Severity,
+) -> Vec<Diagnostic> {
+    let warnings = Severity::Warning.is_at_least(min);
+    let hints = Severity::Hint.is_at_least(min);
     let mut diags = Vec::new();
 
     // Parsing.
     let parse = db.parse(file);
-    diags.extend(parse.errors().iter().map(|&err| Diagnostic::from(err)));
+    let root = parse.syntax_node();
+    diags.extend(
+        parse
+            .errors()
+            .iter()
+            .map(|&err| syntax_error_diagnostic(file, &root, err)),
+    );
 
     // Lowering.
     let module = db.module(file);
@@ -14,21 +68,1096 @@ pub(crate) fn diagnostics(db: &dyn DefDatabase, file: FileId) -> Vec<Diagnostic>
     // Name resolution.
     diags.extend(db.name_resolution(file).to_diagnostics(db, file));
 
+    // Imports.
+    if warnings {
+        diags.extend(import_diagnostics(db, file));
+        diags.extend(imported_option_conflict_diagnostics(db, file));
+    }
+
+    // Lints.
+    if hints {
+        diags.extend(constant_condition_diagnostics(db, file));
+    }
+    diags.extend(not_callable_diagnostics(db, file));
+    diags.extend(trivial_infinite_recursion_diagnostics(db, file));
+    if warnings {
+        diags.extend(attrset_as_function_diagnostics(db, file));
+        diags.extend(unknown_builtin_diagnostics(db, file, &config.builtins));
+    }
+    if hints {
+        diags.extend(mixed_indent_string_diagnostics(file, &root));
+        diags.extend(redundant_merge_diagnostics(&root));
+        if let Some(max_names) = config.replaceable_with_max_names {
+            diags.extend(replaceable_with_diagnostics(db, file, max_names));
+        }
+        if config.duplicate_list_elements {
+            diags.extend(duplicate_list_element_diagnostics(db, file));
+        }
+        if config.broad_with_scope {
+            diags.extend(broad_with_scope_diagnostics(db, file));
+        }
+
+        // Style.
+        if config.trailing_whitespace {
+            diags.extend(
+                trailing_whitespaces(&root, &db.file_content(file))
+                    .into_iter()
+                    .map(|range| Diagnostic::new(range, DiagnosticKind::TrailingWhitespace)),
+            );
+        }
+        if let Some(max_len) = config.max_line_length {
+            diags.extend(long_line_diagnostics(&db.file_content(file), max_len));
+        }
+    }
+
     // Liveness check.
-    let liveness = db.liveness_check(file);
-    diags.extend(liveness.to_diagnostics(db, file));
+    if warnings {
+        let liveness = db.liveness_check(file);
+        diags.extend(liveness.to_diagnostics(db, file));
+    }
+
+    diags.retain(|diag| diag.severity().is_at_least(min));
+    dedup_diagnostics(&mut diags);
+    suppress_diagnostics(&root, &db.file_content(file), &mut diags);
+
+    diags
+}
+
+/// Only syntax errors, without semantic analysis.
+/// It is cheap to compute, for quick feedback before full diagnostics of large files.
+pub(crate) fn syntax_errors(db: &dyn DefDatabase, file: FileId) -> Vec<Diagnostic> {
+    let parse = db.parse(file);
+    let root = parse.syntax_node();
+    let mut diags = parse
+        .errors()
+        .iter()
+        .map(|&err| syntax_error_diagnostic(file, &root, err))
+        .collect();
+    dedup_diagnostics(&mut diags);
+    suppress_diagnostics(&root, &db.file_content(file), &mut diags);
+    diags
+}
+
+/// Drop diagnostics of the same range and kind as an earlier one, keeping the order.
+/// Different passes, or recoveries of the parser, may report the same problem twice.
+/// Since the severity is decided by the kind, the kept one is as severe as dropped ones.
+fn dedup_diagnostics(diags: &mut Vec<Diagnostic>) {
+    let mut seen = HashSet::new();
+    diags.retain(|diag| seen.insert((diag.range, diag.kind)));
+}
+
+/// Drop diagnostics silenced by directive comments:
+/// - `# nil: disable=<codes>` before any code disables them in the whole file.
+/// - `# nil: disable-line=<codes>` disables them on the line of the comment.
+/// - `# nil: disable-next-line=<codes>` disables them on the line following the comment.
+///
+/// `<codes>` is a comma separated list of [`DiagnosticKind::code`].
+/// A diagnostic is matched by the line where it starts.
+fn suppress_diagnostics(root: &SyntaxNode, src: &str, diags: &mut Vec<Diagnostic>) {
+    let line_starts = std::iter::once(0)
+        .chain(src.match_indices('\n').map(|(i, _)| i + 1))
+        .collect::<Vec<_>>();
+    let line_of = |pos: TextSize| {
+        let pos = usize::from(pos);
+        line_starts.partition_point(|&start| start <= pos) - 1
+    };
+
+    let mut file_codes = Vec::new();
+    let mut line_codes = Vec::new();
+    let mut in_header = true;
+    for tok in root
+        .descendants_with_tokens()
+        .filter_map(|elem| elem.into_token())
+    {
+        match tok.kind() {
+            SyntaxKind::SPACE => continue,
+            SyntaxKind::COMMENT => {}
+            _ => {
+                in_header = false;
+                continue;
+            }
+        }
+        let (directive, codes) = match parse_directive(tok.text()) {
+            Some(ret) => ret,
+            None => continue,
+        };
+        let codes = codes.map(String::from);
+        let line = line_of(tok.text_range().start());
+        match directive {
+            "disable" if in_header => file_codes.extend(codes),
+            "disable-line" => line_codes.extend(codes.map(|code| (line, code))),
+            "disable-next-line" => line_codes.extend(codes.map(|code| (line + 1, code))),
+            _ => {}
+        }
+    }
+    if file_codes.is_empty() && line_codes.is_empty() {
+        return;
+    }
+
+    diags.retain(|diag| {
+        let code = diag.code();
+        let line = line_of(diag.range.start());
+        !file_codes.iter().any(|c| c == code)
+            && !line_codes.iter().any(|(l, c)| *l == line && c == code)
+    });
+}
+
+/// Parse a comment `# nil: <directive>=<code>,<code>`.
+fn parse_directive(comment: &str) -> Option<(&str, impl Iterator<Item = &str>)> {
+    let text = match comment.strip_prefix('#') {
+        Some(text) => text,
+        None => comment.strip_prefix("/*")?.strip_suffix("*/")?,
+    };
+    let (directive, codes) = text.trim().strip_prefix("nil:")?.split_once('=')?;
+    let codes = codes
+        .split(',')
+        .map(|code| code.trim())
+        .filter(|code| !code.is_empty());
+    Some((directive.trim(), codes))
+}
+
+fn syntax_error_diagnostic(file: FileId, root: &SyntaxNode, err: syntax::Error) -> Diagnostic {
+    let diag = Diagnostic::from(err);
+    match err.kind {
+        SynErrorKind::MissingToken(expect) => {
+            match missing_token_note(root, err.range.start(), expect) {
+                Some((range, msg)) => diag.with_note(FileRange::new(file, range), msg),
+                None => diag,
+            }
+        }
+        _ => diag,
+    }
+}
+
+/// Describe the construct which the missing token `expect` belongs to,
+/// by looking at the nodes before the error position `pos`.
+/// Returns the range of its starting token and the message.
+fn missing_token_note(
+    root: &SyntaxNode,
+    pos: TextSize,
+    expect: SyntaxKind,
+) -> Option<(TextRange, String)> {
+    let constructs: &[SyntaxKind] = match expect {
+        T![in] => &[SyntaxKind::LET_IN],
+        T![then] | T![else] => &[SyntaxKind::IF_THEN_ELSE],
+        T![;] => &[
+            SyntaxKind::ATTR_PATH_VALUE,
+            SyntaxKind::INHERIT,
+            SyntaxKind::WITH,
+            SyntaxKind::ASSERT,
+        ],
+        T![=] => &[SyntaxKind::ATTR_PATH_VALUE],
+        T!['}'] => &[SyntaxKind::ATTR_SET, SyntaxKind::PAT, SyntaxKind::DYNAMIC],
+        T![,] => &[SyntaxKind::PAT],
+        T![']'] => &[SyntaxKind::LIST],
+        T![')'] => &[SyntaxKind::PAREN],
+        T!['"'] => &[SyntaxKind::STRING],
+        T!["''"] => &[SyntaxKind::INDENT_STRING],
+        _ => return None,
+    };
+
+    // The last non-whitespace token before the error.
+    let mut tok = root.token_at_offset(pos).left_biased()?;
+    while tok.kind().is_whitespace() {
+        tok = tok.prev_token()?;
+    }
+    let node = tok
+        .parent_ancestors()
+        .find(|node| constructs.contains(&node.kind()))?;
+    let start_tok = node.first_token()?;
+
+    let what = match node.kind() {
+        SyntaxKind::ATTR_PATH_VALUE => "this binding".into(),
+        _ => format!("this {}", start_tok.kind()),
+    };
+    let verb = match expect {
+        T![then] | T![else] | T![=] | T![,] => "continue",
+        _ => "close",
+    };
+    Some((
+        start_tok.text_range(),
+        format!("Expected {} to {} {}", expect, verb, what),
+    ))
+}
+
+/// Check `import`s of path literals.
+fn import_diagnostics(db: &dyn DefDatabase, file: FileId) -> Vec<Diagnostic> {
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+    let name_res = db.name_resolution(file);
+    module
+        .exprs()
+        .filter_map(|(_, kind)| match kind {
+            &Expr::Apply(func, arg)
+                if name_res.get(func) == Some(&ResolveResult::Builtin("import")) =>
+            {
+                Some(arg)
+            }
+            _ => None,
+        })
+        .filter_map(|arg| {
+            let path = match &module[arg] {
+                Expr::Literal(Literal::Path(path)) => *path,
+                _ => return None,
+            };
+            if path.resolve(db).is_some() {
+                return None;
+            }
+            // Skip search paths, and paths going beyond the root.
+            let (_, vpath) = path.to_vfs_path(db)?;
+            let range = source_map.node_for_expr(arg)?.text_range();
+            let diag = if path.is_dir_without_default_nix(db) {
+                Diagnostic::new(range, DiagnosticKind::MissingDefaultNix)
+            } else {
+                Diagnostic::new(range, DiagnosticKind::UnresolvedImport)
+                    .with_message(format!("Imported file `{}` does not exist", vpath.as_str()))
+            };
+            Some(diag)
+        })
+        .collect()
+}
+
+/// Top-level keys of NixOS modules which are not options.
+const NON_CONFIG_MODULE_ATTRS: &[&str] = &["_file", "disabledModules", "imports", "key", "meta"];
+
+/// Check options set to different values by modules in the same `imports` list,
+/// like `imports = [ ./a.nix ./b.nix ];` where both set `config.foo.port`.
+/// Attrsets are mergeable, and values like `mkDefault 1` have priorities,
+/// so only literal values are compared to avoid false positives.
+fn imported_option_conflict_diagnostics(db: &dyn DefDatabase, file: FileId) -> Vec<Diagnostic> {
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+    let import_lists = module
+        .exprs()
+        .filter_map(|(_, kind)| kind.bindings())
+        .flat_map(|bindings| bindings.statics.iter())
+        .filter(|&&(name, _)| module[name].text == "imports")
+        .filter_map(|&(_, value)| match value {
+            BindingValue::Expr(value) => match &module[value] {
+                Expr::List(elems) => Some(elems),
+                _ => None,
+            },
+            BindingValue::Inherit(_) | BindingValue::InheritFrom(_) => None,
+        });
+
+    let mut diags = Vec::new();
+    for elems in import_lists {
+        // The first definition of each option path, as `(imported file, name, value)`.
+        let mut seen = HashMap::new();
+        for &elem in elems.iter() {
+            let target = match &module[elem] {
+                Expr::Literal(Literal::Path(path)) => match path.resolve(db) {
+                    Some(target) => target,
+                    None => continue,
+                },
+                _ => continue,
+            };
+            for (path, name, value) in module_scalar_options(db, target) {
+                let (prev_file, prev_name, prev_value) = match seen.entry(path.clone()) {
+                    Entry::Vacant(entry) => {
+                        entry.insert((target, name, value));
+                        continue;
+                    }
+                    Entry::Occupied(entry) => entry.get().clone(),
+                };
+                // Equal values are accepted by most option types.
+                if prev_file == target || prev_value == value {
+                    continue;
+                }
+                let range = match source_map.node_for_expr(elem) {
+                    Some(ptr) => ptr.text_range(),
+                    None => continue,
+                };
+                let path = path.join(".");
+                let mut diag = Diagnostic::new(range, DiagnosticKind::ConflictingImportedOption);
+                for (file, name, value) in
+                    [(prev_file, prev_name, prev_value), (target, name, value)]
+                {
+                    if let Some(ptr) = db.source_map(file).nodes_for_name(name).next() {
+                        diag = diag.with_note(
+                            FileRange::new(file, ptr.text_range()),
+                            format!("`{path}` is set to `{value}` here"),
+                        );
+                    }
+                }
+                diags.push(diag);
+            }
+        }
+    }
+    diags
+}
+
+/// Options set to numbers, booleans or `null` by the NixOS module in `file`, with their
+/// attribute paths. Strings are skipped since options like `types.lines` merge them.
+/// Both `{ config.foo = 1; }` and the shorthand `{ foo = 1; }` are recognized.
+fn module_scalar_options(
+    db: &dyn DefDatabase,
+    file: FileId,
+) -> Vec<(Vec<SmolStr>, NameId, String)> {
+    let module = db.module(file);
+    let name_res = db.name_resolution(file);
+
+    // Skip the module function `{ config, lib, ... }: { }`.
+    let mut body = module.entry_expr();
+    while let Expr::Lambda(_, _, lambda_body) = module[body] {
+        body = lambda_body;
+    }
+    let bindings = match &module[body] {
+        Expr::Attrset(bindings) | Expr::RecAttrset(bindings) => bindings,
+        _ => return Vec::new(),
+    };
+    let key = |key: &str| {
+        bindings
+            .statics
+            .iter()
+            .find(|&&(name, _)| module[name].text == key)
+    };
+
+    let mut ret = Vec::new();
+    match key("config") {
+        Some(&(_, BindingValue::Expr(config))) => match &module[config] {
+            Expr::Attrset(config) | Expr::RecAttrset(config) => {
+                collect_scalar_options(&module, &name_res, config, &mut Vec::new(), &mut ret);
+            }
+            _ => {}
+        },
+        Some(_) => {}
+        None if key("options").is_none() => {
+            let mut path = Vec::new();
+            for &(name, value) in bindings.statics.iter() {
+                if NON_CONFIG_MODULE_ATTRS.contains(&&*module[name].text) {
+                    continue;
+                }
+                collect_scalar_option(&module, &name_res, name, value, &mut path, &mut ret);
+            }
+        }
+        None => {}
+    }
+    ret
+}
+
+fn collect_scalar_options(
+    module: &Module,
+    name_res: &NameResolution,
+    bindings: &Bindings,
+    path: &mut Vec<SmolStr>,
+    out: &mut Vec<(Vec<SmolStr>, NameId, String)>,
+) {
+    for &(name, value) in bindings.statics.iter() {
+        collect_scalar_option(module, name_res, name, value, path, out);
+    }
+}
+
+fn collect_scalar_option(
+    module: &Module,
+    name_res: &NameResolution,
+    name: NameId,
+    value: BindingValue,
+    path: &mut Vec<SmolStr>,
+    out: &mut Vec<(Vec<SmolStr>, NameId, String)>,
+) {
+    let value = match value {
+        BindingValue::Expr(value) => value,
+        BindingValue::Inherit(_) | BindingValue::InheritFrom(_) => return,
+    };
+    path.push(module[name].text.clone());
+    match &module[value] {
+        Expr::Attrset(bindings) | Expr::RecAttrset(bindings) => {
+            collect_scalar_options(module, name_res, bindings, path, out);
+        }
+        Expr::Literal(Literal::Int(x)) => out.push((path.clone(), name, x.to_string())),
+        Expr::Literal(Literal::Float(x)) => out.push((path.clone(), name, x.to_string())),
+        Expr::Reference(_) => {
+            if let Some(&ResolveResult::Builtin(b @ ("true" | "false" | "null"))) =
+                name_res.get(value)
+            {
+                out.push((path.clone(), name, b.to_owned()));
+            }
+        }
+        _ => {}
+    }
+    path.pop();
+}
+
+/// Check conditions of `if` and `assert` which are statically known.
+fn constant_condition_diagnostics(db: &dyn DefDatabase, file: FileId) -> Vec<Diagnostic> {
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+    let name_res = db.name_resolution(file);
+    module
+        .exprs()
+        .filter_map(|(_, kind)| match kind {
+            &Expr::IfThenElse(cond, _, _) | &Expr::Assert(cond, _) => Some(cond),
+            _ => None,
+        })
+        .filter_map(|cond| {
+            let value = constant_bool(&module, &name_res, cond)?;
+            let range = source_map.node_for_expr(cond)?.text_range();
+            Some(
+                Diagnostic::new(range, DiagnosticKind::ConstantCondition).with_note(
+                    FileRange::new(file, range),
+                    format!("This condition is always `{}`", value),
+                ),
+            )
+        })
+        .collect()
+}
+
+/// Check indented strings whose lines are indented by both tabs and spaces.
+/// Lines containing only whitespaces are ignored, as they are in indentation stripping.
+fn mixed_indent_string_diagnostics(file: FileId, root: &SyntaxNode) -> Vec<Diagnostic> {
+    root.descendants()
+        .filter(|node| node.kind() == SyntaxKind::INDENT_STRING)
+        .filter_map(|node| {
+            let mut first_tab = None;
+            let mut first_space = None;
+            // The start of the current line and whether tabs and spaces appear,
+            // if it contains only whitespaces so far.
+            let mut line = None;
+            let mut end_line = |line: Option<(TextSize, bool, bool)>, end: TextSize| {
+                if let Some((start, tab, space)) = line {
+                    let indent = TextRange::new(start, end);
+                    if tab {
+                        first_tab.get_or_insert(indent);
+                    }
+                    if space {
+                        first_space.get_or_insert(indent);
+                    }
+                }
+            };
+            for elem in node.children_with_tokens() {
+                let tok = match elem.as_token() {
+                    Some(tok) if tok.kind() == SyntaxKind::STRING_FRAGMENT => tok,
+                    // The closing quote ends the last line, which has only whitespaces.
+                    Some(tok) if tok.kind() == T!["''"] && line.is_some() => break,
+                    // Escapes and interpolations are contents.
+                    _ => {
+                        end_line(line.take(), elem.text_range().start());
+                        continue;
+                    }
+                };
+                for (i, c) in tok.text().char_indices() {
+                    let pos = tok.text_range().start() + TextSize::from(i as u32);
+                    match (c, &mut line) {
+                        ('\n', _) => line = Some((pos + TextSize::from(1), false, false)),
+                        ('\t', Some((_, tab, _))) => *tab = true,
+                        (' ', Some((_, _, space))) => *space = true,
+                        (_, Some(_)) => end_line(line.take(), pos),
+                        (_, None) => {}
+                    }
+                }
+            }
+            let (tab, space) = first_tab.zip(first_space)?;
+            Some(
+                Diagnostic::new(node.text_range(), DiagnosticKind::MixedIndentString)
+                    .with_note(FileRange::new(file, tab), "Indented by tabs here")
+                    .with_note(FileRange::new(file, space), "Indented by spaces here"),
+            )
+        })
+        .collect()
+}
+
+/// Check `//` with an empty attrset literal on either side, like `x // { }`.
+fn redundant_merge_diagnostics(root: &SyntaxNode) -> Vec<Diagnostic> {
+    root.descendants()
+        .filter_map(ast::BinaryOp::cast)
+        .filter_map(|node| {
+            let (_, range) = redundant_merge(&node)?;
+            Some(Diagnostic::new(range, DiagnosticKind::RedundantMerge))
+        })
+        .collect()
+}
+
+/// If `node` is a `//` with an empty attrset literal on either side, returns the other
+/// operand to keep, and the redundant range of the operator and the empty side.
+pub(crate) fn redundant_merge(node: &ast::BinaryOp) -> Option<(ast::Expr, TextRange)> {
+    let (op, kind) = node.op_details()?;
+    if kind != BinaryOp::Update {
+        return None;
+    }
+    let (lhs, rhs) = (node.lhs()?, node.rhs()?);
+    let is_empty = |expr: &ast::Expr| matches!(expr, ast::Expr::AttrSet(set) if set.let_token().is_none() && set.bindings().next().is_none());
+    if is_empty(&rhs) {
+        let range = TextRange::new(op.text_range().start(), rhs.syntax().text_range().end());
+        Some((lhs, range))
+    } else if is_empty(&lhs) {
+        let range = TextRange::new(lhs.syntax().text_range().start(), op.text_range().end());
+        Some((rhs, range))
+    } else {
+        None
+    }
+}
+
+/// Ranges of whitespaces at the end of lines.
+/// Those inside string literals are skipped, since they are a part of the content.
+pub(crate) fn trailing_whitespaces(root: &SyntaxNode, src: &str) -> Vec<TextRange> {
+    let mut ret = Vec::new();
+    let mut line_start = 0;
+    for line in src.split('\n') {
+        let content = line.strip_suffix('\r').unwrap_or(line);
+        let trimmed = content.trim_end_matches([' ', '\t']);
+        if trimmed.len() != content.len() {
+            let start = TextSize::from((line_start + trimmed.len()) as u32);
+            let end = TextSize::from((line_start + content.len()) as u32);
+            // Whitespaces after the last token are not in the tree.
+            let in_trivia = start >= root.text_range().end()
+                || matches!(
+                    root.token_at_offset(start).right_biased(),
+                    Some(tok) if matches!(tok.kind(), SyntaxKind::SPACE | SyntaxKind::COMMENT)
+                );
+            if in_trivia {
+                ret.push(TextRange::new(start, end));
+            }
+        }
+        line_start += line.len() + 1;
+    }
+    ret
+}
+
+/// Check lines longer than `max_len` characters. The exceeding part is reported.
+fn long_line_diagnostics(src: &str, max_len: usize) -> Vec<Diagnostic> {
+    let mut diags = Vec::new();
+    let mut line_start = 0;
+    for line in src.split('\n') {
+        let content = line.strip_suffix('\r').unwrap_or(line);
+        if let Some((i, _)) = content.char_indices().nth(max_len) {
+            let range = TextRange::new(
+                TextSize::from((line_start + i) as u32),
+                TextSize::from((line_start + content.len()) as u32),
+            );
+            diags.push(Diagnostic::new(range, DiagnosticKind::LineTooLong));
+        }
+        line_start += line.len() + 1;
+    }
+    diags
+}
+
+/// Check applications whose callee is a literal which can never be a function.
+fn not_callable_diagnostics(db: &dyn DefDatabase, file: FileId) -> Vec<Diagnostic> {
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+    module
+        .exprs()
+        .filter_map(|(_, kind)| match kind {
+            &Expr::Apply(func, _) => Some(func),
+            _ => None,
+        })
+        .filter(|&func| match &module[func] {
+            Expr::Literal(Literal::Int(_) | Literal::Float(_) | Literal::String(_))
+            | Expr::StringInterpolation(_)
+            | Expr::List(_) => true,
+            Expr::Attrset(_) | Expr::RecAttrset(_) => is_non_callable_attrset(&module, func),
+            _ => false,
+        })
+        .filter_map(|func| {
+            let range = source_map.node_for_expr(func)?.text_range();
+            Some(
+                Diagnostic::new(range, DiagnosticKind::NotCallable).with_note(
+                    FileRange::new(file, range),
+                    "This value cannot be called as a function",
+                ),
+            )
+        })
+        .collect()
+}
+
+/// Check `let` bindings defined as references to each other in a cycle,
+/// like `let a = a; in a` or `let a = b; b = a; in a`.
+/// Only direct references are followed, since lambdas and attrsets may be lazily guarded.
+/// Cycles are only reported if they are referenced elsewhere, thus possibly forced.
+fn trivial_infinite_recursion_diagnostics(db: &dyn DefDatabase, file: FileId) -> Vec<Diagnostic> {
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+    let name_res = db.name_resolution(file);
+    let name_refs = db.name_reference(file);
+
+    // `let` bindings whose values are references to other `let` bindings.
+    let targets = module
+        .exprs()
+        .filter_map(|(_, kind)| match kind {
+            Expr::LetIn(bindings, _) => Some(bindings.statics.iter()),
+            _ => None,
+        })
+        .flatten()
+        .filter_map(|&(name, value)| {
+            let value = match value {
+                BindingValue::Expr(value) => value,
+                BindingValue::Inherit(_) | BindingValue::InheritFrom(_) => return None,
+            };
+            match (&module[value], name_res.get(value)?) {
+                (Expr::Reference(_), &ResolveResult::Definition(def))
+                    if module[def].kind == NameKind::LetIn =>
+                {
+                    Some((name, (value, def)))
+                }
+                _ => None,
+            }
+        })
+        .collect::<HashMap<_, _>>();
+
+    // The cycle starting and ending at `start`, if any.
+    let cycle_of = |start: NameId| -> Option<Vec<NameId>> {
+        let mut cycle = vec![start];
+        loop {
+            let next = targets.get(cycle.last().unwrap())?.1;
+            if next == start {
+                return Some(cycle);
+            }
+            // Entering another cycle.
+            if cycle.contains(&next) {
+                return None;
+            }
+            cycle.push(next);
+        }
+    };
+
+    let mut names = Vec::new();
+    let mut visited = HashSet::new();
+    for &start in targets.keys() {
+        if visited.contains(&start) {
+            continue;
+        }
+        let cycle = match cycle_of(start) {
+            Some(cycle) => cycle,
+            None => continue,
+        };
+        visited.extend(cycle.iter().copied());
+        let values = cycle
+            .iter()
+            .map(|name| targets[name].0)
+            .collect::<HashSet<_>>();
+        let is_used = cycle.iter().any(|&name| {
+            name_refs
+                .name_references(name)
+                .unwrap_or_default()
+                .iter()
+                .any(|expr| !values.contains(expr))
+        });
+        if is_used {
+            names.extend(cycle);
+        }
+    }
+
+    names.sort_unstable_by_key(|name| u32::from(name.into_raw()));
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let range = source_map.nodes_for_name(name).next()?.text_range();
+            Some(Diagnostic::new(
+                range,
+                DiagnosticKind::TrivialInfiniteRecursion,
+            ))
+        })
+        .collect()
+}
+
+/// Check if `expr` is an attrset literal which is surely not callable.
+/// Attrsets with `__functor` are callable, and dynamic attributes may define it.
+fn is_non_callable_attrset(module: &Module, expr: ExprId) -> bool {
+    match &module[expr] {
+        Expr::Attrset(bindings) | Expr::RecAttrset(bindings) => {
+            bindings.dynamics.is_empty()
+                && bindings
+                    .statics
+                    .iter()
+                    .all(|&(name, _)| module[name].text != "__functor")
+        }
+        _ => false,
+    }
+}
+
+/// Builtins taking a function as the first argument.
+const FUNCTION_FIRST_BUILTINS: &[&str] = &[
+    "all",
+    "any",
+    "concatMap",
+    "filter",
+    "foldl'",
+    "genList",
+    "groupBy",
+    "map",
+    "mapAttrs",
+    "partition",
+    "sort",
+    "zipAttrsWith",
+];
+
+/// Check attrset literals in well-known positions where only functions are accepted:
+/// the first argument of some builtins like `map`, and elements of `overlays` lists.
+fn attrset_as_function_diagnostics(db: &dyn DefDatabase, file: FileId) -> Vec<Diagnostic> {
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+    let name_res = db.name_resolution(file);
+
+    // Resolve `map` and `builtins.map` to the builtin name.
+    let builtin_name = |expr: ExprId| match &module[expr] {
+        Expr::Reference(_) => match name_res.get(expr)? {
+            ResolveResult::Builtin(name) => Some(*name),
+            _ => None,
+        },
+        Expr::Select(set, path, None) => {
+            if name_res.get(*set) != Some(&ResolveResult::Builtin("builtins")) {
+                return None;
+            }
+            match &**path {
+                [attr] => match &module[*attr] {
+                    Expr::Literal(Literal::String(name)) => {
+                        FUNCTION_FIRST_BUILTINS.iter().copied().find(|b| b == name)
+                    }
+                    _ => None,
+                },
+                _ => None,
+            }
+        }
+        _ => None,
+    };
+
+    let builtin_args = module.exprs().filter_map(|(_, kind)| match kind {
+        &Expr::Apply(func, arg) => {
+            let name = builtin_name(func)?;
+            FUNCTION_FIRST_BUILTINS.contains(&name).then(|| {
+                let note = format!(
+                    "`builtins.{}` expects a function as its first argument",
+                    name
+                );
+                (arg, note)
+            })
+        }
+        _ => None,
+    });
+
+    let overlays = module
+        .exprs()
+        .filter_map(|(_, kind)| kind.bindings())
+        .flat_map(|bindings| bindings.statics.iter())
+        .filter(|&&(name, _)| module[name].text == "overlays")
+        .filter_map(|&(_, value)| match value {
+            BindingValue::Expr(e) => match &module[e] {
+                Expr::List(elems) => Some(elems.iter().copied()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .flatten()
+        .map(|elem| {
+            let note = "Overlays are functions like `final: prev: { ... }`".to_owned();
+            (elem, note)
+        });
+
+    builtin_args
+        .chain(overlays)
+        .filter(|&(expr, _)| is_non_callable_attrset(&module, expr))
+        .filter_map(|(expr, note)| {
+            let range = source_map.node_for_expr(expr)?.text_range();
+            Some(
+                Diagnostic::new(range, DiagnosticKind::AttrsetAsFunction)
+                    .with_note(FileRange::new(file, range), note),
+            )
+        })
+        .collect()
+}
+
+fn replaceable_with_diagnostics(
+    db: &dyn DefDatabase,
+    file: FileId,
+    max_names: usize,
+) -> Vec<Diagnostic> {
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+    let root = db.parse(file).syntax_node();
+    module
+        .exprs()
+        .filter(|(_, kind)| matches!(kind, Expr::With(..)))
+        .filter_map(|(with_expr, _)| {
+            let uses = replaceable_with_uses(db, file, with_expr)?;
+            let names = uses
+                .iter()
+                .filter_map(|&expr| match &module[expr] {
+                    Expr::Reference(name) => Some(name),
+                    _ => None,
+                })
+                .collect::<HashSet<_>>();
+            if names.len() > max_names {
+                return None;
+            }
+            let node = ast::With::cast(source_map.node_for_expr(with_expr)?.to_node(&root))?;
+            let header_range = node
+                .with_token()?
+                .text_range()
+                .cover(node.semicolon_token()?.text_range());
+            let diag = Diagnostic::new(header_range, DiagnosticKind::ReplaceableWith);
+            Some(uses.iter().fold(diag, |diag, &expr| {
+                let range = source_map.node_for_expr(expr).unwrap().text_range();
+                diag.with_note(FileRange::new(file, range), "Provided by `with` here")
+            }))
+        })
+        .collect()
+}
+
+/// `with` at the top of a file, whose environment is not an attrset literal, like
+/// `with import <nixpkgs> { };` or `{ pkgs, ... }: with pkgs;`.
+/// They bring unknown and usually a lot of names into the scope of the whole file.
+fn broad_with_scope_diagnostics(db: &dyn DefDatabase, file: FileId) -> Vec<Diagnostic> {
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+    let root = db.parse(file).syntax_node();
+    let mut diags = Vec::new();
+    let mut expr = module.entry_expr();
+    loop {
+        expr = match module[expr] {
+            Expr::Lambda(_, _, body) | Expr::Assert(_, body) => body,
+            Expr::With(env, body) => {
+                let is_literal = matches!(
+                    module[env],
+                    Expr::Attrset(_) | Expr::RecAttrset(_) | Expr::LetAttrset(_)
+                );
+                let node = source_map
+                    .node_for_expr(expr)
+                    .and_then(|ptr| ast::With::cast(ptr.to_node(&root)));
+                let header_range = node.and_then(|node| {
+                    Some(
+                        node.with_token()?
+                            .text_range()
+                            .cover(node.semicolon_token()?.text_range()),
+                    )
+                });
+                if let (false, Some(range)) = (is_literal, header_range) {
+                    diags.push(Diagnostic::new(range, DiagnosticKind::BroadWithScope));
+                }
+                body
+            }
+            _ => break,
+        };
+    }
+    diags
+}
+
+/// Integer, string and boolean literals appearing more than once in a list.
+/// Other expressions are not compared.
+/// Strings are compared by their source text, which may miss some equal ones like `"a"` and `''a''`.
+fn duplicate_list_element_diagnostics(db: &dyn DefDatabase, file: FileId) -> Vec<Diagnostic> {
+    #[derive(PartialEq, Eq, Hash)]
+    enum Key<'a> {
+        Int(i64),
+        String(&'a str),
+        Bool(bool),
+    }
 
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+    let nameres = db.name_resolution(file);
+    let src = db.file_content(file);
+    let key_of = |expr: ExprId| match &module[expr] {
+        &Expr::Literal(Literal::Int(x)) => Some(Key::Int(x)),
+        Expr::StringInterpolation(parts) if parts.is_empty() => Some(Key::String(
+            &src[source_map.node_for_expr(expr)?.text_range()],
+        )),
+        Expr::Reference(_) => match nameres.get(expr)? {
+            ResolveResult::Builtin("true") => Some(Key::Bool(true)),
+            ResolveResult::Builtin("false") => Some(Key::Bool(false)),
+            _ => None,
+        },
+        _ => None,
+    };
+
+    let mut diags = Vec::new();
+    for (_, kind) in module.exprs() {
+        let elems = match kind {
+            Expr::List(elems) => elems,
+            _ => continue,
+        };
+        let mut firsts = HashMap::new();
+        for &elem in elems.iter() {
+            let key = match key_of(elem) {
+                Some(key) => key,
+                None => continue,
+            };
+            let first = *firsts.entry(key).or_insert(elem);
+            if first == elem {
+                continue;
+            }
+            let (range, first_range) = match (
+                source_map.node_for_expr(elem),
+                source_map.node_for_expr(first),
+            ) {
+                (Some(ptr), Some(first_ptr)) => (ptr.text_range(), first_ptr.text_range()),
+                _ => continue,
+            };
+            diags.push(
+                Diagnostic::new(range, DiagnosticKind::DuplicateListElement)
+                    .with_note(FileRange::new(file, first_range), "First occurrence here"),
+            );
+        }
+    }
     diags
 }
 
+/// Selections like `builtins.foo` where `foo` is not an available builtin,
+/// and `builtins` refers to the global one.
+/// Selections with defaults like `builtins.foo or null` are feature detections, thus ignored.
+pub(crate) fn unknown_builtin_diagnostics(
+    db: &dyn DefDatabase,
+    file: FileId,
+    builtins: &BuiltinSet,
+) -> Vec<Diagnostic> {
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+    let nameres = db.name_resolution(file);
+    module
+        .exprs()
+        .filter_map(|(_, kind)| match kind {
+            Expr::Select(set, path, None) => Some((*set, *path.first()?)),
+            _ => None,
+        })
+        .filter(|&(set, _)| matches!(nameres.get(set), Some(ResolveResult::Builtin("builtins"))))
+        .filter_map(|(_, attr)| {
+            let name = match &module[attr] {
+                Expr::Literal(Literal::String(name)) => name,
+                _ => return None,
+            };
+            if is_known_builtin(name) && builtins.contains(name) {
+                return None;
+            }
+            let range = source_map.node_for_expr(attr)?.text_range();
+            Some(Diagnostic::new(range, DiagnosticKind::UnknownBuiltin))
+        })
+        .collect()
+}
+
+/// The available builtin closest to a misspelled `name`, if it is close enough.
+pub(crate) fn closest_builtin(name: &str, builtins: &BuiltinSet) -> Option<&'static str> {
+    let max_dist = (name.len() / 3).max(1);
+    ALL_BUILTINS
+        .keys()
+        .filter(|builtin| builtins.contains(builtin))
+        .map(|&builtin| (edit_distance(name, builtin), builtin))
+        .filter(|&(dist, _)| dist <= max_dist)
+        // Break ties by name, since the map is unordered.
+        .min()
+        .map(|(_, builtin)| builtin)
+}
+
+/// The Levenshtein distance between two strings, by chars.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diag = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let sub = diag + usize::from(ca != cb);
+            diag = row[j + 1];
+            row[j + 1] = sub.min(row[j] + 1).min(diag + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// The uses of names provided by `with_expr`, if the `with` can be replaced by explicit
+/// selections on its environment, like `with pkgs; [ a ]` into `[ pkgs.a ]`.
+/// The environment must be a name or a static selection on it, which is not shadowed in the body.
+/// All uses must be plain references, not `inherit`s. It must not be the fallback of any inner
+/// `with`, since removing it would change where the names of the inner one come from.
+/// Returns uses ordered by their positions, or `None` if there are none.
+pub(crate) fn replaceable_with_uses(
+    db: &dyn DefDatabase,
+    file: FileId,
+    with_expr: ExprId,
+) -> Option<Vec<ExprId>> {
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+    let name_res = db.name_resolution(file);
+    let scopes = db.scopes(file);
+
+    let env = match module[with_expr] {
+        Expr::With(env, _) => env,
+        _ => return None,
+    };
+    let env_root = static_selection_root(&module, env)?;
+
+    let mut uses = db.name_reference(file).with_references(with_expr)?.to_vec();
+    for &expr in &uses {
+        match name_res.get(expr) {
+            Some(ResolveResult::WithExprs(withs)) if withs[0] == with_expr => {}
+            _ => return None,
+        }
+        let ptr = source_map.node_for_expr(expr)?;
+        if ptr.kind() != SyntaxKind::REF {
+            return None;
+        }
+        let shadowed = scopes
+            .ancestors(scopes.scope_for_expr(expr)?)
+            .take_while(|data| data.as_with() != Some(with_expr))
+            .any(|data| matches!(data.as_definitions(), Some(defs) if defs.contains_key(env_root)));
+        if shadowed {
+            return None;
+        }
+    }
+    if uses.is_empty() {
+        return None;
+    }
+    uses.sort_by_key(|&expr| {
+        source_map
+            .node_for_expr(expr)
+            .map(|ptr| ptr.text_range().start())
+    });
+    Some(uses)
+}
+
+/// The root name of `a` or `a.b."c"`, without dynamic attributes or `or` defaults.
+fn static_selection_root(module: &Module, expr: ExprId) -> Option<&SmolStr> {
+    match &module[expr] {
+        Expr::Reference(name) => Some(name),
+        Expr::Select(set, path, None)
+            if path
+                .iter()
+                .all(|&attr| matches!(module[attr], Expr::Literal(Literal::String(_)))) =>
+        {
+            static_selection_root(module, *set)
+        }
+        _ => None,
+    }
+}
+
+/// Evaluate a boolean expression if it is trivially constant.
+/// Only `true`, `false`, and `!`, `&&`, `||` of them are folded.
+pub(crate) fn constant_bool(
+    module: &Module,
+    name_res: &NameResolution,
+    expr: ExprId,
+) -> Option<bool> {
+    match &module[expr] {
+        Expr::Reference(_) => match name_res.get(expr)? {
+            ResolveResult::Builtin("true") => Some(true),
+            ResolveResult::Builtin("false") => Some(false),
+            _ => None,
+        },
+        &Expr::Unary(Some(UnaryOp::Not), operand) => {
+            constant_bool(module, name_res, operand).map(|b| !b)
+        }
+        &Expr::Binary(Some(op @ (BinaryOp::And | BinaryOp::Or)), lhs, rhs) => {
+            let lhs = constant_bool(module, name_res, lhs)?;
+            let rhs = constant_bool(module, name_res, rhs)?;
+            Some(if op == BinaryOp::And {
+                lhs && rhs
+            } else {
+                lhs || rhs
+            })
+        }
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use super::DiagnosticsConfig;
     use crate::tests::TestDB;
+    use crate::{BuiltinSet, DiagnosticKind, NixVersion, Severity};
     use expect_test::{expect, Expect};
 
     fn check(fixture: &str, expect: Expect) {
-        let (db, file_id) = TestDB::single_file(fixture).unwrap();
-        let diags = super::diagnostics(&db, file_id);
+        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        let diags = super::diagnostics(&db, &DiagnosticsConfig::default(), f["/default.nix"]);
         assert!(!diags.is_empty());
         let mut got = diags
             .iter()
@@ -41,6 +1170,16 @@ mod tests {
         expect.assert_eq(&got);
     }
 
+    fn check_syntax(src: &str, expect: Expect) {
+        let (db, file) = TestDB::single_file(src).unwrap();
+        let got = super::diagnostics(&db, &DiagnosticsConfig::default(), file)
+            .iter()
+            .filter(|d| matches!(d.kind, DiagnosticKind::SyntaxError(_)))
+            .map(|d| d.debug_display().to_string() + "\n")
+            .collect::<String>();
+        expect.assert_eq(&got);
+    }
+
     #[test]
     fn syntax_error() {
         check(
@@ -49,6 +1188,387 @@ mod tests {
         );
     }
 
+    #[test]
+    fn syntax_errors_only() {
+        let (db, file) = TestDB::single_file("let a = b; in (a").unwrap();
+        let got = super::syntax_errors(&db, file)
+            .iter()
+            .map(|d| d.debug_display().to_string() + "\n")
+            .collect::<String>();
+        expect![[r#"
+            16..16: Missing ")"
+              14..15: Expected ")" to close this "("
+        "#]]
+        .assert_eq(&got);
+        assert!(super::diagnostics(&db, &DiagnosticsConfig::default(), file).len() > 1);
+    }
+
+    /// Compare the time of syntax errors and full diagnostics on a large file.
+    /// Run by `cargo test -p ide --release -- --ignored --nocapture bench_syntax_errors`.
+    #[test]
+    #[ignore = "benchmark"]
+    fn bench_syntax_errors() {
+        use std::time::Instant;
+
+        let src = (0..20000)
+            .map(|i| format!("a{i} = {{ x = a{i}; y = with b; c; }};\n"))
+            .collect::<String>();
+        let src = format!("let b = {{ }};\n{src}in (a0");
+
+        let (db, file) = TestDB::single_file(&src).unwrap();
+        let start = Instant::now();
+        let syntax = super::syntax_errors(&db, file);
+        let syntax_time = start.elapsed();
+
+        let (db, file) = TestDB::single_file(&src).unwrap();
+        let start = Instant::now();
+        let full = super::diagnostics(&db, &DiagnosticsConfig::default(), file);
+        let full_time = start.elapsed();
+
+        eprintln!("syntax errors: {} in {syntax_time:?}", syntax.len());
+        eprintln!("full diagnostics: {} in {full_time:?}", full.len());
+    }
+
+    #[test]
+    fn missing_token() {
+        check_syntax(
+            "let a = 1;",
+            expect![[r#"
+            10..10: Missing "in"
+              0..3: Expected "in" to close this "let"
+            10..10: Missing expression
+        "#]],
+        );
+        check_syntax(
+            "if a then b",
+            expect![[r#"
+            11..11: Missing "else"
+              0..2: Expected "else" to continue this "if"
+        "#]],
+        );
+        check_syntax(
+            "{ a = { b = 1; };",
+            expect![[r#"
+            17..17: Missing "}"
+              0..1: Expected "}" to close this "{"
+        "#]],
+        );
+        check_syntax(
+            "{ a = 1 }",
+            expect![[r#"
+            8..9: Missing ";"
+              2..3: Expected ";" to close this binding
+        "#]],
+        );
+        check_syntax(
+            "[ 1 2",
+            expect![[r#"
+            5..5: Missing "]"
+              0..1: Expected "]" to close this "["
+        "#]],
+        );
+        check_syntax(
+            "with a",
+            expect![[r#"
+            6..6: Missing ";"
+              0..4: Expected ";" to close this "with"
+            6..6: Missing expression
+        "#]],
+        );
+        check_syntax(
+            "\"a${b",
+            expect![[r#"
+            5..5: Missing "}"
+              2..4: Expected "}" to close this "${"
+            5..5: Missing '"'
+              0..1: Expected '"' to close this '"'
+        "#]],
+        );
+        check_syntax(
+            "{ a, b c }: a",
+            expect![[r#"
+            7..8: Missing ","
+              0..1: Expected "," to continue this "{"
+        "#]],
+        );
+    }
+
+    #[test]
+    fn constant_condition() {
+        check(
+            "if true then 1 else 2",
+            expect![[r#"
+                3..7: Condition is constant
+                  3..7: This condition is always `true`
+            "#]],
+        );
+        check(
+            "if !(false || true && true) then 1 else 2",
+            expect![[r#"
+                3..28: Condition is constant
+                  3..28: This condition is always `false`
+            "#]],
+        );
+        check(
+            "assert true; 1",
+            expect![[r#"
+                7..11: Condition is constant
+                  7..11: This condition is always `true`
+            "#]],
+        );
+        check(
+            "assert !false; 1",
+            expect![[r#"
+                7..13: Condition is constant
+                  7..13: This condition is always `true`
+            "#]],
+        );
+    }
+
+    #[test]
+    fn non_constant_condition() {
+        let (db, file) = TestDB::single_file(
+            "a: let false = a; in [ (if a then 1 else 2) (if false then 1 else 2) (assert a && true; 1) (if 1 == 1 then 1 else 2) ]",
+        )
+        .unwrap();
+        assert_eq!(
+            super::diagnostics(&db, &DiagnosticsConfig::default(), file),
+            Vec::new()
+        );
+    }
+
+    #[test]
+    fn not_callable() {
+        check(
+            "{} 1",
+            expect![[r#"
+                0..2: Calling a non-function value
+                  0..2: This value cannot be called as a function
+            "#]],
+        );
+        check(
+            "[1] 2",
+            expect![[r#"
+                0..3: Calling a non-function value
+                  0..3: This value cannot be called as a function
+            "#]],
+        );
+        check(
+            r#"("a${"b"}" 1)"#,
+            expect![[r#"
+                1..10: Calling a non-function value
+                  1..10: This value cannot be called as a function
+            "#]],
+        );
+    }
+
+    #[test]
+    fn trivial_infinite_recursion() {
+        check(
+            "let a = a; in a",
+            expect!["4..5: Infinite recursion. The binding is defined as itself"],
+        );
+        check(
+            "let a = b; b = c; c = a; d = a; in d",
+            expect!["4..5: Infinite recursion. The binding is defined as itself11..12: Infinite recursion. The binding is defined as itself18..19: Infinite recursion. The binding is defined as itself"],
+        );
+    }
+
+    #[test]
+    fn lazy_recursion() {
+        let (db, file) = TestDB::single_file(
+            "let f = x: f x; a = { b = a; }; c = [ c ]; d = d.e or 1; in [ f a c d ]",
+        )
+        .unwrap();
+        let diags = super::diagnostics(&db, &DiagnosticsConfig::default(), file);
+        assert_eq!(diags, Vec::new());
+
+        // Never forced.
+        let (db, file) = TestDB::single_file("let a = b; b = a; in 1").unwrap();
+        let diags = super::diagnostics(&db, &DiagnosticsConfig::default(), file);
+        assert!(diags
+            .iter()
+            .all(|diag| diag.kind != DiagnosticKind::TrivialInfiniteRecursion));
+    }
+
+    #[test]
+    fn mixed_indent_string() {
+        check(
+            "''\n  a\n\tb\n''",
+            expect![[r#"
+                0..12: Indented string mixes tabs and spaces in indentation. Only spaces are stripped
+                  7..8: Indented by tabs here
+                  3..5: Indented by spaces here
+            "#]],
+        );
+        check(
+            "''\n \ta\n''",
+            expect![[r#"
+                0..9: Indented string mixes tabs and spaces in indentation. Only spaces are stripped
+                  3..5: Indented by tabs here
+                  3..5: Indented by spaces here
+            "#]],
+        );
+    }
+
+    #[test]
+    fn consistent_indent_string() {
+        let (db, file) = TestDB::single_file(
+            "[ ''\n  a\n    b\n'' ''\n\ta\n\t\tb\n'' ''\n  a\n\t\n  ${x}\tb\n\t'' ''\ta\n  b'' ]",
+        )
+        .unwrap();
+        assert_eq!(
+            super::diagnostics(&db, &DiagnosticsConfig::default(), file)
+                .into_iter()
+                .filter(|diag| diag.kind == crate::DiagnosticKind::MixedIndentString)
+                .collect::<Vec<_>>(),
+            Vec::new(),
+        );
+    }
+
+    #[test]
+    fn redundant_merge() {
+        check(
+            "x: x // { }",
+            expect!["5..11: Merging with an empty attrset has no effect"],
+        );
+        check(
+            "x: {} // x",
+            expect!["3..8: Merging with an empty attrset has no effect"],
+        );
+        check(
+            "{ } // { }",
+            expect!["4..10: Merging with an empty attrset has no effect"],
+        );
+
+        let (db, file) =
+            TestDB::single_file("x: [ (x // { a = 1; }) ({ inherit x; } // x) (x // (x: x)) ]")
+                .unwrap();
+        assert_eq!(
+            super::diagnostics(&db, &DiagnosticsConfig::default(), file),
+            Vec::new()
+        );
+    }
+
+    #[test]
+    fn callable() {
+        let (db, file) = TestDB::single_file(
+            "f: [ ((x: x) 1) (f 1) ({ __functor = self: x: x; } 1) (rec { a = 1; __functor = _: _: a; } 1) ]",
+        )
+        .unwrap();
+        assert_eq!(
+            super::diagnostics(&db, &DiagnosticsConfig::default(), file),
+            Vec::new()
+        );
+    }
+
+    #[test]
+    fn attrset_as_function() {
+        check(
+            "map { } [ 1 ]",
+            expect![[r#"
+                4..7: Attrset is used where a function is expected
+                  4..7: `builtins.map` expects a function as its first argument
+            "#]],
+        );
+        check(
+            "builtins.filter { a = 1; } [ 1 ]",
+            expect![[r#"
+                16..26: Attrset is used where a function is expected
+                  16..26: `builtins.filter` expects a function as its first argument
+            "#]],
+        );
+        check(
+            "{ nixpkgs.overlays = [ (final: prev: { }) { } ]; }",
+            expect![[r#"
+                42..45: Attrset is used where a function is expected
+                  42..45: Overlays are functions like `final: prev: { ... }`
+            "#]],
+        );
+    }
+
+    #[test]
+    fn attrset_as_function_false_positive() {
+        let (db, file) = TestDB::single_file(
+            "f: [ (map { __functor = _: x: x; } [ ]) (f { } [ ]) (builtins.attrNames { }) { overlays = { }; } { overlays = [ f ]; } ]",
+        )
+        .unwrap();
+        assert_eq!(
+            super::diagnostics(&db, &DiagnosticsConfig::default(), file),
+            Vec::new()
+        );
+    }
+
+    #[test]
+    fn suppression() {
+        let check_suppressed = |src: &str, expect: Expect| {
+            let (db, file) = TestDB::single_file(src).unwrap();
+            let got = super::diagnostics(&db, &DiagnosticsConfig::default(), file)
+                .iter()
+                .map(|d| format!("{:?}: {}\n", d.range, d.code()))
+                .collect::<String>();
+            expect.assert_eq(&got);
+        };
+
+        check_suppressed(
+            "
+# nil: disable=unused_binding, unused_with
+let a = 1; in with 1; rec { }
+            ",
+            expect![[r#"
+                65..68: unused_rec
+            "#]],
+        );
+        // File-level directives only work before any code.
+        check_suppressed(
+            "
+let
+  # nil: disable=unused_binding
+  a = 1;
+in 1
+            ",
+            expect![[r#"
+                38..39: unused_binding
+            "#]],
+        );
+        check_suppressed(
+            "
+let
+  a = 1; # nil: disable-line=unused_binding
+  b = 1;
+in 1
+            ",
+            expect![[r#"
+                50..51: unused_binding
+            "#]],
+        );
+        check_suppressed(
+            "
+let
+  # nil: disable-next-line=unused_binding,undefined_name
+  a = c;
+  b = 1;
+in 1
+            ",
+            expect![[r#"
+                72..73: unused_binding
+            "#]],
+        );
+        check_suppressed(
+            "
+let
+  /* nil: disable-next-line=unused_binding */
+  a = 1;
+  /* nil: disable-next-line=unused_with */
+  b = 1;
+in 1
+            ",
+            expect![[r#"
+                104..105: unused_binding
+            "#]],
+        );
+    }
+
     #[test]
     fn lower_error() {
         check(
@@ -65,6 +1585,125 @@ mod tests {
         check("a", expect!["0..1: Undefined name"]);
     }
 
+    #[test]
+    fn import_existing() {
+        let (db, f) = TestDB::from_fixture(
+            "
+#- /default.nix
+foo: import ./foo.nix + import ./bar + import <nixpkgs> + import ./${foo}.nix
+
+#- /foo.nix
+1
+
+#- /bar/default.nix
+1
+            ",
+        )
+        .unwrap();
+        let diags = super::diagnostics(&db, &DiagnosticsConfig::default(), f["/default.nix"]);
+        assert_eq!(diags, Vec::new());
+    }
+
+    #[test]
+    fn import_missing() {
+        check(
+            "
+#- /default.nix
+import ./foo.nix
+
+#- /bar.nix
+1
+            ",
+            expect!["7..16: Imported file `/foo.nix` does not exist"],
+        );
+        check(
+            "
+#- /default.nix
+import ./lib/../foo/bar.nix
+
+#- /foo/baz.nix
+1
+            ",
+            expect!["7..27: Imported file `/foo/bar.nix` does not exist"],
+        );
+        check(
+            "
+#- /default.nix
+import ./foo
+
+#- /bar.nix
+1
+            ",
+            expect!["7..12: Imported file `/foo` does not exist"],
+        );
+    }
+
+    #[test]
+    fn import_dir_without_default_nix() {
+        check(
+            "
+#- /default.nix
+import ./foo
+
+#- /foo/bar.nix
+1
+            ",
+            expect!["7..12: Imported directory has no `default.nix`"],
+        );
+    }
+
+    #[test]
+    fn conflicting_imported_option() {
+        check(
+            "
+#- /default.nix
+{ imports = [ ./a.nix ./b.nix ./c.nix ]; }
+
+#- /a.nix
+{ config, ... }: { config.services.foo.port = 80; }
+
+#- /b.nix
+{ services.foo.port = 8080; services.foo.enable = true; }
+
+#- /c.nix
+{ lib, ... }: {
+  config = { services.foo = { enable = true; port = lib.mkForce 1; }; };
+}
+            ",
+            expect![[r#"
+                22..29: Option is set to different values by multiple imported modules
+                  39..43: `services.foo.port` is set to `80` here
+                  15..19: `services.foo.port` is set to `8080` here
+            "#]],
+        );
+
+        // Mergeable or not statically known.
+        let (db, f) = TestDB::from_fixture(
+            r#"
+#- /default.nix
+{ imports = [ ./a.nix ./b.nix ./c.nix ./d.nix ./a.nix ]; }
+
+#- /a.nix
+{ environment.packages = [ 1 ]; bar = "a"; baz = "a"; }
+
+#- /b.nix
+{ environment.packages = [ 2 ]; bar = ''a''; baz = "b"; }
+
+#- /c.nix
+{ options.bar = 1; config = import ./e.nix; }
+
+#- /d.nix
+{ environment = import ./e.nix; }
+
+#- /e.nix
+{ packages = 3; }
+            "#,
+        )
+        .unwrap();
+        let diags = super::diagnostics(&db, &DiagnosticsConfig::default(), f["/default.nix"]);
+        assert_eq!(diags, Vec::new());
+    }
+
     #[test]
     fn liveness() {
         check(
@@ -72,4 +1711,267 @@ mod tests {
             expect!["4..5: Unused binding21..28: Unused `with`33..36: Unused `rec`"],
         );
     }
+
+    #[test]
+    fn replaceable_with() {
+        let config = DiagnosticsConfig {
+            replaceable_with_max_names: Some(2),
+            ..DiagnosticsConfig::default()
+        };
+        let check = |src: &str| {
+            let (db, file) = TestDB::single_file(src).unwrap();
+            super::diagnostics(&db, &config, file)
+                .iter()
+                .filter(|d| d.kind == DiagnosticKind::ReplaceableWith)
+                .map(|d| d.debug_display().to_string() + "\n")
+                .collect::<String>()
+        };
+
+        expect![[r#"
+            6..16: `with` provides only a few names, which can be referenced explicitly
+              19..24: Provided by `with` here
+        "#]]
+        .assert_eq(&check("pkgs: with pkgs; [ hello ]"));
+        expect![""].assert_eq(&check("pkgs: with pkgs; [ a b c d e ]"));
+        expect![""].assert_eq(&check("pkgs: with pkgs; let pkgs = 1; in a"));
+        expect![""].assert_eq(&check("pkgs: with pkgs; { inherit a; }"));
+        // Only the inner one, since the outer one is also its fallback.
+        expect![[r#"
+            21..31: `with` provides only a few names, which can be referenced explicitly
+              34..35: Provided by `with` here
+        "#]]
+        .assert_eq(&check("lib: pkgs: with lib; with pkgs; [ a ]"));
+
+        let (db, file) = TestDB::single_file("pkgs: with pkgs; [ hello ]").unwrap();
+        assert_eq!(
+            super::diagnostics(&db, &DiagnosticsConfig::default(), file),
+            Vec::new()
+        );
+    }
+
+    #[test]
+    fn broad_with_scope() {
+        let config = DiagnosticsConfig {
+            broad_with_scope: true,
+            ..DiagnosticsConfig::default()
+        };
+        let check = |src: &str| {
+            let (db, file) = TestDB::single_file(src).unwrap();
+            super::diagnostics(&db, &config, file)
+                .iter()
+                .filter(|d| d.kind == DiagnosticKind::BroadWithScope)
+                .map(|d| d.debug_display().to_string() + "\n")
+                .collect::<String>()
+        };
+
+        expect![[r#"
+            0..26: `with` brings all names of a large attrset into scope. Consider `inherit`ing used names explicitly
+        "#]]
+        .assert_eq(&check("with import <nixpkgs> { }; hello"));
+        expect![[r#"
+            15..25: `with` brings all names of a large attrset into scope. Consider `inherit`ing used names explicitly
+            26..35: `with` brings all names of a large attrset into scope. Consider `inherit`ing used names explicitly
+        "#]]
+        .assert_eq(&check("{ pkgs, lib }: with pkgs; with lib; [ hello ]"));
+        // Attrset literals.
+        expect![""].assert_eq(&check("with { a = 1; }; a"));
+        // Not at the top.
+        expect![""].assert_eq(&check("pkgs: [ (with pkgs; hello) ]"));
+        expect![""].assert_eq(&check("pkgs: let a = with pkgs; hello; in a"));
+
+        let (db, file) = TestDB::single_file("with import <nixpkgs> { }; hello").unwrap();
+        assert_eq!(
+            super::diagnostics(&db, &DiagnosticsConfig::default(), file),
+            Vec::new()
+        );
+    }
+
+    #[test]
+    fn dedup() {
+        // The parser reports the missing body twice while recovering.
+        check_syntax(
+            "a: : b",
+            expect![[r#"
+                3..4: Missing expression
+                3..4: Multiple root expressions
+                5..6: Multiple root expressions
+            "#]],
+        );
+    }
+
+    #[test]
+    fn of_severity() {
+        // Unused binding (warning), undefined name (error), and constant condition (hint).
+        let src = "let a = 1; in if true then b else 1";
+        let (db, file) = TestDB::single_file(src).unwrap();
+        let config = DiagnosticsConfig::default();
+        let check = |min, expect: Expect| {
+            let got = super::diagnostics_of_severity(&db, &config, file, min)
+                .iter()
+                .map(|d| d.debug_display().to_string() + "\n")
+                .collect::<String>();
+            expect.assert_eq(&got);
+        };
+        check(
+            Severity::Error,
+            expect![[r#"
+            27..28: Undefined name
+        "#]],
+        );
+        check(
+            Severity::Warning,
+            expect![[r#"
+            27..28: Undefined name
+            4..5: Unused binding
+        "#]],
+        );
+        check(
+            Severity::Hint,
+            expect![[r#"
+            27..28: Undefined name
+            17..21: Condition is constant
+              17..21: This condition is always `true`
+            4..5: Unused binding
+        "#]],
+        );
+    }
+
+    #[test]
+    fn unknown_builtin() {
+        let check = |src: &str, builtins: BuiltinSet| {
+            let (db, file) = TestDB::single_file(src).unwrap();
+            let config = DiagnosticsConfig {
+                builtins,
+                ..DiagnosticsConfig::default()
+            };
+            super::diagnostics(&db, &config, file)
+                .iter()
+                .filter(|d| d.kind == DiagnosticKind::UnknownBuiltin)
+                .map(|d| d.debug_display().to_string() + "\n")
+                .collect::<String>()
+        };
+
+        expect![[r#"
+            9..15: Unknown builtin
+        "#]]
+        .assert_eq(&check("builtins.toJSNO", BuiltinSet::Latest));
+        expect![""].assert_eq(&check("builtins.toJSON", BuiltinSet::Latest));
+        // Feature detection.
+        expect![""].assert_eq(&check("builtins.foo or null", BuiltinSet::Latest));
+        // Shadowed.
+        expect![""].assert_eq(&check(
+            "let builtins = { }; in builtins.toJSNO",
+            BuiltinSet::Latest,
+        ));
+        expect![""].assert_eq(&check("builtins: builtins.toJSNO", BuiltinSet::Latest));
+        // Unavailable in the configured version.
+        expect![[r#"
+            9..13: Unknown builtin
+        "#]]
+        .assert_eq(&check(
+            "builtins.warn",
+            BuiltinSet::Version(NixVersion::new(2, 18)),
+        ));
+        // Gated by experimental features, thus possibly missing in generated builtins.
+        expect![""].assert_eq(&check("builtins.getFlake", BuiltinSet::Latest));
+        expect![""].assert_eq(&check("builtins.fetchClosure", BuiltinSet::Latest));
+    }
+
+    #[test]
+    fn closest_builtin() {
+        assert_eq!(super::edit_distance("kitten", "sitting"), 3);
+        assert_eq!(super::edit_distance("", "abc"), 3);
+        assert_eq!(
+            super::closest_builtin("toJSNO", &BuiltinSet::Latest),
+            Some("toJSON"),
+        );
+        assert_eq!(
+            super::closest_builtin("fooBarBaz", &BuiltinSet::Latest),
+            None
+        );
+        assert_eq!(
+            super::closest_builtin("warm", &BuiltinSet::Latest),
+            Some("warn"),
+        );
+        // `warn` is unavailable.
+        assert_eq!(
+            super::closest_builtin("warm", &BuiltinSet::Version(NixVersion::new(2, 18))),
+            None,
+        );
+    }
+
+    #[test]
+    fn duplicate_list_element() {
+        let config = DiagnosticsConfig {
+            duplicate_list_elements: true,
+            ..DiagnosticsConfig::default()
+        };
+        let check = |src: &str| {
+            let (db, file) = TestDB::single_file(src).unwrap();
+            super::diagnostics(&db, &config, file)
+                .iter()
+                .filter(|d| d.kind == DiagnosticKind::DuplicateListElement)
+                .map(|d| d.debug_display().to_string() + "\n")
+                .collect::<String>()
+        };
+
+        expect![[r#"
+            6..7: Duplicated list element
+              2..3: First occurrence here
+            8..9: Duplicated list element
+              2..3: First occurrence here
+        "#]]
+        .assert_eq(&check("[ 1 2 1 1 ]"));
+        expect![[r#"
+            11..14: Duplicated list element
+              2..5: First occurrence here
+            21..25: Duplicated list element
+              6..10: First occurrence here
+        "#]]
+        .assert_eq(&check(r#"[ "a" true "a" ''a'' true false ]"#));
+        expect![""].assert_eq(&check("[ 1 \"1\" 1.0 ]"));
+        expect![""].assert_eq(&check("x: [ x x (f 1) (f 1) [ 1 ] [ 1 ] { } { } ]"));
+        expect![""].assert_eq(&check("let true = 1; in [ true true ]"));
+
+        let (db, file) = TestDB::single_file("[ 1 1 ]").unwrap();
+        assert_eq!(
+            super::diagnostics(&db, &DiagnosticsConfig::default(), file),
+            [],
+        );
+    }
+
+    #[test]
+    fn style() {
+        let config = DiagnosticsConfig {
+            trailing_whitespace: true,
+            max_line_length: Some(10),
+            ..DiagnosticsConfig::default()
+        };
+        let check = |src: &str| {
+            let (db, file) = TestDB::single_file(src).unwrap();
+            super::diagnostics(&db, &config, file)
+                .iter()
+                .map(|d| d.debug_display().to_string() + "\n")
+                .collect::<String>()
+        };
+
+        expect![[r#"
+            10..12: Trailing whitespace
+            34..36: Trailing whitespace
+            10..12: Line is too long
+            23..27: Line is too long
+        "#]]
+        .assert_eq(&check("let a = 1;  \n# 0123456789ab\nin a # \t\n# end"));
+        // Whitespaces in strings are contents.
+        expect![""].assert_eq(&check("''\n  a  \n''"));
+        // Lengths are in characters.
+        expect![""].assert_eq(&check("\"ąąąąąąąą\""));
+
+        let src = "let a = 1;  \nin a # 0123456789";
+        let (db, file) = TestDB::single_file(src).unwrap();
+        assert_eq!(
+            super::diagnostics(&db, &DiagnosticsConfig::default(), file),
+            [],
+        );
+    }
 }
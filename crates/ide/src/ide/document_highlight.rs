@@ -0,0 +1,166 @@
+use crate::{DefDatabase, FilePos};
+use rowan::TextRange;
+use syntax::{SyntaxKind, SyntaxToken, T};
+
+/// Highlight the structural pair of the token under the cursor, like `let` and its `in`,
+/// or a bracket and its matching one. Both ranges are returned, with the opening one first.
+/// Returns `None` if the pair is incomplete.
+pub(crate) fn document_highlight(
+    db: &dyn DefDatabase,
+    FilePos { file_id, pos }: FilePos,
+) -> Option<Vec<TextRange>> {
+    let parse = db.parse(file_id);
+    // Prefer the paired token on either side of the cursor, like `}` in `}|`.
+    let tok = parse
+        .syntax_node()
+        .token_at_offset(pos)
+        .find(|tok| is_open(tok.kind()) || is_close(tok.kind()))?;
+    let node = tok.parent()?;
+
+    // The first opening and the last closing children of the same node, which are
+    // the pair because inner ones always belong to nested nodes.
+    let mut toks = node
+        .children_with_tokens()
+        .filter_map(|elem| elem.into_token());
+    let (open, close): (SyntaxToken, SyntaxToken) = match node.kind() {
+        SyntaxKind::LET_IN => (
+            toks.clone().find(|t| t.kind() == T![let])?,
+            toks.find(|t| t.kind() == T![in])?,
+        ),
+        _ => (
+            toks.clone().find(|t| is_open(t.kind()))?,
+            toks.filter(|t| is_close(t.kind())).last()?,
+        ),
+    };
+    if tok != open && tok != close {
+        return None;
+    }
+    Some(vec![open.text_range(), close.text_range()])
+}
+
+fn is_open(kind: SyntaxKind) -> bool {
+    matches!(kind, T!['{'] | T!["${"] | T!['['] | T!['('] | T![let])
+}
+
+fn is_close(kind: SyntaxKind) -> bool {
+    matches!(kind, T!['}'] | T![']'] | T![')'] | T![in])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::base::SourceDatabase;
+    use crate::tests::TestDB;
+    use expect_test::{expect, Expect};
+
+    #[track_caller]
+    fn check(fixture: &str, expect: Expect) {
+        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        let src = db.file_content(f[0].file_id);
+        let got = super::document_highlight(&db, f[0])
+            .into_iter()
+            .flatten()
+            .map(|range| format!("{:?} {}\n", range, &src[range]))
+            .collect::<String>();
+        expect.assert_eq(&got);
+    }
+
+    #[test]
+    fn let_in() {
+        check(
+            "$0let a = let b = 1; in b; in a",
+            expect![[r#"
+                0..3 let
+                25..27 in
+            "#]],
+        );
+        check(
+            "let a = let b = 1; in b; $0in a",
+            expect![[r#"
+                0..3 let
+                25..27 in
+            "#]],
+        );
+        check(
+            "let a = $0let b = 1; in b; in a",
+            expect![[r#"
+                8..11 let
+                19..21 in
+            "#]],
+        );
+        // Incomplete.
+        check("$0let a = 1;", expect![""]);
+    }
+
+    #[test]
+    fn brackets() {
+        check(
+            "$0{ a = [ (1) { } ]; }",
+            expect![[r#"
+                0..1 {
+                19..20 }
+            "#]],
+        );
+        check(
+            "{ a = [ (1) { }$0 ]; }",
+            expect![[r#"
+                12..13 {
+                14..15 }
+            "#]],
+        );
+        check(
+            "{ a = $0[ (1) { } ]; }",
+            expect![[r#"
+                6..7 [
+                16..17 ]
+            "#]],
+        );
+        check(
+            "{ a = [ (1$0) { } ]; }",
+            expect![[r#"
+                8..9 (
+                10..11 )
+            "#]],
+        );
+        check(
+            "rec $0{ }",
+            expect![[r#"
+                4..5 {
+                6..7 }
+            "#]],
+        );
+        check(
+            "{ a, b ? { } }$0: a",
+            expect![[r#"
+                0..1 {
+                13..14 }
+            "#]],
+        );
+        // Not on a bracket.
+        check("{ $0a = 1; }", expect![""]);
+    }
+
+    #[test]
+    fn interpolation() {
+        check(
+            r#""a $0${ { b = 1; }.b } c""#,
+            expect![[r#"
+                3..5 ${
+                19..20 }
+            "#]],
+        );
+        check(
+            r#"{ ${"a"$0} = 1; }"#,
+            expect![[r#"
+                2..4 ${
+                7..8 }
+            "#]],
+        );
+        check(
+            "./a/${b$0}",
+            expect![[r#"
+                4..6 ${
+                7..8 }
+            "#]],
+        );
+    }
+}
@@ -0,0 +1,118 @@
+use crate::def::AttrKind;
+use crate::{DefDatabase, FilePos};
+use rowan::ast::AstNode;
+use syntax::{ast, best_token_at_offset, match_ast, SyntaxKind, SyntaxToken, TextSize};
+
+/// The attribute path from the outermost attrset to the cursor, through nested attrsets.
+/// For example, it is `["a", "b", "c"]` in `{ a.b = { c = $0; }; }`.
+///
+/// The path stops at anything other than attrsets, like `let` bindings, lists or lambdas,
+/// and at dynamic keys, whose names are unknown.
+/// Returns `None` if the cursor is not inside an attrset.
+pub(crate) fn enclosing_attrpath(
+    db: &dyn DefDatabase,
+    FilePos { file_id, pos }: FilePos,
+) -> Option<Vec<String>> {
+    let parse = db.parse(file_id);
+    let tok = best_token_at_offset(&parse.syntax_node(), pos)?;
+    let segments = attrpath_segments(&tok, pos)?;
+    let start = segments
+        .iter()
+        .rposition(Option::is_none)
+        .map_or(0, |i| i + 1);
+    Some(segments.into_iter().skip(start).flatten().collect())
+}
+
+/// Like [`enclosing_attrpath`], but dynamic keys are kept as `None` instead of stopping the path.
+pub(crate) fn attrpath_segments(tok: &SyntaxToken, pos: TextSize) -> Option<Vec<Option<String>>> {
+    let mut path = Vec::new();
+    let mut in_attrset = false;
+    for node in tok.parent_ancestors() {
+        match_ast! {
+            match node {
+                ast::AttrpathValue(n) => {
+                    if !matches!(n.syntax().parent(), Some(p) if p.kind() == SyntaxKind::ATTR_SET) {
+                        break;
+                    }
+                    in_attrset = true;
+                    // Inside the attrpath, only keys up to the cursor are included.
+                    let keys = n
+                        .attrpath()?
+                        .attrs()
+                        .take_while(|attr| attr.syntax().text_range().start() <= pos)
+                        .map(|attr| match AttrKind::classify(attr) {
+                            AttrKind::Static(name) => Some(name.to_string()),
+                            AttrKind::Dynamic(_) => None,
+                        })
+                        .collect::<Vec<_>>();
+                    path.splice(0..0, keys);
+                },
+                ast::AttrSet(_) => in_attrset = true,
+                ast::Paren(_) => {},
+                _ => {
+                    if in_attrset {
+                        break;
+                    }
+                },
+            }
+        }
+    }
+    in_attrset.then_some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::TestDB;
+    use expect_test::{expect, Expect};
+
+    #[track_caller]
+    fn check(fixture: &str, expect: Expect) {
+        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        let got = super::enclosing_attrpath(&db, f[0]);
+        expect.assert_eq(&format!("{:?}", got));
+    }
+
+    #[test]
+    fn nested() {
+        check(
+            "{ a.b = { c = $0x; }; }",
+            expect![[r#"Some(["a", "b", "c"])"#]],
+        );
+        check(
+            "{ a.b = ({ c = f $0x; }); }",
+            expect![[r#"Some(["a", "b", "c"])"#]],
+        );
+        check("{ a.b.c = 1; d$0 = 2; }", expect![[r#"Some(["d"])"#]]);
+        check("{ a.$0b.c = 1; }", expect![[r#"Some(["a", "b"])"#]]);
+        check(
+            r#"{ "a b" = rec { c = $01; }; }"#,
+            expect![[r#"Some(["a b", "c"])"#]],
+        );
+        check("{ a = { $0 }; }", expect![[r#"Some(["a"])"#]]);
+        check("{ a = [ { b = $01; } ]; }", expect![[r#"Some(["b"])"#]]);
+        check("{ a = x: { b = $01; }; }", expect![[r#"Some(["b"])"#]]);
+    }
+
+    #[test]
+    fn let_in() {
+        check("let a = { b = $01; }; in a", expect![[r#"Some(["b"])"#]]);
+        check("let a = 1; in { b = $0a; }", expect![[r#"Some(["b"])"#]]);
+        check("{ a = let b = 1; in $0b; }", expect![[r#"Some(["a"])"#]]);
+        check("{ a = let b = $01; in b; }", expect!["None"]);
+        check("let a = $01; in a", expect!["None"]);
+    }
+
+    #[test]
+    fn dynamic() {
+        check(
+            "{ ${a}.b = { c = $01; }; }",
+            expect![[r#"Some(["b", "c"])"#]],
+        );
+        check("{ a.${b} = { c = $01; }; }", expect![[r#"Some(["c"])"#]]);
+        check(
+            r#"{ a.${"b"} = { c = $01; }; }"#,
+            expect![[r#"Some(["a", "b", "c"])"#]],
+        );
+        check(r#"{ a."${b}" = $01; }"#, expect!["Some([])"]);
+    }
+}
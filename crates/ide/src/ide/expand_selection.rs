@@ -1,6 +1,7 @@
-use crate::{DefDatabase, FileRange};
+use crate::{DefDatabase, FilePos, FileRange};
+use rowan::ast::AstNode;
 use rowan::{NodeOrToken, TextRange};
-use syntax::{best_token_at_offset, SyntaxKind, SyntaxNode, T};
+use syntax::{ast, best_token_at_offset, SyntaxKind, SyntaxNode, T};
 
 /// Interesting parent ranges covering the given range.
 /// Returns all ranges from the smallest to the largest.
@@ -29,18 +30,48 @@ pub(crate) fn expand_selection(
         }
     };
 
-    ret.extend(
-        std::iter::successors(node, |node| node.parent()).filter_map(|node| {
-            is_node_kind_good(node.kind())
-                .then(|| non_space_range(&node))
-                .flatten()
-        }),
-    );
+    for node in std::iter::successors(node, |node| node.parent()) {
+        if let Some(path) = ast::Attrpath::cast(node.clone()) {
+            let cur = ret.last().copied().unwrap_or(range);
+            ret.extend(attrpath_prefix_ranges(&path, cur));
+            // The whole attrpath of a `Select` is not a prefix of it.
+            if node.parent().map(|p| p.kind()) == Some(SyntaxKind::SELECT) {
+                continue;
+            }
+        }
+        if is_node_kind_good(node.kind()) {
+            ret.extend(non_space_range(&node));
+        }
+    }
     ret.dedup();
 
     Some(ret)
 }
 
+/// The range of the smallest `name = value;` binding containing the position.
+pub(crate) fn enclosing_binding(
+    db: &dyn DefDatabase,
+    FilePos { file_id, pos }: FilePos,
+) -> Option<TextRange> {
+    let parse = db.parse(file_id);
+    let tok = best_token_at_offset(&parse.syntax_node(), pos)?;
+    let binding = tok.parent_ancestors().find_map(ast::AttrpathValue::cast)?;
+    non_space_range(binding.syntax())
+}
+
+/// Ranges of attrpath prefixes strictly covering `cur`, expanding one segment at a time.
+/// For `Select`, prefixes start from the set expression, like `x.a` in `x.a.b`.
+fn attrpath_prefix_ranges(path: &ast::Attrpath, cur: TextRange) -> Vec<TextRange> {
+    let start = match path.syntax().parent().and_then(ast::Select::cast) {
+        Some(select) => select.syntax().text_range().start(),
+        None => path.syntax().text_range().start(),
+    };
+    path.attrs()
+        .map(|attr| TextRange::new(start, attr.syntax().text_range().end()))
+        .filter(|&prefix| prefix.contains_range(cur) && prefix != cur)
+        .collect()
+}
+
 /// Trim spaces for the range of a node.
 /// Note that comments are not trimmed.
 fn non_space_range(node: &SyntaxNode) -> Option<TextRange> {
@@ -177,6 +208,26 @@ mod tests {
             "let a.$0b.c = a; in a",
             expect![[r#"
                 b
+                a.b
+                a.b.c
+                a.b.c = a;
+                let a.b.c = a; in a
+            "#]],
+        );
+        check(
+            "let a.b.$0c = a; in a",
+            expect![[r#"
+                c
+                a.b.c
+                a.b.c = a;
+                let a.b.c = a; in a
+            "#]],
+        );
+        check(
+            "let $0a.b.c = a; in a",
+            expect![[r#"
+                a
+                a.b
                 a.b.c
                 a.b.c = a;
                 let a.b.c = a; in a
@@ -192,6 +243,36 @@ mod tests {
         );
     }
 
+    #[test]
+    fn select() {
+        check(
+            "x.a.$0b.c or d",
+            expect![[r#"
+                b
+                x.a.b
+                x.a.b.c
+                x.a.b.c or d
+            "#]],
+        );
+        check(
+            "f x.$0a.${b}",
+            expect![[r#"
+                a
+                x.a
+                x.a.${b}
+                f x.a.${b}
+            "#]],
+        );
+        check(
+            "x.a.${$0b}",
+            expect![[r#"
+                b
+                ${b}
+                x.a.${b}
+            "#]],
+        );
+    }
+
     #[test]
     fn inherit() {
         check(
@@ -236,4 +317,22 @@ mod tests {
             "#]],
         );
     }
+
+    #[track_caller]
+    fn check_binding(fixture: &str, expect: Expect) {
+        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        let src = db.file_content(f[0].file_id);
+        let got = super::enclosing_binding(&db, f[0]).map_or("", |range| &src[range]);
+        expect.assert_eq(got);
+    }
+
+    #[test]
+    fn enclosing_binding() {
+        check_binding("{ a = 1; b = $0c; }", expect!["b = c;"]);
+        check_binding("{ a.b$0.c = 1; }", expect!["a.b.c = 1;"]);
+        check_binding("let a = { b = $01; }; in a", expect!["b = 1;"]);
+        check_binding("{ a = { b = 1; }$0; }", expect!["a = { b = 1; };"]);
+        check_binding("{ a = 1; inherit $0b; }", expect![""]);
+        check_binding("f $0x", expect![""]);
+    }
 }
@@ -0,0 +1,169 @@
+use crate::def::{Expr, Literal};
+use crate::{DefDatabase, FileId, FileRange, VfsPath};
+
+/// A path literal referencing another file, like `import ./foo.nix` or `callPackage ./bar { }`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileImport {
+    /// The range of the path literal.
+    pub site: FileRange,
+    /// The resolved file.
+    pub target: FileId,
+}
+
+/// Files referenced by `file`, ordered by their sites.
+pub(crate) fn file_imports(db: &dyn DefDatabase, file: FileId) -> Vec<FileImport> {
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+    let mut ret = module
+        .exprs()
+        .filter_map(|(expr, kind)| {
+            let target = match kind {
+                Expr::Literal(Literal::Path(path)) => path.resolve(db)?,
+                _ => return None,
+            };
+            let range = source_map.node_for_expr(expr)?.text_range();
+            Some(FileImport {
+                site: FileRange::new(file, range),
+                target,
+            })
+        })
+        .collect::<Vec<_>>();
+    ret.sort_by_key(|import| import.site.range.start());
+    ret
+}
+
+/// Paths referenced by path literals in `file`, whether they resolve to known files or not,
+/// ordered and deduplicated. Non-relative paths and paths going beyond the root are skipped.
+pub(crate) fn referenced_paths(db: &dyn DefDatabase, file: FileId) -> Vec<VfsPath> {
+    let module = db.module(file);
+    let mut ret = module
+        .exprs()
+        .filter_map(|(_, kind)| match kind {
+            Expr::Literal(Literal::Path(path)) => Some(path.to_vfs_path(db)?.1),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    ret.sort_by(|lhs, rhs| lhs.as_str().cmp(rhs.as_str()));
+    ret.dedup();
+    ret
+}
+
+/// Other files in the same source root referencing `file`, ordered by their sites.
+pub(crate) fn file_importers(db: &dyn DefDatabase, file: FileId) -> Vec<FileImport> {
+    let root = db.source_root(db.file_source_root(file));
+    let mut ret = root
+        .iter()
+        .filter(|&(importer, _)| importer != file && db.module_references(importer).contains(&file))
+        .flat_map(|(importer, _)| file_imports(db, importer))
+        .filter(|import| import.target == file)
+        .collect::<Vec<_>>();
+    ret.sort_by_key(|import| (import.site.file_id.0, import.site.range.start()));
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::base::SourceDatabase;
+    use crate::tests::TestDB;
+    use expect_test::{expect, Expect};
+
+    const FIXTURE: &str = "
+#- /default.nix
+{ callPackage }: {
+  foo = import ./foo.nix;
+  bar = callPackage ./bar { };
+  baz = import ./baz.nix;
+}
+
+#- /foo.nix
+import ./bar/default.nix
+
+#- /bar/default.nix
+./.
+    ";
+
+    fn render(db: &TestDB, imports: Vec<super::FileImport>) -> String {
+        imports
+            .into_iter()
+            .map(|import| {
+                let site = import.site;
+                let src = db.file_content(site.file_id);
+                let root = db.source_root(db.file_source_root(import.target));
+                format!(
+                    "{} {} -> {}\n",
+                    root.path_for_file(site.file_id).as_str(),
+                    &src[site.range],
+                    root.path_for_file(import.target).as_str(),
+                )
+            })
+            .collect()
+    }
+
+    fn check_imports(path: &str, expect: Expect) {
+        let (db, f) = TestDB::from_fixture(FIXTURE).unwrap();
+        let imports = super::file_imports(&db, f[path]);
+        expect.assert_eq(&render(&db, imports));
+    }
+
+    fn check_importers(path: &str, expect: Expect) {
+        let (db, f) = TestDB::from_fixture(FIXTURE).unwrap();
+        let imports = super::file_importers(&db, f[path]);
+        expect.assert_eq(&render(&db, imports));
+    }
+
+    #[test]
+    fn imports() {
+        check_imports(
+            "/default.nix",
+            expect![[r#"
+            /default.nix ./foo.nix -> /foo.nix
+            /default.nix ./bar -> /bar/default.nix
+        "#]],
+        );
+        check_imports(
+            "/foo.nix",
+            expect![[r#"
+            /foo.nix ./bar/default.nix -> /bar/default.nix
+        "#]],
+        );
+        check_imports(
+            "/bar/default.nix",
+            expect![[r#"
+            /bar/default.nix ./. -> /bar/default.nix
+        "#]],
+        );
+    }
+
+    #[test]
+    fn importers() {
+        check_importers("/default.nix", expect![]);
+        check_importers(
+            "/foo.nix",
+            expect![[r#"
+            /default.nix ./foo.nix -> /foo.nix
+        "#]],
+        );
+        check_importers(
+            "/bar/default.nix",
+            expect![[r#"
+            /default.nix ./bar -> /bar/default.nix
+            /foo.nix ./bar/default.nix -> /bar/default.nix
+        "#]],
+        );
+    }
+
+    #[test]
+    fn referenced_paths() {
+        let (db, f) = TestDB::from_fixture(FIXTURE).unwrap();
+        let paths = super::referenced_paths(&db, f["/default.nix"])
+            .iter()
+            .map(|path| format!("{}\n", path.as_str()))
+            .collect::<String>();
+        expect![[r#"
+            /bar
+            /baz.nix
+            /foo.nix
+        "#]]
+        .assert_eq(&paths);
+    }
+}
@@ -0,0 +1,145 @@
+use crate::{DefDatabase, FileRange};
+use rowan::ast::AstNode;
+use rowan::{NodeOrToken, TextRange, WalkEvent};
+use smol_str::SmolStr;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use syntax::{ast, SyntaxKind, SyntaxNode, SyntaxToken};
+
+/// Expressions with fewer tokens than this are too trivial to match, like a single name.
+const MIN_TOKENS: usize = 2;
+
+/// Find all expressions in the same file which are structurally equal to the expression
+/// selected by `range`, ignoring whitespaces and comments.
+/// The selected expression itself is also included. Results are ordered by their positions.
+pub(crate) fn find_similar(
+    db: &dyn DefDatabase,
+    FileRange { file_id, range }: FileRange,
+) -> Option<Vec<FileRange>> {
+    let parse = db.parse(file_id);
+    let root = parse.syntax_node();
+    let node = match root.covering_element(range) {
+        NodeOrToken::Node(node) => node,
+        NodeOrToken::Token(tok) => tok.parent()?,
+    };
+    let target = node
+        .ancestors()
+        .find(|node| ast::Expr::can_cast(node.kind()))?;
+    if significant_tokens(&target).count() < MIN_TOKENS {
+        return None;
+    }
+
+    let target_hash = structural_hash(&target);
+    let ret = root
+        .descendants()
+        .filter(|node| node.kind() == target.kind())
+        .filter(|node| structural_hash(node) == target_hash && structurally_eq(node, &target))
+        .filter_map(|node| Some(FileRange::new(file_id, trimmed_range(&node)?)))
+        .collect();
+    Some(ret)
+}
+
+/// Tokens except whitespaces and comments.
+fn significant_tokens(node: &SyntaxNode) -> impl Iterator<Item = SyntaxToken> {
+    node.descendants_with_tokens()
+        .filter_map(NodeOrToken::into_token)
+        .filter(|tok| !tok.kind().is_whitespace())
+}
+
+fn trimmed_range(node: &SyntaxNode) -> Option<TextRange> {
+    let mut toks = significant_tokens(node);
+    let first = toks.next()?;
+    let last = toks.last().unwrap_or_else(|| first.clone());
+    Some(first.text_range().cover(last.text_range()))
+}
+
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum ShapeEvent {
+    Enter(SyntaxKind),
+    Token(SyntaxKind, SmolStr),
+    // Mark the end of children, so that different nestings are distinguished.
+    Leave,
+}
+
+/// The shape of the subtree, ignoring whitespaces and comments.
+fn shape(node: &SyntaxNode) -> impl Iterator<Item = ShapeEvent> {
+    node.preorder_with_tokens().filter_map(|event| match event {
+        WalkEvent::Enter(NodeOrToken::Node(node)) => Some(ShapeEvent::Enter(node.kind())),
+        WalkEvent::Enter(NodeOrToken::Token(tok)) if !tok.kind().is_whitespace() => {
+            Some(ShapeEvent::Token(tok.kind(), tok.text().into()))
+        }
+        WalkEvent::Leave(NodeOrToken::Node(_)) => Some(ShapeEvent::Leave),
+        _ => None,
+    })
+}
+
+/// Structurally equal subtrees have the same hash.
+fn structural_hash(node: &SyntaxNode) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    shape(node).for_each(|event| event.hash(&mut hasher));
+    hasher.finish()
+}
+
+fn structurally_eq(lhs: &SyntaxNode, rhs: &SyntaxNode) -> bool {
+    shape(lhs).eq(shape(rhs))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::base::SourceDatabase;
+    use crate::tests::TestDB;
+    use crate::FileRange;
+    use expect_test::{expect, Expect};
+    use rowan::TextRange;
+
+    #[track_caller]
+    fn check(fixture: &str, expect: Expect) {
+        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        let frange = FileRange::new(f[0].file_id, TextRange::new(f[0].pos, f[1].pos));
+        let src = db.file_content(frange.file_id);
+        let got = super::find_similar(&db, frange)
+            .into_iter()
+            .flatten()
+            .map(|frange| format!("{:?}: {}\n", frange.range, &src[frange.range]))
+            .collect::<String>();
+        expect.assert_eq(&got);
+    }
+
+    #[test]
+    fn attrset() {
+        check(
+            "[ $0{ a = 1; b = x; }$1 { a = 1; b = x; } {a=1;b=x;} { a = 1; /* c */ b = x; } { b = x; a = 1; } ]",
+            expect![[r#"
+                2..19: { a = 1; b = x; }
+                20..37: { a = 1; b = x; }
+                38..48: {a=1;b=x;}
+                49..74: { a = 1; /* c */ b = x; }
+            "#]],
+        );
+    }
+
+    #[test]
+    fn apply() {
+        check(
+            "let f = x: x; in [ ($0f f 1$1) (f f 1) (f (f 1)) (f f 2) ((f f) 1) ]",
+            expect![[r#"
+                20..25: f f 1
+                28..33: f f 1
+            "#]],
+        );
+        check(
+            "let f = x: x; in { a = $0f 1$1; b = f   1; c = f 1 + 1; }",
+            expect![[r#"
+                23..26: f 1
+                32..37: f   1
+                43..46: f 1
+            "#]],
+        );
+    }
+
+    #[test]
+    fn trivial() {
+        check("[ $0a$1 a a ]", expect![]);
+        check("[ $01$1 1 ]", expect![]);
+    }
+}
@@ -0,0 +1,203 @@
+use crate::{DefDatabase, FileId};
+use rowan::{NodeOrToken, TextRange};
+use syntax::{SyntaxKind, SyntaxNode, T};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FoldingRange {
+    pub range: TextRange,
+    pub kind: FoldingRangeKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FoldingRangeKind {
+    Region,
+    Comment,
+}
+
+pub(crate) fn folding_ranges(db: &dyn DefDatabase, file: FileId) -> Vec<FoldingRange> {
+    let parse = db.parse(file);
+    let src = db.file_content(file);
+    let mut ret = Vec::new();
+    let mut push = |range: TextRange, kind: FoldingRangeKind| {
+        if src[range].contains('\n') {
+            ret.push(FoldingRange { range, kind });
+        }
+    };
+
+    // Consecutive line comments are folded together.
+    let mut comments: Option<TextRange> = None;
+    for elem in parse.syntax_node().descendants_with_tokens() {
+        let tok = match elem {
+            NodeOrToken::Node(node) => {
+                if let Some(range) = node_fold_range(&node) {
+                    push(range, FoldingRangeKind::Region);
+                }
+                continue;
+            }
+            NodeOrToken::Token(tok) => tok,
+        };
+        match tok.kind() {
+            SyntaxKind::COMMENT if tok.text().starts_with('#') => {
+                comments = Some(match comments {
+                    Some(range) => range.cover(tok.text_range()),
+                    None => tok.text_range(),
+                });
+            }
+            SyntaxKind::SPACE if tok.text().matches('\n').count() <= 1 => {}
+            kind => {
+                if let Some(range) = comments.take() {
+                    push(range, FoldingRangeKind::Comment);
+                }
+                // Block comments.
+                if kind == SyntaxKind::COMMENT {
+                    push(tok.text_range(), FoldingRangeKind::Comment);
+                }
+            }
+        }
+    }
+    if let Some(range) = comments {
+        push(range, FoldingRangeKind::Comment);
+    }
+    ret
+}
+
+/// The foldable range of a node, like from `{` to `}` of attrsets and lambda patterns,
+/// or from `let` to `in`.
+fn node_fold_range(node: &SyntaxNode) -> Option<TextRange> {
+    let (open, close) = match node.kind() {
+        SyntaxKind::ATTR_SET | SyntaxKind::PAT => (T!['{'], T!['}']),
+        SyntaxKind::LIST => (T!['['], T![']']),
+        SyntaxKind::LET_IN => (T![let], T![in]),
+        SyntaxKind::STRING | SyntaxKind::INDENT_STRING => return Some(node.text_range()),
+        _ => return None,
+    };
+    let mut toks = node
+        .children_with_tokens()
+        .filter_map(NodeOrToken::into_token);
+    let open = toks.find(|tok| tok.kind() == open)?;
+    let close = toks.filter(|tok| tok.kind() == close).last()?;
+    Some(open.text_range().cover(close.text_range()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::base::SourceDatabase;
+    use crate::tests::TestDB;
+    use expect_test::{expect, Expect};
+
+    fn check(fixture: &str, expect: Expect) {
+        let (db, file) = TestDB::single_file(fixture).unwrap();
+        let src = db.file_content(file);
+        let got = super::folding_ranges(&db, file)
+            .into_iter()
+            .map(|fold| format!("{:?} {:?}\n", fold.kind, &src[fold.range]))
+            .collect::<String>();
+        expect.assert_eq(&got);
+    }
+
+    #[test]
+    fn single_line_pattern() {
+        check("{ a, b, ... }: a", expect![""]);
+        check("{ }: 1", expect![""]);
+    }
+
+    #[test]
+    fn multi_line_pattern() {
+        check(
+            "
+{ a
+, b ? 1
+, ...
+}@args: a
+            ",
+            expect![[r#"
+                Region "{ a\n, b ? 1\n, ...\n}"
+            "#]],
+        );
+    }
+
+    #[test]
+    fn nested_pattern() {
+        check(
+            "
+{
+  a,
+  b,
+}:
+{ c }: {
+  f = { d,
+        e }: d;
+}
+            ",
+            expect![[r#"
+                Region "{\n  a,\n  b,\n}"
+                Region "{\n  f = { d,\n        e }: d;\n}"
+                Region "{ d,\n        e }"
+            "#]],
+        );
+    }
+
+    #[test]
+    fn attrset_list_let() {
+        check("let a = { b = [ 1 ]; }; in a", expect![""]);
+        check(
+            "
+let
+  a = rec {
+    b = [
+      1
+    ];
+  };
+in a
+            ",
+            expect![[r#"
+                Region "let\n  a = rec {\n    b = [\n      1\n    ];\n  };\nin"
+                Region "{\n    b = [\n      1\n    ];\n  }"
+                Region "[\n      1\n    ]"
+            "#]],
+        );
+    }
+
+    #[test]
+    fn string() {
+        check(r#"["a" ''b'']"#, expect![""]);
+        check(
+            r#"
+{
+  a = "
+    1
+  ";
+  b = ''
+    2
+  '';
+}
+            "#,
+            expect![[r#"
+                Region "{\n  a = \"\n    1\n  \";\n  b = ''\n    2\n  '';\n}"
+                Region "\"\n    1\n  \""
+                Region "''\n    2\n  ''"
+            "#]],
+        );
+    }
+
+    #[test]
+    fn comment() {
+        check("# a\n1 # b\n/* c */ 2", expect![""]);
+        check(
+            "
+# a
+# b
+
+# c
+1
+/*
+ * d
+ */
+            ",
+            expect![[r##"
+                Comment "# a\n# b"
+                Comment "/*\n * d\n */"
+            "##]],
+        );
+    }
+}
@@ -0,0 +1,124 @@
+use crate::def::{Expr, ResolveResult};
+use crate::{DefDatabase, FileRange};
+
+/// Names used in `range` but not bound inside it, with the ranges of their uses.
+/// Names from `with`s outside the range and undefined names are included, but builtins are not.
+/// Results are ordered by their first uses.
+pub(crate) fn free_variables(
+    db: &dyn DefDatabase,
+    FileRange { file_id, range }: FileRange,
+) -> Vec<(String, Vec<FileRange>)> {
+    let module = db.module(file_id);
+    let source_map = db.source_map(file_id);
+    let name_res = db.name_resolution(file_id);
+
+    let mut uses = module
+        .exprs()
+        .filter_map(|(expr, kind)| {
+            let text = match kind {
+                Expr::Reference(text) => text,
+                _ => return None,
+            };
+            let use_range = source_map.node_for_expr(expr)?.text_range();
+            if !range.contains_range(use_range) {
+                return None;
+            }
+            let is_bound_inside = match name_res.get(expr) {
+                Some(ResolveResult::Builtin(_)) => return None,
+                Some(ResolveResult::Definition(name)) => source_map
+                    .nodes_for_name(*name)
+                    .any(|ptr| range.contains_range(ptr.text_range())),
+                // The innermost `with` decides where the name comes from.
+                Some(ResolveResult::WithExprs(withs)) => matches!(
+                    withs.first().and_then(|&with| source_map.node_for_expr(with)),
+                    Some(ptr) if range.contains_range(ptr.text_range())
+                ),
+                None => false,
+            };
+            (!is_bound_inside).then_some((text, use_range))
+        })
+        .collect::<Vec<_>>();
+    uses.sort_by_key(|(_, use_range)| use_range.start());
+
+    let mut ret: Vec<(String, Vec<FileRange>)> = Vec::new();
+    for (text, use_range) in uses {
+        let frange = FileRange::new(file_id, use_range);
+        match ret.iter_mut().find(|(name, _)| name == text) {
+            Some((_, franges)) => franges.push(frange),
+            None => ret.push((text.to_string(), vec![frange])),
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::base::SourceDatabase;
+    use crate::tests::TestDB;
+    use crate::FileRange;
+    use expect_test::{expect, Expect};
+    use rowan::TextRange;
+
+    #[track_caller]
+    fn check(fixture: &str, expect: Expect) {
+        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        let frange = FileRange::new(f[0].file_id, TextRange::new(f[0].pos, f[1].pos));
+        let src = db.file_content(frange.file_id);
+        let mut got = String::new();
+        for (name, franges) in super::free_variables(&db, frange) {
+            got += &name;
+            for frange in franges {
+                assert_eq!(&src[frange.range], name);
+                got += &format!(" {:?}", frange.range);
+            }
+            got += "\n";
+        }
+        expect.assert_eq(&got);
+    }
+
+    #[test]
+    fn selection() {
+        check(
+            "a: let b = 1; c = 2; in $0let d = a + b; in [ a d c.x e builtins.map ]$1",
+            expect![[r#"
+                a 32..33 44..45
+                b 36..37
+                c 48..49
+                e 52..53
+            "#]],
+        );
+    }
+
+    #[test]
+    fn lambda() {
+        check(
+            "f: $0x: { y ? x }: f x y z$1",
+            expect![[r#"
+                f 17..18
+                z 23..24
+            "#]],
+        );
+    }
+
+    #[test]
+    fn with() {
+        check(
+            "with a; [ $0b (with c; d)$1 ]",
+            expect![[r#"
+                b 10..11
+                c 18..19
+            "#]],
+        );
+    }
+
+    #[test]
+    fn inherit() {
+        check(
+            "let a = 1; in $0{ inherit a; b = rec { inherit a; c = b; }; }$1",
+            expect![[r#"
+                a 24..25 45..46
+                b 52..53
+            "#]],
+        );
+    }
+}
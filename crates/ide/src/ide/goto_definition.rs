@@ -1,9 +1,11 @@
 use super::NavigationTarget;
-use crate::def::{AstPtr, Expr, Literal, ResolveResult};
-use crate::{DefDatabase, FilePos};
+use crate::def::{
+    AstPtr, BinaryOp, BindingValue, Expr, ExprId, Literal, NameId, NameResolution, ResolveResult,
+};
+use crate::{DefDatabase, FileId, FilePos, Module};
 use rowan::ast::AstNode;
 use rowan::{TextRange, TextSize};
-use syntax::{ast, best_token_at_offset, match_ast, SyntaxKind, T};
+use syntax::{ast, best_token_at_offset, match_ast, SyntaxKind, SyntaxNode, T};
 
 pub(crate) fn goto_definition(
     db: &dyn DefDatabase,
@@ -45,24 +47,28 @@ pub(crate) fn goto_definition(
     }
 
     let name_res = db.name_resolution(file_id);
-    match name_res.get(expr_id)? {
-        &ResolveResult::Definition(name) => {
+    let module = db.module(file_id);
+    let root = parse.syntax_node();
+    let res = match name_res.get(expr_id) {
+        Some(res) => res,
+        None => {
+            // An attribute of `set.a.b`.
+            let name = select_source(&module, &name_res, expr_id)?;
             let targets = source_map
                 .nodes_for_name(name)
-                .filter_map(|ptr| {
-                    let name_node = ptr.to_node(&parse.syntax_node());
-                    let full_node = name_node.ancestors().find(|n| {
-                        matches!(
-                            n.kind(),
-                            SyntaxKind::LAMBDA | SyntaxKind::ATTR_PATH_VALUE | SyntaxKind::INHERIT
-                        )
-                    })?;
-                    Some(NavigationTarget {
-                        file_id,
-                        focus_range: name_node.text_range(),
-                        full_range: full_node.text_range(),
-                    })
-                })
+                .filter_map(|ptr| name_target(file_id, &root, ptr))
+                .collect();
+            return Some(targets);
+        }
+    };
+    match res {
+        &ResolveResult::Definition(name) => {
+            // For `inherit (from) name`, also go to `name` in `from` if it's statically known.
+            let source_name = inherit_from_source(&module, &name_res, name);
+            let targets = std::iter::once(name)
+                .chain(source_name)
+                .flat_map(|name| source_map.nodes_for_name(name))
+                .filter_map(|ptr| name_target(file_id, &root, ptr))
                 .collect();
             Some(targets)
         }
@@ -97,6 +103,162 @@ pub(crate) fn goto_definition(
     }
 }
 
+fn name_target(file_id: FileId, root: &SyntaxNode, ptr: AstPtr) -> Option<NavigationTarget> {
+    let name_node = ptr.to_node(root);
+    let full_node = name_node.ancestors().find(|n| {
+        matches!(
+            n.kind(),
+            SyntaxKind::LAMBDA | SyntaxKind::ATTR_PATH_VALUE | SyntaxKind::INHERIT
+        )
+    })?;
+    Some(NavigationTarget {
+        file_id,
+        focus_range: name_node.text_range(),
+        full_range: full_node.text_range(),
+    })
+}
+
+/// Find the field defining the attribute `attr` in `set.a.b`,
+/// where `set` is statically known attrsets possibly merged by `//`.
+pub(crate) fn select_source(
+    module: &Module,
+    name_res: &NameResolution,
+    attr: ExprId,
+) -> Option<NameId> {
+    let (set, path) = module.exprs().find_map(|(_, kind)| match kind {
+        Expr::Select(set, path, _) if path.contains(&attr) => Some((*set, path)),
+        _ => None,
+    })?;
+    let idx = path.iter().position(|&key| key == attr)?;
+    select_path_sources(module, name_res, set, path)
+        .into_iter()
+        .nth(idx)
+}
+
+/// Find all attributes in `set.a.b` selecting the field `name` of a statically known attrset.
+pub(crate) fn select_references(
+    module: &Module,
+    name_res: &NameResolution,
+    name: NameId,
+) -> Vec<ExprId> {
+    module
+        .exprs()
+        .filter_map(|(_, kind)| match kind {
+            Expr::Select(set, path, _) => Some((*set, path)),
+            _ => None,
+        })
+        .flat_map(|(set, path)| {
+            path.iter()
+                .zip(select_path_sources(module, name_res, set, path))
+                .filter(|&(_, field)| field == name)
+                .map(|(&key, _)| key)
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Find the field defining each attribute of the attrpath `path` selected from `set`.
+/// Since `//` is shallow and right-biased, it is the one in the rightmost operand defining it.
+/// It stops at the first dynamic or unknown attribute.
+fn select_path_sources(
+    module: &Module,
+    name_res: &NameResolution,
+    set: ExprId,
+    path: &[ExprId],
+) -> Vec<NameId> {
+    let mut ret = Vec::new();
+    let mut sets = Vec::new();
+    merged_attrsets(module, name_res, set, 0, &mut sets);
+    for &key in path {
+        let text = match &module[key] {
+            Expr::Literal(Literal::String(text)) => text,
+            _ => break,
+        };
+        let (field, value) = match sets
+            .iter()
+            .rev()
+            .filter_map(|&set| module[set].bindings())
+            .find_map(|bindings| {
+                bindings
+                    .statics
+                    .iter()
+                    .find(|&&(field, _)| module[field].text == *text)
+            }) {
+            Some(&(field, value)) => (field, value),
+            None => break,
+        };
+        sets.clear();
+        if let BindingValue::Expr(value) = value {
+            merged_attrsets(module, name_res, value, 0, &mut sets);
+        }
+        ret.push(field);
+    }
+    ret
+}
+
+/// Collect statically known attrsets merged into `expr` by `//`, following references
+/// to `let` bindings or attrset fields, up to a limited depth.
+pub(crate) fn merged_attrsets(
+    module: &Module,
+    name_res: &NameResolution,
+    expr: ExprId,
+    depth: usize,
+    out: &mut Vec<ExprId>,
+) {
+    const MAX_DEPTH: usize = 8;
+
+    if depth >= MAX_DEPTH {
+        return;
+    }
+    match &module[expr] {
+        Expr::Attrset(_) | Expr::RecAttrset(_) => out.push(expr),
+        &Expr::Binary(Some(BinaryOp::Update), lhs, rhs) => {
+            merged_attrsets(module, name_res, lhs, depth + 1, out);
+            merged_attrsets(module, name_res, rhs, depth + 1, out);
+        }
+        Expr::Reference(_) => {
+            if let Some(&ResolveResult::Definition(def)) = name_res.get(expr) {
+                if let Some(BindingValue::Expr(value)) = module.binding_value(def) {
+                    merged_attrsets(module, name_res, value, depth + 1, out);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Find the definition of `name` in the attrset `from` of `inherit (from) name`.
+/// References to `let` bindings or attrset fields are followed, up to a limited depth.
+fn inherit_from_source(module: &Module, name_res: &NameResolution, name: NameId) -> Option<NameId> {
+    const MAX_DEPTH: usize = 8;
+
+    let mut from = match module.binding_value(name)? {
+        BindingValue::InheritFrom(from) => from,
+        BindingValue::Inherit(_) | BindingValue::Expr(_) => return None,
+    };
+    for _ in 0..MAX_DEPTH {
+        match &module[from] {
+            Expr::Attrset(bindings) | Expr::RecAttrset(bindings) => {
+                let text = &module[name].text;
+                return bindings
+                    .statics
+                    .iter()
+                    .map(|&(field, _)| field)
+                    .find(|&field| module[field].text == *text);
+            }
+            Expr::Reference(_) => match name_res.get(from)? {
+                &ResolveResult::Definition(def) => match module.binding_value(def)? {
+                    BindingValue::Expr(value) => from = value,
+                    BindingValue::Inherit(_) | BindingValue::InheritFrom(_) => return None,
+                },
+                ResolveResult::Builtin(_) | ResolveResult::WithExprs(_) => return None,
+            },
+            _ => return None,
+        }
+    }
+    None
+}
+
 #[cfg(test)]
 mod tests {
     use crate::base::SourceDatabase;
@@ -175,6 +337,13 @@ mod tests {
         );
     }
 
+    #[test]
+    fn self_reference() {
+        check("rec { a = 1; b = $0a + 1; }", expect!["<a> = 1;"]);
+        check("rec { a = { b = $0a; }; }", expect!["<a> = { b = a; };"]);
+        check("let f = x: $0f x; in f", expect!["<f> = x: f x;"]);
+    }
+
     #[test]
     fn left_and_right() {
         check("let a = 1; in $0a ", expect!["<a> = 1;"]);
@@ -202,6 +371,39 @@ mod tests {
         );
     }
 
+    #[test]
+    fn merged_select() {
+        check("({ a.b = 1; } // { a.c = 2; }).$0a", expect!["<a>.c = 2;"]);
+        check(
+            "let x = { a = 1; b = 2; }; y = x // { a = 3; }; in y.$0a",
+            expect!["<a> = 3;"],
+        );
+        check(
+            "let x = { a = 1; b = 2; }; y = x // { a = 3; }; in y.$0b",
+            expect!["<b> = 2;"],
+        );
+        check("({ a = 1; } // { b = 2; }).$0b", expect!["<b> = 2;"]);
+        check("({ a = 1; } // f).$0a", expect!["<a> = 1;"]);
+        check("f.$0a", expect![]);
+    }
+
+    #[test]
+    fn merged_select_nested() {
+        check(
+            "let x = { a.b.c = 1; a.b.d = 2; } // { a = { b = { e = 3; }; }; }; in x.a.$0b",
+            expect!["<b> = { e = 3; };"],
+        );
+        // `a` is the right operand only.
+        check(
+            "let x = { a.b.c = 1; a.b.d = 2; } // { a = { b = { e = 3; }; }; }; in x.a.b.$0c",
+            expect![""],
+        );
+        check(
+            "let x = { a.b = 1; } // { a.b = 2; a.c = 3; }; in x.a.$0b",
+            expect!["a.<b> = 2;"],
+        );
+    }
+
     #[test]
     fn builtin() {
         check("let true = 1; in $0true && false", expect!["<true> = 1;"]);
@@ -222,4 +424,79 @@ hello
             expect!["<>hello"],
         );
     }
+
+    #[test]
+    fn inherit_from() {
+        // Single hop: the source is unknown.
+        check(
+            "{ pkgs }: let inherit (pkgs) hello; in $0hello",
+            expect!["inherit (pkgs) <hello>;"],
+        );
+        // Double hop.
+        check(
+            "let inherit ({ hello = 1; }) hello; in $0hello",
+            expect![[r#"
+                inherit ({ hello = 1; }) <hello>;
+                <hello> = 1;
+            "#]],
+        );
+        check(
+            "let pkgs = { hello = 1; }; inherit (pkgs) hello; in $0hello",
+            expect![[r#"
+                inherit (pkgs) <hello>;
+                <hello> = 1;
+            "#]],
+        );
+        check(
+            "let pkgs = top; top = rec { hello = 1; }; inherit (pkgs) hello; in $0hello",
+            expect![[r#"
+                inherit (pkgs) <hello>;
+                <hello> = 1;
+            "#]],
+        );
+        // Missing in the source.
+        check(
+            "let pkgs = { }; inherit (pkgs) hello; in $0hello",
+            expect!["inherit (pkgs) <hello>;"],
+        );
+        // Cyclic references.
+        check(
+            "let a = b; b = a; inherit (a) hello; in $0hello",
+            expect!["inherit (a) <hello>;"],
+        );
+    }
+
+    #[test]
+    fn path_directory() {
+        check(
+            "
+#- /default.nix
+import $0./foo
+
+#- /foo/default.nix
+hello
+            ",
+            expect!["<>hello"],
+        );
+        check(
+            "
+#- /default.nix
+import $0./foo/..
+
+#- /foo/bar.nix
+hello
+            ",
+            expect!["<>import ./foo/.."],
+        );
+        check(
+            "
+#- /default.nix
+import $0./foo
+
+#- /foo/bar.nix
+hello
+            ",
+            expect![],
+        );
+    }
 }
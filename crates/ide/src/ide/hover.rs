@@ -1,10 +1,15 @@
-use crate::def::{AstPtr, Expr, ResolveResult};
-use crate::{DefDatabase, FilePos, NameKind};
+use super::enclosing_attrpath::attrpath_segments;
+use crate::def::{
+    AstPtr, BindingValue, Bindings, Expr, Literal, LivenessCheckResult, NameId, NameResolution,
+    Path, ResolveResult,
+};
+use crate::{DefDatabase, FileId, FilePos, Module, NameKind};
 use builtin::ALL_BUILTINS;
 use rowan::ast::AstNode;
 use rowan::TextRange;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Write;
-use syntax::{ast, best_token_at_offset, match_ast};
+use syntax::{ast, best_token_at_offset, match_ast, SyntaxKind, SyntaxNode, T};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct HoverResult {
@@ -18,6 +23,31 @@ pub(crate) fn hover(
 ) -> Option<HoverResult> {
     let parse = db.parse(file_id);
     let tok = best_token_at_offset(&parse.syntax_node(), pos)?;
+
+    // The header comment of the file.
+    if tok.kind() == SyntaxKind::COMMENT {
+        let (range, markup) = module_doc(&parse.syntax_node())?;
+        return range
+            .contains_range(tok.text_range())
+            .then_some(HoverResult { range, markup });
+    }
+
+    // The opening token of an attrset or a `let`.
+    if matches!(tok.kind(), T!['{'] | T![rec] | T![let]) {
+        let node = tok.parent()?;
+        if matches!(node.kind(), SyntaxKind::ATTR_SET | SyntaxKind::LET_IN) {
+            let module = db.module(file_id);
+            let source_map = db.source_map(file_id);
+            let liveness = db.liveness_check(file_id);
+            let expr = source_map.expr_for_node(AstPtr::new(&node))?;
+            let markup = bindings_summary(&liveness, &module[expr])?;
+            return Some(HoverResult {
+                range: tok.text_range(),
+                markup,
+            });
+        }
+    }
+
     let ptr = tok.parent_ancestors().find_map(|node| {
         match_ast! {
             match node {
@@ -38,6 +68,10 @@ pub(crate) fn hover(
     let mut name = None;
 
     if let Some(expr) = source_map.expr_for_node(ptr.clone()) {
+        if let Expr::Literal(Literal::Path(path)) = &module[expr] {
+            let markup = path_target(db, *path)?;
+            return Some(HoverResult { range, markup });
+        }
         match nameres.get(expr) {
             None => {}
             Some(ResolveResult::Builtin(name)) => {
@@ -83,15 +117,265 @@ pub(crate) fn hover(
             NameKind::Param => "Parameter",
             NameKind::PatField => "Field parameter",
         };
-        return Some(HoverResult {
-            range,
-            markup: format!("{kind} `{text}`"),
-        });
+        // Show the full path for attribute keys in nested attrsets.
+        let is_key = source_map.name_for_node(ptr) == Some(name);
+        let path = match module[name].kind {
+            NameKind::PlainAttrset | NameKind::RecAttrset if is_key => {
+                attrpath_segments(&tok, pos).map(|segments| render_attrpath(&segments))
+            }
+            _ => None,
+        };
+        let text = path.as_deref().unwrap_or(text);
+        let mut markup = format!("{kind} `{text}`");
+        if is_recursive(&module, &nameres, name) {
+            markup += " (recursive)";
+        }
+        if module[name].kind == NameKind::PatField {
+            match pat_field_default(&parse.syntax_node(), source_map.nodes_for_name(name)) {
+                Some(default) if !default.contains('\n') => {
+                    write!(markup, " (default: `{default}`)").unwrap();
+                }
+                Some(default) => write!(markup, " (default)\n\n```nix\n{default}\n```").unwrap(),
+                None => markup += " (required)",
+            }
+        }
+        // Distinguish parameters from other bindings at their uses.
+        if !is_key && matches!(module[name].kind, NameKind::Param | NameKind::PatField) {
+            if let Some(ctx) =
+                lambda_context(&parse.syntax_node(), &src, source_map.nodes_for_name(name))
+            {
+                write!(markup, "\n\n{ctx}").unwrap();
+            }
+        }
+        if let Some(BindingValue::Expr(value)) = module.binding_value(name) {
+            let liveness = db.liveness_check(file_id);
+            if let Some(summary) = bindings_summary(&liveness, &module[value]) {
+                write!(markup, "\n\n{summary}").unwrap();
+            }
+        }
+        return Some(HoverResult { range, markup });
     }
 
     None
 }
 
+/// The source text of the default value of a lambda pattern field, if any.
+fn pat_field_default(root: &SyntaxNode, mut ptrs: impl Iterator<Item = AstPtr>) -> Option<String> {
+    let node = ptrs.next()?.to_node(root);
+    let field = node.parent().and_then(ast::PatField::cast)?;
+    Some(field.default_expr()?.syntax().to_string())
+}
+
+/// Describe the lambda declaring a parameter, with its pattern if any.
+fn lambda_context(
+    root: &SyntaxNode,
+    src: &str,
+    mut ptrs: impl Iterator<Item = AstPtr>,
+) -> Option<String> {
+    let node = ptrs.next()?.to_node(root);
+    let lambda = node.ancestors().find_map(ast::Lambda::cast)?;
+    let param = lambda.param()?;
+    let line = src[..usize::from(lambda.syntax().text_range().start())]
+        .matches('\n')
+        .count()
+        + 1;
+    let mut ret = format!("Parameter of lambda at line {line}");
+    if let Some(pat) = param.pat() {
+        let mut fields = pat
+            .fields()
+            .filter_map(|field| {
+                let name = field.name()?.syntax().to_string();
+                Some(match field.default_expr() {
+                    Some(_) => format!("{name} ? ..."),
+                    None => name,
+                })
+            })
+            .collect::<Vec<_>>();
+        if pat.ellipsis_token().is_some() {
+            fields.push("...".into());
+        }
+        let at = match param.name() {
+            Some(name) => format!("{}@", name.syntax()),
+            None => String::new(),
+        };
+        write!(ret, ", from pattern `{at}{{ {} }}`", fields.join(", ")).unwrap();
+    }
+    Some(ret)
+}
+
+/// Render an attribute path like `a."b c".${...}`, with placeholders for dynamic keys.
+fn render_attrpath(segments: &[Option<String>]) -> String {
+    let mut ret = String::new();
+    for (i, seg) in segments.iter().enumerate() {
+        if i != 0 {
+            ret.push('.');
+        }
+        match seg {
+            None => ret.push_str("${...}"),
+            Some(seg) if is_valid_ident(seg) => ret.push_str(seg),
+            Some(seg) => write!(ret, "{:?}", seg).unwrap(),
+        }
+    }
+    ret
+}
+
+fn is_valid_ident(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '\'' | '-'))
+}
+
+/// Show the resolved target of a relative path literal, and whether it exists.
+/// The module documentation of the target file is also shown if any.
+fn path_target(db: &dyn DefDatabase, path: Path) -> Option<String> {
+    let (root, vpath) = path.to_vfs_path(db)?;
+    let markup = match path.resolve(db) {
+        Some(file) => {
+            let mut markup = format!("`{}` (exists)", root.path_for_file(file).as_str());
+            if let Some(doc) = file_module_doc(db, file) {
+                write!(markup, "\n\n---\n\n{doc}").unwrap();
+            }
+            markup
+        }
+        None => format!("`{}` (not found)", vpath.as_str()),
+    };
+    Some(markup)
+}
+
+fn file_module_doc(db: &dyn DefDatabase, file: FileId) -> Option<String> {
+    let parse = db.parse(file);
+    module_doc(&parse.syntax_node()).map(|(_, doc)| doc)
+}
+
+/// The leading consecutive comments of a file as Markdown, with their range.
+/// `#!` lines of `nix-shell` shebangs are skipped, and a blank line ends the block.
+fn module_doc(root: &SyntaxNode) -> Option<(TextRange, String)> {
+    let mut range: Option<TextRange> = None;
+    let mut lines = Vec::new();
+    let mut tok = root.first_token();
+    while let Some(cur) = tok {
+        match cur.kind() {
+            SyntaxKind::SPACE if range.is_some() && cur.text().matches('\n').count() > 1 => break,
+            SyntaxKind::SPACE => {}
+            SyntaxKind::COMMENT if cur.text().starts_with("#!") && range.is_none() => {}
+            SyntaxKind::COMMENT => {
+                let text = cur.text();
+                match text.strip_prefix('#') {
+                    Some(line) => lines.push(line.strip_prefix(' ').unwrap_or(line).to_owned()),
+                    None => lines.extend(block_comment_lines(text)),
+                }
+                range = Some(match range {
+                    Some(range) => range.cover(cur.text_range()),
+                    None => cur.text_range(),
+                });
+            }
+            _ => break,
+        }
+        tok = cur.next_token();
+    }
+
+    let doc = lines.join("\n");
+    let doc = doc.trim();
+    if doc.is_empty() {
+        return None;
+    }
+    Some((range?, doc.to_owned()))
+}
+
+/// The content lines of a block comment `/* ... */`, without the common indentation,
+/// and without leading `*` if every continuation line has one.
+fn block_comment_lines(text: &str) -> Vec<String> {
+    let text = text.trim_start_matches("/*").trim_end_matches("*/");
+    let mut lines = text.lines();
+    let first = lines.next().unwrap_or("").trim();
+    let mut rest = lines.collect::<Vec<_>>();
+    if rest
+        .iter()
+        .all(|line| line.trim().is_empty() || line.trim_start().starts_with('*'))
+    {
+        for line in &mut rest {
+            *line = line.trim_start().trim_start_matches('*');
+        }
+    }
+    let indent = rest
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    std::iter::once(first)
+        .chain(
+            rest.iter()
+                .map(|line| line.get(indent..).unwrap_or("").trim_end()),
+        )
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Summarize the number of bindings of an attrset or `let`, and how many of them are unused.
+fn bindings_summary(liveness: &LivenessCheckResult, expr: &Expr) -> Option<String> {
+    let (title, noun, bindings): (_, _, &Bindings) = match expr {
+        Expr::Attrset(bindings) => ("Attrset", "attribute", bindings),
+        Expr::RecAttrset(bindings) => ("Rec-attrset", "attribute", bindings),
+        Expr::LetAttrset(bindings) => ("Let-attrset", "attribute", bindings),
+        Expr::LetIn(bindings, _) => ("Let-in", "binding", bindings),
+        _ => return None,
+    };
+    let total = bindings.statics.len() + bindings.dynamics.len();
+    let unused = bindings
+        .statics
+        .iter()
+        .filter(|&&(name, _)| liveness.is_unused_name(name))
+        .count();
+    let plural = if total == 1 { "" } else { "s" };
+    let mut markup = format!("{title}\n- {total} {noun}{plural}");
+    if unused != 0 {
+        write!(markup, "\n- {unused} unused").unwrap();
+    }
+    Some(markup)
+}
+
+/// Check if the value of a `let` or `rec` binding references the binding itself,
+/// directly or through other `let` or `rec` bindings.
+fn is_recursive(module: &Module, name_res: &NameResolution, name: NameId) -> bool {
+    if !matches!(module[name].kind, NameKind::LetIn | NameKind::RecAttrset) {
+        return false;
+    }
+
+    let values = module
+        .exprs()
+        .filter_map(|(_, kind)| kind.bindings())
+        .flat_map(|bindings| bindings.statics.iter())
+        .map(|&(name, value)| {
+            let (BindingValue::Inherit(e) | BindingValue::InheritFrom(e) | BindingValue::Expr(e)) =
+                value;
+            (name, e)
+        })
+        .collect::<HashMap<_, _>>();
+
+    let mut visited = HashSet::new();
+    let mut stack = match values.get(&name) {
+        Some(&value) => vec![value],
+        None => return false,
+    };
+    while let Some(expr) = stack.pop() {
+        if let Expr::Reference(_) = &module[expr] {
+            if let Some(&ResolveResult::Definition(def)) = name_res.get(expr) {
+                if def == name {
+                    return true;
+                }
+                if matches!(module[def].kind, NameKind::LetIn | NameKind::RecAttrset)
+                    && visited.insert(def)
+                {
+                    stack.extend(values.get(&def).copied());
+                }
+            }
+        }
+        module[expr].walk_child_exprs(|e| stack.push(e));
+    }
+    false
+}
+
 #[cfg(test)]
 mod tests {
     use crate::base::SourceDatabase;
@@ -123,7 +407,7 @@ mod tests {
             expect!["Rec-attrset attribute `a`"],
         );
         check("$0a: a", "a", expect!["Parameter `a`"]);
-        check("{$0a}: a", "a", expect!["Field parameter `a`"]);
+        check("{$0a}: a", "a", expect!["Field parameter `a` (required)"]);
     }
 
     #[test]
@@ -139,8 +423,169 @@ mod tests {
             "a",
             expect!["Let binding `a`"],
         );
-        check("a: $0a", "a", expect!["Parameter `a`"]);
-        check("{a}: $0a", "a", expect!["Field parameter `a`"]);
+        check(
+            "a: $0a",
+            "a",
+            expect![[r#"
+            Parameter `a`
+
+            Parameter of lambda at line 1
+        "#]],
+        );
+        check(
+            "{a}: $0a",
+            "a",
+            expect![[r#"
+            Field parameter `a` (required)
+
+            Parameter of lambda at line 1, from pattern `{ a }`
+        "#]],
+        );
+    }
+
+    #[test]
+    fn lambda_param() {
+        check(
+            "let f = x: $0x; in f",
+            "x",
+            expect![[r#"
+                Parameter `x`
+
+                Parameter of lambda at line 1
+            "#]],
+        );
+        check(
+            "{\n  f = { a, b ? 1, ... }:\n    $0a;\n}",
+            "a",
+            expect![[r#"
+                Field parameter `a` (required)
+
+                Parameter of lambda at line 2, from pattern `{ a, b ? ..., ... }`
+            "#]],
+        );
+        check(
+            "args@{ a }: $0args",
+            "args",
+            expect![[r#"
+                Parameter `args`
+
+                Parameter of lambda at line 1, from pattern `args@{ a }`
+            "#]],
+        );
+    }
+
+    #[test]
+    fn pat_field_default() {
+        check(
+            "{ a, $0b ? 42 }: b",
+            "b",
+            expect!["Field parameter `b` (default: `42`)"],
+        );
+        check(
+            "{ a, b ? { c = 1; } }: $0b",
+            "b",
+            expect![[r#"
+                Field parameter `b` (default: `{ c = 1; }`)
+
+                Parameter of lambda at line 1, from pattern `{ a, b ? ... }`
+            "#]],
+        );
+        check(
+            "{ $0a, b ? 42 }: a",
+            "a",
+            expect!["Field parameter `a` (required)"],
+        );
+        check(
+            "{ $0a ? {\n  c = 1;\n} }: a",
+            "a",
+            expect![[r#"
+                Field parameter `a` (default)
+
+                ```nix
+                {
+                  c = 1;
+                }
+                ```
+            "#]],
+        );
+    }
+
+    #[test]
+    fn recursive() {
+        check(
+            "let $0f = x: f x; in f",
+            "f",
+            expect!["Let binding `f` (recursive)"],
+        );
+        check(
+            "let a = { inherit b; }; b = a.b; in $0a",
+            "a",
+            expect![[r#"
+                Let binding `a` (recursive)
+
+                Attrset
+                - 1 attribute
+            "#]],
+        );
+        check(
+            "rec { $0a = [ a ]; }",
+            "a",
+            expect!["Rec-attrset attribute `a` (recursive)"],
+        );
+        check(
+            "let a = 1; b = a + 1; in $0b",
+            "b",
+            expect!["Let binding `b`"],
+        );
+        check(
+            "let a = 1; in { $0a = a; }",
+            "a",
+            expect!["Attrset attribute `a`"],
+        );
+        check(
+            "let inherit ({ a = 1; }) $0a; in a",
+            "a",
+            expect!["Let binding `a`"],
+        );
+    }
+
+    #[test]
+    fn bindings_summary() {
+        check(
+            "$0{ a = 1; b.c = 2; ${x} = 3; }",
+            "{",
+            expect![[r#"
+                Attrset
+                - 3 attributes
+            "#]],
+        );
+        check(
+            "$0rec { a = 1; }",
+            "rec",
+            expect![[r#"
+                Rec-attrset
+                - 1 attribute
+            "#]],
+        );
+        check(
+            "$0let a = 1; b = 2; c = b; in c",
+            "let",
+            expect![[r#"
+                Let-in
+                - 3 bindings
+                - 1 unused
+            "#]],
+        );
+        check(
+            "let $0a = { b = 1; c = 2; }; in a",
+            "a",
+            expect![[r#"
+                Let binding `a`
+
+                Attrset
+                - 2 attributes
+            "#]],
+        );
     }
 
     #[test]
@@ -184,4 +629,122 @@ mod tests {
             "#]],
         );
     }
+
+    #[test]
+    fn attrpath() {
+        check(
+            "{ services.nginx = { virtualHosts.\"a.com\" = { $0root = 1; }; }; }",
+            "root",
+            expect![[r#"Attrset attribute `services.nginx.virtualHosts."a.com".root`"#]],
+        );
+        check(
+            "{ a = { b = { $0c = 1; }; }; }",
+            "c",
+            expect!["Attrset attribute `a.b.c`"],
+        );
+        check(
+            "{ a.$0b.c = 1; }",
+            "b",
+            expect![[r#"
+                Attrset attribute `a.b`
+
+                Attrset
+                - 1 attribute
+            "#]],
+        );
+        check(
+            "{ ${x}.a = rec { $0b = 1; }; }",
+            "b",
+            expect!["Rec-attrset attribute `${...}.a.b`"],
+        );
+        check("let a.$0b = 1; in a", "b", expect!["Attrset attribute `b`"]);
+    }
+
+    #[test]
+    fn path() {
+        let fixture = "
+#- /default.nix
+[ $0./lib/foo.nix ./lib ./lib/bar.nix ../foo.nix ]
+
+#- /lib/foo.nix
+1
+
+#- /lib/default.nix
+2
+        ";
+        check(fixture, "./lib/foo.nix", expect!["`/lib/foo.nix` (exists)"]);
+        let fixture = fixture.replace("$0", "").replacen("./lib ", "$0./lib ", 1);
+        check(&fixture, "./lib", expect!["`/lib/default.nix` (exists)"]);
+        let fixture = fixture
+            .replace("$0", "")
+            .replace("./lib/bar", "$0./lib/bar");
+        check(
+            &fixture,
+            "./lib/bar.nix",
+            expect!["`/lib/bar.nix` (not found)"],
+        );
+    }
+
+    #[test]
+    fn module_doc() {
+        check(
+            "#! nix-shell -i bash\n# The $0module.\n#\n# Details.\n\n# Unrelated.\n{ }",
+            "# The module.\n#\n# Details.",
+            expect![[r#"
+                The module.
+
+                Details.
+            "#]],
+        );
+        check(
+            "/* The\n * $0module.\n *   - item\n */\n{ }",
+            "/* The\n * module.\n *   - item\n */",
+            expect![[r#"
+                The
+                module.
+                  - item
+            "#]],
+        );
+        check(
+            "/*\n  Block\n    indented\n*/\n# Line $0comment.\n{ }",
+            "/*\n  Block\n    indented\n*/\n# Line comment.",
+            expect![[r#"
+                Block
+                  indented
+                Line comment.
+            "#]],
+        );
+    }
+
+    #[test]
+    fn no_module_doc() {
+        let (db, f) = TestDB::from_fixture("{ }\n# Not a $0header.").unwrap();
+        assert_eq!(super::hover(&db, f[0]), None);
+        let (db, f) = TestDB::from_fixture("{\n  # Not a $0header.\n}").unwrap();
+        assert_eq!(super::hover(&db, f[0]), None);
+        let (db, f) = TestDB::from_fixture("#$0\n{ }").unwrap();
+        assert_eq!(super::hover(&db, f[0]), None);
+    }
+
+    #[test]
+    fn path_module_doc() {
+        check(
+            "
+#- /default.nix
+import $0./foo.nix
+
+#- /foo.nix
+# Foo `module`.
+{ }
+            ",
+            "./foo.nix",
+            expect![[r#"
+                `/foo.nix` (exists)
+
+                ---
+
+                Foo `module`.
+            "#]],
+        );
+    }
 }
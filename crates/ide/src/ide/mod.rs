@@ -1,22 +1,45 @@
+mod assists;
+mod builtin_set;
 mod completion;
+mod def_use_graph;
 mod diagnostics;
+mod document_highlight;
+mod enclosing_attrpath;
 mod expand_selection;
+mod file_imports;
+mod find_similar;
+mod folding_ranges;
+mod free_variables;
 mod goto_definition;
 mod hover;
+mod name_resolution;
+mod parse_status;
 mod references;
 mod rename;
 mod syntax_highlighting;
 
-use crate::base::SourceDatabaseStorage;
+use crate::base::{SourceDatabase, SourceDatabaseStorage};
 use crate::def::DefDatabaseStorage;
-use crate::{Change, Diagnostic, FileId, FilePos, FileRange, WorkspaceEdit};
+use crate::{
+    Change, Diagnostic, FileId, FilePos, FileRange, Severity, SourceRoot, SourceRootId, VfsPath,
+    WorkspaceEdit,
+};
 use rowan::TextRange;
 use salsa::{Database, Durability, ParallelDatabase};
 use smol_str::SmolStr;
 use std::fmt;
-
-pub use completion::{CompletionItem, CompletionItemKind};
+use std::sync::Arc;
+
+pub use assists::{Assist, AssistKind, AssistsConfig};
+pub use builtin_set::{BuiltinSet, NixVersion};
+pub use completion::{CompletionConfig, CompletionItem, CompletionItemKind};
+pub use def_use_graph::{DefUseEdge, DefUseGraph, DefUseNode, DefUseNodeKind};
+pub use diagnostics::DiagnosticsConfig;
+pub use file_imports::FileImport;
+pub use folding_ranges::{FoldingRange, FoldingRangeKind};
 pub use hover::HoverResult;
+pub use name_resolution::{ResolvedName, ResolvedNameKind};
+pub use parse_status::ParseStatus;
 pub use syntax_highlighting::{HlKeyword, HlOperator, HlPunct, HlRange, HlTag};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -92,10 +115,73 @@ impl Analysis {
         Cancelled::catch(|| f(&self.db))
     }
 
+    pub fn enclosing_attrpath(&self, fpos: FilePos) -> Cancellable<Option<Vec<String>>> {
+        self.with_db(|db| enclosing_attrpath::enclosing_attrpath(db, fpos))
+    }
+
     pub fn expand_selection(&self, frange: FileRange) -> Cancellable<Option<Vec<TextRange>>> {
         self.with_db(|db| expand_selection::expand_selection(db, frange))
     }
 
+    pub fn enclosing_binding(&self, pos: FilePos) -> Cancellable<Option<TextRange>> {
+        self.with_db(|db| expand_selection::enclosing_binding(db, pos))
+    }
+
+    pub fn def_use_graph(&self, file: FileId) -> Cancellable<DefUseGraph> {
+        self.with_db(|db| def_use_graph::def_use_graph(db, file))
+    }
+
+    pub fn name_resolution(&self, file: FileId) -> Cancellable<Vec<ResolvedName>> {
+        self.with_db(|db| name_resolution::name_resolution(db, file))
+    }
+
+    pub fn parse_status(&self, file: FileId) -> Cancellable<ParseStatus> {
+        self.with_db(|db| parse_status::parse_status(db, file))
+    }
+
+    /// The source root containing `file`, and the entry file of that root.
+    pub fn source_root_for_file(
+        &self,
+        file: FileId,
+    ) -> Cancellable<(SourceRootId, Option<FileId>)> {
+        self.with_db(|db| {
+            let root = db.file_source_root(file);
+            (root, db.source_root(root).entry())
+        })
+    }
+
+    pub fn source_root(&self, id: SourceRootId) -> Cancellable<Arc<SourceRoot>> {
+        self.with_db(|db| db.source_root(id))
+    }
+
+    pub fn file_imports(&self, file: FileId) -> Cancellable<Vec<FileImport>> {
+        self.with_db(|db| file_imports::file_imports(db, file))
+    }
+
+    pub fn referenced_paths(&self, file: FileId) -> Cancellable<Vec<VfsPath>> {
+        self.with_db(|db| file_imports::referenced_paths(db, file))
+    }
+
+    pub fn file_importers(&self, file: FileId) -> Cancellable<Vec<FileImport>> {
+        self.with_db(|db| file_imports::file_importers(db, file))
+    }
+
+    pub fn find_similar(&self, frange: FileRange) -> Cancellable<Option<Vec<FileRange>>> {
+        self.with_db(|db| find_similar::find_similar(db, frange))
+    }
+
+    pub fn document_highlight(&self, fpos: FilePos) -> Cancellable<Option<Vec<TextRange>>> {
+        self.with_db(|db| document_highlight::document_highlight(db, fpos))
+    }
+
+    pub fn free_variables(&self, frange: FileRange) -> Cancellable<Vec<(String, Vec<FileRange>)>> {
+        self.with_db(|db| free_variables::free_variables(db, frange))
+    }
+
+    pub fn folding_ranges(&self, file: FileId) -> Cancellable<Vec<FoldingRange>> {
+        self.with_db(|db| folding_ranges::folding_ranges(db, file))
+    }
+
     pub fn syntax_highlight(
         &self,
         file: FileId,
@@ -104,16 +190,39 @@ impl Analysis {
         self.with_db(|db| syntax_highlighting::highlight(db, file, range))
     }
 
-    pub fn diagnostics(&self, file: FileId) -> Cancellable<Vec<Diagnostic>> {
-        self.with_db(|db| diagnostics::diagnostics(db, file))
+    pub fn diagnostics(
+        &self,
+        config: &DiagnosticsConfig,
+        file: FileId,
+    ) -> Cancellable<Vec<Diagnostic>> {
+        self.with_db(|db| diagnostics::diagnostics(db, config, file))
+    }
+
+    /// Diagnostics at least as severe as `min`.
+    pub fn diagnostics_of_severity(
+        &self,
+        config: &DiagnosticsConfig,
+        file: FileId,
+        min: Severity,
+    ) -> Cancellable<Vec<Diagnostic>> {
+        self.with_db(|db| diagnostics::diagnostics_of_severity(db, config, file, min))
+    }
+
+    /// Syntax errors only, which are much faster to compute than full diagnostics.
+    pub fn syntax_errors(&self, file: FileId) -> Cancellable<Vec<Diagnostic>> {
+        self.with_db(|db| diagnostics::syntax_errors(db, file))
     }
 
     pub fn goto_definition(&self, pos: FilePos) -> Cancellable<Option<Vec<NavigationTarget>>> {
         self.with_db(|db| goto_definition::goto_definition(db, pos))
     }
 
-    pub fn completions(&self, pos: FilePos) -> Cancellable<Option<Vec<CompletionItem>>> {
-        self.with_db(|db| completion::completions(db, pos))
+    pub fn completions(
+        &self,
+        config: &CompletionConfig,
+        pos: FilePos,
+    ) -> Cancellable<Option<Vec<CompletionItem>>> {
+        self.with_db(|db| completion::completions(db, config, pos))
     }
 
     pub fn references(&self, pos: FilePos) -> Cancellable<Option<Vec<FileRange>>> {
@@ -132,6 +241,10 @@ impl Analysis {
         self.with_db(|db| rename::rename(db, fpos, new_name))
     }
 
+    pub fn assists(&self, config: &AssistsConfig, frange: FileRange) -> Cancellable<Vec<Assist>> {
+        self.with_db(|db| assists::assists(db, config, frange))
+    }
+
     pub fn hover(&self, fpos: FilePos) -> Cancellable<Option<HoverResult>> {
         self.with_db(|db| hover::hover(db, fpos))
     }
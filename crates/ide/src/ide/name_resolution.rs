@@ -0,0 +1,113 @@
+use crate::def::{Expr, ResolveResult};
+use crate::{DefDatabase, FileId};
+use rowan::TextRange;
+
+/// A use of a name in a file, with what it resolves to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedName {
+    pub range: TextRange,
+    pub kind: ResolvedNameKind,
+    /// The range of the definition, or the environments of all enclosing `with`s.
+    /// Empty for builtins and unresolved names.
+    pub targets: Vec<TextRange>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ResolvedNameKind {
+    Definition,
+    Builtin,
+    WithScope,
+    Unresolved,
+}
+
+/// All name uses in a file ordered by their ranges, with their resolution results.
+pub(crate) fn name_resolution(db: &dyn DefDatabase, file: FileId) -> Vec<ResolvedName> {
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+    let name_res = db.name_resolution(file);
+
+    let mut ret = module
+        .exprs()
+        .filter(|(_, kind)| matches!(kind, Expr::Reference(_)))
+        .filter_map(|(expr, _)| {
+            let range = source_map.node_for_expr(expr)?.text_range();
+            let (kind, targets) = match name_res.get(expr) {
+                Some(ResolveResult::Definition(name)) => (
+                    ResolvedNameKind::Definition,
+                    source_map
+                        .nodes_for_name(*name)
+                        .map(|ptr| ptr.text_range())
+                        .take(1)
+                        .collect(),
+                ),
+                Some(ResolveResult::WithExprs(withs)) => (
+                    ResolvedNameKind::WithScope,
+                    withs
+                        .iter()
+                        .filter_map(|&with| match module[with] {
+                            Expr::With(env, _) => Some(source_map.node_for_expr(env)?.text_range()),
+                            _ => None,
+                        })
+                        .collect(),
+                ),
+                Some(ResolveResult::Builtin(_)) => (ResolvedNameKind::Builtin, Vec::new()),
+                None => (ResolvedNameKind::Unresolved, Vec::new()),
+            };
+            Some(ResolvedName {
+                range,
+                kind,
+                targets,
+            })
+        })
+        .collect::<Vec<_>>();
+    ret.sort_by_key(|name| (name.range.start(), name.range.end()));
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::base::SourceDatabase;
+    use crate::tests::TestDB;
+    use expect_test::{expect, Expect};
+
+    #[track_caller]
+    fn check(fixture: &str, expect: Expect) {
+        let (db, file) = TestDB::single_file(fixture).unwrap();
+        let src = db.file_content(file);
+        let mut got = String::new();
+        for name in super::name_resolution(&db, file) {
+            got += &format!("{:?} {} {:?}", name.range, &src[name.range], name.kind);
+            for target in &name.targets {
+                got += &format!(" {target:?}");
+            }
+            got += "\n";
+        }
+        expect.assert_eq(&got);
+    }
+
+    #[test]
+    fn resolution() {
+        check(
+            "x: let a = x; in [ a b builtins.map true ]",
+            expect![[r#"
+                11..12 x Definition 0..1
+                19..20 a Definition 7..8
+                21..22 b Unresolved
+                23..31 builtins Builtin
+                36..40 true Builtin
+            "#]],
+        );
+    }
+
+    #[test]
+    fn with_scope() {
+        check(
+            "with a; with b; { inherit c; }",
+            expect![[r#"
+                5..6 a Unresolved
+                13..14 b WithScope 5..6
+                26..27 c WithScope 13..14 5..6
+            "#]],
+        );
+    }
+}
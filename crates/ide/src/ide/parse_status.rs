@@ -0,0 +1,116 @@
+use crate::{DefDatabase, FileId};
+use syntax::{ErrorKind, SyntaxKind};
+
+/// A summary of how a file is parsed and recovered from syntax errors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseStatus {
+    /// The number of syntax errors.
+    pub error_count: usize,
+    /// Kinds of syntax errors with their counts, in the order of first occurrences.
+    pub error_kinds: Vec<(ErrorKind, usize)>,
+    /// The number of `ERROR` nodes and tokens in the recovered tree, covering unrecognized input.
+    pub error_node_count: usize,
+}
+
+impl ParseStatus {
+    pub fn has_errors(&self) -> bool {
+        self.error_count != 0
+    }
+}
+
+pub(crate) fn parse_status(db: &dyn DefDatabase, file: FileId) -> ParseStatus {
+    let parse = db.parse(file);
+    let mut error_kinds: Vec<(ErrorKind, usize)> = Vec::new();
+    for err in parse.errors() {
+        match error_kinds.iter_mut().find(|(kind, _)| *kind == err.kind) {
+            Some((_, count)) => *count += 1,
+            None => error_kinds.push((err.kind, 1)),
+        }
+    }
+    let error_node_count = parse
+        .syntax_node()
+        .descendants_with_tokens()
+        .filter(|elem| elem.kind() == SyntaxKind::ERROR)
+        .count();
+    ParseStatus {
+        error_count: parse.errors().len(),
+        error_kinds,
+        error_node_count,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::base::SourceDatabase;
+    use crate::tests::TestDB;
+    use crate::DefDatabase;
+    use expect_test::{expect, Expect};
+
+    #[track_caller]
+    fn check(src: &str, expect: Expect) {
+        let (db, file) = TestDB::single_file(src).unwrap();
+        let status = super::parse_status(&db, file);
+        expect.assert_eq(&format!("{status:?}"));
+    }
+
+    #[test]
+    fn status() {
+        check(
+            "{ a = 1; }",
+            expect!["ParseStatus { error_count: 0, error_kinds: [], error_node_count: 0 }"],
+        );
+        check(
+            "{ a = ; b = ; c = 1 }",
+            expect!["ParseStatus { error_count: 3, error_kinds: [(MissingExpr, 2), (MissingToken(SEMICOLON), 1)], error_node_count: 0 }"],
+        );
+        check(
+            "[ ) ]",
+            expect!["ParseStatus { error_count: 5, error_kinds: [(MissingToken(R_BRACK), 1), (MultipleRoots, 2), (MissingExpr, 2)], error_node_count: 0 }"],
+        );
+        check(
+            "[ 1 % 2 ]",
+            expect!["ParseStatus { error_count: 1, error_kinds: [(MissingElemExpr, 1)], error_node_count: 1 }"],
+        );
+    }
+
+    /// Every truncation of a well-formed file is parsed losslessly and lowered without panics,
+    /// and is reported with errors until the final closing parenthesis.
+    #[test]
+    fn truncated() {
+        let src = r#"({ lib, pkgs ? import <nixpkgs> { }, ... }:
+let
+  inherit (lib) mkIf;
+  f = x: y: if x == null then y else x // { z = y.z or 1; };
+  s = "a ${toString 1} b";
+  p = ./foo/bar.nix;
+in
+rec {
+  list = [ 1 2.5 (f 3 4) ];
+  nested.attr."quoted" = assert true; with pkgs; hello;
+  inherit f;
+  result = let { body = 1; }; # comment
+})
+"#;
+        let complete_len = src.rfind(')').unwrap() + 1;
+        for (len, _) in src.char_indices().skip(1) {
+            let prefix = &src[..len];
+            let (db, file) = TestDB::single_file(prefix).unwrap();
+            assert_eq!(
+                db.parse(file).syntax_node().to_string(),
+                &*db.file_content(file),
+            );
+            let status = super::parse_status(&db, file);
+            assert_eq!(
+                status.has_errors(),
+                len < complete_len,
+                "Unexpected status of {prefix:?}: {status:?}",
+            );
+            assert_eq!(
+                status.error_kinds.iter().map(|(_, n)| n).sum::<usize>(),
+                status.error_count,
+            );
+            db.module(file);
+            db.name_resolution(file);
+        }
+    }
+}
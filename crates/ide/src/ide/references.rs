@@ -1,3 +1,4 @@
+use super::goto_definition::{select_references, select_source};
 use crate::def::AstPtr;
 use crate::{DefDatabase, FilePos, FileRange};
 use rowan::ast::AstNode;
@@ -37,26 +38,36 @@ pub(crate) fn references(
 
     let source_map = db.source_map(file_id);
     let name_ref = db.name_reference(file_id);
+    // When {name,with}_references returns None, it means no references,
+    // not a failure.
     let refs = match kind {
         DefKind::Attr(ptr) => {
-            let name = source_map.name_for_node(ptr)?;
-            name_ref.name_references(name)
+            let module = db.module(file_id);
+            let name_res = db.name_resolution(file_id);
+            let name = match source_map.name_for_node(ptr) {
+                Some(name) => name,
+                // An attribute of `set.a.b`.
+                None => select_source(&module, &name_res, source_map.expr_for_node(ptr)?)?,
+            };
+            let mut refs = Vec::new();
+            refs.extend(name_ref.name_references(name).into_iter().flatten());
+            refs.extend(select_references(&module, &name_res, name));
+            refs.sort_by_key(|expr| u32::from(expr.into_raw()));
+            refs.dedup();
+            refs
         }
         DefKind::With(ptr) => {
             let expr = source_map.expr_for_node(ptr)?;
-            name_ref.with_references(expr)
+            name_ref.with_references(expr).unwrap_or_default().to_vec()
         }
     };
-    // When {name,with}_references returns None, it means no references,
-    // not a failure.
-    let refs = refs.map_or(Vec::new(), |refs| {
-        refs.iter()
-            .map(|&expr| {
-                let ptr = source_map.node_for_expr(expr).expect("Id must be valid");
-                FileRange::new(file_id, ptr.text_range())
-            })
-            .collect()
-    });
+    let refs = refs
+        .iter()
+        .map(|&expr| {
+            let ptr = source_map.node_for_expr(expr).expect("Id must be valid");
+            FileRange::new(file_id, ptr.text_range())
+        })
+        .collect();
     Some(refs)
 }
 
@@ -91,6 +102,14 @@ mod tests {
         check("rec { $0a.b = $1a.c; }");
     }
 
+    #[test]
+    fn self_reference() {
+        check("rec { $0a = 1; b = $1a + 1; }");
+        check("rec { a = $1b; $0b = a + $2b; }");
+        check("let $0a = [ $1a ]; in $2a");
+        check("let $0f = x: $1f x; in { g = $2f; }");
+    }
+
     #[test]
     fn special_attr() {
         check(r#"let $0" " = 1; in { inherit $1" "; }"#);
@@ -115,4 +134,18 @@ mod tests {
         check("a: $0with {}; $1x + (with {}; { inherit a $2b; })");
         check("a: with {}; x + ($0with {}; { inherit a $1b; })");
     }
+
+    #[test]
+    fn attr_key() {
+        check("let x = { $0a = 1; }; in x.$1a + x.b");
+        check("let x = { a = 1; }; in x.$0$1a + x.$2a");
+        check("let x = { $0a.b = 1; }; in [ x.$1a.b x.$2a ]");
+        check("let x = { a.$0b = 1; }; in [ x.a.$1b x.b ]");
+        check("let x = { $0a = 1; } // { b = 2; }; in [ x.$1a x.b ({ a = 3; } // x).$2a ]");
+        // Overridden by `//`.
+        check("let x = { $0a = 1; }; y = x // { a = 2; }; in [ x.$1a y.a ]");
+        check("rec { $0a = 1; b = $1a; c = { a = 2; }.a; }");
+        check("{ x = { $0a = 1; }; y = x.a; }");
+        check("f: (f { $0a = 1; }).a");
+    }
 }
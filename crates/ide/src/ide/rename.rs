@@ -1,3 +1,4 @@
+use super::goto_definition::{select_references, select_source};
 use crate::def::{AstPtr, NameId, ResolveResult};
 use crate::{DefDatabase, FilePos, TextEdit, WorkspaceEdit};
 use rowan::ast::AstNode;
@@ -150,6 +151,18 @@ pub(crate) fn rename(
         });
     }
 
+    // Rename attributes selecting the field, like `a` in `set.a`.
+    let name_res = db.name_resolution(file_id);
+    for expr in select_references(&module, &name_res, name) {
+        let ptr = source_map
+            .node_for_expr(expr)
+            .expect("Must be a valid attribute");
+        edits.push(TextEdit {
+            delete: ptr.text_range(),
+            insert: new_attr.clone(),
+        });
+    }
+
     edits.sort_by_key(|edit| edit.delete.start());
 
     // Sanity check.
@@ -162,10 +175,11 @@ pub(crate) fn rename(
 
     Ok(WorkspaceEdit {
         content_edits: [(file_id, edits)].into_iter().collect(),
+        file_system_edits: Vec::new(),
     })
 }
 
-fn find_name(
+pub(crate) fn find_name(
     db: &dyn DefDatabase,
     FilePos { file_id, pos }: FilePos,
 ) -> Option<(TextRange, NameId)> {
@@ -209,12 +223,17 @@ fn find_name(
         if let Some(ResolveResult::Definition(name)) = nameres.get(expr) {
             return Some((ptr.text_range(), *name));
         }
+        // An attribute of `set.a.b`.
+        let module = db.module(file_id);
+        if let Some(name) = select_source(&module, &nameres, expr) {
+            return Some((ptr.text_range(), name));
+        }
     }
 
     None
 }
 
-fn is_valid_ident(name: &str) -> bool {
+pub(crate) fn is_valid_ident(name: &str) -> bool {
     const KEYWORDS: &[&[u8]] = &[
         b"assert", b"else", b"if", b"in", b"inherit", b"let", b"or", b"rec", b"then", b"with",
     ];
@@ -393,6 +412,26 @@ mod tests {
         );
     }
 
+    #[test]
+    fn rename_selected_attr() {
+        check(
+            "let x = { $0a = 1; }; in x.a + x.b",
+            "b",
+            expect!["let x = { b = 1; }; in x.b + x.b"],
+        );
+        check(
+            "let x = { a.b = 1; }; in x.$0a.b",
+            "c d",
+            expect![[r#"let x = { "c d".b = 1; }; in x."c d".b"#]],
+        );
+        // Only the field not overridden by `//`.
+        check(
+            "let x = { a = 1; } // { a = 2; }; in x.$0a",
+            "b",
+            expect!["let x = { a = 1; } // { b = 2; }; in x.b"],
+        );
+    }
+
     #[test]
     fn rename_rec_attrset() {
         check(
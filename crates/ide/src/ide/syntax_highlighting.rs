@@ -1,9 +1,10 @@
 //! This is actually so-called "semantic highlighting".
 //! Ref: <https://github.com/rust-lang/rust-analyzer/blob/a670ff888437f4b6a3d24cc2996e9f969a87cbae/crates/ide/src/syntax_highlighting/tags.rs>
-use crate::def::{AstPtr, Expr, NameKind, ResolveResult};
+use crate::def::{AstPtr, BindingValue, Expr, Literal, NameKind, ResolveResult};
 use crate::{DefDatabase, FileId};
 use builtin::{BuiltinKind, ALL_BUILTINS};
 use rowan::{NodeOrToken, TextRange, WalkEvent};
+use std::collections::HashSet;
 use syntax::{SyntaxKind, SyntaxToken, T};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -16,6 +17,8 @@ pub struct HlRange {
 pub enum HlTag {
     NameDef(NameKind),
     NameRef(NameKind),
+    /// A reference to a user-defined binding whose value is a lambda.
+    FunctionRef,
     UnresolvedRef,
 
     AttrField,
@@ -71,6 +74,26 @@ pub(crate) fn highlight(
     let nameres = db.name_resolution(file);
     let module = db.module(file);
 
+    // Bindings of lambdas, and attributes of `builtins.name` selections.
+    let mut function_names = HashSet::new();
+    let mut builtin_attrs = HashSet::new();
+    for (_, kind) in module.exprs() {
+        if let Some(bindings) = kind.bindings() {
+            for &(name, value) in bindings.statics.iter() {
+                if let BindingValue::Expr(value) = value {
+                    if matches!(module[value], Expr::Lambda(..)) {
+                        function_names.insert(name);
+                    }
+                }
+            }
+        }
+        if let Expr::Select(set, path, _) = kind {
+            if nameres.get(*set) == Some(&ResolveResult::Builtin("builtins")) {
+                builtin_attrs.extend(path.first().copied());
+            }
+        }
+    }
+
     let highlight_token = |tok: &SyntaxToken| -> Option<HlRange> {
         let tag = match tok.kind() {
             SyntaxKind::SPACE => return None,
@@ -112,6 +135,9 @@ pub(crate) fn highlight(
                     let expr = source_map.expr_for_node(AstPtr::new(&node))?;
                     match nameres.get(expr) {
                         None => HlTag::UnresolvedRef,
+                        Some(ResolveResult::Definition(def)) if function_names.contains(def) => {
+                            HlTag::FunctionRef
+                        }
                         Some(ResolveResult::Definition(def)) => HlTag::NameRef(module[*def].kind),
                         Some(ResolveResult::WithExprs(_)) => HlTag::NameRef(NameKind::PlainAttrset),
                         Some(ResolveResult::Builtin(name)) => {
@@ -126,9 +152,18 @@ pub(crate) fn highlight(
                         None => {
                             match source_map.expr_for_node(ptr) {
                                 // `Attr`s are converted into string literals.
-                                Some(expr) if matches!(&module[expr], Expr::Literal(_)) => {
-                                    HlTag::AttrField
-                                }
+                                Some(expr) => match &module[expr] {
+                                    Expr::Literal(Literal::String(name))
+                                        if builtin_attrs.contains(&expr) =>
+                                    {
+                                        match ALL_BUILTINS.get(name) {
+                                            Some(b) => HlTag::Builtin(b.kind),
+                                            None => HlTag::AttrField,
+                                        }
+                                    }
+                                    Expr::Literal(_) => HlTag::AttrField,
+                                    _ => return None,
+                                },
                                 _ => return None,
                             }
                         }
@@ -214,6 +249,8 @@ mod tests {
     #[test]
     fn path() {
         check("$0./.", expect!["Path"]);
+        check("$0./path", expect!["Path"]);
+        check("$0<nixpkgs>", expect!["Path"]);
     }
 
     #[test]
@@ -230,6 +267,22 @@ mod tests {
         check("$0true", expect!["Builtin(Const)"]);
         check("$0builtins", expect!["Builtin(Attrset)"]);
         check("$0map", expect!["Builtin(Function)"]);
+        check("builtins.$0map", expect!["Builtin(Function)"]);
+        check("builtins.$0true", expect!["Builtin(Const)"]);
+        check("builtins.$0unknown", expect!["AttrField"]);
+        check(
+            "let builtins = { }; in builtins.$0map",
+            expect!["AttrField"],
+        );
+        check("builtins.map.$0map", expect!["AttrField"]);
+    }
+
+    #[test]
+    fn function() {
+        check("let f = x: x; in $0f 1", expect!["FunctionRef"]);
+        check("rec { f = { x }: x; g = $0f; }", expect!["FunctionRef"]);
+        check("let f = map; in $0f", expect!["NameRef(LetIn)"]);
+        check("f: $0f 1", expect!["NameRef(Param)"]);
     }
 
     #[test]
@@ -8,8 +8,11 @@ mod text_edit;
 mod tests;
 
 pub use self::ide::{
-    Analysis, AnalysisHost, Cancelled, CompletionItem, CompletionItemKind, HlKeyword, HlOperator,
-    HlPunct, HlRange, HlTag, HoverResult, NavigationTarget, RootDatabase,
+    Analysis, AnalysisHost, Assist, AssistKind, AssistsConfig, BuiltinSet, Cancelled,
+    CompletionConfig, CompletionItem, CompletionItemKind, DefUseEdge, DefUseGraph, DefUseNode,
+    DefUseNodeKind, DiagnosticsConfig, FileImport, FoldingRange, FoldingRangeKind, HlKeyword,
+    HlOperator, HlPunct, HlRange, HlTag, HoverResult, NavigationTarget, NixVersion, ParseStatus,
+    ResolvedName, ResolvedNameKind, RootDatabase,
 };
 pub use base::{
     Change, FileId, FilePos, FileRange, FileSet, InFile, SourceDatabase, SourceRoot, SourceRootId,
@@ -18,4 +21,4 @@ pub use base::{
 pub use builtin::BuiltinKind;
 pub use def::{DefDatabase, Module, ModuleSourceMap, NameKind};
 pub use diagnostic::{Diagnostic, DiagnosticKind, Severity};
-pub use text_edit::{TextEdit, WorkspaceEdit};
+pub use text_edit::{FileSystemEdit, TextEdit, WorkspaceEdit};
@@ -1,4 +1,4 @@
-use crate::FileId;
+use crate::{FileId, VfsPath};
 use smol_str::SmolStr;
 use std::collections::HashMap;
 use syntax::TextRange;
@@ -6,7 +6,15 @@ use syntax::TextRange;
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct WorkspaceEdit {
     pub content_edits: HashMap<FileId, Vec<TextEdit>>,
-    // Filesystem edit is not implemented yet.
+    /// File creations and deletions.
+    /// Creations are applied before `content_edits`, and deletions after them.
+    pub file_system_edits: Vec<FileSystemEdit>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileSystemEdit {
+    CreateFile { path: VfsPath, content: String },
+    DeleteFile { file: FileId },
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
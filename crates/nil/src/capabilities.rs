@@ -1,13 +1,168 @@
-use crate::semantic_tokens::{SEMANTIC_TOKEN_MODIFIERS, SEMANTIC_TOKEN_TYPES};
+use crate::lsp_ext;
+use crate::semantic_tokens::NegotiatedLegend;
 use lsp_types::{
-    CompletionOptions, HoverProviderCapability, OneOf, RenameOptions,
-    SelectionRangeProviderCapability, SemanticTokensFullOptions, SemanticTokensLegend,
+    ClientCapabilities, CodeActionOptions, CodeActionProviderCapability, CompletionOptions,
+    ExecuteCommandOptions, FoldingRangeProviderCapability, HoverProviderCapability, MarkupKind,
+    OneOf, RenameOptions, SaveOptions, SelectionRangeProviderCapability, SemanticTokensFullOptions,
     SemanticTokensOptions, SemanticTokensServerCapabilities, ServerCapabilities,
     TextDocumentSyncCapability, TextDocumentSyncKind, TextDocumentSyncOptions,
-    WorkDoneProgressOptions,
+    TextDocumentSyncSaveOptions, WorkDoneProgressOptions,
 };
 
-pub(crate) fn server_capabilities() -> ServerCapabilities {
+pub(crate) fn negotiate_semantic_legend(client_caps: &ClientCapabilities) -> NegotiatedLegend {
+    match client_caps
+        .text_document
+        .as_ref()
+        .and_then(|caps| caps.semantic_tokens.as_ref())
+    {
+        Some(caps) => NegotiatedLegend::new(&caps.token_types, &caps.token_modifiers),
+        None => NegotiatedLegend::default(),
+    }
+}
+
+/// Whether the client can resolve edits of code actions lazily.
+pub(crate) fn negotiate_code_action_resolve(client_caps: &ClientCapabilities) -> bool {
+    (|| {
+        let caps = client_caps.text_document.as_ref()?.code_action.as_ref()?;
+        let props = &caps.resolve_support.as_ref()?.properties;
+        Some(props.iter().any(|prop| prop == "edit"))
+    })()
+    .unwrap_or(false)
+}
+
+/// Whether the client accepts `relatedInformation` of diagnostics, possibly in other files.
+pub(crate) fn negotiate_related_information(client_caps: &ClientCapabilities) -> bool {
+    (|| {
+        let caps = client_caps
+            .text_document
+            .as_ref()?
+            .publish_diagnostics
+            .as_ref()?;
+        caps.related_information
+    })()
+    .unwrap_or(false)
+}
+
+/// Whether the client accepts `documentChanges` in workspace edits, which carry document versions.
+pub(crate) fn negotiate_document_changes(client_caps: &ClientCapabilities) -> bool {
+    (|| {
+        client_caps
+            .workspace
+            .as_ref()?
+            .workspace_edit
+            .as_ref()?
+            .document_changes
+    })()
+    .unwrap_or(false)
+}
+
+/// Whether the client accepts change annotations in workspace edits, to group edits for preview.
+/// Annotated edits are only possible in `documentChanges`.
+pub(crate) fn negotiate_change_annotations(client_caps: &ClientCapabilities) -> bool {
+    negotiate_document_changes(client_caps)
+        && (|| {
+            client_caps
+                .workspace
+                .as_ref()?
+                .workspace_edit
+                .as_ref()?
+                .change_annotation_support
+                .as_ref()
+        })()
+        .is_some()
+}
+
+/// Whether the client can apply edits requested by the server via `workspace/applyEdit`.
+pub(crate) fn negotiate_apply_edit(client_caps: &ClientCapabilities) -> bool {
+    (|| client_caps.workspace.as_ref()?.apply_edit)().unwrap_or(false)
+}
+
+/// Whether the client supports registering formatting dynamically. If so, formatting is only
+/// registered when a formatter is configured, so that the client uses other formatters otherwise.
+pub(crate) fn negotiate_dynamic_formatting(client_caps: &ClientCapabilities) -> bool {
+    (|| {
+        let caps = client_caps.text_document.as_ref()?;
+        Some(
+            caps.formatting.as_ref()?.dynamic_registration?
+                && caps.range_formatting.as_ref()?.dynamic_registration?,
+        )
+    })()
+    .unwrap_or(false)
+}
+
+/// Whether the client accepts snippets in completion items.
+pub(crate) fn negotiate_snippet_support(client_caps: &ClientCapabilities) -> bool {
+    (|| {
+        client_caps
+            .text_document
+            .as_ref()?
+            .completion
+            .as_ref()?
+            .completion_item
+            .as_ref()?
+            .snippet_support
+    })()
+    .unwrap_or(false)
+}
+
+/// Whether the client renders Markdown in hover contents.
+/// Clients preferring plain text first in `contentFormat` get plain text.
+pub(crate) fn negotiate_hover_markdown(client_caps: &ClientCapabilities) -> bool {
+    (|| {
+        let formats = client_caps
+            .text_document
+            .as_ref()?
+            .hover
+            .as_ref()?
+            .content_format
+            .as_ref()?;
+        Some(formats.first() != Some(&MarkupKind::PlainText))
+    })()
+    .unwrap_or(true)
+}
+
+/// All capabilities of the server, gated on what the client supports.
+pub(crate) fn server_capabilities(
+    client_caps: &ClientCapabilities,
+    semantic_legend: &NegotiatedLegend,
+) -> ServerCapabilities {
+    let text_document_caps = client_caps.text_document.as_ref();
+
+    // Without literal support, clients expect `Command`s instead of `CodeAction`s.
+    let code_action_provider = text_document_caps
+        .and_then(|caps| {
+            caps.code_action
+                .as_ref()?
+                .code_action_literal_support
+                .as_ref()
+        })
+        .map(|_| {
+            CodeActionProviderCapability::Options(CodeActionOptions {
+                code_action_kinds: None,
+                work_done_progress_options: WorkDoneProgressOptions::default(),
+                resolve_provider: Some(negotiate_code_action_resolve(client_caps)),
+            })
+        });
+
+    let prepare_rename = text_document_caps
+        .and_then(|caps| caps.rename.as_ref()?.prepare_support)
+        .unwrap_or(false);
+
+    // We only produce full tokens without deltas.
+    let semantic_tokens_provider = text_document_caps
+        .and_then(|caps| caps.semantic_tokens.as_ref())
+        .map(|caps| {
+            SemanticTokensServerCapabilities::SemanticTokensOptions(SemanticTokensOptions {
+                work_done_progress_options: WorkDoneProgressOptions::default(),
+                legend: semantic_legend.legend(),
+                range: Some(caps.requests.range == Some(true)),
+                full: Some(SemanticTokensFullOptions::Bool(true)),
+            })
+        });
+
+    // Otherwise, it is registered later when a formatter is configured.
+    let static_formatting = !negotiate_dynamic_formatting(client_caps);
+
     ServerCapabilities {
         text_document_sync: Some(TextDocumentSyncCapability::Options(
             TextDocumentSyncOptions {
@@ -15,7 +170,10 @@ pub(crate) fn server_capabilities() -> ServerCapabilities {
                 change: Some(TextDocumentSyncKind::INCREMENTAL),
                 will_save: None,
                 will_save_wait_until: None,
-                save: None,
+                // Post-save hooks may change the content, like formatters.
+                save: Some(TextDocumentSyncSaveOptions::SaveOptions(SaveOptions {
+                    include_text: Some(true),
+                })),
             },
         )),
         definition_provider: Some(OneOf::Left(true)),
@@ -25,22 +183,153 @@ pub(crate) fn server_capabilities() -> ServerCapabilities {
         }),
         references_provider: Some(OneOf::Left(true)),
         selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
-        rename_provider: Some(OneOf::Right(RenameOptions {
-            prepare_provider: Some(true),
-            work_done_progress_options: WorkDoneProgressOptions::default(),
-        })),
-        semantic_tokens_provider: Some(SemanticTokensServerCapabilities::SemanticTokensOptions(
-            SemanticTokensOptions {
+        rename_provider: Some(if prepare_rename {
+            OneOf::Right(RenameOptions {
+                prepare_provider: Some(true),
                 work_done_progress_options: WorkDoneProgressOptions::default(),
-                legend: SemanticTokensLegend {
-                    token_types: SEMANTIC_TOKEN_TYPES.to_vec(),
-                    token_modifiers: SEMANTIC_TOKEN_MODIFIERS.to_vec(),
-                },
-                range: Some(true),
-                full: Some(SemanticTokensFullOptions::Delta { delta: Some(false) }),
-            },
-        )),
+            })
+        } else {
+            OneOf::Left(true)
+        }),
+        semantic_tokens_provider,
         hover_provider: Some(HoverProviderCapability::Simple(true)),
+        document_highlight_provider: Some(OneOf::Left(true)),
+        folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+        document_formatting_provider: static_formatting.then_some(OneOf::Left(true)),
+        document_range_formatting_provider: static_formatting.then_some(OneOf::Left(true)),
+        code_action_provider,
+        execute_command_provider: Some(ExecuteCommandOptions {
+            commands: lsp_ext::COMMANDS.iter().map(|&cmd| cmd.into()).collect(),
+            work_done_progress_options: WorkDoneProgressOptions::default(),
+        }),
         ..Default::default()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        negotiate_dynamic_formatting, negotiate_hover_markdown, negotiate_semantic_legend,
+        negotiate_snippet_support, server_capabilities,
+    };
+    use lsp_types::ClientCapabilities;
+    use serde_json::json;
+
+    #[test]
+    fn typical_client() {
+        let client_caps = serde_json::from_value::<ClientCapabilities>(json!({
+            "textDocument": {
+                "codeAction": {
+                    "codeActionLiteralSupport": {
+                        "codeActionKind": { "valueSet": ["quickfix"] },
+                    },
+                    "resolveSupport": { "properties": ["edit"] },
+                },
+                "rename": { "prepareSupport": true },
+                "semanticTokens": {
+                    "requests": { "range": true, "full": { "delta": true } },
+                    "tokenTypes": ["keyword", "string"],
+                    "tokenModifiers": ["definition"],
+                    "formats": ["relative"],
+                },
+            },
+        }))
+        .unwrap();
+        let legend = negotiate_semantic_legend(&client_caps);
+        let caps = server_capabilities(&client_caps, &legend);
+        assert_eq!(
+            serde_json::to_value(caps).unwrap(),
+            json!({
+                "textDocumentSync": { "openClose": true, "change": 2, "save": { "includeText": true } },
+                "selectionRangeProvider": true,
+                "hoverProvider": true,
+                "documentHighlightProvider": true,
+                "completionProvider": { "triggerCharacters": ["."] },
+                "definitionProvider": true,
+                "referencesProvider": true,
+                "codeActionProvider": { "resolveProvider": true },
+                "executeCommandProvider": {
+                    "commands": [
+                        "nil.formatDocument",
+                        "nil.selectEnclosingBinding",
+                        "nil.completionAccepted",
+                    ],
+                },
+                "documentFormattingProvider": true,
+                "documentRangeFormattingProvider": true,
+                "renameProvider": { "prepareProvider": true },
+                "foldingRangeProvider": true,
+                "semanticTokensProvider": {
+                    "legend": {
+                        "tokenTypes": ["keyword", "string"],
+                        "tokenModifiers": ["definition"],
+                    },
+                    "range": true,
+                    "full": true,
+                },
+            }),
+        );
+    }
+
+    #[test]
+    fn minimal_client() {
+        let client_caps = ClientCapabilities::default();
+        let legend = negotiate_semantic_legend(&client_caps);
+        let caps = server_capabilities(&client_caps, &legend);
+        assert_eq!(caps.code_action_provider, None);
+        assert_eq!(caps.semantic_tokens_provider, None);
+        assert_eq!(caps.rename_provider, Some(lsp_types::OneOf::Left(true)));
+        assert_eq!(
+            caps.document_formatting_provider,
+            Some(lsp_types::OneOf::Left(true)),
+        );
+    }
+
+    #[test]
+    fn dynamic_formatting() {
+        let client_caps = serde_json::from_value::<ClientCapabilities>(json!({
+            "textDocument": {
+                "formatting": { "dynamicRegistration": true },
+                "rangeFormatting": { "dynamicRegistration": true },
+            },
+        }))
+        .unwrap();
+        assert!(negotiate_dynamic_formatting(&client_caps));
+        let legend = negotiate_semantic_legend(&client_caps);
+        let caps = server_capabilities(&client_caps, &legend);
+        assert_eq!(caps.document_formatting_provider, None);
+        assert_eq!(caps.document_range_formatting_provider, None);
+    }
+
+    #[test]
+    fn hover_content_format() {
+        let with_formats = |formats: serde_json::Value| {
+            serde_json::from_value::<ClientCapabilities>(json!({
+                "textDocument": { "hover": { "contentFormat": formats } },
+            }))
+            .unwrap()
+        };
+        assert!(negotiate_hover_markdown(&ClientCapabilities::default()));
+        assert!(negotiate_hover_markdown(&with_formats(json!([
+            "markdown",
+            "plaintext"
+        ]))));
+        assert!(!negotiate_hover_markdown(&with_formats(json!([
+            "plaintext",
+            "markdown"
+        ]))));
+        assert!(!negotiate_hover_markdown(&with_formats(json!([
+            "plaintext"
+        ]))));
+    }
+
+    #[test]
+    fn snippet_support() {
+        assert!(!negotiate_snippet_support(&ClientCapabilities::default()));
+        let client_caps = serde_json::from_value::<ClientCapabilities>(json!({
+            "textDocument": { "completion": { "completionItem": { "snippetSupport": true } } },
+        }))
+        .unwrap();
+        assert!(negotiate_snippet_support(&client_caps));
+    }
+}
@@ -0,0 +1,165 @@
+//! Command line checks without an editor, like `nil diagnostics`.
+use crate::state::{retain_missing_imports, scan_workspace_files};
+use crate::{Config, Result, Vfs};
+use ide::{AnalysisHost, Diagnostic, Severity};
+use lsp_types::Url;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+
+/// Print diagnostics of `files` in the workspace `root` to `out`, or of all files if none is given.
+/// The project config file in `root` is respected.
+///
+/// Returns whether the check passes, that is, there is no error and no diagnostic whose code is
+/// listed in `errorOnLints`.
+pub fn run_diagnostics(root: &Path, files: &[PathBuf], out: &mut dyn Write) -> Result<bool> {
+    let root = root.canonicalize()?;
+    let config = match Config::read_project_file(&root) {
+        Some(value) => serde_json::from_value::<Config>(value?)?,
+        None => Config::default(),
+    };
+
+    let mut vfs = Vfs::new(root.clone());
+    let scanned = scan_workspace_files(&root, config.scan_threads, false, &AtomicBool::new(false));
+    for (vpath, text) in scanned {
+        vfs.set_path_content(vpath, text.unwrap_or_default());
+    }
+    let mut host = AnalysisHost::new();
+    host.apply_change(vfs.take_change());
+    let analysis = host.snapshot();
+
+    let targets = if files.is_empty() {
+        vfs.files().collect()
+    } else {
+        files
+            .iter()
+            .map(|path| {
+                let path = path
+                    .canonicalize()
+                    .map_err(|err| format!("{}: {}", path.display(), err))?;
+                let uri = Url::from_file_path(&path)
+                    .map_err(|()| format!("Invalid path: {}", path.display()))?;
+                vfs.file_for_uri(&uri)
+            })
+            .collect::<Result<Vec<_>>>()?
+    };
+
+    let diagnostics_config = config.diagnostics_config();
+    let mut passed = true;
+    for file in targets {
+        if config.is_file_too_large(vfs.content_for_file(file).len()) {
+            continue;
+        }
+        let path = vfs.uri_for_file(file).to_file_path().unwrap_or_default();
+        let path = path.strip_prefix(&root).unwrap_or(&path);
+        let line_map = vfs.line_map_for_file(file);
+        let mut diags = analysis.diagnostics(&diagnostics_config, file)?;
+        retain_missing_imports(&vfs, file, &mut diags);
+        for diag in diags {
+            let (line, col) = line_map.line_col_for_pos(diag.range.start());
+            writeln!(
+                out,
+                "{}:{}:{}: {}[{}]: {}",
+                path.display(),
+                line + 1,
+                col + 1,
+                severity_name(diag.severity()),
+                diag.code(),
+                diag.message(),
+            )?;
+            if is_failure(&config, &diag) {
+                passed = false;
+            }
+        }
+    }
+    Ok(passed)
+}
+
+fn severity_name(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error | Severity::IncompleteSyntax => "error",
+        Severity::Warning => "warning",
+        Severity::Hint => "hint",
+    }
+}
+
+fn is_failure(config: &Config, diag: &Diagnostic) -> bool {
+    diag.severity().is_at_least(Severity::Error)
+        || config.error_on_lints.iter().any(|code| code == diag.code())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    #[test]
+    fn error_on_lints() {
+        let root = std::env::temp_dir().join(format!("nil-cli-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("default.nix"), "let a = 1; in 2").unwrap();
+
+        let run = |files: &[std::path::PathBuf]| {
+            let mut out = Vec::new();
+            let passed = super::run_diagnostics(&root, files, &mut out).unwrap();
+            (passed, String::from_utf8(out).unwrap())
+        };
+
+        // Warnings don't fail by default.
+        let (passed, out) = run(&[]);
+        assert!(passed);
+        assert_eq!(
+            out,
+            "default.nix:1:5: warning[unused_binding]: Unused binding\n",
+        );
+
+        fs::write(
+            root.join("nil.toml"),
+            "errorOnLints = [\"unused_binding\"]\n",
+        )
+        .unwrap();
+        let (passed, _) = run(&[root.join("default.nix")]);
+        assert!(!passed);
+
+        fs::write(root.join("nil.toml"), "errorOnLints = [\"unused_with\"]\n").unwrap();
+        let (passed, _) = run(&[root.join("default.nix")]);
+        assert!(passed);
+
+        // Errors always fail.
+        fs::write(root.join("default.nix"), "a").unwrap();
+        let (passed, out) = run(&[]);
+        assert!(!passed);
+        assert!(out.contains("error[undefined_name]"));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn missing_imports() {
+        let root = std::env::temp_dir().join(format!("nil-cli-imports-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let root = root.canonicalize().unwrap();
+        // Ignored files are not scanned, but exist.
+        fs::write(root.join(".ignore"), "ignored.nix\n").unwrap();
+        fs::write(root.join("ignored.nix"), "1").unwrap();
+        fs::write(
+            root.join("default.nix"),
+            "[ (import ./ignored.nix) (import ./missing.nix) ]",
+        )
+        .unwrap();
+
+        let mut out = Vec::new();
+        let passed = super::run_diagnostics(&root, &[], &mut out).unwrap();
+        assert!(passed);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "default.nix:1:34: warning[unresolved_import]: Imported file `{}` does not exist\n",
+                root.join("missing.nix").display(),
+            ),
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
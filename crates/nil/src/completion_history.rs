@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+
+/// The maximum number of remembered labels. The least recently accepted one is forgotten first.
+const MAX_ENTRIES: usize = 64;
+
+/// Completion items accepted in this session, to rank frequently used ones first.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct CompletionHistory {
+    /// The accepted count and the latest time of each label.
+    entries: HashMap<String, (u32, u64)>,
+    time: u64,
+}
+
+impl CompletionHistory {
+    pub fn record(&mut self, label: &str) {
+        self.time += 1;
+        let entry = self.entries.entry(label.to_owned()).or_default();
+        entry.0 += 1;
+        entry.1 = self.time;
+
+        if self.entries.len() > MAX_ENTRIES {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, &(_, time))| time)
+                .map(|(label, _)| label.clone())
+                .unwrap();
+            self.entries.remove(&oldest);
+        }
+    }
+
+    /// Ranks of remembered labels, from 0 for the most frequently used one.
+    /// Ties are broken by recency.
+    pub fn ranks(&self) -> HashMap<&str, usize> {
+        let mut labels = self.entries.iter().collect::<Vec<_>>();
+        labels.sort_by_key(|(_, &(count, time))| (u32::MAX - count, u64::MAX - time));
+        labels
+            .into_iter()
+            .enumerate()
+            .map(|(rank, (label, _))| (&**label, rank))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CompletionHistory, MAX_ENTRIES};
+
+    #[test]
+    fn ranks() {
+        let mut history = CompletionHistory::default();
+        history.record("a");
+        history.record("b");
+        history.record("b");
+        history.record("c");
+        let ranks = history.ranks();
+        assert_eq!(ranks["b"], 0);
+        assert_eq!(ranks["c"], 1);
+        assert_eq!(ranks["a"], 2);
+        assert_eq!(ranks.get("d"), None);
+    }
+
+    #[test]
+    fn bounded() {
+        let mut history = CompletionHistory::default();
+        history.record("first");
+        history.record("first");
+        for i in 0..MAX_ENTRIES {
+            history.record(&i.to_string());
+        }
+        let ranks = history.ranks();
+        assert_eq!(ranks.len(), MAX_ENTRIES);
+        assert!(!ranks.contains_key("first"));
+    }
+}
@@ -0,0 +1,662 @@
+use ide::{AssistsConfig, BuiltinSet, CompletionConfig, NixVersion};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::{fs, thread};
+
+/// The default of `maxFileSizeBytes`, 2 MiB.
+pub const DEFAULT_MAX_FILE_SIZE_BYTES: u64 = 2 << 20;
+
+/// The maximum number of diagnostics published for a file.
+pub const MAX_DIAGNOSTICS_CNT: usize = 128;
+
+/// Nested sections of [`Config`]. They are also queried separately under the top-level section,
+/// like `nil.formatting`, for clients which don't return them nested in the whole section.
+pub const CONFIG_SUB_SECTIONS: &[&str] = &[
+    "codeActions",
+    "formatting",
+    "builtins",
+    "diagnostics",
+    "scan",
+];
+
+/// Project config files in the workspace root, in the order of priority.
+/// They share the format of [`Config`], and are overridden by settings pushed by the client.
+pub const PROJECT_CONFIG_FILES: &[&str] = &[".nil.toml", "nil.toml"];
+
+/// Settings running external programs, as `(section, key)`. They are ignored in project config
+/// files, so that opening an untrusted repository doesn't execute anything.
+const PROJECT_IGNORED_SETTINGS: &[(&str, &str)] =
+    &[("formatting", "command"), ("formatting", "rangeArgs")];
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Config {
+    /// Files larger than this are still loaded, but are not analyzed.
+    pub max_file_size_bytes: u64,
+    /// Enable heuristics for conventions of nixpkgs.
+    pub nixpkgs_hints: bool,
+    pub code_actions: CodeActionsConfig,
+    pub formatting: FormattingConfig,
+    /// The number of threads for scanning workspace files. Defaults to the number of CPUs.
+    pub scan_threads: usize,
+    pub scan: ScanConfig,
+    /// Clear diagnostics of a file when it is closed.
+    pub clear_diagnostics_on_close: bool,
+    /// Show panics of request handlers to the user via `window/showMessage`.
+    pub report_panics: bool,
+    /// Read saved files from the disk if the client doesn't send their text on save.
+    pub reload_on_save: bool,
+    /// Analyze all workspace files in background after loading them,
+    /// so that the first requests on each file are fast.
+    pub prewarm: bool,
+    pub builtins: BuiltinsConfig,
+    /// Shut down the server if no message arrives in this many seconds. Zero disables it.
+    pub idle_shutdown_secs: u64,
+    /// The maximum number of completion items in a response. Zero disables the limit.
+    /// Truncated responses are marked incomplete, so that the client asks again on typing.
+    pub max_completion_items: usize,
+    pub diagnostics: DiagnosticsConfig,
+    /// Gitignore-style globs of generated files, relative to the workspace root.
+    /// Unused bindings, `with` and `rec` are not reported in them.
+    pub generated_file_globs: Vec<String>,
+    /// Codes of diagnostics failing `nil diagnostics`, even if they are not errors.
+    pub error_on_lints: Vec<String>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DiagnosticsConfig {
+    pub replaceable_with: ReplaceableWithConfig,
+    pub duplicate_list_element: DuplicateListElementConfig,
+    pub broad_with_scope: BroadWithScopeConfig,
+    pub style: StyleConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct StyleConfig {
+    /// Hint trailing whitespaces and long lines.
+    pub enable: bool,
+    /// The maximum number of characters of a line. `None` disables the check.
+    pub max_line_length: Option<usize>,
+}
+
+impl Default for StyleConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            max_line_length: Some(100),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DuplicateListElementConfig {
+    /// Hint repeated literal elements in lists.
+    pub enable: bool,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct BroadWithScopeConfig {
+    /// Hint `with` of non-literal attrsets at the top of files, like `with import <nixpkgs> { };`.
+    pub enable: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ReplaceableWithConfig {
+    /// Hint `with` expressions which provide only a few names.
+    pub enable: bool,
+    /// The maximum number of distinct names provided by a hinted `with`.
+    pub max_names: usize,
+}
+
+impl Default for ReplaceableWithConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            max_names: 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CodeActionsConfig {
+    /// Ids or kinds of code actions never to offer.
+    pub disabled: Vec<String>,
+    /// Parameters of the lambda added by the "wrap in module" action.
+    pub module_params: Vec<String>,
+}
+
+impl Default for CodeActionsConfig {
+    fn default() -> Self {
+        Self {
+            disabled: Vec::new(),
+            module_params: AssistsConfig::default()
+                .module_params
+                .iter()
+                .map(|param| param.to_string())
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ScanConfig {
+    /// Read contents of unopened files when they are first used, instead of during the scan.
+    /// It saves memory for huge workspaces, at the cost of latency.
+    pub lazy: bool,
+    /// Skip the initial scan. Instead, index directories of opened files and files imported by
+    /// them, expanding to other directories when they are referenced.
+    pub on_demand: bool,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct FormattingConfig {
+    /// The external formatter command with arguments, reading from stdin and writing to stdout.
+    pub command: Option<Vec<String>>,
+    /// Arguments appended to `command` for range formatting, if the formatter supports it.
+    /// `{start}` and `{end}` are replaced by byte offsets of the selection, and
+    /// `{startLine}` and `{endLine}` by its 1-based lines. The whole document is passed,
+    /// and expected back. Without it, only the selected text is passed to `command`.
+    pub range_args: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct BuiltinsConfig {
+    /// The Nix version whose builtins are offered, like `"2.18"`. Defaults to the latest.
+    pub version: Option<String>,
+    /// Only offer these builtins. Overrides `version`.
+    pub allowlist: Option<Vec<String>>,
+}
+
+impl BuiltinsConfig {
+    /// Invalid versions fall back to the latest.
+    pub fn builtin_set(&self) -> BuiltinSet {
+        if let Some(names) = &self.allowlist {
+            return BuiltinSet::Allowlist(names.iter().map(|name| name.into()).collect());
+        }
+        match self.version.as_deref().and_then(NixVersion::parse) {
+            Some(version) => BuiltinSet::Version(version),
+            None => BuiltinSet::Latest,
+        }
+    }
+}
+
+impl CodeActionsConfig {
+    /// Check if a code action is disabled by its id, or its kind or any parent kind.
+    /// Eg. `refactor` disables both `refactor` and `refactor.rewrite`.
+    pub fn is_disabled(&self, id: &str, kind: &str) -> bool {
+        self.disabled
+            .iter()
+            .any(|pat| pat == id || is_sub_kind(kind, pat))
+    }
+}
+
+/// Check if a code action kind is `parent` or any of its sub-kinds.
+/// Eg. `refactor.rewrite` is a sub-kind of `refactor`.
+pub fn is_sub_kind(kind: &str, parent: &str) -> bool {
+    matches!(kind.strip_prefix(parent), Some(rest) if rest.is_empty() || rest.starts_with('.'))
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_file_size_bytes: DEFAULT_MAX_FILE_SIZE_BYTES,
+            nixpkgs_hints: false,
+            code_actions: CodeActionsConfig::default(),
+            formatting: FormattingConfig::default(),
+            scan_threads: thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            scan: ScanConfig::default(),
+            clear_diagnostics_on_close: true,
+            report_panics: true,
+            reload_on_save: false,
+            prewarm: false,
+            builtins: BuiltinsConfig::default(),
+            idle_shutdown_secs: 0,
+            max_completion_items: 0,
+            diagnostics: DiagnosticsConfig::default(),
+            generated_file_globs: Vec::new(),
+            error_on_lints: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn is_file_too_large(&self, len: usize) -> bool {
+        len as u64 > self.max_file_size_bytes
+    }
+
+    /// Merge the values of the top-level section followed by each of [`CONFIG_SUB_SECTIONS`],
+    /// in the order of a `workspace/configuration` request, into a single config value.
+    /// Dotted keys like `"formatting.command"` are expanded into nested objects.
+    /// Values nested in the top-level section take precedence over separately queried sections.
+    pub fn merge_sections(values: Vec<Value>) -> Value {
+        let mut values = values.into_iter();
+        let mut root = match values.next() {
+            Some(Value::Object(obj)) => expand_dotted_keys(obj),
+            _ => Map::new(),
+        };
+        for (&section, value) in CONFIG_SUB_SECTIONS.iter().zip(values) {
+            if let Value::Object(obj) = value {
+                merge_into(
+                    root.entry(section).or_insert_with(|| Map::new().into()),
+                    Value::Object(expand_dotted_keys(obj)),
+                );
+            }
+        }
+        Value::Object(root)
+    }
+
+    /// Parse the content of a project config file into a config value.
+    /// Settings running external programs are dropped.
+    pub fn parse_project_file(text: &str) -> Result<Value, toml::de::Error> {
+        let mut value = toml::from_str::<Value>(text)?;
+        for &(section, key) in PROJECT_IGNORED_SETTINGS {
+            if let Some(Value::Object(obj)) = value.get_mut(section) {
+                obj.remove(key);
+            }
+        }
+        Ok(value)
+    }
+
+    /// Read the first existing project config file in `root`, if any.
+    /// The error message contains the path of the invalid file.
+    pub fn read_project_file(root: &Path) -> Option<Result<Value, String>> {
+        PROJECT_CONFIG_FILES.iter().find_map(|name| {
+            let path = root.join(name);
+            let text = fs::read_to_string(&path).ok()?;
+            match Self::parse_project_file(&text) {
+                Ok(value) => Some(Ok(value)),
+                Err(err) => Some(Err(format!("{}: {}", path.display(), err))),
+            }
+        })
+    }
+
+    /// Fill settings missing in the client config `client` from the project config `project`.
+    pub fn merge_project(mut client: Value, project: Value) -> Value {
+        merge_into(&mut client, project);
+        client
+    }
+
+    pub fn diagnostics_config(&self) -> ide::DiagnosticsConfig {
+        let replaceable_with = &self.diagnostics.replaceable_with;
+        let style = &self.diagnostics.style;
+        ide::DiagnosticsConfig {
+            replaceable_with_max_names: replaceable_with
+                .enable
+                .then_some(replaceable_with.max_names),
+            duplicate_list_elements: self.diagnostics.duplicate_list_element.enable,
+            broad_with_scope: self.diagnostics.broad_with_scope.enable,
+            builtins: self.builtins.builtin_set(),
+            trailing_whitespace: style.enable,
+            max_line_length: style.max_line_length.filter(|_| style.enable),
+        }
+    }
+
+    pub fn assists_config(&self) -> AssistsConfig {
+        AssistsConfig {
+            module_params: self
+                .code_actions
+                .module_params
+                .iter()
+                .map(|param| param.into())
+                .collect(),
+            builtins: self.builtins.builtin_set(),
+            trailing_whitespace: self.diagnostics.style.enable,
+        }
+    }
+
+    pub fn completion_config(&self, snippet_support: bool) -> CompletionConfig {
+        CompletionConfig {
+            nixpkgs_hints: self.nixpkgs_hints,
+            builtins: self.builtins.builtin_set(),
+            snippet_support,
+        }
+    }
+}
+
+fn expand_dotted_keys(obj: Map<String, Value>) -> Map<String, Value> {
+    let mut ret = Map::new();
+    for (key, value) in obj {
+        let value = match value {
+            Value::Object(obj) => Value::Object(expand_dotted_keys(obj)),
+            value => value,
+        };
+        let mut segments = key.split('.').rev();
+        let last = segments.next().unwrap_or_default();
+        let nested = segments.fold(Map::from_iter([(last.to_owned(), value)]), |inner, seg| {
+            Map::from_iter([(seg.to_owned(), Value::Object(inner))])
+        });
+        for (key, value) in nested {
+            merge_into(ret.entry(key).or_insert(Value::Null), value);
+        }
+    }
+    ret
+}
+
+/// Merge `src` into `dest` recursively. Existing non-object values in `dest` are kept.
+fn merge_into(dest: &mut Value, src: Value) {
+    match (dest, src) {
+        (Value::Object(dest), Value::Object(src)) => {
+            for (key, value) in src {
+                merge_into(dest.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (dest @ Value::Null, src) => *dest = src,
+        _ => {}
+    }
+}
+
+/// Client hints passed in `initializationOptions`.
+/// Unlike [`Config`], they are fixed after initialization.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct InitOptions {
+    /// The preferred maximum number of diagnostics per file.
+    pub max_reported_diagnostics: Option<usize>,
+}
+
+impl InitOptions {
+    /// The client can ask for fewer diagnostics than `MAX_DIAGNOSTICS_CNT`, but not more.
+    pub fn diagnostics_limit(&self) -> usize {
+        match self.max_reported_diagnostics {
+            Some(limit) => limit.min(MAX_DIAGNOSTICS_CNT),
+            None => MAX_DIAGNOSTICS_CNT,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Config, FormattingConfig, InitOptions, DEFAULT_MAX_FILE_SIZE_BYTES, MAX_DIAGNOSTICS_CNT,
+    };
+    use ide::{AssistsConfig, BuiltinSet, NixVersion};
+    use serde_json::{json, Value};
+
+    #[test]
+    fn deserialize() {
+        let config = serde_json::from_value::<Config>(json!({})).unwrap();
+        assert_eq!(config, Config::default());
+        assert_eq!(config.max_file_size_bytes, DEFAULT_MAX_FILE_SIZE_BYTES);
+
+        let config = serde_json::from_value::<Config>(json!({ "maxFileSizeBytes": 42 })).unwrap();
+        assert_eq!(config.max_file_size_bytes, 42);
+        assert!(!config.nixpkgs_hints);
+
+        let config = serde_json::from_value::<Config>(json!({ "nixpkgsHints": true })).unwrap();
+        assert!(config.nixpkgs_hints);
+
+        let config = serde_json::from_value::<Config>(json!({
+            "formatting": { "command": ["nixpkgs-fmt"] },
+        }))
+        .unwrap();
+        assert_eq!(config.formatting.command, Some(vec!["nixpkgs-fmt".into()]));
+        assert_eq!(config.formatting.range_args, None);
+        let config = serde_json::from_value::<Config>(json!({
+            "formatting": { "rangeArgs": ["--range", "{start}:{end}"] },
+        }))
+        .unwrap();
+        assert_eq!(
+            config.formatting.range_args,
+            Some(vec!["--range".into(), "{start}:{end}".into()]),
+        );
+
+        let config = serde_json::from_value::<Config>(json!({ "scanThreads": 3 })).unwrap();
+        assert_eq!(config.scan_threads, 3);
+        assert_ne!(Config::default().scan_threads, 0);
+
+        assert!(!Config::default().scan.lazy);
+        let config = serde_json::from_value::<Config>(json!({ "scan": { "lazy": true } })).unwrap();
+        assert!(config.scan.lazy);
+        assert!(!config.scan.on_demand);
+        let config =
+            serde_json::from_value::<Config>(json!({ "scan": { "onDemand": true } })).unwrap();
+        assert!(config.scan.on_demand);
+
+        assert!(Config::default().clear_diagnostics_on_close);
+        let config =
+            serde_json::from_value::<Config>(json!({ "clearDiagnosticsOnClose": false })).unwrap();
+        assert!(!config.clear_diagnostics_on_close);
+
+        assert!(Config::default().report_panics);
+        let config = serde_json::from_value::<Config>(json!({ "reportPanics": false })).unwrap();
+        assert!(!config.report_panics);
+
+        assert!(!Config::default().reload_on_save);
+        let config = serde_json::from_value::<Config>(json!({ "reloadOnSave": true })).unwrap();
+        assert!(config.reload_on_save);
+
+        let config = serde_json::from_value::<Config>(json!({ "prewarm": true })).unwrap();
+        assert!(config.prewarm);
+
+        assert_eq!(Config::default().idle_shutdown_secs, 0);
+        let config = serde_json::from_value::<Config>(json!({ "idleShutdownSecs": 600 })).unwrap();
+        assert_eq!(config.idle_shutdown_secs, 600);
+
+        assert_eq!(Config::default().max_completion_items, 0);
+        let config =
+            serde_json::from_value::<Config>(json!({ "maxCompletionItems": 100 })).unwrap();
+        assert_eq!(config.max_completion_items, 100);
+
+        let config =
+            serde_json::from_value::<Config>(json!({ "errorOnLints": ["unused_binding"] }))
+                .unwrap();
+        assert_eq!(config.error_on_lints, ["unused_binding"]);
+
+        assert_eq!(Config::default().assists_config(), AssistsConfig::default());
+        let config = serde_json::from_value::<Config>(json!({
+            "codeActions": { "moduleParams": ["lib"] },
+        }))
+        .unwrap();
+        assert_eq!(config.assists_config().module_params, ["lib"]);
+
+        assert_eq!(
+            Config::default().diagnostics_config(),
+            ide::DiagnosticsConfig::default(),
+        );
+        let config = serde_json::from_value::<Config>(json!({
+            "diagnostics": { "replaceableWith": { "enable": true } },
+        }))
+        .unwrap();
+        assert_eq!(
+            config.diagnostics_config().replaceable_with_max_names,
+            Some(2),
+        );
+        let config = serde_json::from_value::<Config>(json!({
+            "diagnostics": { "duplicateListElement": { "enable": true } },
+        }))
+        .unwrap();
+        assert!(config.diagnostics_config().duplicate_list_elements);
+        let config = serde_json::from_value::<Config>(json!({
+            "diagnostics": { "broadWithScope": { "enable": true } },
+        }))
+        .unwrap();
+        assert!(config.diagnostics_config().broad_with_scope);
+        let config = serde_json::from_value::<Config>(json!({
+            "diagnostics": { "style": { "enable": true } },
+        }))
+        .unwrap();
+        let diagnostics = config.diagnostics_config();
+        assert!(diagnostics.trailing_whitespace);
+        assert_eq!(diagnostics.max_line_length, Some(100));
+        assert!(config.assists_config().trailing_whitespace);
+        let config = serde_json::from_value::<Config>(json!({
+            "diagnostics": { "style": { "enable": true, "maxLineLength": null } },
+        }))
+        .unwrap();
+        let diagnostics = config.diagnostics_config();
+        assert!(diagnostics.trailing_whitespace);
+        assert_eq!(diagnostics.max_line_length, None);
+    }
+
+    #[test]
+    fn merge_sections() {
+        // A realistic response of `nil`, `nil.codeActions`, `nil.formatting` and `nil.builtins`,
+        // from a client storing settings by dotted keys.
+        let value = Config::merge_sections(vec![
+            json!({
+                "maxFileSizeBytes": 1024,
+                "nixpkgsHints": true,
+                "formatting.command": ["nixpkgs-fmt"],
+                "builtins": { "version": "2.18" },
+            }),
+            json!({ "disabled": ["refactor"] }),
+            json!({ "command": ["alejandra"] }),
+            json!({ "version": "2.3", "allowlist": null }),
+        ]);
+        assert_eq!(
+            value,
+            json!({
+                "maxFileSizeBytes": 1024,
+                "nixpkgsHints": true,
+                "codeActions": { "disabled": ["refactor"] },
+                "formatting": { "command": ["nixpkgs-fmt"] },
+                "builtins": { "version": "2.18", "allowlist": null },
+            }),
+        );
+        let config = serde_json::from_value::<Config>(value).unwrap();
+        assert_eq!(config.max_file_size_bytes, 1024);
+        assert!(config.nixpkgs_hints);
+        assert_eq!(config.code_actions.disabled, ["refactor"]);
+        assert_eq!(config.formatting.command, Some(vec!["nixpkgs-fmt".into()]));
+        assert_eq!(config.builtins.version.as_deref(), Some("2.18"));
+
+        // Missing or null sections.
+        assert_eq!(Config::merge_sections(Vec::new()), json!({}));
+        assert_eq!(
+            Config::merge_sections(vec![Value::Null, Value::Null, json!({ "command": ["a"] })]),
+            json!({ "formatting": { "command": ["a"] } }),
+        );
+    }
+
+    #[test]
+    fn project_file() {
+        let project = Config::parse_project_file(
+            r#"
+nixpkgsHints = true
+maxFileSizeBytes = 1024
+
+[formatting]
+command = ["nixfmt"]
+rangeArgs = ["--range"]
+
+[diagnostics.duplicateListElement]
+enable = true
+"#,
+        )
+        .unwrap();
+        let config = serde_json::from_value::<Config>(project.clone()).unwrap();
+        assert!(config.nixpkgs_hints);
+        assert_eq!(config.max_file_size_bytes, 1024);
+        // Commands are only accepted from the client.
+        assert_eq!(config.formatting, FormattingConfig::default());
+        assert!(config.diagnostics.duplicate_list_element.enable);
+        assert!(config.clear_diagnostics_on_close);
+
+        // Client settings take precedence. Null ones are unset.
+        let value = Config::merge_project(
+            json!({
+                "nixpkgsHints": false,
+                "maxFileSizeBytes": null,
+                "formatting": { "command": ["alejandra"] },
+            }),
+            project,
+        );
+        let config = serde_json::from_value::<Config>(value).unwrap();
+        assert!(!config.nixpkgs_hints);
+        assert_eq!(config.max_file_size_bytes, 1024);
+        assert_eq!(config.formatting.command, Some(vec!["alejandra".into()]));
+        assert!(config.diagnostics.duplicate_list_element.enable);
+
+        assert!(Config::parse_project_file("nixpkgsHints = ").is_err());
+    }
+
+    #[test]
+    fn builtins() {
+        let builtin_set = |value| {
+            serde_json::from_value::<Config>(json!({ "builtins": value }))
+                .unwrap()
+                .builtins
+                .builtin_set()
+        };
+        assert_eq!(builtin_set(json!({})), BuiltinSet::Latest);
+        assert_eq!(
+            builtin_set(json!({ "version": "2.18" })),
+            BuiltinSet::Version(NixVersion::new(2, 18)),
+        );
+        assert_eq!(
+            builtin_set(json!({ "version": "latest" })),
+            BuiltinSet::Latest
+        );
+        assert_eq!(
+            builtin_set(json!({ "version": "2.18", "allowlist": ["map"] })),
+            BuiltinSet::Allowlist(vec!["map".into()]),
+        );
+    }
+
+    #[test]
+    fn code_actions_disabled() {
+        let config = serde_json::from_value::<Config>(json!({
+            "codeActions": { "disabled": ["remove_unused_inherited_name", "refactor"] },
+        }))
+        .unwrap();
+        let code_actions = &config.code_actions;
+        assert!(code_actions.is_disabled("remove_unused_inherited_name", "quickfix"));
+        assert!(!code_actions.is_disabled("simplify_constant_condition", "quickfix"));
+        assert!(code_actions.is_disabled("foo", "refactor"));
+        assert!(code_actions.is_disabled("foo", "refactor.rewrite"));
+        assert!(!code_actions.is_disabled("foo", "refactoring"));
+
+        let config = Config::default();
+        assert!(!config
+            .code_actions
+            .is_disabled("remove_unused_inherited_name", "quickfix"));
+    }
+
+    #[test]
+    fn file_size_boundary() {
+        let config = Config {
+            max_file_size_bytes: 100,
+            ..Config::default()
+        };
+        assert!(!config.is_file_too_large(0));
+        assert!(!config.is_file_too_large(99));
+        assert!(!config.is_file_too_large(100));
+        assert!(config.is_file_too_large(101));
+
+        let config = Config::default();
+        assert!(!config.is_file_too_large(2 * 1024 * 1024));
+        assert!(config.is_file_too_large(2 * 1024 * 1024 + 1));
+    }
+
+    #[test]
+    fn diagnostics_limit() {
+        let limit = |v| {
+            serde_json::from_value::<InitOptions>(v)
+                .unwrap()
+                .diagnostics_limit()
+        };
+        assert_eq!(limit(json!({})), MAX_DIAGNOSTICS_CNT);
+        assert_eq!(
+            limit(json!({ "maxReportedDiagnostics": null })),
+            MAX_DIAGNOSTICS_CNT
+        );
+        assert_eq!(limit(json!({ "maxReportedDiagnostics": 10 })), 10);
+        assert_eq!(limit(json!({ "maxReportedDiagnostics": 0 })), 0);
+        assert_eq!(
+            limit(json!({ "maxReportedDiagnostics": MAX_DIAGNOSTICS_CNT + 1 })),
+            MAX_DIAGNOSTICS_CNT,
+        );
+        assert_eq!(limit(json!({ "unknown": 1 })), MAX_DIAGNOSTICS_CNT);
+    }
+}
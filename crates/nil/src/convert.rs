@@ -1,7 +1,11 @@
-use crate::{semantic_tokens, LineMap, LspError, Result, Vfs};
+use crate::lsp_ext;
+use crate::semantic_tokens::{self, NegotiatedLegend};
+use crate::{LineMap, LspError, Result, Vfs};
 use ide::{
-    CompletionItem, CompletionItemKind, Diagnostic, FileId, FilePos, FileRange, HlRange,
-    HoverResult, Severity, TextEdit, WorkspaceEdit,
+    Assist, AssistKind, CompletionItem, CompletionItemKind, DefUseGraph, DefUseNodeKind,
+    Diagnostic, FileId, FileImport, FilePos, FileRange, FileSystemEdit, FoldingRange,
+    FoldingRangeKind, HlRange, HoverResult, ParseStatus, ResolvedName, ResolvedNameKind, Severity,
+    TextEdit, WorkspaceEdit,
 };
 use lsp_server::ErrorCode;
 use lsp_types::{
@@ -9,6 +13,7 @@ use lsp_types::{
     Hover, Location, MarkupContent, MarkupKind, Position, PrepareRenameResponse, Range,
     SemanticToken, TextDocumentIdentifier, TextDocumentPositionParams,
 };
+use std::collections::HashMap;
 use std::sync::Arc;
 use text_size::{TextRange, TextSize};
 
@@ -53,10 +58,15 @@ pub(crate) fn to_range(line_map: &LineMap, range: TextRange) -> Range {
     Range::new(Position::new(line1, col1), Position::new(line2, col2))
 }
 
+/// Convert diagnostics of `file`.
+/// Notes are emitted as related information, which may locate in other files,
+/// if `related_information` is supported by the client.
+/// Notes in the same file are also hoisted to top-level hints.
 pub(crate) fn to_diagnostics(
     vfs: &Vfs,
     file: FileId,
     diags: &[Diagnostic],
+    related_information: bool,
 ) -> Vec<lsp::Diagnostic> {
     let line_map = vfs.line_map_for_file(file);
     let mut ret = Vec::with_capacity(diags.len() * 2);
@@ -65,23 +75,22 @@ pub(crate) fn to_diagnostics(
             severity: match diag.severity() {
                 Severity::Error | Severity::IncompleteSyntax => Some(DiagnosticSeverity::ERROR),
                 Severity::Warning => Some(DiagnosticSeverity::WARNING),
+                Severity::Hint => Some(DiagnosticSeverity::HINT),
             },
             range: to_range(&line_map, diag.range),
-            code: None,
+            code: Some(lsp::NumberOrString::String(diag.code().into())),
             code_description: None,
             source: None,
             message: diag.message(),
-            related_information: {
-                Some(
-                    diag.notes
-                        .iter()
-                        .map(|(frange, msg)| DiagnosticRelatedInformation {
-                            location: to_location(vfs, *frange),
-                            message: msg.to_owned(),
-                        })
-                        .collect(),
-                )
-            },
+            related_information: related_information.then(|| {
+                diag.notes
+                    .iter()
+                    .map(|(frange, msg)| DiagnosticRelatedInformation {
+                        location: to_location(vfs, *frange),
+                        message: msg.to_owned(),
+                    })
+                    .collect()
+            }),
             tags: {
                 let mut tags = Vec::new();
                 if diag.is_deprecated() {
@@ -97,7 +106,7 @@ pub(crate) fn to_diagnostics(
 
         // Hoist related information to top-level Hints.
         for (frange, msg) in &diag.notes {
-            // We cannot handle cross-file diagnostics here.
+            // Hints are published per file. Notes in other files are only related information.
             if frange.file_id != file {
                 continue;
             }
@@ -109,10 +118,12 @@ pub(crate) fn to_diagnostics(
                 code_description: primary_diag.code_description.clone(),
                 source: primary_diag.source.clone(),
                 message: msg.into(),
-                related_information: Some(vec![DiagnosticRelatedInformation {
-                    location: to_location(vfs, FileRange::new(file, diag.range)),
-                    message: "original diagnostic".into(),
-                }]),
+                related_information: related_information.then(|| {
+                    vec![DiagnosticRelatedInformation {
+                        location: to_location(vfs, FileRange::new(file, diag.range)),
+                        message: "original diagnostic".into(),
+                    }]
+                }),
                 tags: None,
                 data: None,
             });
@@ -130,15 +141,29 @@ pub(crate) fn to_completion_item(line_map: &LineMap, item: CompletionItem) -> ls
         CompletionItemKind::Param => lsp::CompletionItemKind::VARIABLE,
         CompletionItemKind::LetBinding => lsp::CompletionItemKind::VARIABLE,
         CompletionItemKind::Field => lsp::CompletionItemKind::FIELD,
+        CompletionItemKind::Function => lsp::CompletionItemKind::FUNCTION,
         CompletionItemKind::BuiltinConst => lsp::CompletionItemKind::CONSTANT,
         CompletionItemKind::BuiltinFunction => lsp::CompletionItemKind::FUNCTION,
-        CompletionItemKind::BuiltinAttrset => lsp::CompletionItemKind::CLASS,
+        CompletionItemKind::BuiltinAttrset => lsp::CompletionItemKind::MODULE,
+    };
+    let additional_text_edits = (!item.additional_edits.is_empty()).then(|| {
+        item.additional_edits
+            .into_iter()
+            .map(|edit| to_text_edit(line_map, edit))
+            .collect()
+    });
+    let insert_text_format = if item.is_snippet {
+        lsp::InsertTextFormat::SNIPPET
+    } else {
+        lsp::InsertTextFormat::PLAIN_TEXT
     };
+    // Sort deprecated items last. `~` is after all characters of identifiers.
+    let sort_text = item.deprecated.then(|| format!("~{}", item.label));
     lsp::CompletionItem {
         label: item.label.into(),
         kind: Some(kind),
         insert_text: None,
-        insert_text_format: Some(lsp::InsertTextFormat::PLAIN_TEXT),
+        insert_text_format: Some(insert_text_format),
         // We don't support indentation yet.
         insert_text_mode: Some(lsp::InsertTextMode::ADJUST_INDENTATION),
         text_edit: Some(lsp::CompletionTextEdit::Edit(lsp::TextEdit {
@@ -146,6 +171,7 @@ pub(crate) fn to_completion_item(line_map: &LineMap, item: CompletionItem) -> ls
             new_text: item.replace.into(),
         })),
         detail: item.brief,
+        additional_text_edits,
         documentation: item.doc.map(|doc| {
             Documentation::MarkupContent(MarkupContent {
                 kind: MarkupKind::Markdown,
@@ -153,16 +179,33 @@ pub(crate) fn to_completion_item(line_map: &LineMap, item: CompletionItem) -> ls
             })
         }),
 
-        // TODO
-        deprecated: None,
+        // `deprecated` is itself deprecated by `tags`, but older clients only support the former.
+        deprecated: item.deprecated.then_some(true),
         preselect: None,
-        sort_text: None,
+        sort_text,
         filter_text: None,
-        additional_text_edits: None,
         command: None,
         commit_characters: None,
         data: None,
-        tags: None,
+        tags: item
+            .deprecated
+            .then(|| vec![lsp::CompletionItemTag::DEPRECATED]),
+    }
+}
+
+pub(crate) fn to_folding_range(line_map: &LineMap, fold: FoldingRange) -> lsp::FoldingRange {
+    let (start_line, start_col) = line_map.line_col_for_pos(fold.range.start());
+    let (end_line, end_col) = line_map.line_col_for_pos(fold.range.end());
+    let kind = match fold.kind {
+        FoldingRangeKind::Region => lsp::FoldingRangeKind::Region,
+        FoldingRangeKind::Comment => lsp::FoldingRangeKind::Comment,
+    };
+    lsp::FoldingRange {
+        start_line,
+        start_character: Some(start_col),
+        end_line,
+        end_character: Some(end_col),
+        kind: Some(kind),
     }
 }
 
@@ -185,29 +228,354 @@ pub(crate) fn to_prepare_rename_response(
     }
 }
 
-pub(crate) fn to_workspace_edit(vfs: &Vfs, ws_edit: WorkspaceEdit) -> lsp::WorkspaceEdit {
-    let content_edits = ws_edit
-        .content_edits
+/// Convert a workspace edit. Edits of opened documents carry their versions in `doc_versions`,
+/// which is `None` if the client doesn't support `documentChanges`.
+pub(crate) fn to_workspace_edit(
+    vfs: &Vfs,
+    ws_edit: WorkspaceEdit,
+    doc_versions: Option<&HashMap<lsp::Url, i32>>,
+) -> lsp::WorkspaceEdit {
+    let content_edits = ws_edit.content_edits.into_iter().map(|(file, edits)| {
+        let line_map = vfs.line_map_for_file(file);
+        let edits = edits
+            .into_iter()
+            .map(|edit| to_text_edit(&line_map, edit))
+            .collect::<Vec<_>>();
+        (file, edits)
+    });
+
+    // Fallback to plain `changes` if the client doesn't support `documentChanges`,
+    // which is still required to create or delete files.
+    if doc_versions.is_none() && ws_edit.file_system_edits.is_empty() {
+        return lsp::WorkspaceEdit {
+            changes: Some(
+                content_edits
+                    .map(|(file, edits)| (vfs.uri_for_file(file), edits))
+                    .collect(),
+            ),
+            document_changes: None,
+            change_annotations: None,
+        };
+    }
+
+    let text_document_edit = |uri: lsp::Url, edits: Vec<lsp::TextEdit>| lsp::TextDocumentEdit {
+        text_document: lsp::OptionalVersionedTextDocumentIdentifier {
+            version: doc_versions.and_then(|versions| versions.get(&uri).copied()),
+            uri,
+        },
+        edits: edits.into_iter().map(lsp::OneOf::Left).collect(),
+    };
+
+    let mut content_edits = content_edits.collect::<Vec<_>>();
+    content_edits.sort_by_key(|(file, _)| file.0);
+    let content_edits = content_edits
         .into_iter()
-        .map(|(file, edits)| {
-            let uri = vfs.uri_for_file(file);
-            let edits = edits
-                .into_iter()
-                .map(|edit| {
-                    let line_map = vfs.line_map_for_file(file);
-                    to_text_edit(&line_map, edit)
-                })
-                .collect();
-            (uri, edits)
-        })
+        .map(|(file, edits)| text_document_edit(vfs.uri_for_file(file), edits));
+
+    // Resource operations are not necessarily supported along with `documentChanges`.
+    if ws_edit.file_system_edits.is_empty() {
+        return lsp::WorkspaceEdit {
+            changes: None,
+            document_changes: Some(lsp::DocumentChanges::Edits(content_edits.collect())),
+            change_annotations: None,
+        };
+    }
+
+    let (mut creates, mut deletes) = (Vec::new(), Vec::new());
+    for edit in ws_edit.file_system_edits {
+        match edit {
+            FileSystemEdit::CreateFile { path, content } => {
+                let uri = vfs.uri_for_path(&path);
+                creates.push(lsp::DocumentChangeOperation::Op(lsp::ResourceOp::Create(
+                    lsp::CreateFile {
+                        uri: uri.clone(),
+                        options: None,
+                        annotation_id: None,
+                    },
+                )));
+                if !content.is_empty() {
+                    let edit = lsp::TextEdit {
+                        range: Range::default(),
+                        new_text: content,
+                    };
+                    creates.push(lsp::DocumentChangeOperation::Edit(text_document_edit(
+                        uri,
+                        vec![edit],
+                    )));
+                }
+            }
+            FileSystemEdit::DeleteFile { file } => {
+                deletes.push(lsp::DocumentChangeOperation::Op(lsp::ResourceOp::Delete(
+                    lsp::DeleteFile {
+                        uri: vfs.uri_for_file(file),
+                        options: None,
+                    },
+                )));
+            }
+        }
+    }
+
+    let ops = creates
+        .into_iter()
+        .chain(content_edits.map(lsp::DocumentChangeOperation::Edit))
+        .chain(deletes)
         .collect();
     lsp::WorkspaceEdit {
-        changes: Some(content_edits),
-        document_changes: None,
+        changes: None,
+        document_changes: Some(lsp::DocumentChanges::Operations(ops)),
         change_annotations: None,
     }
 }
 
+/// Attach a single change annotation `id` to all text edits of a workspace edit.
+/// Only edits in `documentChanges` can be annotated.
+pub(crate) fn annotate_workspace_edit(
+    ws_edit: &mut lsp::WorkspaceEdit,
+    id: &str,
+    annotation: lsp::ChangeAnnotation,
+) {
+    let doc_edits: Vec<&mut lsp::TextDocumentEdit> = match &mut ws_edit.document_changes {
+        None => return,
+        Some(lsp::DocumentChanges::Edits(edits)) => edits.iter_mut().collect(),
+        Some(lsp::DocumentChanges::Operations(ops)) => ops
+            .iter_mut()
+            .filter_map(|op| match op {
+                lsp::DocumentChangeOperation::Edit(edit) => Some(edit),
+                lsp::DocumentChangeOperation::Op(_) => None,
+            })
+            .collect(),
+    };
+    for edit in doc_edits.into_iter().flat_map(|doc| &mut doc.edits) {
+        if let lsp::OneOf::Left(text_edit) = edit {
+            *edit = lsp::OneOf::Right(lsp::AnnotatedTextEdit {
+                text_edit: text_edit.clone(),
+                annotation_id: id.into(),
+            });
+        }
+    }
+    ws_edit.change_annotations = Some(HashMap::from([(id.into(), annotation)]));
+}
+
+pub(crate) fn to_code_action_kind(kind: AssistKind) -> lsp::CodeActionKind {
+    match kind {
+        AssistKind::QuickFix => lsp::CodeActionKind::QUICKFIX,
+        AssistKind::RefactorRewrite => lsp::CodeActionKind::REFACTOR_REWRITE,
+    }
+}
+
+pub(crate) fn to_code_action(
+    vfs: &Vfs,
+    file: FileId,
+    assist: Assist,
+    doc_versions: Option<&HashMap<lsp::Url, i32>>,
+) -> lsp::CodeAction {
+    let kind = to_code_action_kind(assist.kind);
+    let ws_edit = WorkspaceEdit {
+        content_edits: HashMap::from([(file, assist.edits)]),
+        file_system_edits: Vec::new(),
+    };
+    lsp::CodeAction {
+        title: assist.label,
+        kind: Some(kind),
+        diagnostics: None,
+        edit: Some(to_workspace_edit(vfs, ws_edit, doc_versions)),
+        command: None,
+        is_preferred: None,
+        disabled: None,
+        data: None,
+    }
+}
+
+pub(crate) fn to_code_action_stub(
+    assist: Assist,
+    data: lsp_ext::CodeActionData,
+) -> lsp::CodeAction {
+    lsp::CodeAction {
+        title: assist.label,
+        kind: Some(to_code_action_kind(assist.kind)),
+        diagnostics: None,
+        edit: None,
+        command: None,
+        is_preferred: None,
+        disabled: None,
+        data: Some(serde_json::to_value(data).unwrap()),
+    }
+}
+
+/// A source action formatting the document by `nil.formatDocument`.
+pub(crate) fn to_format_document_action(
+    formatter: &str,
+    text_document: lsp::TextDocumentIdentifier,
+) -> lsp::CodeAction {
+    let title = format!("Format with {formatter}");
+    lsp::CodeAction {
+        title: title.clone(),
+        kind: Some(lsp::CodeActionKind::SOURCE),
+        diagnostics: None,
+        edit: None,
+        command: Some(lsp::Command {
+            title,
+            command: lsp_ext::FORMAT_DOCUMENT_COMMAND.into(),
+            arguments: Some(vec![serde_json::to_value(text_document).unwrap()]),
+        }),
+        is_preferred: None,
+        disabled: None,
+        data: None,
+    }
+}
+
+/// Edits of a single document, versioned if `doc_versions` is given.
+pub(crate) fn to_document_edit(
+    uri: lsp::Url,
+    edits: Vec<lsp::TextEdit>,
+    doc_versions: Option<&HashMap<lsp::Url, i32>>,
+) -> lsp::WorkspaceEdit {
+    match doc_versions {
+        None => lsp::WorkspaceEdit {
+            changes: Some(HashMap::from([(uri, edits)])),
+            document_changes: None,
+            change_annotations: None,
+        },
+        Some(versions) => lsp::WorkspaceEdit {
+            changes: None,
+            document_changes: Some(lsp::DocumentChanges::Edits(vec![lsp::TextDocumentEdit {
+                text_document: lsp::OptionalVersionedTextDocumentIdentifier {
+                    version: versions.get(&uri).copied(),
+                    uri,
+                },
+                edits: edits.into_iter().map(lsp::OneOf::Left).collect(),
+            }])),
+            change_annotations: None,
+        },
+    }
+}
+
+pub(crate) fn to_content_modified_error(message: String) -> LspError {
+    LspError {
+        code: ErrorCode::ContentModified,
+        message,
+    }
+}
+
+pub(crate) fn to_file_import(vfs: &Vfs, import: FileImport, incoming: bool) -> lsp_ext::FileImport {
+    lsp_ext::FileImport {
+        location: to_location(vfs, import.site),
+        target: vfs.uri_for_file(import.target),
+        incoming,
+    }
+}
+
+pub(crate) fn to_def_use_graph(
+    line_map: &LineMap,
+    graph: DefUseGraph,
+) -> lsp_ext::DefUseGraphResult {
+    let nodes = graph
+        .nodes
+        .into_iter()
+        .map(|node| lsp_ext::DefUseNode {
+            id: node.id,
+            name: node.name.into(),
+            range: to_range(line_map, node.range),
+            kind: match node.kind {
+                DefUseNodeKind::Definition => lsp_ext::DefUseNodeKind::Definition,
+                DefUseNodeKind::Inherited => lsp_ext::DefUseNodeKind::Inherited,
+                DefUseNodeKind::With => lsp_ext::DefUseNodeKind::With,
+            },
+        })
+        .collect();
+    let edges = graph
+        .edges
+        .into_iter()
+        .map(|edge| lsp_ext::DefUseEdge {
+            range: to_range(line_map, edge.use_range),
+            target: edge.def,
+        })
+        .collect();
+    lsp_ext::DefUseGraphResult { nodes, edges }
+}
+
+pub(crate) fn to_parse_status(status: ParseStatus) -> lsp_ext::ParseStatusResult {
+    lsp_ext::ParseStatusResult {
+        has_errors: status.has_errors(),
+        error_count: status.error_count,
+        error_kinds: status
+            .error_kinds
+            .into_iter()
+            .map(|(kind, count)| lsp_ext::ParseErrorKindCount {
+                kind: kind.to_string(),
+                count,
+            })
+            .collect(),
+        error_node_count: status.error_node_count,
+    }
+}
+
+pub(crate) fn to_name_resolution_entry(
+    line_map: &LineMap,
+    name: ResolvedName,
+) -> lsp_ext::NameResolutionEntry {
+    lsp_ext::NameResolutionEntry {
+        range: to_range(line_map, name.range),
+        resolution: match name.kind {
+            ResolvedNameKind::Definition => lsp_ext::NameResolutionKind::Definition,
+            ResolvedNameKind::Builtin => lsp_ext::NameResolutionKind::Builtin,
+            ResolvedNameKind::WithScope => lsp_ext::NameResolutionKind::WithScope,
+            ResolvedNameKind::Unresolved => lsp_ext::NameResolutionKind::Unresolved,
+        },
+        targets: name
+            .targets
+            .into_iter()
+            .map(|range| to_range(line_map, range))
+            .collect(),
+    }
+}
+
+/// Replace `range` of `src` with the formatted text, or return no edits if nothing changes.
+/// Identical replacements are avoided since some editors mishandle them, like moving the cursor.
+pub(crate) fn to_formatting_edits(
+    line_map: &LineMap,
+    src: &str,
+    range: TextRange,
+    formatted: String,
+) -> Vec<lsp::TextEdit> {
+    let formatted = formatted.replace('\r', "");
+    if src[range] == formatted {
+        return Vec::new();
+    }
+    vec![lsp::TextEdit {
+        range: to_range(line_map, range),
+        new_text: formatted,
+    }]
+}
+
+/// The edit replacing `src` with `formatted`, limited to the changed part between their common
+/// prefix and suffix. Returns no edits if nothing changes.
+pub(crate) fn to_changed_edits(
+    line_map: &LineMap,
+    src: &str,
+    formatted: String,
+) -> Vec<lsp::TextEdit> {
+    let formatted = formatted.replace('\r', "");
+    let prefix = src
+        .char_indices()
+        .zip(formatted.chars())
+        .find(|((_, lhs), rhs)| lhs != rhs)
+        .map_or(src.len().min(formatted.len()), |((i, _), _)| i);
+    let suffix = src[prefix..]
+        .chars()
+        .rev()
+        .zip(formatted[prefix..].chars().rev())
+        .take_while(|(lhs, rhs)| lhs == rhs)
+        .map(|(c, _)| c.len_utf8())
+        .sum::<usize>();
+    let range = TextRange::new(
+        TextSize::from(prefix as u32),
+        TextSize::from((src.len() - suffix) as u32),
+    );
+    let new_text = formatted[prefix..formatted.len() - suffix].to_owned();
+    to_formatting_edits(line_map, src, range, new_text)
+}
+
 pub(crate) fn to_text_edit(line_map: &LineMap, edit: TextEdit) -> lsp::TextEdit {
     lsp::TextEdit {
         range: to_range(line_map, edit.delete),
@@ -215,7 +583,11 @@ pub(crate) fn to_text_edit(line_map: &LineMap, edit: TextEdit) -> lsp::TextEdit
     }
 }
 
-pub(crate) fn to_semantic_tokens(line_map: &LineMap, hls: &[HlRange]) -> Vec<SemanticToken> {
+pub(crate) fn to_semantic_tokens(
+    line_map: &LineMap,
+    legend: &NegotiatedLegend,
+    hls: &[HlRange],
+) -> Vec<SemanticToken> {
     // We must now exceed the last line.
     let line_count = line_map.line_count();
     if line_count == 0 {
@@ -227,6 +599,12 @@ pub(crate) fn to_semantic_tokens(line_map: &LineMap, hls: &[HlRange]) -> Vec<Sem
     let (mut prev_line, mut prev_start) = (0, 0);
     for hl in hls {
         let (ty_idx, mod_set) = semantic_tokens::to_semantic_type_and_modifiers(hl.tag);
+        // Drop tokens whose type is not supported by the client.
+        let token_type = match legend.map_type(ty_idx) {
+            Some(ty) => ty,
+            None => continue,
+        };
+        let token_modifiers_bitset = legend.map_modifiers(mod_set);
         let range = to_range(line_map, hl.range);
         for line in range.start.line..=range.end.line.min(last_line) {
             // N.B. For relative encoding, column offset is relative to
@@ -256,8 +634,8 @@ pub(crate) fn to_semantic_tokens(line_map: &LineMap, hls: &[HlRange]) -> Vec<Sem
                 delta_line: line - prev_line,
                 delta_start: start - prev_start,
                 length: end - start,
-                token_type: ty_idx as u32,
-                token_modifiers_bitset: mod_set.0,
+                token_type,
+                token_modifiers_bitset,
             });
 
             (prev_line, prev_start) = (line, start);
@@ -267,12 +645,604 @@ pub(crate) fn to_semantic_tokens(line_map: &LineMap, hls: &[HlRange]) -> Vec<Sem
     toks
 }
 
-pub(crate) fn to_hover(line_map: &LineMap, hover: HoverResult) -> Hover {
-    Hover {
-        range: Some(to_range(line_map, hover.range)),
-        contents: lsp::HoverContents::Markup(MarkupContent {
+pub(crate) fn to_hover(line_map: &LineMap, hover: HoverResult, markdown: bool) -> Hover {
+    let contents = if markdown {
+        MarkupContent {
             kind: MarkupKind::Markdown,
             value: hover.markup,
-        }),
+        }
+    } else {
+        MarkupContent {
+            kind: MarkupKind::PlainText,
+            value: markdown_to_plaintext(&hover.markup),
+        }
+    };
+    Hover {
+        range: Some(to_range(line_map, hover.range)),
+        contents: lsp::HoverContents::Markup(contents),
+    }
+}
+
+/// Strip Markdown formatting for clients rendering text as-is.
+/// Only constructs produced by hover and builtin docs are handled: code spans and fences,
+/// strong emphasis, headings and links.
+fn markdown_to_plaintext(markup: &str) -> String {
+    let mut out = String::with_capacity(markup.len());
+    let mut in_fence = false;
+    for line in markup.lines() {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            out += line;
+        } else {
+            let line = match line.trim_start_matches('#').strip_prefix(' ') {
+                Some(heading) if line.starts_with('#') => heading,
+                _ => line,
+            };
+            out += &strip_links(line).replace("**", "").replace('`', "");
+        }
+        out.push('\n');
+    }
+    if !markup.ends_with('\n') {
+        out.pop();
+    }
+    out
+}
+
+/// Replace links `[text](url)` by `text (url)`.
+fn strip_links(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(open) = rest.find('[') {
+        let (before, after) = rest.split_at(open);
+        out += before;
+        let link = after[1..].split_once("](").and_then(|(text, tail)| {
+            let (url, tail) = tail.split_once(')')?;
+            (!text.contains('[')).then_some((text, url, tail))
+        });
+        match link {
+            Some((text, url, tail)) => {
+                out += text;
+                out += " (";
+                out += url;
+                out += ")";
+                rest = tail;
+            }
+            None => {
+                out.push('[');
+                rest = &after[1..];
+            }
+        }
+    }
+    out += rest;
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        to_changed_edits, to_completion_item, to_diagnostics, to_formatting_edits, to_hover,
+        to_semantic_tokens, to_workspace_edit,
+    };
+    use crate::semantic_tokens::NegotiatedLegend;
+    use crate::Vfs;
+    use ide::{
+        CompletionItem, CompletionItemKind, Diagnostic, DiagnosticKind, FileRange, FileSystemEdit,
+        HlPunct, HlRange, HlTag, HoverResult, TextEdit, VfsPath, WorkspaceEdit,
+    };
+    use lsp_types::{self as lsp, SemanticToken, SemanticTokenModifier, SemanticTokenType};
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use text_size::TextRange;
+
+    #[test]
+    fn semantic_tokens_unsupported_type() {
+        let mut vfs = Vfs::new(PathBuf::from("/"));
+        vfs.set_path_content(VfsPath::new("/default.nix").unwrap(), "./a + 1".into());
+        let file = vfs
+            .file_for_uri(&"file:///default.nix".parse().unwrap())
+            .unwrap();
+        let line_map = vfs.line_map_for_file(file);
+        let hls = [
+            HlRange {
+                range: TextRange::new(0.into(), 3.into()),
+                tag: HlTag::Path,
+            },
+            HlRange {
+                range: TextRange::new(6.into(), 7.into()),
+                tag: HlTag::IntLiteral,
+            },
+        ];
+
+        let legend = NegotiatedLegend::default();
+        let toks = to_semantic_tokens(&line_map, &legend, &hls);
+        assert_eq!(toks.len(), 2);
+
+        // The client doesn't know `path`.
+        let legend = NegotiatedLegend::new(&[SemanticTokenType::NUMBER], &[]);
+        let toks = to_semantic_tokens(&line_map, &legend, &hls);
+        assert_eq!(
+            toks,
+            [SemanticToken {
+                delta_line: 0,
+                delta_start: 6,
+                length: 1,
+                token_type: 0,
+                token_modifiers_bitset: 0,
+            }]
+        );
+    }
+
+    #[test]
+    fn semantic_tokens_standard_types() {
+        let src = "[ ./a ]";
+        let mut vfs = Vfs::new(PathBuf::from("/"));
+        vfs.set_path_content(VfsPath::new("/default.nix").unwrap(), src.into());
+        let file = vfs
+            .file_for_uri(&"file:///default.nix".parse().unwrap())
+            .unwrap();
+        let line_map = vfs.line_map_for_file(file);
+        let hls = [
+            HlRange {
+                range: TextRange::new(0.into(), 1.into()),
+                tag: HlTag::Punct(HlPunct::Bracket),
+            },
+            HlRange {
+                range: TextRange::new(2.into(), 5.into()),
+                tag: HlTag::Path,
+            },
+        ];
+
+        // Capabilities of VS Code, listing only standard types and modifiers.
+        let types = [
+            "namespace",
+            "type",
+            "class",
+            "enum",
+            "interface",
+            "struct",
+            "typeParameter",
+            "parameter",
+            "variable",
+            "property",
+            "enumMember",
+            "event",
+            "function",
+            "method",
+            "macro",
+            "keyword",
+            "modifier",
+            "comment",
+            "string",
+            "number",
+            "regexp",
+            "operator",
+            "decorator",
+        ]
+        .map(SemanticTokenType::new);
+        let modifiers = [
+            "declaration",
+            "definition",
+            "readonly",
+            "static",
+            "deprecated",
+            "abstract",
+            "async",
+            "modification",
+            "documentation",
+            "defaultLibrary",
+        ]
+        .map(SemanticTokenModifier::new);
+        let legend = NegotiatedLegend::new(&types, &modifiers);
+        let toks = to_semantic_tokens(&line_map, &legend, &hls);
+        let got = toks
+            .iter()
+            .map(|tok| legend.legend().token_types[tok.token_type as usize].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            got,
+            [SemanticTokenType::OPERATOR, SemanticTokenType::STRING]
+        );
+    }
+
+    #[test]
+    fn semantic_tokens_literals() {
+        let src = r#"[ 1 1.0 ./path "str" ]"#;
+        let mut vfs = Vfs::new(PathBuf::from("/"));
+        vfs.set_path_content(VfsPath::new("/default.nix").unwrap(), src.into());
+        let file = vfs
+            .file_for_uri(&"file:///default.nix".parse().unwrap())
+            .unwrap();
+        let line_map = vfs.line_map_for_file(file);
+        let hl = |text: &str, tag| {
+            let start = src.find(text).unwrap() as u32;
+            HlRange {
+                range: TextRange::at(start.into(), (text.len() as u32).into()),
+                tag,
+            }
+        };
+        let hls = [
+            hl("1", HlTag::IntLiteral),
+            hl("1.0", HlTag::FloatLiteral),
+            hl("./path", HlTag::Path),
+            hl(r#""str""#, HlTag::StringLiteral),
+        ];
+
+        let legend = NegotiatedLegend::default();
+        let types = to_semantic_tokens(&line_map, &legend, &hls)
+            .into_iter()
+            .map(|tok| legend.legend().token_types[tok.token_type as usize].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            [
+                SemanticTokenType::NUMBER,
+                SemanticTokenType::new("float"),
+                SemanticTokenType::new("path"),
+                SemanticTokenType::STRING,
+            ]
+        );
+    }
+
+    #[test]
+    fn workspace_edit_create_file() {
+        let mut vfs = Vfs::new(PathBuf::from("/"));
+        vfs.set_path_content(VfsPath::new("/default.nix").unwrap(), "{ a = 1; }".into());
+        vfs.set_path_content(VfsPath::new("/old.nix").unwrap(), "1".into());
+        let file = vfs
+            .file_for_uri(&"file:///default.nix".parse().unwrap())
+            .unwrap();
+        let old_file = vfs
+            .file_for_uri(&"file:///old.nix".parse().unwrap())
+            .unwrap();
+
+        let ws_edit = WorkspaceEdit {
+            content_edits: [(
+                file,
+                vec![TextEdit {
+                    delete: TextRange::new(6.into(), 7.into()),
+                    insert: "import ./a.nix".into(),
+                }],
+            )]
+            .into_iter()
+            .collect(),
+            file_system_edits: vec![
+                FileSystemEdit::DeleteFile { file: old_file },
+                FileSystemEdit::CreateFile {
+                    path: VfsPath::new("/a.nix").unwrap(),
+                    content: "1".into(),
+                },
+            ],
+        };
+        let got = to_workspace_edit(&vfs, ws_edit, None);
+        assert_eq!(got.changes, None);
+
+        let range = |start, end| lsp::Range {
+            start: lsp::Position::new(0, start),
+            end: lsp::Position::new(0, end),
+        };
+        let edit = |uri: &str, range, text: &str| {
+            lsp::DocumentChangeOperation::Edit(lsp::TextDocumentEdit {
+                text_document: lsp::OptionalVersionedTextDocumentIdentifier {
+                    uri: uri.parse().unwrap(),
+                    version: None,
+                },
+                edits: vec![lsp::OneOf::Left(lsp::TextEdit {
+                    range,
+                    new_text: text.into(),
+                })],
+            })
+        };
+        let expect = vec![
+            lsp::DocumentChangeOperation::Op(lsp::ResourceOp::Create(lsp::CreateFile {
+                uri: "file:///a.nix".parse().unwrap(),
+                options: None,
+                annotation_id: None,
+            })),
+            edit("file:///a.nix", range(0, 0), "1"),
+            edit("file:///default.nix", range(6, 7), "import ./a.nix"),
+            lsp::DocumentChangeOperation::Op(lsp::ResourceOp::Delete(lsp::DeleteFile {
+                uri: "file:///old.nix".parse().unwrap(),
+                options: None,
+            })),
+        ];
+        assert_eq!(
+            got.document_changes,
+            Some(lsp::DocumentChanges::Operations(expect)),
+        );
+    }
+
+    #[test]
+    fn workspace_edit_versions() {
+        let mut vfs = Vfs::new(PathBuf::from("/"));
+        vfs.set_path_content(VfsPath::new("/default.nix").unwrap(), "a".into());
+        vfs.set_path_content(VfsPath::new("/b.nix").unwrap(), "b".into());
+        let uri = |path: &str| format!("file://{path}").parse::<lsp::Url>().unwrap();
+        let file = vfs.file_for_uri(&uri("/default.nix")).unwrap();
+        let other = vfs.file_for_uri(&uri("/b.nix")).unwrap();
+        let ws_edit = WorkspaceEdit {
+            content_edits: [file, other]
+                .into_iter()
+                .map(|file| {
+                    let edit = TextEdit {
+                        delete: TextRange::up_to(1.into()),
+                        insert: "c".into(),
+                    };
+                    (file, vec![edit])
+                })
+                .collect(),
+            file_system_edits: Vec::new(),
+        };
+
+        // Unsupported by the client.
+        let got = to_workspace_edit(&vfs, ws_edit.clone(), None);
+        assert_eq!(got.document_changes, None);
+        assert_eq!(got.changes.unwrap().len(), 2);
+
+        // Only `/default.nix` is opened.
+        let versions = HashMap::from([(uri("/default.nix"), 42)]);
+        let got = to_workspace_edit(&vfs, ws_edit, Some(&versions));
+        assert_eq!(got.changes, None);
+        let edits = match got.document_changes {
+            Some(lsp::DocumentChanges::Edits(edits)) => edits,
+            changes => panic!("Unexpected document changes: {changes:?}"),
+        };
+        let got = edits
+            .iter()
+            .map(|edit| (edit.text_document.uri.as_str(), edit.text_document.version))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            got,
+            [("file:///default.nix", Some(42)), ("file:///b.nix", None)],
+        );
+    }
+
+    #[test]
+    fn formatting_no_op() {
+        let mut vfs = Vfs::new(PathBuf::from("/"));
+        let src = "{\n  a = 1;\n}\n";
+        vfs.set_path_content(VfsPath::new("/default.nix").unwrap(), src.into());
+        let file = vfs
+            .file_for_uri(&"file:///default.nix".parse().unwrap())
+            .unwrap();
+        let line_map = vfs.line_map_for_file(file);
+        let full = TextRange::up_to((src.len() as u32).into());
+
+        // Already formatted.
+        let edits = to_formatting_edits(&line_map, src, full, src.into());
+        assert_eq!(edits, Vec::new());
+        let edits = to_formatting_edits(&line_map, src, full, src.replace('\n', "\r\n"));
+        assert_eq!(edits, Vec::new());
+        let range = TextRange::new(4.into(), 10.into());
+        let edits = to_formatting_edits(&line_map, src, range, "a = 1;".into());
+        assert_eq!(edits, Vec::new());
+
+        let edits = to_formatting_edits(&line_map, src, range, "a=1;".into());
+        assert_eq!(
+            edits,
+            vec![lsp::TextEdit {
+                range: lsp::Range::new(lsp::Position::new(1, 2), lsp::Position::new(1, 8)),
+                new_text: "a=1;".into(),
+            }],
+        );
+    }
+
+    #[test]
+    fn formatting_changed_part() {
+        let mut vfs = Vfs::new(PathBuf::from("/"));
+        let src = "{ a=1; b = \"ß\"; }\n";
+        vfs.set_path_content(VfsPath::new("/default.nix").unwrap(), src.into());
+        let file = vfs
+            .file_for_uri(&"file:///default.nix".parse().unwrap())
+            .unwrap();
+        let line_map = vfs.line_map_for_file(file);
+        let edits = to_changed_edits(&line_map, src, "{ a = 1; b = \"ß\"; }\n".into());
+        assert_eq!(
+            edits,
+            [lsp::TextEdit {
+                range: lsp::Range::new(lsp::Position::new(0, 3), lsp::Position::new(0, 4)),
+                new_text: " = ".into(),
+            }],
+        );
+        let edits = to_changed_edits(&line_map, src, "{ a=1; b = \"ä\"; }\n".into());
+        assert_eq!(
+            edits,
+            [lsp::TextEdit {
+                range: lsp::Range::new(lsp::Position::new(0, 12), lsp::Position::new(0, 13)),
+                new_text: "ä".into(),
+            }],
+        );
+        assert_eq!(to_changed_edits(&line_map, src, src.into()), []);
+        let edits = to_changed_edits(&line_map, src, format!("# x\n{src}"));
+        assert_eq!(edits[0].range.start, lsp::Position::new(0, 0));
+        assert_eq!(edits[0].new_text, "# x\n");
+    }
+
+    #[test]
+    fn diagnostics_cross_file_note() {
+        let mut vfs = Vfs::new(PathBuf::from("/"));
+        vfs.set_path_content(VfsPath::new("/default.nix").unwrap(), "{ a = 1; }".into());
+        vfs.set_path_content(VfsPath::new("/a.nix").unwrap(), "\n{ a = 2; }".into());
+        let file = vfs
+            .file_for_uri(&"file:///default.nix".parse().unwrap())
+            .unwrap();
+        let other = vfs.file_for_uri(&"file:///a.nix".parse().unwrap()).unwrap();
+        let diags = [Diagnostic::new(
+            TextRange::new(2.into(), 3.into()),
+            DiagnosticKind::DuplicatedKey,
+        )
+        .with_note(
+            FileRange::new(file, TextRange::new(2.into(), 3.into())),
+            "Defined here",
+        )
+        .with_note(
+            FileRange::new(other, TextRange::new(3.into(), 4.into())),
+            "Previously defined here",
+        )];
+
+        let got = to_diagnostics(&vfs, file, &diags, true);
+        // The hoisted hint of the same-file note, and the primary diagnostic.
+        assert_eq!(got.len(), 2);
+        let related = got[1].related_information.clone().unwrap();
+        let locations = related
+            .iter()
+            .map(|info| (info.location.uri.as_str(), info.location.range))
+            .collect::<Vec<_>>();
+        let range = |line, start, end| {
+            lsp::Range::new(
+                lsp::Position::new(line, start),
+                lsp::Position::new(line, end),
+            )
+        };
+        assert_eq!(
+            locations,
+            [
+                ("file:///default.nix", range(0, 2, 3)),
+                ("file:///a.nix", range(1, 2, 3)),
+            ],
+        );
+
+        // Unsupported by the client.
+        let got = to_diagnostics(&vfs, file, &diags, false);
+        assert_eq!(got.len(), 2);
+        assert!(got.iter().all(|diag| diag.related_information.is_none()));
+    }
+
+    #[test]
+    fn completion_item_deprecated() {
+        let mut vfs = Vfs::new(PathBuf::from("/"));
+        vfs.set_path_content(
+            VfsPath::new("/default.nix").unwrap(),
+            "let { foo = 1; body = f; }".into(),
+        );
+        let file = vfs
+            .file_for_uri(&"file:///default.nix".parse().unwrap())
+            .unwrap();
+        let line_map = vfs.line_map_for_file(file);
+        let item = CompletionItem {
+            label: "foo".into(),
+            source_range: TextRange::new(22.into(), 23.into()),
+            replace: "foo".into(),
+            kind: CompletionItemKind::LetBinding,
+            brief: None,
+            doc: None,
+            is_snippet: false,
+            additional_edits: Vec::new(),
+            deprecated: true,
+        };
+        let got = to_completion_item(&line_map, item.clone());
+        assert_eq!(got.tags, Some(vec![lsp::CompletionItemTag::DEPRECATED]));
+        assert_eq!(got.deprecated, Some(true));
+        assert_eq!(got.sort_text.as_deref(), Some("~foo"));
+
+        let got = to_completion_item(
+            &line_map,
+            CompletionItem {
+                deprecated: false,
+                ..item
+            },
+        );
+        assert_eq!(got.tags, None);
+        assert_eq!(got.deprecated, None);
+        assert_eq!(got.sort_text, None);
+    }
+
+    #[test]
+    fn completion_item_kind() {
+        let mut vfs = Vfs::new(PathBuf::from("/"));
+        vfs.set_path_content(VfsPath::new("/default.nix").unwrap(), "f".into());
+        let file = vfs
+            .file_for_uri(&"file:///default.nix".parse().unwrap())
+            .unwrap();
+        let line_map = vfs.line_map_for_file(file);
+        let item = |kind| CompletionItem {
+            label: "f".into(),
+            source_range: TextRange::new(0.into(), 1.into()),
+            replace: "f".into(),
+            kind,
+            brief: None,
+            doc: None,
+            is_snippet: false,
+            additional_edits: Vec::new(),
+            deprecated: false,
+        };
+        for (kind, expect) in [
+            (
+                CompletionItemKind::Keyword,
+                lsp::CompletionItemKind::KEYWORD,
+            ),
+            (CompletionItemKind::Param, lsp::CompletionItemKind::VARIABLE),
+            (
+                CompletionItemKind::LetBinding,
+                lsp::CompletionItemKind::VARIABLE,
+            ),
+            (CompletionItemKind::Field, lsp::CompletionItemKind::FIELD),
+            (
+                CompletionItemKind::Function,
+                lsp::CompletionItemKind::FUNCTION,
+            ),
+            (
+                CompletionItemKind::BuiltinConst,
+                lsp::CompletionItemKind::CONSTANT,
+            ),
+            (
+                CompletionItemKind::BuiltinFunction,
+                lsp::CompletionItemKind::FUNCTION,
+            ),
+            (
+                CompletionItemKind::BuiltinAttrset,
+                lsp::CompletionItemKind::MODULE,
+            ),
+        ] {
+            let got = to_completion_item(&line_map, item(kind));
+            assert_eq!(got.kind, Some(expect), "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn hover_markup_kind() {
+        let mut vfs = Vfs::new(PathBuf::from("/"));
+        vfs.set_path_content(VfsPath::new("/default.nix").unwrap(), "map".into());
+        let file = vfs
+            .file_for_uri(&"file:///default.nix".parse().unwrap())
+            .unwrap();
+        let line_map = vfs.line_map_for_file(file);
+        let hover = HoverResult {
+            range: TextRange::new(0.into(), 3.into()),
+            markup: "\
+`builtins.map f list`
+
+Apply the function **f** to each element, see [the manual](https://nixos.org).
+
+```nix
+map (x: \"foo\" + x) [ \"bar\" ]
+```
+"
+            .into(),
+        };
+
+        let contents = |markdown| match to_hover(&line_map, hover.clone(), markdown).contents {
+            lsp::HoverContents::Markup(content) => content,
+            _ => unreachable!(),
+        };
+
+        let got = contents(true);
+        assert_eq!(got.kind, lsp::MarkupKind::Markdown);
+        assert_eq!(got.value, hover.markup);
+
+        let got = contents(false);
+        assert_eq!(got.kind, lsp::MarkupKind::PlainText);
+        assert_eq!(
+            got.value,
+            "\
+builtins.map f list
+
+Apply the function f to each element, see the manual (https://nixos.org).
+
+map (x: \"foo\" + x) [ \"bar\" ]
+"
+        );
     }
 }
@@ -1,12 +1,29 @@
-use crate::{convert, Result, StateSnapshot};
-use ide::FileRange;
+use crate::lsp_ext::{
+    CodeActionData, DefUseGraphParams, DefUseGraphResult, FileImport, FileImportsParams,
+    FindSimilarParams, FormatTextParams, FreeVariable, FreeVariablesParams, NameResolutionEntry,
+    NameResolutionParams, ParseStatusParams, ParseStatusResult, SourceRootParams, SourceRootResult,
+};
+use crate::{config, convert, lsp_ext, Result, StateSnapshot};
+use ide::{Assist, FileRange};
 use lsp_types::{
-    CompletionParams, CompletionResponse, GotoDefinitionParams, GotoDefinitionResponse, Hover,
-    HoverParams, Location, PrepareRenameResponse, ReferenceParams, RenameParams, SelectionRange,
+    ChangeAnnotation, CodeAction, CodeActionKind, CodeActionParams, CodeActionResponse,
+    CompletionList, CompletionParams, CompletionResponse, DocumentFormattingParams,
+    DocumentHighlight, DocumentHighlightKind, DocumentHighlightParams,
+    DocumentRangeFormattingParams, FoldingRange, FoldingRangeParams, FormattingOptions,
+    GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverParams, Location,
+    PrepareRenameResponse, Range, ReferenceParams, RenameParams, SelectionRange,
     SelectionRangeParams, SemanticTokens, SemanticTokensParams, SemanticTokensRangeParams,
-    SemanticTokensRangeResult, SemanticTokensResult, TextDocumentPositionParams, WorkspaceEdit,
+    SemanticTokensRangeResult, SemanticTokensResult, TextDocumentIdentifier,
+    TextDocumentPositionParams, TextEdit, WorkDoneProgressParams, WorkspaceEdit,
 };
-use text_size::TextRange;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use text_size::{TextRange, TextSize};
+
+/// The id of the source action running `nil.formatDocument`, to be disabled in the config.
+const FORMAT_DOCUMENT_ACTION: &str = "format_document";
 
 pub(crate) fn goto_definition(
     snap: StateSnapshot,
@@ -49,15 +66,49 @@ pub(crate) fn completion(
     params: CompletionParams,
 ) -> Result<Option<CompletionResponse>> {
     let (line_map, fpos) = convert::from_file_pos(&snap.vfs(), &params.text_document_position)?;
-    let items = match snap.analysis.completions(fpos)? {
+    if snap.is_file_too_large(fpos.file_id) {
+        return Ok(None);
+    }
+    let config = snap.config.completion_config(snap.snippet_support);
+    let items = match snap.analysis.completions(&config, fpos)? {
         None => return Ok(None),
         Some(items) => items,
     };
+    let ranks = snap.completion_history.ranks();
     let items = items
         .into_iter()
-        .map(|item| convert::to_completion_item(&line_map, item))
+        .map(|item| {
+            let mut item = convert::to_completion_item(&line_map, item);
+            // Keywords are not ranked, so don't cost a round trip on acceptance.
+            if item.kind == Some(lsp_types::CompletionItemKind::KEYWORD) {
+                return item;
+            }
+            // Rank accepted items first. `!` is before all characters of identifiers.
+            if let Some(rank) = ranks.get(&*item.label) {
+                let sort_text = item.sort_text.as_deref().unwrap_or(&item.label);
+                item.sort_text = Some(format!("!{rank:02}{sort_text}"));
+            }
+            item.command = Some(lsp_types::Command {
+                title: String::new(),
+                command: lsp_ext::COMPLETION_ACCEPTED_COMMAND.into(),
+                arguments: Some(vec![item.label.clone().into()]),
+            });
+            item
+        })
         .collect::<Vec<_>>();
-    Ok(Some(CompletionResponse::Array(items)))
+
+    let max_items = snap.config.max_completion_items;
+    if max_items == 0 || items.len() <= max_items {
+        return Ok(Some(CompletionResponse::Array(items)));
+    }
+    // Keep the items the client would show first.
+    let mut items = items;
+    items.sort_by_cached_key(|item| item.sort_text.clone().unwrap_or(item.label.clone()));
+    items.truncate(max_items);
+    Ok(Some(CompletionResponse::List(CompletionList {
+        is_incomplete: true,
+        items,
+    })))
 }
 
 pub(crate) fn selection_range(
@@ -114,7 +165,29 @@ pub(crate) fn rename(snap: StateSnapshot, params: RenameParams) -> Result<Option
         .analysis
         .rename(fpos, &params.new_name)?
         .map_err(convert::to_rename_error)?;
-    let resp = convert::to_workspace_edit(&snap.vfs(), ws_edit);
+    let file_count = ws_edit.content_edits.len();
+    let edit_count = ws_edit
+        .content_edits
+        .values()
+        .map(|edits| edits.len())
+        .sum::<usize>();
+    let mut resp = convert::to_workspace_edit(&snap.vfs(), ws_edit, snap.doc_versions().as_ref());
+    if snap.change_annotations {
+        let label = match snap.analysis.prepare_rename(fpos)? {
+            Ok((_, old_name)) => format!("Rename `{}` to `{}`", old_name, params.new_name),
+            Err(_) => format!("Rename to `{}`", params.new_name),
+        };
+        let annotation = ChangeAnnotation {
+            label,
+            needs_confirmation: None,
+            description: Some(format!(
+                "{edit_count} edit{} in {file_count} file{}",
+                if edit_count == 1 { "" } else { "s" },
+                if file_count == 1 { "" } else { "s" },
+            )),
+        };
+        convert::annotate_workspace_edit(&mut resp, "rename", annotation);
+    }
     Ok(Some(resp))
 }
 
@@ -124,8 +197,13 @@ pub(crate) fn semantic_token_full(
 ) -> Result<Option<SemanticTokensResult>> {
     let file = convert::from_file(&snap.vfs(), &params.text_document)?;
     let line_map = snap.vfs().line_map_for_file(file);
+    if snap.is_file_too_large(file) {
+        return Ok(Some(
+            SemanticTokensResult::Tokens(SemanticTokens::default()),
+        ));
+    }
     let hls = snap.analysis.syntax_highlight(file, None)?;
-    let toks = convert::to_semantic_tokens(&line_map, &hls);
+    let toks = convert::to_semantic_tokens(&line_map, &snap.semantic_legend, &hls);
     Ok(Some(SemanticTokensResult::Tokens(SemanticTokens {
         result_id: None,
         data: toks,
@@ -138,8 +216,13 @@ pub(crate) fn semantic_token_range(
 ) -> Result<Option<SemanticTokensRangeResult>> {
     let file = convert::from_file(&snap.vfs(), &params.text_document)?;
     let (line_map, range) = convert::from_range(&snap.vfs(), file, params.range)?;
+    if snap.is_file_too_large(file) {
+        return Ok(Some(SemanticTokensRangeResult::Tokens(
+            SemanticTokens::default(),
+        )));
+    }
     let hls = snap.analysis.syntax_highlight(file, Some(range))?;
-    let toks = convert::to_semantic_tokens(&line_map, &hls);
+    let toks = convert::to_semantic_tokens(&line_map, &snap.semantic_legend, &hls);
     Ok(Some(SemanticTokensRangeResult::Tokens(SemanticTokens {
         result_id: None,
         data: toks,
@@ -150,5 +233,385 @@ pub(crate) fn hover(snap: StateSnapshot, params: HoverParams) -> Result<Option<H
     let (line_map, fpos) =
         convert::from_file_pos(&snap.vfs(), &params.text_document_position_params)?;
     let ret = snap.analysis.hover(fpos)?;
-    Ok(ret.map(|hover| convert::to_hover(&line_map, hover)))
+    Ok(ret.map(|hover| convert::to_hover(&line_map, hover, snap.hover_markdown)))
+}
+
+pub(crate) fn document_highlight(
+    snap: StateSnapshot,
+    params: DocumentHighlightParams,
+) -> Result<Option<Vec<DocumentHighlight>>> {
+    let (line_map, fpos) =
+        convert::from_file_pos(&snap.vfs(), &params.text_document_position_params)?;
+    let ranges = match snap.analysis.document_highlight(fpos)? {
+        None => return Ok(None),
+        Some(ranges) => ranges,
+    };
+    let highlights = ranges
+        .into_iter()
+        .map(|range| DocumentHighlight {
+            range: convert::to_range(&line_map, range),
+            kind: Some(DocumentHighlightKind::TEXT),
+        })
+        .collect();
+    Ok(Some(highlights))
+}
+
+pub(crate) fn code_action(
+    snap: StateSnapshot,
+    params: CodeActionParams,
+) -> Result<Option<CodeActionResponse>> {
+    let file = convert::from_file(&snap.vfs(), &params.text_document)?;
+    let (_, range) = convert::from_range(&snap.vfs(), file, params.range)?;
+    let assists = enabled_assists(&snap, FileRange::new(file, range))?;
+    let vfs = snap.vfs();
+    let doc_versions = snap.doc_versions();
+    let wants_kind = |kind: &CodeActionKind| match &params.context.only {
+        Some(only) => only
+            .iter()
+            .any(|pat| config::is_sub_kind(kind.as_str(), pat.as_str())),
+        None => true,
+    };
+    let mut actions = assists
+        .into_iter()
+        .enumerate()
+        // Filter after indexing, to keep indices in `codeAction/resolve` consistent.
+        .filter(|(_, assist)| wants_kind(&convert::to_code_action_kind(assist.kind)))
+        .map(|(index, assist)| {
+            // Defer edits to `codeAction/resolve` if possible.
+            let action = if snap.code_action_resolve {
+                let data = CodeActionData {
+                    text_document: params.text_document.clone(),
+                    range: params.range,
+                    index,
+                    id: assist.id.into(),
+                };
+                convert::to_code_action_stub(assist, data)
+            } else {
+                convert::to_code_action(&vfs, file, assist, doc_versions.as_ref())
+            };
+            action.into()
+        })
+        .collect::<Vec<_>>();
+
+    // Formatting is done lazily by the command, when the action is chosen.
+    if let Some([formatter, ..]) = snap.config.formatting.command.as_deref() {
+        if wants_kind(&CodeActionKind::SOURCE)
+            && snap.apply_edit
+            && !snap
+                .config
+                .code_actions
+                .is_disabled(FORMAT_DOCUMENT_ACTION, CodeActionKind::SOURCE.as_str())
+        {
+            let formatter = Path::new(formatter)
+                .file_name()
+                .map_or_else(|| formatter.clone(), |name| name.to_string_lossy().into());
+            actions
+                .push(convert::to_format_document_action(&formatter, params.text_document).into());
+        }
+    }
+    Ok(Some(actions))
+}
+
+pub(crate) fn code_action_resolve(
+    snap: StateSnapshot,
+    mut action: CodeAction,
+) -> Result<CodeAction> {
+    // Actions backed by commands are complete already.
+    if action.data.is_none() && action.command.is_some() {
+        return Ok(action);
+    }
+    let data = action.data.take().ok_or("Missing code action data")?;
+    let data = serde_json::from_value::<CodeActionData>(data)?;
+    let file = convert::from_file(&snap.vfs(), &data.text_document)?;
+    let (_, range) = convert::from_range(&snap.vfs(), file, data.range)?;
+    let assist = enabled_assists(&snap, FileRange::new(file, range))?
+        .into_iter()
+        .nth(data.index)
+        .filter(|assist| assist.id == data.id)
+        .ok_or_else(|| convert::to_content_modified_error("Code action is outdated".into()))?;
+    let doc_versions = snap.doc_versions();
+    action.edit = convert::to_code_action(&snap.vfs(), file, assist, doc_versions.as_ref()).edit;
+    Ok(action)
+}
+
+/// Assists at `frange` which are not disabled in the config.
+fn enabled_assists(snap: &StateSnapshot, frange: FileRange) -> Result<Vec<Assist>> {
+    let mut assists = snap
+        .analysis
+        .assists(&snap.config.assists_config(), frange)?;
+    assists.retain(|assist| {
+        let kind = convert::to_code_action_kind(assist.kind);
+        !snap
+            .config
+            .code_actions
+            .is_disabled(assist.id, kind.as_str())
+    });
+    Ok(assists)
+}
+
+pub(crate) fn formatting(
+    snap: StateSnapshot,
+    params: DocumentFormattingParams,
+) -> Result<Option<Vec<TextEdit>>> {
+    let file = convert::from_file(&snap.vfs(), &params.text_document)?;
+    let (src, line_map) = {
+        let vfs = snap.vfs();
+        (vfs.content_for_file(file), vfs.line_map_for_file(file))
+    };
+    let formatted = match run_formatter(&snap, &src, &[])? {
+        Some(formatted) => formatted,
+        None => return Ok(None),
+    };
+    let range = TextRange::up_to(TextSize::of(&*src));
+    Ok(Some(convert::to_formatting_edits(
+        &line_map, &src, range, formatted,
+    )))
+}
+
+pub(crate) fn range_formatting(
+    snap: StateSnapshot,
+    params: DocumentRangeFormattingParams,
+) -> Result<Option<Vec<TextEdit>>> {
+    let file = convert::from_file(&snap.vfs(), &params.text_document)?;
+    let (line_map, range) = convert::from_range(&snap.vfs(), file, params.range)?;
+    let src = snap.vfs().content_for_file(file);
+
+    // The formatter formats the selection in the whole document by itself.
+    if let Some(range_args) = &snap.config.formatting.range_args {
+        let (start_line, _) = line_map.line_col_for_pos(range.start());
+        let (end_line, _) = line_map.line_col_for_pos(range.end());
+        let args = range_args
+            .iter()
+            .map(|arg| {
+                arg.replace("{start}", &u32::from(range.start()).to_string())
+                    .replace("{end}", &u32::from(range.end()).to_string())
+                    .replace("{startLine}", &(start_line + 1).to_string())
+                    .replace("{endLine}", &(end_line + 1).to_string())
+            })
+            .collect::<Vec<_>>();
+        let formatted = match run_formatter(&snap, &src, &args)? {
+            Some(formatted) => formatted,
+            None => return Ok(None),
+        };
+        return Ok(Some(convert::to_changed_edits(&line_map, &src, formatted)));
+    }
+
+    let mut formatted = match run_formatter(&snap, &src[range], &[])? {
+        Some(formatted) => formatted,
+        None => return Ok(None),
+    };
+    // Formatters usually append a newline, which is unexpected inside a line.
+    if !src[range].ends_with('\n') && formatted.ends_with('\n') {
+        formatted.pop();
+    }
+    Ok(Some(convert::to_formatting_edits(
+        &line_map, &src, range, formatted,
+    )))
+}
+
+/// The edit formatting the whole document, for `nil.formatDocument`.
+pub(crate) fn format_document(
+    snap: StateSnapshot,
+    text_document: TextDocumentIdentifier,
+) -> Result<Option<WorkspaceEdit>> {
+    let uri = text_document.uri.clone();
+    let doc_versions = snap.doc_versions();
+    let edits = match formatting(
+        snap,
+        DocumentFormattingParams {
+            text_document,
+            options: FormattingOptions::default(),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        },
+    )? {
+        Some(edits) if !edits.is_empty() => edits,
+        _ => return Ok(None),
+    };
+    Ok(Some(convert::to_document_edit(
+        uri,
+        edits,
+        doc_versions.as_ref(),
+    )))
+}
+
+pub(crate) fn format_text(snap: StateSnapshot, params: FormatTextParams) -> Result<String> {
+    match run_formatter(&snap, &params.text, &[]) {
+        Ok(Some(formatted)) => Ok(formatted),
+        Ok(None) => Ok(params.text),
+        Err(err) => {
+            tracing::warn!("Failed to format text: {}", err);
+            Ok(params.text)
+        }
+    }
+}
+
+/// The range of the binding enclosing the position, for `nil.selectEnclosingBinding`.
+pub(crate) fn select_enclosing_binding(
+    snap: StateSnapshot,
+    params: TextDocumentPositionParams,
+) -> Result<Option<Range>> {
+    let (line_map, fpos) = convert::from_file_pos(&snap.vfs(), &params)?;
+    let ret = snap.analysis.enclosing_binding(fpos)?;
+    Ok(ret.map(|range| convert::to_range(&line_map, range)))
+}
+
+/// Run the configured formatter on `src`, with `extra_args` appended.
+/// Returns `None` if no formatter is configured.
+fn run_formatter(snap: &StateSnapshot, src: &str, extra_args: &[String]) -> Result<Option<String>> {
+    let (cmd, args) = match snap.config.formatting.command.as_deref() {
+        Some([cmd, args @ ..]) => (cmd, args),
+        _ => return Ok(None),
+    };
+    let mut child = Command::new(cmd)
+        .args(args)
+        .args(extra_args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("Failed to run formatter {:?}: {}", cmd, err))?;
+    // Write in another thread, or it may deadlock when pipe buffers are full.
+    let mut stdin = child.stdin.take().unwrap();
+    let input = src.to_owned();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output()?;
+    writer.join().unwrap()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("Formatter failed with {}: {}", output.status, stderr.trim()).into());
+    }
+    let formatted =
+        String::from_utf8(output.stdout).map_err(|_| "Formatter output is not UTF-8")?;
+    Ok(Some(formatted))
+}
+
+pub(crate) fn folding_range(
+    snap: StateSnapshot,
+    params: FoldingRangeParams,
+) -> Result<Option<Vec<FoldingRange>>> {
+    let file = convert::from_file(&snap.vfs(), &params.text_document)?;
+    let line_map = snap.vfs().line_map_for_file(file);
+    let folds = snap
+        .analysis
+        .folding_ranges(file)?
+        .into_iter()
+        .map(|fold| convert::to_folding_range(&line_map, fold))
+        .collect();
+    Ok(Some(folds))
+}
+
+pub(crate) fn file_imports(
+    snap: StateSnapshot,
+    params: FileImportsParams,
+) -> Result<Vec<FileImport>> {
+    let file = convert::from_file(&snap.vfs(), &params.text_document)?;
+    let imports = snap.analysis.file_imports(file)?;
+    let importers = if params.include_importers {
+        snap.analysis.file_importers(file)?
+    } else {
+        Vec::new()
+    };
+    let vfs = snap.vfs();
+    let imports = imports
+        .into_iter()
+        .map(|import| convert::to_file_import(&vfs, import, false))
+        .chain(
+            importers
+                .into_iter()
+                .map(|import| convert::to_file_import(&vfs, import, true)),
+        )
+        .collect();
+    Ok(imports)
+}
+
+pub(crate) fn source_root(
+    snap: StateSnapshot,
+    params: SourceRootParams,
+) -> Result<SourceRootResult> {
+    let file = convert::from_file(&snap.vfs(), &params.text_document)?;
+    let (id, entry) = snap.analysis.source_root_for_file(file)?;
+    let file_count = snap.analysis.source_root(id)?.iter().count();
+    Ok(SourceRootResult {
+        id: id.0,
+        entry: entry.map(|entry| snap.vfs().uri_for_file(entry)),
+        file_count,
+    })
+}
+
+pub(crate) fn attr_path(
+    snap: StateSnapshot,
+    params: TextDocumentPositionParams,
+) -> Result<Option<Vec<String>>> {
+    let (_, fpos) = convert::from_file_pos(&snap.vfs(), &params)?;
+    Ok(snap.analysis.enclosing_attrpath(fpos)?)
+}
+
+pub(crate) fn def_use_graph(
+    snap: StateSnapshot,
+    params: DefUseGraphParams,
+) -> Result<DefUseGraphResult> {
+    let file = convert::from_file(&snap.vfs(), &params.text_document)?;
+    let graph = snap.analysis.def_use_graph(file)?;
+    let line_map = snap.vfs().line_map_for_file(file);
+    Ok(convert::to_def_use_graph(&line_map, graph))
+}
+
+pub(crate) fn name_resolution(
+    snap: StateSnapshot,
+    params: NameResolutionParams,
+) -> Result<Vec<NameResolutionEntry>> {
+    let file = convert::from_file(&snap.vfs(), &params.text_document)?;
+    let names = snap.analysis.name_resolution(file)?;
+    let line_map = snap.vfs().line_map_for_file(file);
+    Ok(names
+        .into_iter()
+        .map(|name| convert::to_name_resolution_entry(&line_map, name))
+        .collect())
+}
+
+pub(crate) fn parse_status(
+    snap: StateSnapshot,
+    params: ParseStatusParams,
+) -> Result<ParseStatusResult> {
+    let file = convert::from_file(&snap.vfs(), &params.text_document)?;
+    let status = snap.analysis.parse_status(file)?;
+    Ok(convert::to_parse_status(status))
+}
+
+pub(crate) fn free_variables(
+    snap: StateSnapshot,
+    params: FreeVariablesParams,
+) -> Result<Vec<FreeVariable>> {
+    let file = convert::from_file(&snap.vfs(), &params.text_document)?;
+    let (_, range) = convert::from_range(&snap.vfs(), file, params.range)?;
+    let vars = snap.analysis.free_variables(FileRange::new(file, range))?;
+    let vfs = snap.vfs();
+    Ok(vars
+        .into_iter()
+        .map(|(name, franges)| FreeVariable {
+            name,
+            locations: franges
+                .into_iter()
+                .map(|frange| convert::to_location(&vfs, frange))
+                .collect(),
+        })
+        .collect())
+}
+
+pub(crate) fn find_similar(
+    snap: StateSnapshot,
+    params: FindSimilarParams,
+) -> Result<Option<Vec<Location>>> {
+    let file = convert::from_file(&snap.vfs(), &params.text_document)?;
+    let (_, range) = convert::from_range(&snap.vfs(), file, params.range)?;
+    let franges = match snap.analysis.find_similar(FileRange::new(file, range))? {
+        None => return Ok(None),
+        Some(franges) => franges,
+    };
+    let vfs = snap.vfs();
+    let locs = franges
+        .into_iter()
+        .map(|frange| convert::to_location(&vfs, frange))
+        .collect();
+    Ok(Some(locs))
 }
@@ -1,15 +1,23 @@
 mod capabilities;
+mod cli;
+mod completion_history;
+mod config;
 mod convert;
 mod handler;
+mod lsp_ext;
 mod semantic_tokens;
 mod state;
 mod vfs;
 
 use lsp_server::{Connection, ErrorCode};
-use lsp_types::InitializeParams;
+use lsp_types::{InitializeParams, InitializeResult};
 use std::path::PathBuf;
 use std::{env, fmt};
 
+pub use cli::run_diagnostics;
+pub(crate) use completion_history::CompletionHistory;
+pub(crate) use config::{Config, InitOptions};
+pub use state::ExitReason;
 pub(crate) use state::{State, StateSnapshot};
 pub(crate) use vfs::{LineMap, Vfs};
 
@@ -30,12 +38,21 @@ impl std::error::Error for LspError {}
 pub type Error = Box<dyn std::error::Error + Send + Sync>;
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
-pub fn main_loop(conn: Connection) -> Result<()> {
-    let init_params =
-        conn.initialize(serde_json::to_value(&capabilities::server_capabilities()).unwrap())?;
+pub fn main_loop(conn: Connection) -> Result<ExitReason> {
+    let (init_id, init_params) = conn.initialize_start()?;
     tracing::info!("Init params: {}", init_params);
 
     let init_params = serde_json::from_value::<InitializeParams>(init_params)?;
+    let semantic_legend = capabilities::negotiate_semantic_legend(&init_params.capabilities);
+    let init_result = InitializeResult {
+        capabilities: capabilities::server_capabilities(
+            &init_params.capabilities,
+            &semantic_legend,
+        ),
+        server_info: None,
+    };
+    conn.initialize_finish(init_id, serde_json::to_value(init_result).unwrap())?;
+
     let workspace_path = (|| -> Option<PathBuf> {
         if let Some(folders) = &init_params.workspace_folders {
             return folders.get(0)?.uri.to_file_path().ok();
@@ -46,9 +63,23 @@ pub fn main_loop(conn: Connection) -> Result<()> {
         env::current_dir().ok()
     })();
 
-    let mut state = State::new(conn.sender.clone(), workspace_path);
-    state.run(conn.receiver)?;
+    let init_options = match init_params.initialization_options {
+        Some(v) => serde_json::from_value::<InitOptions>(v).unwrap_or_else(|err| {
+            tracing::error!("Invalid initialization options: {}", err);
+            InitOptions::default()
+        }),
+        None => InitOptions::default(),
+    };
+
+    let mut state = State::new(
+        conn.sender.clone(),
+        workspace_path,
+        init_params.capabilities,
+        init_options,
+        semantic_legend,
+    );
+    let reason = state.run(conn.receiver)?;
 
-    tracing::info!("Leaving main loop");
-    Ok(())
+    tracing::info!("Leaving main loop: {:?}", reason);
+    Ok(reason)
 }
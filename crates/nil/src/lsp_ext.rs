@@ -0,0 +1,268 @@
+use lsp_types::request::Request;
+use lsp_types::{Location, Range, TextDocumentIdentifier, TextDocumentPositionParams, Url};
+use serde::{Deserialize, Serialize};
+
+/// Format a document with the configured formatter, and apply the edits via `workspace/applyEdit`.
+/// Arguments: `[TextDocumentIdentifier]`.
+pub(crate) const FORMAT_DOCUMENT_COMMAND: &str = "nil.formatDocument";
+
+/// Get the range of the smallest `name = value;` binding enclosing a position,
+/// for the editor to select it.
+/// Arguments: `[TextDocumentPositionParams]`. Returns: `Range | null`.
+pub(crate) const SELECT_ENCLOSING_BINDING_COMMAND: &str = "nil.selectEnclosingBinding";
+
+/// Sent by the client when a completion item is accepted, to rank it higher afterwards.
+/// It is attached to every completion item. Arguments: `[string]`, the label of the item.
+pub(crate) const COMPLETION_ACCEPTED_COMMAND: &str = "nil.completionAccepted";
+
+/// All commands handled by `workspace/executeCommand`.
+pub(crate) const COMMANDS: &[&str] = &[
+    FORMAT_DOCUMENT_COMMAND,
+    SELECT_ENCLOSING_BINDING_COMMAND,
+    COMPLETION_ACCEPTED_COMMAND,
+];
+
+pub(crate) enum FileImports {}
+
+impl Request for FileImports {
+    type Params = FileImportsParams;
+    type Result = Vec<FileImport>;
+    const METHOD: &'static str = "nil/fileImports";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct FileImportsParams {
+    pub text_document: TextDocumentIdentifier,
+    /// Also return files importing this file.
+    #[serde(default)]
+    pub include_importers: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct FileImport {
+    /// The path literal of the import.
+    pub location: Location,
+    /// The imported file.
+    pub target: Url,
+    /// Whether this file is imported by `location`, instead of importing `target`.
+    pub incoming: bool,
+}
+
+pub(crate) enum FindSimilar {}
+
+impl Request for FindSimilar {
+    type Params = FindSimilarParams;
+    type Result = Option<Vec<Location>>;
+    const METHOD: &'static str = "nil/findSimilar";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct FindSimilarParams {
+    pub text_document: TextDocumentIdentifier,
+    /// The range of the selected expression.
+    pub range: Range,
+}
+
+pub(crate) enum FreeVariables {}
+
+impl Request for FreeVariables {
+    type Params = FreeVariablesParams;
+    type Result = Vec<FreeVariable>;
+    const METHOD: &'static str = "nil/freeVariables";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct FreeVariablesParams {
+    pub text_document: TextDocumentIdentifier,
+    /// The selected range, whose free variables are returned.
+    pub range: Range,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct FreeVariable {
+    pub name: String,
+    /// All uses of the name in the range.
+    pub locations: Vec<Location>,
+}
+
+pub(crate) enum AttrPath {}
+
+impl Request for AttrPath {
+    type Params = TextDocumentPositionParams;
+    type Result = Option<Vec<String>>;
+    const METHOD: &'static str = "nil/attrPath";
+}
+
+pub(crate) enum SourceRoot {}
+
+impl Request for SourceRoot {
+    type Params = SourceRootParams;
+    type Result = SourceRootResult;
+    const METHOD: &'static str = "nil/sourceRoot";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SourceRootParams {
+    pub text_document: TextDocumentIdentifier,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SourceRootResult {
+    /// The id of the source root containing the document.
+    pub id: u32,
+    /// The entry file of the source root, like `flake.nix`.
+    pub entry: Option<Url>,
+    /// The number of files in the source root.
+    pub file_count: usize,
+}
+
+/// Format a text not necessarily in any document, like the content of the clipboard.
+/// The original text is returned if no formatter is configured or it fails.
+pub(crate) enum FormatText {}
+
+impl Request for FormatText {
+    type Params = FormatTextParams;
+    type Result = String;
+    const METHOD: &'static str = "nil/formatText";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct FormatTextParams {
+    pub text: String,
+}
+
+pub(crate) enum DefUseGraph {}
+
+impl Request for DefUseGraph {
+    type Params = DefUseGraphParams;
+    type Result = DefUseGraphResult;
+    const METHOD: &'static str = "nil/defUseGraph";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct DefUseGraphParams {
+    pub text_document: TextDocumentIdentifier,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct DefUseGraphResult {
+    pub nodes: Vec<DefUseNode>,
+    pub edges: Vec<DefUseEdge>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct DefUseNode {
+    pub id: usize,
+    pub name: String,
+    /// The range of the definition, or the environment of `with`.
+    pub range: Range,
+    pub kind: DefUseNodeKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum DefUseNodeKind {
+    Definition,
+    Inherited,
+    With,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct DefUseEdge {
+    /// The range of the use.
+    pub range: Range,
+    /// The id of the definition node.
+    pub target: usize,
+}
+
+pub(crate) enum NameResolution {}
+
+impl Request for NameResolution {
+    type Params = NameResolutionParams;
+    type Result = Vec<NameResolutionEntry>;
+    const METHOD: &'static str = "nil/nameResolution";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct NameResolutionParams {
+    pub text_document: TextDocumentIdentifier,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct NameResolutionEntry {
+    /// The range of the use.
+    pub range: Range,
+    pub resolution: NameResolutionKind,
+    /// The range of the definition, or the environments of all enclosing `with`s.
+    pub targets: Vec<Range>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum NameResolutionKind {
+    Definition,
+    Builtin,
+    WithScope,
+    Unresolved,
+}
+
+/// A summary of syntax errors of a document, and how its tree is recovered from them.
+pub(crate) enum ParseStatus {}
+
+impl Request for ParseStatus {
+    type Params = ParseStatusParams;
+    type Result = ParseStatusResult;
+    const METHOD: &'static str = "nil/parseStatus";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ParseStatusParams {
+    pub text_document: TextDocumentIdentifier,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ParseStatusResult {
+    pub has_errors: bool,
+    pub error_count: usize,
+    /// Kinds of syntax errors with their counts, in the order of first occurrences.
+    pub error_kinds: Vec<ParseErrorKindCount>,
+    /// The number of `ERROR` nodes and tokens in the recovered tree.
+    pub error_node_count: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ParseErrorKindCount {
+    /// The message of the error kind, like `Missing expression`.
+    pub kind: String,
+    pub count: usize,
+}
+
+/// The data of an unresolved code action, to find it again in `codeAction/resolve`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CodeActionData {
+    pub text_document: TextDocumentIdentifier,
+    /// The range of the original `textDocument/codeAction` request.
+    pub range: Range,
+    /// The index in all code actions of the original request.
+    pub index: usize,
+    /// The id of the assist, to detect outdated requests.
+    pub id: String,
+}
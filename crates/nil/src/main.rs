@@ -1,6 +1,7 @@
 use lsp_server::Connection;
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use std::{env, fs, io, process};
 use tracing_subscriber::fmt::writer::BoxMakeWriter;
 use tracing_subscriber::EnvFilter;
@@ -9,22 +10,49 @@ const LOG_FILTER_ENV: &str = "NIL_LOG";
 const LOG_PATH_ENV: &str = "NIL_LOG_PATH";
 const BACKTRACE_ENV: &str = "RUST_BACKTRACE";
 
+/// Rotate the log file when it grows larger than this.
+const MAX_LOG_FILE_SIZE: u64 = 16 << 20;
+
 fn main() {
     if env::var(BACKTRACE_ENV).is_err() {
         env::set_var(BACKTRACE_ENV, "short");
     }
 
-    setup_logger();
-
-    if env::args().any(|arg| arg == "--version") {
-        let date = option_env!("CFG_DATE").unwrap_or("unknown");
-        let rev = option_env!("CFG_REV").unwrap_or("unknown");
-        println!("nil {} {}", date, rev);
-        return;
+    let mut log_file = env::var_os(LOG_PATH_ENV).map(PathBuf::from);
+    let mut args = env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--version") => {
+                let date = option_env!("CFG_DATE").unwrap_or("unknown");
+                let rev = option_env!("CFG_REV").unwrap_or("unknown");
+                println!("nil {} {}", date, rev);
+                return;
+            }
+            Some("diagnostics") => {
+                let files = args.map(PathBuf::from).collect::<Vec<_>>();
+                run_diagnostics(&files);
+            }
+            Some("--log-file") => match args.next() {
+                Some(path) => log_file = Some(path.into()),
+                None => {
+                    eprintln!("Missing path for --log-file");
+                    process::exit(1);
+                }
+            },
+            // Ignore other arguments, like `--stdio` passed by some clients.
+            _ => {}
+        }
     }
 
+    setup_logger(log_file.as_deref());
+
     let (conn, io_threads) = Connection::stdio();
-    match nil::main_loop(conn).and_then(|()| io_threads.join().map_err(Into::into)) {
+    let ret = nil::main_loop(conn).and_then(|reason| match reason {
+        nil::ExitReason::Exit => io_threads.join().map_err(Into::into),
+        // The reader thread is blocked on stdin which is still open. Exit without waiting for it.
+        nil::ExitReason::Idle => Ok(()),
+    });
+    match ret {
         Ok(()) => {}
         Err(err) => {
             tracing::error!("Unexpected error: {}", err);
@@ -34,21 +62,27 @@ fn main() {
     }
 }
 
-fn setup_logger() {
-    let file = env::var_os(LOG_PATH_ENV).and_then(|path| {
-        let path = PathBuf::from(path);
-        if let Some(parent) = path.parent() {
-            fs::create_dir_all(&parent).ok()?;
+/// `nil diagnostics [FILE...]`: print diagnostics of files in the current directory and exit.
+/// The exit code is 1 if there are errors or diagnostics listed in `errorOnLints`.
+fn run_diagnostics(files: &[PathBuf]) -> ! {
+    let ret = env::current_dir()
+        .map_err(Into::into)
+        .and_then(|root| nil::run_diagnostics(&root, files, &mut io::stdout().lock()));
+    match ret {
+        Ok(true) => process::exit(0),
+        Ok(false) => process::exit(1),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(101);
         }
-        fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(path)
-            .ok()
-    });
+    }
+}
+
+fn setup_logger(log_file: Option<&Path>) {
+    let file = log_file.and_then(|path| RotatingFile::open(path.to_owned()).ok());
 
     let writer = match file {
-        Some(file) => BoxMakeWriter::new(Arc::new(file)),
+        Some(file) => BoxMakeWriter::new(Mutex::new(file)),
         None => BoxMakeWriter::new(io::stderr),
     };
 
@@ -57,3 +91,51 @@ fn setup_logger() {
         .with_writer(writer)
         .init();
 }
+
+/// An appending log file with simple size-based rotation.
+/// When it exceeds `MAX_LOG_FILE_SIZE`, it is renamed to `<path>.1` and a new one is created.
+///
+/// Writes are unbuffered, so nothing is lost when the process exits.
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self { path, file, size })
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let mut backup = self.path.clone().into_os_string();
+        backup.push(".1");
+        fs::rename(&self.path, backup)?;
+        *self = Self::open(self.path.clone())?;
+        Ok(())
+    }
+}
+
+impl io::Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size != 0 && self.size + buf.len() as u64 > MAX_LOG_FILE_SIZE {
+            // Keep logging into the current file if rotation fails.
+            let _ = self.rotate();
+        }
+        let len = self.file.write(buf)?;
+        self.size += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
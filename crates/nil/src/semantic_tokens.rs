@@ -1,5 +1,5 @@
 use ide::{BuiltinKind, HlKeyword, HlPunct, HlTag, NameKind};
-use lsp_types::{SemanticTokenModifier, SemanticTokenType};
+use lsp_types::{SemanticTokenModifier, SemanticTokenType, SemanticTokensLegend};
 
 macro_rules! def_index {
     (
@@ -30,8 +30,18 @@ def_index! {
     String => SemanticTokenType::STRING,
     Struct => SemanticTokenType::STRUCT,
     Variable => SemanticTokenType::VARIABLE,
+    // Appended to keep the indices of existing types stable.
+    Float => SemanticTokenType::new("float"),
 }
 
+/// Standard types to use instead if the client doesn't support our custom one.
+/// Clients usually only list standard types in their capabilities.
+const TYPE_FALLBACKS: &[(TokenTypeIdx, TokenTypeIdx)] = &[
+    (TokenTypeIdx::Float, TokenTypeIdx::Number),
+    (TokenTypeIdx::Path, TokenTypeIdx::String),
+    (TokenTypeIdx::Punctuation, TokenTypeIdx::Operator),
+];
+
 def_index! {
     SemanticTokenModifier, SEMANTIC_TOKEN_MODIFIERS, TokenModIdx;
 
@@ -60,6 +70,83 @@ impl TokenModSet {
     }
 }
 
+/// The subset of our legend which is also supported by the client.
+/// Types and modifiers are re-indexed in the negotiated legend.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NegotiatedLegend {
+    types: Vec<SemanticTokenType>,
+    modifiers: Vec<SemanticTokenModifier>,
+    type_map: Vec<Option<u32>>,
+    mod_map: Vec<Option<u32>>,
+}
+
+impl Default for NegotiatedLegend {
+    /// The full legend, used when the client doesn't tell what it supports.
+    fn default() -> Self {
+        Self::new(SEMANTIC_TOKEN_TYPES, SEMANTIC_TOKEN_MODIFIERS)
+    }
+}
+
+impl NegotiatedLegend {
+    pub fn new(
+        client_types: &[SemanticTokenType],
+        client_modifiers: &[SemanticTokenModifier],
+    ) -> Self {
+        fn intersect<T: Clone + PartialEq>(ours: &[T], theirs: &[T]) -> (Vec<T>, Vec<Option<u32>>) {
+            let mut subset = Vec::new();
+            let map = ours
+                .iter()
+                .map(|x| {
+                    theirs.contains(x).then(|| {
+                        subset.push(x.clone());
+                        subset.len() as u32 - 1
+                    })
+                })
+                .collect();
+            (subset, map)
+        }
+
+        let (types, mut type_map) = intersect(SEMANTIC_TOKEN_TYPES, client_types);
+        // Fallback to more general types, so these tokens are not dropped.
+        for (ty, general) in TYPE_FALLBACKS {
+            if type_map[*ty as usize].is_none() {
+                type_map[*ty as usize] = type_map[*general as usize];
+            }
+        }
+        let (modifiers, mod_map) = intersect(SEMANTIC_TOKEN_MODIFIERS, client_modifiers);
+        Self {
+            types,
+            modifiers,
+            type_map,
+            mod_map,
+        }
+    }
+
+    pub fn legend(&self) -> SemanticTokensLegend {
+        SemanticTokensLegend {
+            token_types: self.types.clone(),
+            token_modifiers: self.modifiers.clone(),
+        }
+    }
+
+    /// Get the index of a token type in the negotiated legend.
+    /// Returns `None` if the client doesn't support it.
+    pub fn map_type(&self, ty: TokenTypeIdx) -> Option<u32> {
+        self.type_map[ty as usize]
+    }
+
+    /// Get the bitset of token modifiers in the negotiated legend.
+    /// Unsupported modifiers are dropped.
+    pub fn map_modifiers(&self, mods: TokenModSet) -> u32 {
+        self.mod_map
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| mods.0 & (1 << i) != 0)
+            .filter_map(|(_, &idx)| idx)
+            .fold(0, |set, idx| set | (1 << idx))
+    }
+}
+
 pub(crate) fn to_semantic_type_and_modifiers(tag: HlTag) -> (TokenTypeIdx, TokenModSet) {
     let mut mods = TokenModSet::default();
     let ty = match tag {
@@ -74,6 +161,7 @@ pub(crate) fn to_semantic_type_and_modifiers(tag: HlTag) -> (TokenTypeIdx, Token
                 NameKind::Param | NameKind::PatField => TokenTypeIdx::Parameter,
             }
         }
+        HlTag::FunctionRef => TokenTypeIdx::Function,
         HlTag::UnresolvedRef => {
             mods.insert(TokenModIdx::Unresolved);
             TokenTypeIdx::Variable
@@ -95,7 +183,8 @@ pub(crate) fn to_semantic_type_and_modifiers(tag: HlTag) -> (TokenTypeIdx, Token
             mods.insert(TokenModIdx::Escape);
             TokenTypeIdx::String
         }
-        HlTag::FloatLiteral | HlTag::IntLiteral => TokenTypeIdx::Number,
+        HlTag::FloatLiteral => TokenTypeIdx::Float,
+        HlTag::IntLiteral => TokenTypeIdx::Number,
         HlTag::Keyword(kw) => match kw {
             HlKeyword::Conditional => {
                 mods.insert(TokenModIdx::Conditional);
@@ -128,3 +217,72 @@ pub(crate) fn to_semantic_type_and_modifiers(tag: HlTag) -> (TokenTypeIdx, Token
     };
     (ty, mods)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{NegotiatedLegend, TokenModIdx, TokenModSet, TokenTypeIdx};
+    use lsp_types::{SemanticTokenModifier, SemanticTokenType};
+
+    #[test]
+    fn full_legend() {
+        let legend = NegotiatedLegend::default();
+        assert_eq!(legend.map_type(TokenTypeIdx::Comment), Some(0));
+        assert_eq!(legend.map_type(TokenTypeIdx::Variable), Some(11));
+        assert_eq!(legend.map_type(TokenTypeIdx::Float), Some(12));
+        let mut mods = TokenModSet::default();
+        mods.insert(TokenModIdx::Definition);
+        mods.insert(TokenModIdx::Unresolved);
+        assert_eq!(legend.map_modifiers(mods), mods.0);
+    }
+
+    #[test]
+    fn subset_legend() {
+        let legend = NegotiatedLegend::new(
+            &[
+                SemanticTokenType::VARIABLE,
+                SemanticTokenType::new("unknown"),
+                SemanticTokenType::KEYWORD,
+            ],
+            &[SemanticTokenModifier::DEFINITION],
+        );
+        assert_eq!(
+            legend.legend().token_types,
+            [SemanticTokenType::KEYWORD, SemanticTokenType::VARIABLE]
+        );
+        assert_eq!(
+            legend.legend().token_modifiers,
+            [SemanticTokenModifier::DEFINITION]
+        );
+        assert_eq!(legend.map_type(TokenTypeIdx::Keyword), Some(0));
+        assert_eq!(legend.map_type(TokenTypeIdx::Variable), Some(1));
+        assert_eq!(legend.map_type(TokenTypeIdx::Path), None);
+        assert_eq!(legend.map_type(TokenTypeIdx::Float), None);
+        assert_eq!(legend.map_type(TokenTypeIdx::Punctuation), None);
+
+        let mut mods = TokenModSet::default();
+        mods.insert(TokenModIdx::Definition);
+        mods.insert(TokenModIdx::Unresolved);
+        assert_eq!(legend.map_modifiers(mods), 1);
+    }
+
+    #[test]
+    fn fallback_type() {
+        let legend = NegotiatedLegend::new(
+            &[SemanticTokenType::KEYWORD, SemanticTokenType::NUMBER],
+            &[],
+        );
+        assert_eq!(legend.map_type(TokenTypeIdx::Number), Some(1));
+        assert_eq!(legend.map_type(TokenTypeIdx::Float), Some(1));
+
+        let legend = NegotiatedLegend::new(
+            &[
+                SemanticTokenType::OPERATOR,
+                SemanticTokenType::STRING,
+                SemanticTokenType::new("path"),
+            ],
+            &[],
+        );
+        assert_eq!(legend.map_type(TokenTypeIdx::Path), Some(1));
+        assert_eq!(legend.map_type(TokenTypeIdx::Punctuation), Some(0));
+    }
+}
@@ -1,45 +1,132 @@
-use crate::{convert, handler, Result, Vfs};
+use crate::config::{CONFIG_SUB_SECTIONS, PROJECT_CONFIG_FILES};
+use crate::semantic_tokens::NegotiatedLegend;
+use crate::vfs::LineMap;
+use crate::{
+    capabilities, convert, handler, lsp_ext, CompletionHistory, Config, InitOptions, Result, Vfs,
+};
 use crossbeam_channel::{Receiver, Sender};
-use ide::{Analysis, AnalysisHost, Cancelled, VfsPath};
+use ide::{Analysis, AnalysisHost, Cancelled, DiagnosticKind, FileId, VfsPath};
+use ignore::overrides::{Override, OverrideBuilder};
+use ignore::WalkState;
 use lsp_server::{ErrorCode, Message, Notification, ReqQueue, Request, RequestId, Response};
 use lsp_types::notification::Notification as _;
+use lsp_types::request::Request as _;
 use lsp_types::{
-    notification as notif, request as req, ConfigurationItem, ConfigurationParams,
-    PublishDiagnosticsParams, Url,
+    notification as notif, request as req, ApplyWorkspaceEditParams, ClientCapabilities,
+    ConfigurationItem, ConfigurationParams, Diagnostic, DiagnosticSeverity,
+    DidChangeWatchedFilesRegistrationOptions, FileChangeType, FileSystemWatcher, MessageType,
+    NumberOrString, Position, ProgressParams, ProgressParamsValue, PublishDiagnosticsParams, Range,
+    Registration, RegistrationParams, ShowMessageParams, Unregistration, UnregistrationParams, Url,
+    WorkDoneProgress, WorkDoneProgressBegin, WorkDoneProgressCreateParams, WorkDoneProgressEnd,
 };
 use serde::{Deserialize, Serialize};
 use std::cell::Cell;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
 use std::panic::UnwindSafe;
-use std::path::PathBuf;
-use std::sync::{Arc, Once, RwLock};
-use std::{fs, panic};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Once, RwLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use std::{fmt, fs, mem, panic, thread};
 
-const MAX_DIAGNOSTICS_CNT: usize = 128;
 const FILTER_FILE_EXTENTION: &str = "nix";
 const CONFIG_KEY: &str = "nil";
-
-#[derive(Debug, Default, Deserialize)]
-pub struct Config {}
+const INDEXING_PROGRESS_TOKEN: &str = "nil/indexing";
+/// Rebuild the analysis state after a request handler panics this many times in a row.
+const MAX_CONSECUTIVE_PANICS: usize = 3;
 
 type ReqHandler = fn(&mut State, Response);
+/// Paths and contents of scanned files. Contents are `None` if they are read lazily.
+type ScannedFiles = Vec<(VfsPath, Option<String>)>;
+
+/// Why the main loop stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    /// The client sent `exit`.
+    Exit,
+    /// No message arrived in `idleShutdownSecs`. The client may still hold the connection.
+    Idle,
+}
 
 pub struct State {
     host: AnalysisHost,
     vfs: Arc<RwLock<Vfs>>,
-    opened_files: Arc<RwLock<HashSet<Url>>>,
+    /// Opened documents with their versions.
+    opened_files: Arc<RwLock<HashMap<Url, i32>>>,
     workspace_root: Option<PathBuf>,
     req_queue: ReqQueue<(), ReqHandler>,
     sender: Sender<Message>,
     is_shutdown: bool,
-    config: Config,
+    is_workspace_loaded: bool,
+    /// Set to cancel the running workspace scan, if any.
+    scan_cancel: Option<Arc<AtomicBool>>,
+    scan_sender: Sender<ScannedFiles>,
+    scan_receiver: Receiver<ScannedFiles>,
+    indexing_progress: Option<NumberOrString>,
+    /// Directories whose files are added to the workspace, in on-demand scanning.
+    indexed_dirs: HashSet<PathBuf>,
+    /// Requests received before the workspace is loaded, to be handled afterwards.
+    pending_requests: Vec<Request>,
+    /// The latest configuration request. Responses to older ones are stale and ignored.
+    config_request_id: Option<RequestId>,
+    client_caps: ClientCapabilities,
+    init_options: InitOptions,
+    /// The latest config pushed by the client.
+    client_config: serde_json::Value,
+    /// The config from the project config file, overridden by `client_config`.
+    project_config: serde_json::Value,
+    config: Arc<Config>,
+    semantic_legend: Arc<NegotiatedLegend>,
+    code_action_resolve: bool,
+    related_information: bool,
+    document_changes: bool,
+    change_annotations: bool,
+    apply_edit: bool,
+    hover_markdown: bool,
+    snippet_support: bool,
+    /// Whether formatting is registered dynamically on configuring a formatter.
+    dynamic_formatting: bool,
+    /// Whether formatting is currently registered dynamically.
+    formatting_registered: bool,
+    completion_history: Arc<CompletionHistory>,
+    /// The method whose handler panicked in the latest requests, and how many times.
+    consecutive_panics: Option<(&'static str, usize)>,
+    pending_diagnostics: Arc<Mutex<PendingDiagnostics>>,
+    /// Background threads computing diagnostics, which may be still running.
+    diagnostics_threads: Vec<JoinHandle<()>>,
+}
+
+/// Opened files whose diagnostics are being computed in background, with the revisions of
+/// the latest computations. Results of older ones are superseded, and are not published.
+#[derive(Debug, Default)]
+struct PendingDiagnostics {
+    last_revision: u64,
+    files: HashMap<FileId, u64>,
+}
+
+/// A diagnostics computation of a file in background.
+struct DiagnosticsTask {
+    file: FileId,
+    uri: Url,
+    revision: u64,
+    /// The line map of the analyzed content, to detect changes made meanwhile.
+    line_map: Arc<LineMap>,
 }
 
 impl State {
-    pub fn new(responder: Sender<Message>, workspace_root: Option<PathBuf>) -> Self {
+    pub fn new(
+        responder: Sender<Message>,
+        workspace_root: Option<PathBuf>,
+        client_caps: ClientCapabilities,
+        init_options: InitOptions,
+        semantic_legend: NegotiatedLegend,
+    ) -> Self {
         // Vfs root must be absolute.
         let workspace_root = workspace_root.and_then(|root| root.canonicalize().ok());
         let vfs = Vfs::new(workspace_root.clone().unwrap_or_else(|| PathBuf::from("/")));
+        let (scan_sender, scan_receiver) = crossbeam_channel::unbounded();
         Self {
             host: Default::default(),
             vfs: Arc::new(RwLock::new(vfs)),
@@ -48,55 +135,91 @@ impl State {
             req_queue: ReqQueue::default(),
             sender: responder,
             is_shutdown: false,
-            config: Config::default(),
+            is_workspace_loaded: false,
+            scan_cancel: None,
+            scan_sender,
+            scan_receiver,
+            indexing_progress: None,
+            indexed_dirs: HashSet::new(),
+            pending_requests: Vec::new(),
+            config_request_id: None,
+            code_action_resolve: capabilities::negotiate_code_action_resolve(&client_caps),
+            related_information: capabilities::negotiate_related_information(&client_caps),
+            document_changes: capabilities::negotiate_document_changes(&client_caps),
+            change_annotations: capabilities::negotiate_change_annotations(&client_caps),
+            apply_edit: capabilities::negotiate_apply_edit(&client_caps),
+            hover_markdown: capabilities::negotiate_hover_markdown(&client_caps),
+            snippet_support: capabilities::negotiate_snippet_support(&client_caps),
+            dynamic_formatting: capabilities::negotiate_dynamic_formatting(&client_caps),
+            formatting_registered: false,
+            completion_history: Arc::default(),
+            consecutive_panics: None,
+            pending_diagnostics: Arc::default(),
+            diagnostics_threads: Vec::new(),
+            client_caps,
+            init_options,
+            client_config: serde_json::Value::Object(Default::default()),
+            project_config: serde_json::Value::Object(Default::default()),
+            config: Arc::default(),
+            semantic_legend: Arc::new(semantic_legend),
         }
     }
 
-    pub fn run(&mut self, lsp_receiver: Receiver<Message>) -> Result<()> {
-        if let Some(root) = &self.workspace_root {
-            let mut vfs = self.vfs.write().unwrap();
-            for entry in ignore::WalkBuilder::new(root).follow_links(false).build() {
-                (|| -> Option<()> {
-                    let entry = entry.ok()?;
-                    if entry
-                        .path()
-                        .extension()
-                        .map_or(true, |ext| ext != FILTER_FILE_EXTENTION)
-                    {
-                        return None;
-                    }
-
-                    let relative_path = entry.path().strip_prefix(root).ok()?;
-                    let vpath = VfsPath::from_path(relative_path)?;
-                    let text = fs::read_to_string(entry.path()).ok().unwrap_or_default();
-                    vfs.set_path_content(vpath, text);
-                    Some(())
-                })();
-            }
-            drop(vfs);
-            self.apply_vfs_change();
-        }
-
-        for msg in &lsp_receiver {
-            match msg {
-                Message::Request(req) => self.dispatch_request(req),
-                Message::Notification(notif) => {
-                    if notif.method == notif::Exit::METHOD {
-                        return Ok(());
+    pub fn run(&mut self, lsp_receiver: Receiver<Message>) -> Result<ExitReason> {
+        // Messages are still handled during the workspace scan, so that `exit` can interrupt it.
+        let scan_receiver = self.scan_receiver.clone();
+        self.load_project_config();
+        let mut idle_timer = self.idle_timer();
+        loop {
+            crossbeam_channel::select! {
+                recv(lsp_receiver) -> msg => {
+                    match msg {
+                        Ok(Message::Request(req)) => self.dispatch_request(req),
+                        Ok(Message::Notification(notif)) => {
+                            if notif.method == notif::Exit::METHOD {
+                                self.cancel_scan();
+                                return Ok(ExitReason::Exit);
+                            }
+                            self.dispatch_notification(notif)?;
+                        }
+                        Ok(Message::Response(resp)) => self.dispatch_response(resp),
+                        Err(_) => break,
                     }
-                    self.dispatch_notification(notif)?;
+                    // The config may also be changed by this message.
+                    idle_timer = self.idle_timer();
                 }
-                Message::Response(resp) => {
-                    if let Some(callback) = self.req_queue.outgoing.complete(resp.id.clone()) {
-                        callback(self, resp);
-                    }
+                recv(scan_receiver) -> files => {
+                    self.finish_workspace_scan(files.expect("Sender is alive"));
+                }
+                recv(idle_timer) -> _ => {
+                    tracing::info!(
+                        "No message received in {}s, shutting down",
+                        self.config.idle_shutdown_secs,
+                    );
+                    self.cancel_scan();
+                    return Ok(ExitReason::Idle);
                 }
             }
         }
 
+        self.cancel_scan();
         Err("Channel closed".into())
     }
 
+    /// Fires when the server is idle for `idleShutdownSecs`. Never fires if it is disabled.
+    fn idle_timer(&self) -> Receiver<Instant> {
+        match self.config.idle_shutdown_secs {
+            0 => crossbeam_channel::never(),
+            secs => crossbeam_channel::after(Duration::from_secs(secs)),
+        }
+    }
+
+    fn dispatch_response(&mut self, resp: Response) {
+        if let Some(callback) = self.req_queue.outgoing.complete(resp.id.clone()) {
+            callback(self, resp);
+        }
+    }
+
     fn dispatch_request(&mut self, req: Request) {
         if self.is_shutdown {
             let resp = Response::new_err(
@@ -108,9 +231,28 @@ impl State {
             return;
         }
 
+        // Results would be incomplete before all workspace files are loaded.
+        if self.is_indexing() && req.method != req::Shutdown::METHOD {
+            self.pending_requests.push(req);
+            return;
+        }
+        if self.config.scan.lazy || self.config.scan.on_demand {
+            self.load_request_imports(&req);
+        }
+
         RequestDispatcher(self, Some(req))
             .on_sync_mut::<req::Shutdown>(|st, ()| {
                 st.is_shutdown = true;
+                st.cancel_scan();
+                // Clients would retry them, if they are still interested.
+                for req in mem::take(&mut st.pending_requests) {
+                    let resp = Response::new_err(
+                        req.id,
+                        ErrorCode::ContentModified as i32,
+                        "Shutdown before indexing finished.".into(),
+                    );
+                    st.sender.send(resp.into()).unwrap();
+                }
                 Ok(())
             })
             .on::<req::GotoDefinition>(handler::goto_definition)
@@ -122,26 +264,122 @@ impl State {
             .on::<req::SemanticTokensFullRequest>(handler::semantic_token_full)
             .on::<req::SemanticTokensRangeRequest>(handler::semantic_token_range)
             .on::<req::HoverRequest>(handler::hover)
+            .on::<req::DocumentHighlightRequest>(handler::document_highlight)
+            .on::<req::FoldingRangeRequest>(handler::folding_range)
+            .on::<req::Formatting>(handler::formatting)
+            .on::<req::RangeFormatting>(handler::range_formatting)
+            .on::<req::CodeActionRequest>(handler::code_action)
+            .on::<req::CodeActionResolveRequest>(handler::code_action_resolve)
+            .on_sync_mut::<req::ExecuteCommand>(|st, params| {
+                let arg = params
+                    .arguments
+                    .into_iter()
+                    .next()
+                    .ok_or("Missing command argument");
+                let edit = match &*params.command {
+                    lsp_ext::FORMAT_DOCUMENT_COMMAND => {
+                        let text_document = serde_json::from_value(arg?)?;
+                        handler::format_document(st.snapshot(), text_document)?
+                    }
+                    lsp_ext::SELECT_ENCLOSING_BINDING_COMMAND => {
+                        let pos = serde_json::from_value(arg?)?;
+                        let range = handler::select_enclosing_binding(st.snapshot(), pos)?;
+                        return Ok(Some(serde_json::to_value(range)?));
+                    }
+                    lsp_ext::COMPLETION_ACCEPTED_COMMAND => {
+                        let label = serde_json::from_value::<String>(arg?)?;
+                        Arc::make_mut(&mut st.completion_history).record(&label);
+                        return Ok(None);
+                    }
+                    cmd => return Err(format!("Unknown command: {cmd}").into()),
+                };
+                if let Some(edit) = edit {
+                    st.send_request::<req::ApplyWorkspaceEdit>(
+                        ApplyWorkspaceEditParams {
+                            label: Some("Format document".into()),
+                            edit,
+                        },
+                        |_, resp| {
+                            if let Some(err) = resp.error {
+                                tracing::error!("Failed to apply edits: {}", err.message);
+                            }
+                        },
+                    );
+                }
+                Ok(None)
+            })
+            .on::<lsp_ext::FileImports>(handler::file_imports)
+            .on::<lsp_ext::FindSimilar>(handler::find_similar)
+            .on::<lsp_ext::FreeVariables>(handler::free_variables)
+            .on::<lsp_ext::DefUseGraph>(handler::def_use_graph)
+            .on::<lsp_ext::NameResolution>(handler::name_resolution)
+            .on::<lsp_ext::ParseStatus>(handler::parse_status)
+            .on::<lsp_ext::AttrPath>(handler::attr_path)
+            .on::<lsp_ext::SourceRoot>(handler::source_root)
+            .on::<lsp_ext::FormatText>(handler::format_text)
             .finish();
     }
 
     fn dispatch_notification(&mut self, notif: Notification) -> Result<()> {
         NotificationDispatcher(self, Some(notif))
+            .on_sync_mut::<notif::Initialized>(|st, _params| {
+                st.register_file_watchers();
+                // Wait for the config to load the workspace, if the client would send one.
+                if !st.request_config() {
+                    st.load_workspace_files();
+                }
+                Ok(())
+            })?
             .on_sync_mut::<notif::DidOpenTextDocument>(|st, params| {
                 let uri = &params.text_document.uri;
-                st.opened_files.write().unwrap().insert(uri.clone());
-                st.set_vfs_file_content(uri, params.text_document.text)?;
+                st.opened_files
+                    .write()
+                    .unwrap()
+                    .insert(uri.clone(), params.text_document.version);
+                // Diagnostics are always published for opened files, even if empty,
+                // to clear stale ones left by the client from previous sessions.
+                if let Err(err) = st.set_vfs_file_content(uri, params.text_document.text) {
+                    tracing::warn!("Cannot analyze opened file {}: {}", uri, err);
+                    st.send_notification::<notif::PublishDiagnostics>(PublishDiagnosticsParams {
+                        uri: uri.clone(),
+                        diagnostics: Vec::new(),
+                        version: None,
+                    });
+                } else if st.config.scan.on_demand {
+                    st.load_imports_of(uri);
+                }
                 Ok(())
             })?
             .on_sync_mut::<notif::DidCloseTextDocument>(|st, params| {
                 // N.B. Don't clear text here.
-                st.opened_files
-                    .write()
-                    .unwrap()
-                    .remove(&params.text_document.uri);
+                let uri = params.text_document.uri;
+                st.opened_files.write().unwrap().remove(&uri);
+                // Drop diagnostics still being computed.
+                if let Ok(file) = st.vfs.read().unwrap().file_for_uri(&uri) {
+                    st.pending_diagnostics.lock().unwrap().files.remove(&file);
+                }
+                // Documents not backed by disk are gone once closed.
+                if st.vfs.write().unwrap().remove_overlay(&uri) {
+                    st.apply_vfs_change();
+                }
+                if st.config.clear_diagnostics_on_close {
+                    st.send_notification::<notif::PublishDiagnostics>(PublishDiagnosticsParams {
+                        uri,
+                        diagnostics: Vec::new(),
+                        version: None,
+                    });
+                }
                 Ok(())
             })?
             .on_sync_mut::<notif::DidChangeTextDocument>(|st, params| {
+                if let Some(version) = st
+                    .opened_files
+                    .write()
+                    .unwrap()
+                    .get_mut(&params.text_document.uri)
+                {
+                    *version = params.text_document.version;
+                }
                 let mut vfs = st.vfs.write().unwrap();
                 if let Ok(file) = vfs.file_for_uri(&params.text_document.uri) {
                     for change in params.content_changes {
@@ -157,51 +395,500 @@ impl State {
                 }
                 Ok(())
             })?
+            .on_sync_mut::<notif::DidSaveTextDocument>(|st, params| {
+                let uri = &params.text_document.uri;
+                // The saved text may differ from the buffer after post-save hooks.
+                let text = match params.text {
+                    Some(text) => text,
+                    None if st.config.reload_on_save => {
+                        match uri.to_file_path().map(fs::read_to_string) {
+                            Ok(Ok(text)) => text,
+                            _ => return Ok(()),
+                        }
+                    }
+                    None => return Ok(()),
+                };
+                if let Err(err) = st.set_vfs_file_content(uri, text) {
+                    tracing::warn!("Ignored saved file {}: {}", uri, err);
+                }
+                Ok(())
+            })?
+            .on_sync_mut::<notif::WorkDoneProgressCancel>(|st, params| {
+                // Stop the scan early. Files scanned so far are still loaded.
+                if st.indexing_progress.as_ref() == Some(&params.token) {
+                    if let Some(cancel) = &st.scan_cancel {
+                        tracing::info!("Workspace scan cancelled by the client");
+                        cancel.store(true, Ordering::Relaxed);
+                    }
+                }
+                Ok(())
+            })?
             .on_sync_mut::<notif::DidChangeConfiguration>(|st, _params| {
                 // As stated in https://github.com/microsoft/language-server-protocol/issues/676,
                 // this notification's parameters should be ignored and the actual config queried separately.
-                st.send_request::<req::WorkspaceConfiguration>(
-                    ConfigurationParams {
-                        items: vec![ConfigurationItem {
-                            scope_uri: None,
-                            section: Some(CONFIG_KEY.into()),
-                        }],
-                    },
-                    |st, resp| {
-                        let ret = match resp.error {
-                            None => Ok(resp
-                                .result
-                                .and_then(|mut v| Some(v.get_mut(0)?.take()))
-                                .unwrap_or_default()),
-                            Some(err) => Err(format!(
-                                "LSP error {}: {}, data: {:?}",
-                                err.code, err.message, err.data
-                            )),
-                        };
-                        match ret {
-                            Ok(v) => {
-                                tracing::info!("Updating config: {:?}", v);
-                                st.update_config(v);
-                            }
-                            Err(err) => tracing::error!("Failed to update config: {}", err),
+                st.request_config();
+                Ok(())
+            })?
+            .on_sync_mut::<notif::DidChangeWatchedFiles>(|st, params| {
+                let opened_files = st.opened_files.read().unwrap().clone();
+                let mut vfs = st.vfs.write().unwrap();
+                let mut project_config_changed = false;
+                for event in params.changes {
+                    if st.is_project_config_file(&event.uri) {
+                        project_config_changed = true;
+                        continue;
+                    }
+                    // Opened files are managed by the client.
+                    if opened_files.contains_key(&event.uri) {
+                        continue;
+                    }
+                    // Files in directories not indexed yet are added when they are indexed.
+                    if st.config.scan.on_demand && !st.is_dir_indexed(&event.uri) {
+                        continue;
+                    }
+                    // Drop the cached content, to be read again on the next use.
+                    if st.config.scan.lazy && event.typ != FileChangeType::DELETED {
+                        if let Err(err) = vfs.set_uri_unloaded(&event.uri) {
+                            tracing::warn!("Ignored file change of {}: {}", event.uri, err);
+                        }
+                        continue;
+                    }
+                    let text = if event.typ == FileChangeType::DELETED {
+                        String::new()
+                    } else {
+                        match event.uri.to_file_path().map(fs::read_to_string) {
+                            Ok(Ok(text)) => text,
+                            _ => continue,
                         }
-                    },
-                );
+                    };
+                    if let Err(err) = vfs.set_uri_content(&event.uri, text) {
+                        tracing::warn!("Ignored file change of {}: {}", event.uri, err);
+                    }
+                }
+                drop(vfs);
+                st.apply_vfs_change();
+                if project_config_changed {
+                    st.load_project_config();
+                }
                 Ok(())
             })?
             .finish()
     }
 
+    /// Whether the workspace is still waiting to be loaded.
+    /// Without a workspace root, there is nothing to wait for.
+    fn is_indexing(&self) -> bool {
+        self.workspace_root.is_some() && !self.is_workspace_loaded
+    }
+
+    /// Start scanning all files in the workspace in background.
+    /// They are loaded in [`State::finish_workspace_scan`] when the scan completes.
+    fn load_workspace_files(&mut self) {
+        if self.is_workspace_loaded || self.scan_cancel.is_some() {
+            return;
+        }
+        let root = match &self.workspace_root {
+            Some(root) => root.clone(),
+            None => {
+                self.is_workspace_loaded = true;
+                return;
+            }
+        };
+        // Directories are indexed when they are used.
+        if self.config.scan.on_demand {
+            self.is_workspace_loaded = true;
+            return;
+        }
+
+        self.indexing_progress = self.begin_progress(INDEXING_PROGRESS_TOKEN, "Indexing", true);
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.scan_cancel = Some(cancel.clone());
+        let threads = self.config.scan_threads;
+        let lazy = self.config.scan.lazy;
+        let sender = self.scan_sender.clone();
+        thread::Builder::new()
+            .name("scan".into())
+            .spawn(move || {
+                // Partial results are still sent if the user cancelled the progress.
+                // They are dropped in `finish_workspace_scan` if the scan was aborted.
+                let files = scan_workspace_files(&root, threads, lazy, &cancel);
+                // The receiver is gone if the server exited.
+                let _ = sender.send(files);
+            })
+            .expect("Failed to spawn the scanning thread");
+    }
+
+    /// Abort the running workspace scan, dropping its results.
+    fn cancel_scan(&mut self) {
+        if let Some(cancel) = self.scan_cancel.take() {
+            cancel.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Load scanned workspace files, then handle requests pending on them.
+    fn finish_workspace_scan(&mut self, files: ScannedFiles) {
+        if self.scan_cancel.take().is_none() {
+            return;
+        }
+        self.is_workspace_loaded = true;
+
+        self.add_scanned_files(files);
+        self.apply_vfs_change();
+        if let Some(token) = self.indexing_progress.take() {
+            self.end_progress(token);
+        }
+        if self.config.prewarm {
+            self.prewarm();
+        }
+
+        for req in mem::take(&mut self.pending_requests) {
+            self.dispatch_request(req);
+        }
+    }
+
+    /// Add scanned files to the Vfs, except opened ones.
+    fn add_scanned_files(&mut self, files: ScannedFiles) {
+        let opened_files = self.opened_files.read().unwrap().clone();
+        let mut vfs = self.vfs.write().unwrap();
+        for (vpath, text) in files {
+            // Opened files are managed by the client.
+            if opened_files.contains_key(&vfs.uri_for_path(&vpath)) {
+                continue;
+            }
+            match text {
+                Some(text) => vfs.set_path_content(vpath, text),
+                None => vfs.set_path_unloaded(vpath),
+            }
+        }
+    }
+
+    /// Add files directly under `dir` to the workspace, in on-demand scanning.
+    /// Returns `false` if it is already indexed, or it is outside the workspace.
+    fn index_dir(&mut self, dir: &Path) -> bool {
+        let root = match &self.workspace_root {
+            Some(root) if dir.starts_with(root) => root.clone(),
+            _ => return false,
+        };
+        if !self.indexed_dirs.insert(dir.to_owned()) {
+            return false;
+        }
+        tracing::debug!("Indexing {}", dir.display());
+        let files = scan_dir_files(&root, dir, self.config.scan.lazy);
+        self.add_scanned_files(files);
+        true
+    }
+
+    /// Whether the directory containing `uri` is indexed, in on-demand scanning.
+    fn is_dir_indexed(&self, uri: &Url) -> bool {
+        let path = match uri.to_file_path() {
+            Ok(path) => path,
+            Err(()) => return false,
+        };
+        matches!(path.parent(), Some(dir) if self.indexed_dirs.contains(dir))
+    }
+
+    /// Prepare files imported by the document of a request, transitively,
+    /// so that the request can look into them.
+    fn load_request_imports(&mut self, req: &Request) {
+        let uri = match req
+            .params
+            .get("textDocument")
+            .and_then(|doc| doc.get("uri"))
+            .and_then(|uri| Url::deserialize(uri).ok())
+        {
+            Some(uri) => uri,
+            None => return,
+        };
+        self.load_imports_of(&uri);
+    }
+
+    /// Prepare files imported by `uri`, transitively.
+    /// In lazy scanning, their contents are read.
+    /// In on-demand scanning, directories of them are indexed.
+    fn load_imports_of(&mut self, uri: &Url) {
+        let on_demand = self.config.scan.on_demand;
+        if on_demand {
+            if let Some(dir) = uri.to_file_path().ok().as_deref().and_then(Path::parent) {
+                if self.index_dir(dir) {
+                    self.apply_vfs_change();
+                }
+            }
+        }
+        let file = match self.vfs.read().unwrap().file_for_uri(uri) {
+            Ok(file) => file,
+            Err(_) => return,
+        };
+
+        let mut visited = HashSet::from([file]);
+        let mut stack = vec![file];
+        while let Some(file) = stack.pop() {
+            if self.vfs.write().unwrap().load_file(file) {
+                self.apply_vfs_change();
+            }
+            // Relative paths in documents not backed by disk resolve to nothing.
+            if on_demand && !self.vfs.read().unwrap().is_overlay(file) {
+                let paths = match self.host.snapshot().referenced_paths(file) {
+                    Ok(paths) => paths,
+                    Err(_) => return,
+                };
+                let mut changed = false;
+                for vpath in paths {
+                    let path = self.vfs.read().unwrap().local_path(&vpath);
+                    // Directories are imported by their `default.nix`.
+                    let dir = if path.is_dir() {
+                        path
+                    } else {
+                        match path.parent() {
+                            Some(dir) => dir.to_owned(),
+                            None => continue,
+                        }
+                    };
+                    changed |= self.index_dir(&dir);
+                }
+                if changed {
+                    self.apply_vfs_change();
+                }
+            }
+            let imports = match self.host.snapshot().file_imports(file) {
+                Ok(imports) => imports,
+                Err(_) => return,
+            };
+            stack.extend(
+                imports
+                    .into_iter()
+                    .map(|import| import.target)
+                    .filter(|&target| visited.insert(target)),
+            );
+        }
+    }
+
+    /// Compute diagnostics of all files in a background thread, to populate caches.
+    /// It stops as soon as the snapshot is cancelled by any change.
+    fn prewarm(&self) {
+        let snap = self.host.snapshot();
+        let config = self.config.diagnostics_config();
+        let files = {
+            let vfs = self.vfs.read().unwrap();
+            // Unloaded files are empty, and are not worth reading only to warm up.
+            vfs.files()
+                .filter(|&file| vfs.is_loaded(file))
+                .collect::<Vec<_>>()
+        };
+        let ret = thread::Builder::new()
+            .name("prewarm".into())
+            .spawn(move || {
+                for (i, &file) in files.iter().enumerate() {
+                    if snap.diagnostics(&config, file).is_err() {
+                        tracing::debug!("Prewarming cancelled after {}/{} files", i, files.len());
+                        return;
+                    }
+                }
+                tracing::debug!("Prewarmed {} files", files.len());
+            });
+        if let Err(err) = ret {
+            tracing::error!("Failed to spawn the prewarming thread: {}", err);
+        }
+    }
+
+    /// Start reporting work done progress, if the client supports that.
+    /// If `cancellable` is set, the client may send `window/workDoneProgress/cancel` for it.
+    fn begin_progress(
+        &mut self,
+        token: &str,
+        title: &str,
+        cancellable: bool,
+    ) -> Option<NumberOrString> {
+        let supported = (|| self.client_caps.window.as_ref()?.work_done_progress)();
+        if supported != Some(true) {
+            return None;
+        }
+
+        let token = NumberOrString::String(token.into());
+        self.send_request::<req::WorkDoneProgressCreate>(
+            WorkDoneProgressCreateParams {
+                token: token.clone(),
+            },
+            |_, resp| {
+                if let Some(err) = resp.error {
+                    tracing::warn!("Failed to create progress: {}", err.message);
+                }
+            },
+        );
+        self.send_notification::<notif::Progress>(ProgressParams {
+            token: token.clone(),
+            value: ProgressParamsValue::WorkDone(WorkDoneProgress::Begin(WorkDoneProgressBegin {
+                title: title.into(),
+                cancellable: Some(cancellable),
+                ..WorkDoneProgressBegin::default()
+            })),
+        });
+        Some(token)
+    }
+
+    fn end_progress(&self, token: NumberOrString) {
+        self.send_notification::<notif::Progress>(ProgressParams {
+            token,
+            value: ProgressParamsValue::WorkDone(WorkDoneProgress::End(WorkDoneProgressEnd {
+                message: None,
+            })),
+        });
+    }
+
+    /// Ask the client to notify us about changes of files on disk, if it supports that.
+    fn register_file_watchers(&mut self) {
+        let supported = (|| {
+            self.client_caps
+                .workspace
+                .as_ref()?
+                .did_change_watched_files
+                .as_ref()?
+                .dynamic_registration
+        })();
+        if supported != Some(true) {
+            return;
+        }
+
+        let options = DidChangeWatchedFilesRegistrationOptions {
+            watchers: vec![
+                FileSystemWatcher {
+                    glob_pattern: format!("**/*.{}", FILTER_FILE_EXTENTION),
+                    kind: None,
+                },
+                FileSystemWatcher {
+                    glob_pattern: format!("{{{}}}", PROJECT_CONFIG_FILES.join(",")),
+                    kind: None,
+                },
+            ],
+        };
+        self.send_request::<req::RegisterCapability>(
+            RegistrationParams {
+                registrations: vec![Registration {
+                    id: notif::DidChangeWatchedFiles::METHOD.into(),
+                    method: notif::DidChangeWatchedFiles::METHOD.into(),
+                    register_options: Some(serde_json::to_value(options).unwrap()),
+                }],
+            },
+            |_, resp| {
+                if let Some(err) = resp.error {
+                    tracing::error!("Failed to register file watchers: {}", err.message);
+                }
+            },
+        );
+    }
+
+    /// Register formatting only if a formatter is configured, and unregister it once removed,
+    /// if the client supports dynamic registration.
+    fn update_formatting_registration(&mut self) {
+        let enable = self.config.formatting.command.is_some();
+        if !self.dynamic_formatting || enable == self.formatting_registered {
+            return;
+        }
+        self.formatting_registered = enable;
+
+        let methods = [req::Formatting::METHOD, req::RangeFormatting::METHOD];
+        if enable {
+            self.send_request::<req::RegisterCapability>(
+                RegistrationParams {
+                    registrations: methods
+                        .iter()
+                        .map(|&method| Registration {
+                            id: method.into(),
+                            method: method.into(),
+                            register_options: None,
+                        })
+                        .collect(),
+                },
+                |_, resp| {
+                    if let Some(err) = resp.error {
+                        tracing::error!("Failed to register formatting: {}", err.message);
+                    }
+                },
+            );
+        } else {
+            self.send_request::<req::UnregisterCapability>(
+                UnregistrationParams {
+                    unregisterations: methods
+                        .iter()
+                        .map(|&method| Unregistration {
+                            id: method.into(),
+                            method: method.into(),
+                        })
+                        .collect(),
+                },
+                |_, resp| {
+                    if let Some(err) = resp.error {
+                        tracing::error!("Failed to unregister formatting: {}", err.message);
+                    }
+                },
+            );
+        }
+    }
+
+    /// Query the configuration from the client, if it supports that.
+    /// Returns whether the request is sent.
+    fn request_config(&mut self) -> bool {
+        let supported = (|| self.client_caps.workspace.as_ref()?.configuration)();
+        if supported != Some(true) {
+            return false;
+        }
+
+        let id = self.send_request::<req::WorkspaceConfiguration>(
+            ConfigurationParams {
+                items: std::iter::once(CONFIG_KEY.to_owned())
+                    .chain(
+                        CONFIG_SUB_SECTIONS
+                            .iter()
+                            .map(|sub| format!("{CONFIG_KEY}.{sub}")),
+                    )
+                    .map(|section| ConfigurationItem {
+                        scope_uri: None,
+                        section: Some(section),
+                    })
+                    .collect(),
+            },
+            |st, resp| {
+                if st.config_request_id.as_ref() != Some(&resp.id) {
+                    tracing::debug!("Ignored stale config response {}", resp.id);
+                    return;
+                }
+                st.config_request_id = None;
+                let ret = match resp.error {
+                    None => Ok(Config::merge_sections(
+                        resp.result
+                            .and_then(|v| serde_json::from_value(v).ok())
+                            .unwrap_or_default(),
+                    )),
+                    Some(err) => Err(format!(
+                        "LSP error {}: {}, data: {:?}",
+                        err.code, err.message, err.data
+                    )),
+                };
+                match ret {
+                    Ok(v) => {
+                        tracing::info!("Updating config: {:?}", v);
+                        st.update_config(v);
+                    }
+                    Err(err) => tracing::error!("Failed to update config: {}", err),
+                }
+                st.load_workspace_files();
+            },
+        );
+        self.config_request_id = Some(id);
+        true
+    }
+
     fn send_request<R: req::Request>(
         &mut self,
         params: R::Params,
         callback: fn(&mut Self, Response),
-    ) {
+    ) -> RequestId {
         let req = self
             .req_queue
             .outgoing
             .register(R::METHOD.into(), params, callback);
+        let id = req.id.clone();
         self.sender.send(req.into()).unwrap();
+        id
     }
 
     fn send_notification<N: notif::Notification>(&self, params: N::Params) {
@@ -210,16 +897,136 @@ impl State {
             .unwrap();
     }
 
-    fn update_config(&mut self, _config: serde_json::Value) {
-        // No-op.
-        let _ = &mut self.config;
+    fn update_config(&mut self, value: serde_json::Value) {
+        self.client_config = value;
+        self.apply_config();
+    }
+
+    /// Whether `uri` is a project config file in the workspace root.
+    fn is_project_config_file(&self, uri: &Url) -> bool {
+        let (root, path) = match (&self.workspace_root, uri.to_file_path()) {
+            (Some(root), Ok(path)) => (root, path),
+            _ => return false,
+        };
+        PROJECT_CONFIG_FILES
+            .iter()
+            .any(|name| path == root.join(name))
+    }
+
+    /// Read the project config file in the workspace root, if any.
+    /// An invalid file is ignored, keeping the previous project config.
+    fn load_project_config(&mut self) {
+        let root = match &self.workspace_root {
+            Some(root) => root,
+            None => return,
+        };
+        match Config::read_project_file(root)
+            .unwrap_or_else(|| Ok(serde_json::Value::Object(Default::default())))
+        {
+            Ok(value) => {
+                tracing::info!("Updating project config: {:?}", value);
+                self.project_config = value;
+                self.apply_config();
+            }
+            Err(err) => tracing::error!("Invalid project config {}", err),
+        }
+    }
+
+    fn apply_config(&mut self) {
+        let value = Config::merge_project(self.client_config.clone(), self.project_config.clone());
+        match serde_json::from_value::<Config>(value) {
+            Ok(config) if config != *self.config => {
+                self.config = Arc::new(config);
+                self.update_formatting_registration();
+                // Diagnostics may depend on the config.
+                let files = {
+                    let vfs = self.vfs.read().unwrap();
+                    self.opened_files
+                        .read()
+                        .unwrap()
+                        .keys()
+                        .filter_map(|uri| vfs.file_for_uri(uri).ok())
+                        .collect::<Vec<_>>()
+                };
+                self.publish_diagnostics(files);
+            }
+            Ok(_) => {}
+            Err(err) => tracing::error!("Invalid config: {}", err),
+        }
+    }
+
+    /// The matcher of `generatedFileGlobs`, or `None` if there is none.
+    fn generated_file_matcher(&self) -> Option<Override> {
+        if self.config.generated_file_globs.is_empty() {
+            return None;
+        }
+        let root = self
+            .workspace_root
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("/"));
+        let mut builder = OverrideBuilder::new(root);
+        for glob in &self.config.generated_file_globs {
+            if let Err(err) = builder.add(glob) {
+                tracing::error!("Invalid glob {:?} in generatedFileGlobs: {}", glob, err);
+            }
+        }
+        match builder.build() {
+            Ok(matcher) => Some(matcher),
+            Err(err) => {
+                tracing::error!("Invalid generatedFileGlobs: {}", err);
+                None
+            }
+        }
     }
 
     fn snapshot(&self) -> StateSnapshot {
         StateSnapshot {
             analysis: self.host.snapshot(),
             vfs: Arc::clone(&self.vfs),
+            config: Arc::clone(&self.config),
+            semantic_legend: Arc::clone(&self.semantic_legend),
+            code_action_resolve: self.code_action_resolve,
+            // Copied, so that edits computed on this snapshot are not stamped with newer versions.
+            doc_versions: self
+                .document_changes
+                .then(|| self.opened_files.read().unwrap().clone()),
+            change_annotations: self.change_annotations,
+            apply_edit: self.apply_edit,
+            hover_markdown: self.hover_markdown,
+            snippet_support: self.snippet_support,
+            completion_history: Arc::clone(&self.completion_history),
+        }
+    }
+
+    /// Track panics of request handlers. A handler panicking repeatedly likely hits a corrupted
+    /// analysis state, which is rebuilt from scratch then.
+    fn record_handler_result(&mut self, method: &'static str, panicked: bool) {
+        if !panicked {
+            self.consecutive_panics = None;
+            return;
+        }
+        let count = match self.consecutive_panics {
+            Some((last, count)) if last == method => count + 1,
+            _ => 1,
+        };
+        if count < MAX_CONSECUTIVE_PANICS {
+            self.consecutive_panics = Some((method, count));
+            return;
         }
+        tracing::warn!(
+            "Request handler of {} panicked {} times in a row, resetting the analysis state",
+            method,
+            count,
+        );
+        self.consecutive_panics = None;
+        self.reset_analysis_host();
+    }
+
+    /// Replace the analysis state with a fresh one, loaded with the current Vfs content.
+    fn reset_analysis_host(&mut self) {
+        let change = self.vfs.read().unwrap().full_change();
+        self.host = AnalysisHost::default();
+        self.host.apply_change(change);
     }
 
     fn set_vfs_file_content(&mut self, uri: &Url, text: String) -> Result<()> {
@@ -231,40 +1038,251 @@ impl State {
     fn apply_vfs_change(&mut self) {
         let mut vfs = self.vfs.write().unwrap();
         let change = vfs.take_change();
-        let file_changes = change
+        let files = change
             .file_changes
             .iter()
-            .map(|(file, text)| (*file, !text.is_empty()))
+            .map(|(file, _)| *file)
             .collect::<Vec<_>>();
         tracing::debug!("Change: {:?}", change);
+        // This cancels running computations on older snapshots.
         self.host.apply_change(change);
+        drop(vfs);
+        self.publish_diagnostics(files);
+    }
 
-        let snap = self.host.snapshot();
-        let opened_files = self.opened_files.read().unwrap();
-        for (file, has_text) in file_changes {
-            let uri = vfs.uri_for_file(file);
-            if !opened_files.contains(&uri) {
-                continue;
+    /// Publish diagnostics of files, if they are opened.
+    /// They are computed in a background thread, superseding running computations of the same
+    /// files. Files whose computations were cancelled by changes are also computed again.
+    fn publish_diagnostics(&mut self, files: Vec<FileId>) {
+        let mut tasks = Vec::new();
+        {
+            let analysis = self.host.snapshot();
+            let vfs = self.vfs.read().unwrap();
+            let opened_files = self.opened_files.read().unwrap();
+            let mut pending = self.pending_diagnostics.lock().unwrap();
+            let mut files = files;
+            files.extend(pending.files.keys().copied());
+            files.sort_by_key(|file| file.0);
+            files.dedup();
+            for file in files {
+                let uri = vfs.uri_for_file(file);
+                pending.files.remove(&file);
+                if !opened_files.contains_key(&uri) {
+                    continue;
+                }
+
+                let len = vfs.content_for_file(file).len();
+                if self.config.is_file_too_large(len) {
+                    self.send_notification::<notif::PublishDiagnostics>(PublishDiagnosticsParams {
+                        uri,
+                        diagnostics: vec![Diagnostic {
+                            range: Range::new(Position::new(0, 0), Position::new(0, 0)),
+                            severity: Some(DiagnosticSeverity::INFORMATION),
+                            source: Some("nil".into()),
+                            message: "file too large for analysis".into(),
+                            ..Diagnostic::default()
+                        }],
+                        version: None,
+                    });
+                    continue;
+                }
+                if len == 0 {
+                    self.send_notification::<notif::PublishDiagnostics>(PublishDiagnosticsParams {
+                        uri,
+                        diagnostics: Vec::new(),
+                        version: None,
+                    });
+                    continue;
+                }
+
+                // Syntax errors are cheap to compute. Publish them immediately for feedback,
+                // before full diagnostics of large files are computed in the background.
+                if let Ok(mut diags) = analysis.syntax_errors(file) {
+                    if !diags.is_empty() {
+                        diags.truncate(self.init_options.diagnostics_limit());
+                        let diagnostics =
+                            convert::to_diagnostics(&vfs, file, &diags, self.related_information);
+                        self.send_notification::<notif::PublishDiagnostics>(
+                            PublishDiagnosticsParams {
+                                uri: uri.clone(),
+                                diagnostics,
+                                version: None,
+                            },
+                        );
+                    }
+                }
+
+                pending.last_revision += 1;
+                let revision = pending.last_revision;
+                pending.files.insert(file, revision);
+                tasks.push(DiagnosticsTask {
+                    file,
+                    uri,
+                    revision,
+                    line_map: vfs.line_map_for_file(file),
+                });
             }
+        }
+        if tasks.is_empty() {
+            return;
+        }
 
-            // TODO: Error is ignored.
-            let diagnostics = has_text
-                .then(|| {
-                    let mut diags = snap.diagnostics(file).ok()?;
-                    diags.truncate(MAX_DIAGNOSTICS_CNT);
-                    Some(convert::to_diagnostics(&vfs, file, &diags))
-                })
-                .flatten()
-                .unwrap_or_default();
-            self.send_notification::<notif::PublishDiagnostics>(PublishDiagnosticsParams {
-                uri,
-                diagnostics,
-                version: None,
+        let snap = self.host.snapshot();
+        let vfs = Arc::clone(&self.vfs);
+        let pending = Arc::clone(&self.pending_diagnostics);
+        let sender = self.sender.clone();
+        let diagnostics_limit = self.init_options.diagnostics_limit();
+        let diagnostics_config = self.config.diagnostics_config();
+        let generated_files = self.generated_file_matcher();
+        let related_information = self.related_information;
+        let ret = thread::Builder::new()
+            .name("diagnostics".into())
+            .spawn(move || {
+                let mut results = Vec::with_capacity(tasks.len());
+                for task in tasks {
+                    let mut diags = match snap.diagnostics(&diagnostics_config, task.file) {
+                        Ok(diags) => diags,
+                        // Cancelled by a change, which computes them again.
+                        Err(_cancelled) => return,
+                    };
+                    if is_generated_file(generated_files.as_ref(), &task.uri) {
+                        diags.retain(|diag| !diag.kind.is_unused());
+                    }
+                    diags.truncate(diagnostics_limit);
+                    results.push((task, diags));
+                }
+                // Changes wait for snapshots to be dropped. Don't block them on the Vfs lock.
+                drop(snap);
+
+                let vfs = vfs.read().unwrap();
+                let mut pending = pending.lock().unwrap();
+                for (task, mut diags) in results {
+                    let is_latest = pending.files.get(&task.file) == Some(&task.revision)
+                        && Arc::ptr_eq(&vfs.line_map_for_file(task.file), &task.line_map);
+                    if !is_latest {
+                        continue;
+                    }
+                    pending.files.remove(&task.file);
+                    retain_missing_imports(&vfs, task.file, &mut diags);
+                    let diagnostics =
+                        convert::to_diagnostics(&vfs, task.file, &diags, related_information);
+                    let notif = Notification::new(
+                        notif::PublishDiagnostics::METHOD.into(),
+                        PublishDiagnosticsParams {
+                            uri: task.uri,
+                            diagnostics,
+                            version: None,
+                        },
+                    );
+                    // The main loop may have exited.
+                    let _ = sender.send(notif.into());
+                }
             });
+        match ret {
+            Ok(handle) => {
+                self.diagnostics_threads
+                    .retain(|handle| !handle.is_finished());
+                self.diagnostics_threads.push(handle);
+            }
+            Err(err) => tracing::error!("Failed to spawn the diagnostics thread: {}", err),
         }
     }
 }
 
+/// Whether the file of `uri` matches `generatedFileGlobs`.
+fn is_generated_file(matcher: Option<&Override>, uri: &Url) -> bool {
+    match (matcher, uri.to_file_path()) {
+        (Some(matcher), Ok(path)) => matcher.matched(&path, false).is_whitelist(),
+        _ => false,
+    }
+}
+
+/// Drop reports of imported files which exist on disk, and name the full path of missing ones.
+/// Imports are only resolved in loaded files, but with on-demand scanning, the target directory
+/// may be not scanned yet, and ignored files are never loaded.
+/// Imports of documents not on disk cannot be checked at all.
+pub(crate) fn retain_missing_imports(vfs: &Vfs, file: FileId, diags: &mut Vec<ide::Diagnostic>) {
+    let src = vfs.content_for_file(file);
+    diags.retain_mut(|diag| {
+        if diag.kind != DiagnosticKind::UnresolvedImport {
+            return true;
+        }
+        match vfs.local_path_relative_to(file, &src[diag.range]) {
+            Some(path) if !path.exists() => {
+                diag.message = Some(format!("Imported file `{}` does not exist", path.display(),));
+                true
+            }
+            _ => false,
+        }
+    });
+}
+
+/// Collect paths and contents of all Nix files under `root`, sorted by paths.
+/// Files are read in `threads` worker threads, or left unread if `lazy` is set.
+/// The walk stops early when `cancel` is set, returning files collected so far.
+pub(crate) fn scan_workspace_files(
+    root: &Path,
+    threads: usize,
+    lazy: bool,
+    cancel: &AtomicBool,
+) -> ScannedFiles {
+    let (tx, rx) = crossbeam_channel::unbounded();
+    ignore::WalkBuilder::new(root)
+        .follow_links(false)
+        .threads(threads)
+        .build_parallel()
+        .run(|| {
+            let tx = tx.clone();
+            Box::new(move |entry| {
+                if cancel.load(Ordering::Relaxed) {
+                    return WalkState::Quit;
+                }
+                (|| -> Option<()> {
+                    let entry = entry.ok()?;
+                    if entry.path().extension() != Some(OsStr::new(FILTER_FILE_EXTENTION)) {
+                        return None;
+                    }
+
+                    let relative_path = entry.path().strip_prefix(root).ok()?;
+                    let vpath = VfsPath::from_path(relative_path)?;
+                    let text =
+                        (!lazy).then(|| fs::read_to_string(entry.path()).ok().unwrap_or_default());
+                    tx.send((vpath, text)).unwrap();
+                    Some(())
+                })();
+                WalkState::Continue
+            })
+        });
+    drop(tx);
+
+    // Keep the order deterministic, since `FileId`s are assigned sequentially.
+    let mut files = rx.into_iter().collect::<Vec<_>>();
+    files.sort_by(|(lhs, _), (rhs, _)| lhs.as_str().cmp(rhs.as_str()));
+    files
+}
+
+/// Collect paths and contents of Nix files directly under `dir`, sorted by paths.
+/// Files are left unread if `lazy` is set.
+fn scan_dir_files(root: &Path, dir: &Path, lazy: bool) -> ScannedFiles {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut files = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if !path.is_file() || path.extension()? != FILTER_FILE_EXTENTION {
+                return None;
+            }
+            let vpath = VfsPath::from_path(path.strip_prefix(root).ok()?)?;
+            let text = (!lazy).then(|| fs::read_to_string(&path).ok().unwrap_or_default());
+            Some((vpath, text))
+        })
+        .collect::<Vec<_>>();
+    files.sort_by(|(lhs, _), (rhs, _)| lhs.as_str().cmp(rhs.as_str()));
+    files
+}
+
 #[must_use = "RequestDispatcher::finish not called"]
 struct RequestDispatcher<'s>(&'s mut State, Option<Request>);
 
@@ -296,10 +1314,17 @@ impl<'s> RequestDispatcher<'s> {
     {
         if matches!(&self.1, Some(notif) if notif.method == R::METHOD) {
             let req = self.1.take().unwrap();
+            let mut panic_msg = None;
             let ret = match serde_json::from_value::<R::Params>(req.params) {
                 Ok(params) => {
                     let snap = self.0.snapshot();
-                    result_to_response(req.id, with_catch_unwind(R::METHOD, || f(snap, params)))
+                    let ret = with_catch_unwind(R::METHOD, || f(snap, params));
+                    let panicked = matches!(&ret, Err(err) if err.is::<HandlerPanic>());
+                    self.0.record_handler_result(R::METHOD, panicked);
+                    if panicked && self.0.config.report_panics {
+                        panic_msg = ret.as_ref().err().map(|err| err.to_string());
+                    }
+                    result_to_response(req.id, ret)
                 }
                 Err(err) => Ok(Response::new_err(
                     req.id,
@@ -310,6 +1335,16 @@ impl<'s> RequestDispatcher<'s> {
             if let Ok(resp) = ret {
                 self.0.sender.send(resp.into()).unwrap();
             }
+            // Users rarely notice a failed request. Show it so that they can report the bug.
+            if let Some(msg) = panic_msg {
+                self.0
+                    .send_notification::<notif::ShowMessage>(ShowMessageParams {
+                        typ: MessageType::ERROR,
+                        message: format!(
+                            "{msg}\nThis is a bug of nil, please consider reporting it."
+                        ),
+                    });
+            }
         }
         self
     }
@@ -379,15 +1414,27 @@ fn with_catch_unwind<T>(ctx: &str, f: impl FnOnce() -> Result<T> + UnwindSafe) -
                 loc = "unknown".into();
             }
             let msg = format!("Request handler of {} panicked at {}: {}", ctx, loc, reason);
-            Err(msg.into())
+            Err(HandlerPanic(msg).into())
         }
     }
 }
 
-fn result_to_response(id: RequestId, ret: Result<impl Serialize>) -> Result<Response, Cancelled> {
-    match ret {
-        Ok(ret) => Ok(Response::new_ok(id, ret)),
-        Err(err) => match err.downcast::<Cancelled>() {
+/// The error of a request handler which panicked.
+#[derive(Debug)]
+struct HandlerPanic(String);
+
+impl fmt::Display for HandlerPanic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for HandlerPanic {}
+
+fn result_to_response(id: RequestId, ret: Result<impl Serialize>) -> Result<Response, Cancelled> {
+    match ret {
+        Ok(ret) => Ok(Response::new_ok(id, ret)),
+        Err(err) => match err.downcast::<Cancelled>() {
             Ok(cancelled) => Err(*cancelled),
             Err(err) => Ok(Response::new_err(
                 id,
@@ -402,10 +1449,1728 @@ fn result_to_response(id: RequestId, ret: Result<impl Serialize>) -> Result<Resp
 pub struct StateSnapshot {
     pub(crate) analysis: Analysis,
     vfs: Arc<RwLock<Vfs>>,
+    pub(crate) config: Arc<Config>,
+    pub(crate) semantic_legend: Arc<NegotiatedLegend>,
+    /// Whether edits of code actions are resolved lazily.
+    pub(crate) code_action_resolve: bool,
+    /// Versions of opened documents when the snapshot is taken,
+    /// or `None` if the client doesn't support `documentChanges`.
+    doc_versions: Option<HashMap<Url, i32>>,
+    /// Whether edits of renames are annotated, for the client to preview them.
+    pub(crate) change_annotations: bool,
+    /// Whether the client accepts `workspace/applyEdit`, required by commands.
+    pub(crate) apply_edit: bool,
+    /// Whether hover contents are sent as Markdown, instead of plain text.
+    pub(crate) hover_markdown: bool,
+    /// Whether completion items may be snippets.
+    pub(crate) snippet_support: bool,
+    /// Completion items accepted in this session, ranked first in later completions.
+    pub(crate) completion_history: Arc<CompletionHistory>,
 }
 
 impl StateSnapshot {
     pub(crate) fn vfs(&self) -> impl std::ops::Deref<Target = Vfs> + '_ {
         self.vfs.read().unwrap()
     }
+
+    /// Versions of opened documents to be included in workspace edits,
+    /// or `None` if the client doesn't support `documentChanges`.
+    pub(crate) fn doc_versions(&self) -> Option<HashMap<Url, i32>> {
+        self.doc_versions.clone()
+    }
+
+    /// Whether the file exceeds `maxFileSizeBytes` and should be skipped from analysis.
+    pub(crate) fn is_file_too_large(&self, file: FileId) -> bool {
+        let len = self.vfs().content_for_file(file).len();
+        self.config.is_file_too_large(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{scan_workspace_files, ExitReason, RequestDispatcher, State};
+    use crate::config::{Config, FormattingConfig, ScanConfig, DEFAULT_MAX_FILE_SIZE_BYTES};
+    use crate::lsp_ext;
+    use crate::semantic_tokens::NegotiatedLegend;
+    use crossbeam_channel::Receiver;
+    use lsp_server::{Message, Notification, Request, RequestId, Response};
+    use lsp_types::notification::{self as notif, Notification as _};
+    use lsp_types::request::{self as req, Request as _};
+    use lsp_types::{
+        ApplyWorkspaceEditParams, ClientCapabilities, CodeAction, CodeActionOrCommand,
+        CompletionItem, CompletionItemKind, CompletionResponse, DidCloseTextDocumentParams,
+        DidOpenTextDocumentParams, DocumentChanges, MessageType, NumberOrString,
+        PublishDiagnosticsParams, SemanticTokenModifier, SemanticTokenType, SemanticTokens,
+        ShowMessageParams, TextDocumentIdentifier, TextDocumentItem, Url,
+        WorkspaceClientCapabilities, WorkspaceEdit,
+    };
+    use serde_json::json;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    fn open(state: &mut State, uri: &str, text: &str) {
+        let params = DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: uri.parse().unwrap(),
+                language_id: "nix".into(),
+                version: 0,
+                text: text.into(),
+            },
+        };
+        let notif = Notification::new(notif::DidOpenTextDocument::METHOD.into(), params);
+        state.dispatch_notification(notif).unwrap();
+    }
+
+    /// A state with the default init options, and the receiver of messages it sends.
+    fn new_state(
+        root: Option<PathBuf>,
+        client_caps: ClientCapabilities,
+    ) -> (State, Receiver<Message>) {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let state = State::new(
+            sender,
+            root,
+            client_caps,
+            Default::default(),
+            NegotiatedLegend::default(),
+        );
+        (state, receiver)
+    }
+
+    fn close(state: &mut State, uri: &str) {
+        let params = DidCloseTextDocumentParams {
+            text_document: TextDocumentIdentifier::new(uri.parse().unwrap()),
+        };
+        let notif = Notification::new(notif::DidCloseTextDocument::METHOD.into(), params);
+        state.dispatch_notification(notif).unwrap();
+    }
+
+    impl State {
+        /// Wait for diagnostics being computed in background to be published.
+        fn wait_for_diagnostics(&mut self) {
+            for handle in self.diagnostics_threads.drain(..) {
+                handle.join().unwrap();
+            }
+        }
+    }
+
+    fn published(state: &mut State, receiver: &Receiver<Message>) -> Vec<PublishDiagnosticsParams> {
+        state.wait_for_diagnostics();
+        receiver
+            .try_iter()
+            .filter_map(|msg| match msg {
+                Message::Notification(notif)
+                    if notif.method == notif::PublishDiagnostics::METHOD =>
+                {
+                    Some(serde_json::from_value(notif.params).unwrap())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn open_clears_stale_diagnostics() {
+        let root = std::env::temp_dir().canonicalize().unwrap();
+        let uri = Url::from_file_path(root.join("default.nix")).unwrap();
+        let (mut state, receiver) = new_state(Some(root), Default::default());
+
+        open(&mut state, uri.as_str(), "{ a = 1; }");
+        let got = published(&mut state, &receiver);
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].uri, uri);
+        assert_eq!(got[0].diagnostics, Vec::new());
+
+        // Files which cannot be analyzed get their stale diagnostics cleared as well.
+        open(&mut state, "file:///outside.nix", "{ a = 1; }");
+        let got = published(&mut state, &receiver);
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].uri, "file:///outside.nix".parse::<Url>().unwrap());
+        assert_eq!(got[0].diagnostics, Vec::new());
+    }
+
+    #[test]
+    fn unresolved_imports_on_disk() {
+        let root = std::env::temp_dir().join(format!("nil-test-imports-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("sub")).unwrap();
+        let root = root.canonicalize().unwrap();
+        // Exists on disk but not loaded, like a directory not scanned on demand yet.
+        fs::write(root.join("sub/exists.nix"), "1").unwrap();
+        let uri = Url::from_file_path(root.join("sub/default.nix")).unwrap();
+        let (mut state, receiver) = new_state(Some(root.clone()), Default::default());
+
+        open(
+            &mut state,
+            uri.as_str(),
+            "[ (import ./exists.nix) (import ./missing.nix) (import ../sub/missing.nix) ]",
+        );
+        let got = published(&mut state, &receiver);
+        let messages = got[0]
+            .diagnostics
+            .iter()
+            .map(|diag| &*diag.message)
+            .collect::<Vec<_>>();
+        let message = format!(
+            "Imported file `{}` does not exist",
+            root.join("sub/missing.nix").display(),
+        );
+        assert_eq!(messages, [&*message, &*message]);
+
+        // Documents not on disk cannot be checked.
+        open(&mut state, "untitled:Untitled-1", "import ./missing.nix");
+        let got = published(&mut state, &receiver);
+        assert_eq!(got[0].diagnostics, Vec::new());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn did_save() {
+        let dir = std::env::temp_dir().join(format!("nil-test-did-save-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.canonicalize().unwrap().join("default.nix");
+        let uri = Url::from_file_path(&path).unwrap();
+        let (mut state, receiver) = new_state(None, Default::default());
+        let save = |state: &mut State, text: Option<&str>| {
+            let params = json!({ "textDocument": { "uri": uri }, "text": text });
+            let notif = Notification::new(notif::DidSaveTextDocument::METHOD.into(), params);
+            state.dispatch_notification(notif).unwrap();
+            published(state, &receiver)
+                .into_iter()
+                .map(|params| params.diagnostics.len())
+                .collect::<Vec<_>>()
+        };
+
+        open(&mut state, uri.as_str(), "a: a");
+        assert_eq!(published(&mut state, &receiver).len(), 1);
+        // Modified by a post-save hook.
+        assert_eq!(save(&mut state, Some("a: b")), [1]);
+
+        // Only read from the disk if enabled.
+        fs::write(&path, "a: a").unwrap();
+        assert_eq!(save(&mut state, None), Vec::<usize>::new());
+        state.config = Arc::new(Config {
+            reload_on_save: true,
+            ..Config::default()
+        });
+        assert_eq!(save(&mut state, None), [0]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn syntax_errors_first() {
+        let (mut state, receiver) = new_state(None, Default::default());
+
+        open(&mut state, "file:///default.nix", "let a = b; in (a");
+        let got = published(&mut state, &receiver)
+            .into_iter()
+            .map(|params| {
+                params
+                    .diagnostics
+                    .into_iter()
+                    .map(|diag| diag.message)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let syntax_errors = [r#"Expected ")" to close this "(""#, r#"Missing ")""#];
+        assert_eq!(got.len(), 2);
+        assert_eq!(got[0], syntax_errors);
+        assert_eq!(got[1][..2], syntax_errors);
+        assert_eq!(got[1][2..], ["Undefined name"]);
+
+        // No syntax-only publish for valid files.
+        open(&mut state, "file:///default.nix", "let a = b; in a");
+        assert_eq!(published(&mut state, &receiver).len(), 1);
+    }
+
+    #[test]
+    fn source_root() {
+        let (mut state, receiver) = new_state(None, Default::default());
+        for uri in [
+            "file:///default.nix",
+            "file:///a/flake.nix",
+            "file:///a/lib.nix",
+            "file:///b/foo.nix",
+        ] {
+            open(&mut state, uri, "{ }");
+        }
+
+        let mut request = |uri: &str| {
+            let params = json!({ "textDocument": { "uri": uri } });
+            let method = lsp_ext::SourceRoot::METHOD.into();
+            state.dispatch_request(Request::new(RequestId::from(0), method, params));
+            let resp = receiver
+                .try_iter()
+                .find_map(|msg| match msg {
+                    Message::Response(resp) => Some(resp.result.expect("No error")),
+                    _ => None,
+                })
+                .unwrap();
+            serde_json::from_value::<lsp_ext::SourceRootResult>(resp).unwrap()
+        };
+
+        let top = request("file:///b/foo.nix");
+        assert_eq!(top.entry.as_ref().unwrap().as_str(), "file:///default.nix");
+        assert_eq!(top.file_count, 2);
+        assert_eq!(request("file:///default.nix"), top);
+
+        let flake = request("file:///a/lib.nix");
+        assert_ne!(flake.id, top.id);
+        assert_eq!(flake.entry.unwrap().as_str(), "file:///a/flake.nix");
+        assert_eq!(flake.file_count, 2);
+    }
+
+    #[test]
+    fn superseded_diagnostics() {
+        let (mut state, receiver) = new_state(None, Default::default());
+        open(&mut state, "file:///default.nix", "1");
+        assert_eq!(published(&mut state, &receiver)[0].diagnostics, []);
+
+        // Two rapid edits, without waiting for diagnostics in between.
+        for (version, start, end, text) in [(1, 0, 1, "let a = 1; in 1"), (2, 4, 5, "bb")] {
+            let change = Notification::new(
+                notif::DidChangeTextDocument::METHOD.into(),
+                json!({
+                    "textDocument": { "uri": "file:///default.nix", "version": version },
+                    "contentChanges": [{
+                        "range": {
+                            "start": { "line": 0, "character": start },
+                            "end": { "line": 0, "character": end },
+                        },
+                        "text": text,
+                    }],
+                }),
+            );
+            state.dispatch_notification(change).unwrap();
+        }
+        let unused_ranges = published(&mut state, &receiver)
+            .into_iter()
+            .map(|params| {
+                params
+                    .diagnostics
+                    .iter()
+                    .map(|diag| diag.range.end.character)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        // Diagnostics of the first edit are either superseded, or published before the final ones.
+        assert!(
+            unused_ranges == [vec![6]] || unused_ranges == [vec![5], vec![6]],
+            "{unused_ranges:?}",
+        );
+        assert!(state.pending_diagnostics.lock().unwrap().files.is_empty());
+    }
+
+    #[test]
+    fn untitled_document() {
+        let (mut state, receiver) = new_state(None, Default::default());
+        open(&mut state, "file:///default.nix", "{ }");
+        let text = "let foo = import ./default.nix; in fo";
+        open(&mut state, "untitled:Untitled-1", text);
+        let got = published(&mut state, &receiver);
+        assert_eq!(got.len(), 2);
+        assert_eq!(got[1].uri, "untitled:Untitled-1".parse::<Url>().unwrap());
+
+        let mut request = |method: &str, character: u32| {
+            let params = json!({
+                "textDocument": { "uri": "untitled:Untitled-1" },
+                "position": { "line": 0, "character": character },
+            });
+            state.dispatch_request(Request::new(RequestId::from(0), method.into(), params));
+            receiver
+                .try_iter()
+                .find_map(|msg| match msg {
+                    Message::Response(resp) => Some(resp.result.expect("No error")),
+                    _ => None,
+                })
+                .unwrap()
+        };
+
+        let hover = request(req::HoverRequest::METHOD, 5);
+        assert_eq!(hover["contents"]["value"], "Let binding `foo`");
+        // Relative paths resolve to nothing, even if the target exists in the workspace.
+        let hover = request(req::HoverRequest::METHOD, 18);
+        assert_eq!(hover["contents"]["value"], "`/default.nix` (not found)");
+        let completion = request(req::Completion::METHOD, 37);
+        let labels = completion
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["label"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert!(labels.contains(&"foo"), "{labels:?}");
+
+        // Closing drops it from the analysis.
+        let uri = "untitled:Untitled-1".parse::<Url>().unwrap();
+        close(&mut state, uri.as_str());
+        assert!(state.vfs.read().unwrap().file_for_uri(&uri).is_err());
+        // Views of other schemes are not analyzed at all.
+        open(&mut state, "git:/default.nix", "a");
+        let git = "git:/default.nix".parse::<Url>().unwrap();
+        assert!(state.vfs.read().unwrap().file_for_uri(&git).is_err());
+    }
+
+    #[test]
+    fn document_highlight() {
+        let (mut state, receiver) = new_state(None, Default::default());
+        open(&mut state, "file:///default.nix", "let a = 1;\nin a");
+        state.dispatch_request(Request::new(
+            RequestId::from(0),
+            req::DocumentHighlightRequest::METHOD.into(),
+            json!({
+                "textDocument": { "uri": "file:///default.nix" },
+                "position": { "line": 1, "character": 0 },
+            }),
+        ));
+        let result = receiver
+            .try_iter()
+            .find_map(|msg| match msg {
+                Message::Response(resp) => Some(resp.result.expect("No error")),
+                _ => None,
+            })
+            .unwrap();
+        let range = |line: u32, start: u32, end: u32| {
+            json!({
+                "start": { "line": line, "character": start },
+                "end": { "line": line, "character": end },
+            })
+        };
+        assert_eq!(
+            result,
+            json!([
+                { "range": range(0, 0, 3), "kind": 1 },
+                { "range": range(1, 0, 2), "kind": 1 },
+            ]),
+        );
+    }
+
+    #[test]
+    fn close_clears_diagnostics() {
+        let (mut state, receiver) = new_state(None, Default::default());
+
+        open(&mut state, "file:///default.nix", "{ a = 1; a = 2; }");
+        let got = published(&mut state, &receiver);
+        assert_eq!(got.len(), 1);
+        assert_ne!(got[0].diagnostics, Vec::new());
+
+        close(&mut state, "file:///default.nix");
+        let got = published(&mut state, &receiver);
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].uri, "file:///default.nix".parse::<Url>().unwrap());
+        assert_eq!(got[0].diagnostics, Vec::new());
+
+        // Diagnostics are kept if disabled.
+        state.config = Arc::new(Config {
+            clear_diagnostics_on_close: false,
+            ..Config::default()
+        });
+        open(&mut state, "file:///default.nix", "{ a = 1; a = 2; }");
+        published(&mut state, &receiver);
+        close(&mut state, "file:///default.nix");
+        assert_eq!(published(&mut state, &receiver), Vec::new());
+    }
+
+    #[test]
+    fn scan_many_files() {
+        let root = std::env::temp_dir().join(format!("nil-scan-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for i in 0..200 {
+            let dir = root.join(format!("dir{}", i % 7));
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join(format!("{i}.nix")), i.to_string()).unwrap();
+        }
+        fs::write(root.join("README.md"), "not nix").unwrap();
+
+        let files = scan_workspace_files(&root, 4, false, &AtomicBool::new(false));
+        let lazy_files = scan_workspace_files(&root, 4, true, &AtomicBool::new(false));
+        let cancelled = scan_workspace_files(&root, 4, false, &AtomicBool::new(true));
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(cancelled, Vec::new());
+
+        assert_eq!(files.len(), 200);
+        assert!(files.windows(2).all(|w| w[0].0.as_str() < w[1].0.as_str()));
+        for (vpath, text) in &files {
+            let name = vpath.as_str().rsplit('/').next().unwrap();
+            assert_eq!(name, format!("{}.nix", text.as_deref().unwrap()));
+        }
+
+        assert_eq!(lazy_files.len(), 200);
+        assert!(lazy_files.iter().all(|(_, text)| text.is_none()));
+    }
+
+    #[test]
+    fn request_pending_on_indexing() {
+        let root = std::env::temp_dir().join(format!("nil-indexing-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("default.nix"), "import ./foo.nix").unwrap();
+        fs::write(root.join("foo.nix"), "42").unwrap();
+        let uri = Url::from_file_path(root.canonicalize().unwrap().join("default.nix")).unwrap();
+        let client_caps = serde_json::from_value::<ClientCapabilities>(json!({
+            "workspace": { "configuration": true },
+            "window": { "workDoneProgress": true },
+        }))
+        .unwrap();
+        let (mut state, receiver) = new_state(Some(root.clone()), client_caps);
+        let notif = Notification::new(notif::Initialized::METHOD.into(), json!({}));
+        state.dispatch_notification(notif).unwrap();
+        let config_id = receiver
+            .try_iter()
+            .find_map(|msg| match msg {
+                Message::Request(req) if req.method == req::WorkspaceConfiguration::METHOD => {
+                    Some(req.id)
+                }
+                _ => None,
+            })
+            .unwrap();
+
+        // Indexing waits for the config. The request is not handled yet.
+        let params = json!({ "textDocument": { "uri": uri } });
+        let req = Request::new(
+            RequestId::from(1),
+            lsp_ext::FileImports::METHOD.into(),
+            params,
+        );
+        state.dispatch_request(req);
+        assert!(receiver.try_iter().next().is_none());
+
+        state.dispatch_response(Response::new_ok(config_id, json!([{}])));
+        let files = state.scan_receiver.recv().unwrap();
+        state.finish_workspace_scan(files);
+        fs::remove_dir_all(&root).unwrap();
+        let msgs = receiver.try_iter().collect::<Vec<_>>();
+        let methods = msgs
+            .iter()
+            .map(|msg| match msg {
+                Message::Request(req) => req.method.as_str(),
+                Message::Notification(notif) => notif.method.as_str(),
+                Message::Response(_) => "response",
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            methods,
+            [
+                req::WorkDoneProgressCreate::METHOD,
+                notif::Progress::METHOD,
+                notif::Progress::METHOD,
+                "response",
+            ],
+        );
+        let resp = match msgs.last().unwrap() {
+            Message::Response(resp) => resp.clone(),
+            _ => unreachable!(),
+        };
+        assert_eq!(resp.id, RequestId::from(1));
+        let imports = resp.result.expect("No error");
+        assert_eq!(imports.as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn exit_during_scan() {
+        let root = std::env::temp_dir().join(format!("nil-exit-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for i in 0..2000 {
+            let dir = root.join(format!("dir{}", i % 50));
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join(format!("{i}.nix")), i.to_string()).unwrap();
+        }
+        let (mut state, _receiver) = new_state(Some(root.clone()), Default::default());
+        let (client, lsp_receiver) = crossbeam_channel::unbounded();
+        let initialized = Notification::new(notif::Initialized::METHOD.into(), json!({}));
+        client.send(initialized.into()).unwrap();
+        let exit = Notification::new(notif::Exit::METHOD.into(), json!(null));
+        client.send(exit.into()).unwrap();
+
+        let start = Instant::now();
+        assert_eq!(state.run(lsp_receiver).unwrap(), ExitReason::Exit);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(state.scan_cancel.is_none());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn cancel_scan_progress() {
+        let root = std::env::temp_dir().join(format!("nil-cancel-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for i in 0..2000 {
+            let dir = root.join(format!("dir{}", i % 50));
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join(format!("{i}.nix")), i.to_string()).unwrap();
+        }
+        let client_caps = serde_json::from_value::<ClientCapabilities>(json!({
+            "window": { "workDoneProgress": true },
+        }))
+        .unwrap();
+        let (mut state, receiver) = new_state(Some(root.clone()), client_caps);
+        let notif = Notification::new(notif::Initialized::METHOD.into(), json!({}));
+        state.dispatch_notification(notif).unwrap();
+        let cancel = state.scan_cancel.clone().unwrap();
+
+        // Other tokens are ignored.
+        let notif = Notification::new(
+            notif::WorkDoneProgressCancel::METHOD.into(),
+            json!({ "token": "other" }),
+        );
+        state.dispatch_notification(notif).unwrap();
+        assert!(!cancel.load(Ordering::Relaxed));
+
+        let notif = Notification::new(
+            notif::WorkDoneProgressCancel::METHOD.into(),
+            json!({ "token": super::INDEXING_PROGRESS_TOKEN }),
+        );
+        state.dispatch_notification(notif).unwrap();
+        assert!(cancel.load(Ordering::Relaxed));
+
+        // Files scanned so far are still loaded.
+        let files = state.scan_receiver.recv().unwrap();
+        fs::remove_dir_all(&root).unwrap();
+        assert!(files.len() <= 2000);
+        state.finish_workspace_scan(files);
+        assert!(state.is_workspace_loaded);
+        assert!(state.scan_cancel.is_none());
+        assert!(state.indexing_progress.is_none());
+        let progress_ends = receiver
+            .try_iter()
+            .filter(|msg| {
+                matches!(msg, Message::Notification(notif)
+                    if notif.method == notif::Progress::METHOD
+                        && notif.params["value"]["kind"] == "end")
+            })
+            .count();
+        assert_eq!(progress_ends, 1);
+    }
+
+    #[test]
+    fn idle_shutdown() {
+        let (mut state, _receiver) = new_state(None, Default::default());
+        state.config = Arc::new(Config {
+            idle_shutdown_secs: 1,
+            ..Config::default()
+        });
+        // Keep the client alive, so that the channel is not closed.
+        let (_client, lsp_receiver) = crossbeam_channel::unbounded();
+
+        let start = Instant::now();
+        assert_eq!(state.run(lsp_receiver).unwrap(), ExitReason::Idle);
+        let elapsed = start.elapsed();
+        assert!(Duration::from_secs(1) <= elapsed && elapsed < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn out_of_order_config_responses() {
+        let client_caps = ClientCapabilities {
+            workspace: Some(WorkspaceClientCapabilities {
+                configuration: Some(true),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let (mut state, receiver) = new_state(None, client_caps);
+
+        assert!(state.request_config());
+        assert!(state.request_config());
+        let ids = receiver
+            .try_iter()
+            .filter_map(|msg| match msg {
+                Message::Request(req) => Some(req.id),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(ids.len(), 2);
+
+        let respond = |state: &mut State, id: &RequestId, nixpkgs_hints: bool| {
+            let config = json!([{ "nixpkgsHints": nixpkgs_hints }]);
+            state.dispatch_response(Response::new_ok(id.clone(), config));
+        };
+        // The newer response arrives first, and the older one is ignored.
+        respond(&mut state, &ids[1], true);
+        assert!(state.config.nixpkgs_hints);
+        respond(&mut state, &ids[0], false);
+        assert!(state.config.nixpkgs_hints);
+    }
+
+    #[test]
+    fn dynamic_formatting() {
+        let client_caps = serde_json::from_value(json!({
+            "textDocument": {
+                "formatting": { "dynamicRegistration": true },
+                "rangeFormatting": { "dynamicRegistration": true },
+            },
+        }))
+        .unwrap();
+        let (mut state, receiver) = new_state(None, client_caps);
+        let registrations = |state: &mut State, config| {
+            state.update_config(config);
+            receiver
+                .try_iter()
+                .filter_map(|msg| match msg {
+                    Message::Request(req) => Some((req.method, req.params)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            registrations(&mut state, json!({ "nixpkgsHints": true })),
+            []
+        );
+        let got = registrations(
+            &mut state,
+            json!({ "formatting": { "command": ["nixfmt"] } }),
+        );
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].0, req::RegisterCapability::METHOD);
+        let methods = got[0].1["registrations"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|reg| reg["method"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            methods,
+            [req::Formatting::METHOD, req::RangeFormatting::METHOD],
+        );
+        // Already registered.
+        let config = json!({ "formatting": { "command": ["alejandra"] } });
+        assert_eq!(registrations(&mut state, config), []);
+
+        let got = registrations(&mut state, json!({}));
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].0, req::UnregisterCapability::METHOD);
+    }
+
+    #[test]
+    fn project_config() {
+        let root = std::env::temp_dir().join(format!("nil-project-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        fs::write(
+            root.join(".nil.toml"),
+            "nixpkgsHints = true\nclearDiagnosticsOnClose = false\n",
+        )
+        .unwrap();
+        let root = root.canonicalize().unwrap();
+        let (mut state, _receiver) = new_state(Some(root.clone()), Default::default());
+        state.load_project_config();
+        assert!(state.config.nixpkgs_hints);
+        assert!(!state.config.clear_diagnostics_on_close);
+
+        // Client settings take precedence.
+        state.update_config(json!({ "nixpkgsHints": false }));
+        assert!(!state.config.nixpkgs_hints);
+        assert!(!state.config.clear_diagnostics_on_close);
+
+        // Reloaded on changes. Invalid files are ignored.
+        let uri = Url::from_file_path(root.join(".nil.toml")).unwrap();
+        let changed = |state: &mut State| {
+            let params = json!({ "changes": [{ "uri": uri, "type": 2 }] });
+            let notif = Notification::new(notif::DidChangeWatchedFiles::METHOD.into(), params);
+            state.dispatch_notification(notif).unwrap();
+        };
+        fs::write(root.join(".nil.toml"), "maxFileSizeBytes = 1024\n").unwrap();
+        changed(&mut state);
+        assert_eq!(state.config.max_file_size_bytes, 1024);
+        assert!(state.config.clear_diagnostics_on_close);
+        fs::write(root.join(".nil.toml"), "maxFileSizeBytes = ").unwrap();
+        changed(&mut state);
+        assert_eq!(state.config.max_file_size_bytes, 1024);
+        // The config file is not analyzed.
+        assert_eq!(state.vfs.read().unwrap().files().count(), 0);
+
+        fs::remove_file(root.join(".nil.toml")).unwrap();
+        changed(&mut state);
+        assert_eq!(
+            state.config.max_file_size_bytes,
+            DEFAULT_MAX_FILE_SIZE_BYTES
+        );
+        assert!(!state.config.nixpkgs_hints);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn generated_files() {
+        let (mut state, receiver) = new_state(None, Default::default());
+        let text = "let a = 1; inherit (import ./x.nix) d; in [ (with { }; 1) (rec { b = 1; }) c ]";
+        open(&mut state, "file:///gen/default.nix", text);
+        open(&mut state, "file:///default.nix", text);
+        let codes = |params: &PublishDiagnosticsParams| {
+            let mut codes = params
+                .diagnostics
+                .iter()
+                .map(|diag| match &diag.code {
+                    Some(NumberOrString::String(code)) => code.clone(),
+                    code => panic!("Unexpected code: {code:?}"),
+                })
+                .collect::<Vec<_>>();
+            codes.sort();
+            codes
+        };
+        let all = [
+            "undefined_name",
+            "unresolved_import",
+            "unused_binding",
+            "unused_inherited_name",
+            "unused_rec",
+            "unused_with",
+        ];
+        for params in published(&mut state, &receiver) {
+            assert_eq!(codes(&params), all);
+        }
+
+        // Changing the config re-publishes diagnostics of opened files.
+        state.update_config(json!({ "generatedFileGlobs": ["gen/**"] }));
+        let mut got = published(&mut state, &receiver);
+        got.sort_by(|lhs, rhs| lhs.uri.cmp(&rhs.uri));
+        assert_eq!(got.len(), 2);
+        assert_eq!(got[0].uri.as_str(), "file:///default.nix");
+        assert_eq!(codes(&got[0]), all);
+        assert_eq!(got[1].uri.as_str(), "file:///gen/default.nix");
+        assert_eq!(codes(&got[1]), ["undefined_name", "unresolved_import"]);
+
+        // Unchanged config is not re-published.
+        state.update_config(json!({ "generatedFileGlobs": ["gen/**"] }));
+        assert_eq!(published(&mut state, &receiver), []);
+    }
+
+    #[test]
+    fn code_action_resolve() {
+        let client_caps = serde_json::from_value::<ClientCapabilities>(json!({
+            "textDocument": { "codeAction": { "resolveSupport": { "properties": ["edit"] } } },
+        }))
+        .unwrap();
+        let (mut state, receiver) = new_state(None, client_caps);
+        open(&mut state, "file:///default.nix", "if true then 1 else 2");
+
+        let mut request = |method: &str, params: serde_json::Value| {
+            state.dispatch_request(Request::new(RequestId::from(0), method.into(), params));
+            receiver
+                .try_iter()
+                .find_map(|msg| match msg {
+                    Message::Response(resp) => Some(resp.result.expect("No error")),
+                    _ => None,
+                })
+                .unwrap()
+        };
+
+        let actions = request(
+            req::CodeActionRequest::METHOD,
+            json!({
+                "textDocument": { "uri": "file:///default.nix" },
+                "range": { "start": { "line": 0, "character": 4 }, "end": { "line": 0, "character": 4 } },
+                "context": { "diagnostics": [] },
+            }),
+        );
+        let action = match serde_json::from_value::<Vec<CodeActionOrCommand>>(actions)
+            .unwrap()
+            .pop()
+        {
+            Some(CodeActionOrCommand::CodeAction(action)) => action,
+            action => panic!("Unexpected code action: {:?}", action),
+        };
+        assert_eq!(action.edit, None);
+        assert!(action.data.is_some());
+
+        let resolved = request(
+            req::CodeActionResolveRequest::METHOD,
+            serde_json::to_value(&action).unwrap(),
+        );
+        let resolved = serde_json::from_value::<CodeAction>(resolved).unwrap();
+        assert_eq!(resolved.title, action.title);
+        let changes = resolved.edit.unwrap().changes.unwrap();
+        let edits = &changes[&"file:///default.nix".parse::<Url>().unwrap()];
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].new_text, "1");
+    }
+
+    #[test]
+    fn versioned_workspace_edit() {
+        let client_caps = serde_json::from_value::<ClientCapabilities>(json!({
+            "workspace": { "workspaceEdit": { "documentChanges": true } },
+        }))
+        .unwrap();
+        let (mut state, receiver) = new_state(None, client_caps);
+        open(&mut state, "file:///default.nix", "let a = 1; in a");
+        let change = Notification::new(
+            notif::DidChangeTextDocument::METHOD.into(),
+            json!({
+                "textDocument": { "uri": "file:///default.nix", "version": 5 },
+                "contentChanges": [{
+                    "range": { "start": { "line": 0, "character": 8 }, "end": { "line": 0, "character": 9 } },
+                    "text": "2",
+                }],
+            }),
+        );
+        state.dispatch_notification(change).unwrap();
+
+        let params = json!({
+            "textDocument": { "uri": "file:///default.nix" },
+            "position": { "line": 0, "character": 4 },
+            "newName": "b",
+        });
+        state.dispatch_request(Request::new(
+            RequestId::from(0),
+            req::Rename::METHOD.into(),
+            params,
+        ));
+        let resp = receiver
+            .try_iter()
+            .find_map(|msg| match msg {
+                Message::Response(resp) => Some(resp.result.expect("No error")),
+                _ => None,
+            })
+            .unwrap();
+        let ws_edit = serde_json::from_value::<WorkspaceEdit>(resp).unwrap();
+        assert_eq!(ws_edit.changes, None);
+        let edits = match ws_edit.document_changes {
+            Some(DocumentChanges::Edits(edits)) => edits,
+            changes => panic!("Unexpected document changes: {:?}", changes),
+        };
+        assert_eq!(edits.len(), 1);
+        assert_eq!(
+            edits[0].text_document.uri,
+            "file:///default.nix".parse::<Url>().unwrap(),
+        );
+        assert_eq!(edits[0].text_document.version, Some(5));
+        assert_eq!(edits[0].edits.len(), 2);
+
+        // Versions are taken with the snapshot, matching the text that edits are computed on.
+        let uri = "file:///default.nix".parse::<Url>().unwrap();
+        let snap = state.snapshot();
+        state.opened_files.write().unwrap().insert(uri.clone(), 6);
+        assert_eq!(snap.doc_versions().unwrap()[&uri], 5);
+    }
+
+    #[test]
+    fn rename_change_annotations() {
+        let client_caps = serde_json::from_value::<ClientCapabilities>(json!({
+            "workspace": {
+                "workspaceEdit": {
+                    "documentChanges": true,
+                    "changeAnnotationSupport": { "groupsOnLabel": true },
+                },
+            },
+        }))
+        .unwrap();
+        let (mut state, receiver) = new_state(None, client_caps);
+        open(
+            &mut state,
+            "file:///default.nix",
+            "let foo = 1; in foo + foo",
+        );
+
+        let params = json!({
+            "textDocument": { "uri": "file:///default.nix" },
+            "position": { "line": 0, "character": 4 },
+            "newName": "bar",
+        });
+        state.dispatch_request(Request::new(
+            RequestId::from(0),
+            req::Rename::METHOD.into(),
+            params,
+        ));
+        let resp = receiver
+            .try_iter()
+            .find_map(|msg| match msg {
+                Message::Response(resp) => Some(resp.result.expect("No error")),
+                _ => None,
+            })
+            .unwrap();
+        // Annotated edits are indistinguishable from plain ones after deserialization.
+        let edits = resp["documentChanges"][0]["edits"]
+            .as_array()
+            .unwrap()
+            .clone();
+        let ws_edit = serde_json::from_value::<WorkspaceEdit>(resp).unwrap();
+        let annotations = ws_edit.change_annotations.expect("No annotations");
+        assert_eq!(annotations.len(), 1);
+        let (id, annotation) = annotations.into_iter().next().unwrap();
+        assert_eq!(annotation.label, "Rename `foo` to `bar`");
+        assert_eq!(annotation.description.as_deref(), Some("3 edits in 1 file"));
+
+        assert_eq!(edits.len(), 3);
+        for edit in &edits {
+            assert_eq!(edit["annotationId"], id);
+            assert_eq!(edit["newText"], "bar");
+        }
+    }
+
+    #[test]
+    fn format_document_command() {
+        let client_caps = serde_json::from_value::<ClientCapabilities>(json!({
+            "workspace": { "applyEdit": true },
+        }))
+        .unwrap();
+        let (mut state, receiver) = new_state(None, client_caps);
+        state.config = Arc::new(Config {
+            formatting: FormattingConfig {
+                command: Some(vec!["tr".into(), "-s".into(), " ".into()]),
+                ..FormattingConfig::default()
+            },
+            ..Config::default()
+        });
+        open(&mut state, "file:///default.nix", "{  a  = 1; }");
+        published(&mut state, &receiver);
+
+        let mut request = |method: &str, params: serde_json::Value| {
+            state.dispatch_request(Request::new(RequestId::from(0), method.into(), params));
+            receiver.try_iter().collect::<Vec<_>>()
+        };
+        let response = |msgs: &[Message]| {
+            msgs.iter()
+                .find_map(|msg| match msg {
+                    Message::Response(resp) => Some(resp.result.clone().expect("No error")),
+                    _ => None,
+                })
+                .unwrap()
+        };
+
+        let action_params = |only: serde_json::Value| {
+            json!({
+                "textDocument": { "uri": "file:///default.nix" },
+                "range": { "start": { "line": 0, "character": 0 }, "end": { "line": 0, "character": 0 } },
+                "context": { "diagnostics": [], "only": only },
+            })
+        };
+        let actions = response(&request(
+            req::CodeActionRequest::METHOD,
+            action_params(json!(["quickfix"])),
+        ));
+        assert_eq!(actions, json!([]));
+        let actions = response(&request(
+            req::CodeActionRequest::METHOD,
+            action_params(json!(["source"])),
+        ));
+        let action = match serde_json::from_value::<Vec<CodeActionOrCommand>>(actions)
+            .unwrap()
+            .pop()
+        {
+            Some(CodeActionOrCommand::CodeAction(action)) => action,
+            action => panic!("Unexpected code action: {:?}", action),
+        };
+        assert_eq!(action.title, "Format with tr");
+        assert_eq!(action.edit, None);
+
+        // Resolving keeps the command.
+        let resolved = response(&request(
+            req::CodeActionResolveRequest::METHOD,
+            serde_json::to_value(&action).unwrap(),
+        ));
+        let resolved = serde_json::from_value::<CodeAction>(resolved).unwrap();
+        assert_eq!(resolved, action);
+
+        let command = action.command.unwrap();
+        let msgs = request(
+            req::ExecuteCommand::METHOD,
+            json!({ "command": command.command, "arguments": command.arguments }),
+        );
+        assert_eq!(response(&msgs), json!(null));
+        let apply = msgs
+            .iter()
+            .find_map(|msg| match msg {
+                Message::Request(req) if req.method == req::ApplyWorkspaceEdit::METHOD => Some(
+                    serde_json::from_value::<ApplyWorkspaceEditParams>(req.params.clone()).unwrap(),
+                ),
+                _ => None,
+            })
+            .unwrap();
+        let changes = apply.edit.changes.unwrap();
+        let edits = &changes[&"file:///default.nix".parse::<Url>().unwrap()];
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].new_text, "{ a = 1; }");
+    }
+
+    #[test]
+    fn range_formatting_args() {
+        let (mut state, receiver) = new_state(None, Default::default());
+        // A mock formatter prepending the range it is given.
+        let script = r##"echo "# $1..$2 lines $3..$4"; cat"##;
+        state.config = Arc::new(Config {
+            formatting: FormattingConfig {
+                command: Some(vec!["sh".into(), "-c".into(), script.into(), "sh".into()]),
+                range_args: Some(
+                    ["{start}", "{end}", "{startLine}", "{endLine}"]
+                        .map(String::from)
+                        .to_vec(),
+                ),
+            },
+            ..Config::default()
+        });
+        open(&mut state, "file:///default.nix", "{\n  a = 1;\n}\n");
+        published(&mut state, &receiver);
+
+        let params = json!({
+            "textDocument": { "uri": "file:///default.nix" },
+            "range": { "start": { "line": 1, "character": 2 }, "end": { "line": 1, "character": 8 } },
+            "options": { "tabSize": 2, "insertSpaces": true },
+        });
+        state.dispatch_request(Request::new(
+            RequestId::from(0),
+            req::RangeFormatting::METHOD.into(),
+            params,
+        ));
+        let edits = receiver
+            .try_iter()
+            .find_map(|msg| match msg {
+                Message::Response(resp) => Some(resp.result.expect("No error")),
+                _ => None,
+            })
+            .unwrap();
+        // Only the changed part is edited.
+        assert_eq!(
+            edits,
+            json!([{
+                "range": { "start": { "line": 0, "character": 0 }, "end": { "line": 0, "character": 0 } },
+                "newText": "# 4..10 lines 2..2\n",
+            }]),
+        );
+    }
+
+    #[test]
+    fn name_resolution() {
+        let (mut state, receiver) = new_state(None, Default::default());
+        open(&mut state, "file:///default.nix", "a: [ a b ]");
+        state.dispatch_request(Request::new(
+            RequestId::from(0),
+            lsp_ext::NameResolution::METHOD.into(),
+            json!({ "textDocument": { "uri": "file:///default.nix" } }),
+        ));
+        let result = receiver
+            .try_iter()
+            .find_map(|msg| match msg {
+                Message::Response(resp) => Some(resp.result.expect("No error")),
+                _ => None,
+            })
+            .unwrap();
+        let range = |start: u32, end: u32| {
+            json!({
+                "start": { "line": 0, "character": start },
+                "end": { "line": 0, "character": end },
+            })
+        };
+        assert_eq!(
+            result,
+            json!([
+                { "range": range(5, 6), "resolution": "definition", "targets": [range(0, 1)] },
+                { "range": range(7, 8), "resolution": "unresolved", "targets": [] },
+            ]),
+        );
+    }
+
+    #[test]
+    fn builtin_function_tokens() {
+        let (mut state, receiver) = new_state(None, Default::default());
+        let src = "let f = x: x; in builtins.map f [ ]";
+        open(&mut state, "file:///default.nix", src);
+        state.dispatch_request(Request::new(
+            RequestId::from(0),
+            req::SemanticTokensFullRequest::METHOD.into(),
+            json!({ "textDocument": { "uri": "file:///default.nix" } }),
+        ));
+        let resp = receiver
+            .try_iter()
+            .find_map(|msg| match msg {
+                Message::Response(resp) => Some(resp.result.expect("No error")),
+                _ => None,
+            })
+            .unwrap();
+        let toks = serde_json::from_value::<SemanticTokens>(resp).unwrap().data;
+
+        // Find the token at the column, in a single line.
+        let legend = NegotiatedLegend::default().legend();
+        let token_at = |col: usize| {
+            let mut start = 0;
+            let tok = toks
+                .iter()
+                .find(|tok| {
+                    start += tok.delta_start as usize;
+                    start == col
+                })
+                .unwrap();
+            let mods = (0..legend.token_modifiers.len())
+                .filter(|i| tok.token_modifiers_bitset & (1 << i) != 0)
+                .map(|i| legend.token_modifiers[i].clone())
+                .collect::<Vec<_>>();
+            (legend.token_types[tok.token_type as usize].clone(), mods)
+        };
+        assert_eq!(
+            token_at(src.find("map").unwrap()),
+            (
+                SemanticTokenType::FUNCTION,
+                vec![SemanticTokenModifier::DEFAULT_LIBRARY],
+            ),
+        );
+        assert_eq!(
+            token_at(src.find("map f").unwrap() + 4),
+            (SemanticTokenType::FUNCTION, vec![]),
+        );
+    }
+
+    #[test]
+    fn free_variables() {
+        let (mut state, receiver) = new_state(None, Default::default());
+        open(
+            &mut state,
+            "file:///default.nix",
+            "a: b: let c = a; in c + b + 1",
+        );
+        state.dispatch_request(Request::new(
+            RequestId::from(0),
+            lsp_ext::FreeVariables::METHOD.into(),
+            json!({
+                "textDocument": { "uri": "file:///default.nix" },
+                "range": {
+                    "start": { "line": 0, "character": 6 },
+                    "end": { "line": 0, "character": 25 },
+                },
+            }),
+        ));
+        let result = receiver
+            .try_iter()
+            .find_map(|msg| match msg {
+                Message::Response(resp) => Some(resp.result.expect("No error")),
+                _ => None,
+            })
+            .unwrap();
+        let location = |start: u32, end: u32| {
+            json!({
+                "uri": "file:///default.nix",
+                "range": {
+                    "start": { "line": 0, "character": start },
+                    "end": { "line": 0, "character": end },
+                },
+            })
+        };
+        assert_eq!(
+            result,
+            json!([
+                { "name": "a", "locations": [location(14, 15)] },
+                { "name": "b", "locations": [location(24, 25)] },
+            ]),
+        );
+    }
+
+    #[test]
+    fn parse_status() {
+        let (mut state, receiver) = new_state(None, Default::default());
+        open(&mut state, "file:///default.nix", "{ a = ; b = ; c = 1 }");
+        state.dispatch_request(Request::new(
+            RequestId::from(0),
+            lsp_ext::ParseStatus::METHOD.into(),
+            json!({ "textDocument": { "uri": "file:///default.nix" } }),
+        ));
+        let result = receiver
+            .try_iter()
+            .find_map(|msg| match msg {
+                Message::Response(resp) => Some(resp.result.expect("No error")),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(
+            result,
+            json!({
+                "hasErrors": true,
+                "errorCount": 3,
+                "errorKinds": [
+                    { "kind": "Missing expression", "count": 2 },
+                    { "kind": "Missing \";\"", "count": 1 },
+                ],
+                "errorNodeCount": 0,
+            }),
+        );
+    }
+
+    #[test]
+    fn completion_history() {
+        let (mut state, receiver) = new_state(None, Default::default());
+        open(
+            &mut state,
+            "file:///default.nix",
+            "let fooApple = 1; fooBanana = 2; in foo",
+        );
+        let complete = |state: &mut State, character: u32| {
+            state.dispatch_request(Request::new(
+                RequestId::from(0),
+                req::Completion::METHOD.into(),
+                json!({
+                    "textDocument": { "uri": "file:///default.nix" },
+                    "position": { "line": 0, "character": character },
+                }),
+            ));
+            let items = receiver
+                .try_iter()
+                .find_map(|msg| match msg {
+                    Message::Response(resp) if resp.id == RequestId::from(0) => {
+                        Some(resp.result.expect("No error"))
+                    }
+                    _ => None,
+                })
+                .unwrap();
+            let mut items = serde_json::from_value::<Vec<CompletionItem>>(items).unwrap();
+            items.sort_by_key(|item| item.sort_text.clone().unwrap_or(item.label.clone()));
+            items
+        };
+
+        let items = complete(&mut state, 39);
+        let pos = |items: &[CompletionItem], label: &str| {
+            items.iter().position(|item| item.label == label).unwrap()
+        };
+        assert!(pos(&items, "fooApple") < pos(&items, "fooBanana"));
+        let command = items[pos(&items, "fooBanana")].command.clone().unwrap();
+        assert_eq!(command.arguments, Some(vec![json!("fooBanana")]));
+
+        // Accept `fooBanana` twice, then `fooApple` once.
+        for _ in 0..2 {
+            state.dispatch_request(Request::new(
+                RequestId::from(1),
+                req::ExecuteCommand::METHOD.into(),
+                json!({ "command": command.command, "arguments": command.arguments }),
+            ));
+        }
+        state.dispatch_request(Request::new(
+            RequestId::from(1),
+            req::ExecuteCommand::METHOD.into(),
+            json!({ "command": command.command, "arguments": ["fooApple"] }),
+        ));
+        let items = complete(&mut state, 39);
+        assert_eq!(pos(&items, "fooBanana"), 0);
+        assert_eq!(pos(&items, "fooApple"), 1);
+
+        // Keywords are not ranked, thus acceptance is not recorded.
+        open(&mut state, "file:///default.nix", "l");
+        let items = complete(&mut state, 1);
+        let keyword = &items[pos(&items, "let")];
+        assert_eq!(keyword.kind, Some(CompletionItemKind::KEYWORD));
+        assert_eq!(keyword.command, None);
+
+        // The rank prefixes the sort text of deprecated items, instead of replacing it.
+        open(
+            &mut state,
+            "file:///default.nix",
+            "let { fooApple = 1; fooCherry = 2; body = foo; }",
+        );
+        let items = complete(&mut state, 45);
+        assert_eq!(
+            items[pos(&items, "fooApple")].sort_text.as_deref(),
+            Some("!01~fooApple"),
+        );
+        assert_eq!(
+            items[pos(&items, "fooCherry")].sort_text.as_deref(),
+            Some("~fooCherry"),
+        );
+    }
+
+    #[test]
+    fn max_completion_items() {
+        let (mut state, receiver) = new_state(None, Default::default());
+        open(
+            &mut state,
+            "file:///default.nix",
+            "let foo1 = 1; foo2 = 2; foo3 = 3; in foo",
+        );
+        let complete = |state: &mut State, max_items: usize| {
+            state.config = Arc::new(Config {
+                max_completion_items: max_items,
+                ..Config::default()
+            });
+            state.dispatch_request(Request::new(
+                RequestId::from(0),
+                req::Completion::METHOD.into(),
+                json!({
+                    "textDocument": { "uri": "file:///default.nix" },
+                    "position": { "line": 0, "character": 40 },
+                }),
+            ));
+            let resp = receiver
+                .try_iter()
+                .find_map(|msg| match msg {
+                    Message::Response(resp) => Some(resp.result.expect("No error")),
+                    _ => None,
+                })
+                .unwrap();
+            serde_json::from_value::<CompletionResponse>(resp).unwrap()
+        };
+
+        match complete(&mut state, 0) {
+            CompletionResponse::Array(items) => assert_eq!(items.len(), 3),
+            resp => panic!("Unexpected response: {:?}", resp),
+        }
+        match complete(&mut state, 3) {
+            CompletionResponse::Array(items) => assert_eq!(items.len(), 3),
+            resp => panic!("Unexpected response: {:?}", resp),
+        }
+        match complete(&mut state, 2) {
+            CompletionResponse::List(list) => {
+                assert!(list.is_incomplete);
+                let labels = list
+                    .items
+                    .iter()
+                    .map(|item| &*item.label)
+                    .collect::<Vec<_>>();
+                assert_eq!(labels, ["foo1", "foo2"]);
+            }
+            resp => panic!("Unexpected response: {:?}", resp),
+        }
+    }
+
+    #[test]
+    fn format_text() {
+        let (mut state, receiver) = new_state(None, Default::default());
+        let format = |state: &mut State, text: &str| {
+            state.dispatch_request(Request::new(
+                RequestId::from(0),
+                lsp_ext::FormatText::METHOD.into(),
+                json!({ "text": text }),
+            ));
+            receiver
+                .try_iter()
+                .find_map(|msg| match msg {
+                    Message::Response(resp) => Some(resp.result.expect("No error")),
+                    _ => None,
+                })
+                .unwrap()
+        };
+
+        // No formatter.
+        assert_eq!(format(&mut state, "{  a  = 1; }"), json!("{  a  = 1; }"));
+
+        state.config = Arc::new(Config {
+            formatting: FormattingConfig {
+                command: Some(vec!["tr".into(), "-s".into(), " ".into()]),
+                ..FormattingConfig::default()
+            },
+            ..Config::default()
+        });
+        assert_eq!(format(&mut state, "{  a  = 1; }"), json!("{ a = 1; }"));
+        // No document is opened.
+        assert_eq!(state.vfs.read().unwrap().files().count(), 0);
+
+        // The formatter fails.
+        state.config = Arc::new(Config {
+            formatting: FormattingConfig {
+                command: Some(vec!["false".into()]),
+                ..FormattingConfig::default()
+            },
+            ..Config::default()
+        });
+        assert_eq!(format(&mut state, "{  a  = 1; }"), json!("{  a  = 1; }"));
+    }
+
+    #[test]
+    fn select_enclosing_binding_command() {
+        let (mut state, receiver) = new_state(None, Default::default());
+        open(
+            &mut state,
+            "file:///default.nix",
+            "{\n  a = {\n    b = 1;\n  };\n}",
+        );
+        published(&mut state, &receiver);
+
+        let mut execute = |line: u32, character: u32| {
+            let params = json!({
+                "command": "nil.selectEnclosingBinding",
+                "arguments": [{
+                    "textDocument": { "uri": "file:///default.nix" },
+                    "position": { "line": line, "character": character },
+                }],
+            });
+            state.dispatch_request(Request::new(
+                RequestId::from(0),
+                req::ExecuteCommand::METHOD.into(),
+                params,
+            ));
+            receiver
+                .try_iter()
+                .find_map(|msg| match msg {
+                    Message::Response(resp) => Some(resp.result.expect("No error")),
+                    _ => None,
+                })
+                .unwrap()
+        };
+        assert_eq!(
+            execute(2, 8),
+            json!({ "start": { "line": 2, "character": 4 }, "end": { "line": 2, "character": 10 } }),
+        );
+        assert_eq!(
+            execute(1, 2),
+            json!({ "start": { "line": 1, "character": 2 }, "end": { "line": 3, "character": 4 } }),
+        );
+        assert_eq!(execute(0, 0), json!(null));
+    }
+
+    #[test]
+    fn lazy_loading() {
+        let root = std::env::temp_dir().join(format!("nil-lazy-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("default.nix"), "import ./foo.nix").unwrap();
+        fs::write(root.join("foo.nix"), "import ./bar.nix").unwrap();
+        fs::write(root.join("bar.nix"), "42").unwrap();
+        fs::write(root.join("unrelated.nix"), "1").unwrap();
+        let root = root.canonicalize().unwrap();
+        let uri = |name: &str| Url::from_file_path(root.join(name)).unwrap();
+        let (mut state, receiver) = new_state(Some(root.clone()), Default::default());
+        state.config = Arc::new(Config {
+            scan: ScanConfig {
+                lazy: true,
+                ..ScanConfig::default()
+            },
+            ..Config::default()
+        });
+        let notif = Notification::new(notif::Initialized::METHOD.into(), json!({}));
+        state.dispatch_notification(notif).unwrap();
+        let files = state.scan_receiver.recv().unwrap();
+        state.finish_workspace_scan(files);
+
+        let is_loaded = |state: &State, name: &str| {
+            let vfs = state.vfs.read().unwrap();
+            vfs.is_loaded(vfs.file_for_uri(&uri(name)).unwrap())
+        };
+        for name in ["default.nix", "foo.nix", "bar.nix", "unrelated.nix"] {
+            assert!(!is_loaded(&state, name));
+        }
+
+        // Files are read when first referenced by a request.
+        let params = json!({
+            "textDocument": { "uri": uri("default.nix") },
+            "position": { "line": 0, "character": 8 },
+        });
+        let req = Request::new(
+            RequestId::from(1),
+            req::GotoDefinition::METHOD.into(),
+            params,
+        );
+        state.dispatch_request(req);
+        let resp = receiver
+            .try_iter()
+            .find_map(|msg| match msg {
+                Message::Response(resp) => Some(resp.result.expect("No error")),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(resp[0]["uri"], json!(uri("foo.nix")));
+        for name in ["default.nix", "foo.nix", "bar.nix"] {
+            assert!(is_loaded(&state, name));
+        }
+        assert!(!is_loaded(&state, "unrelated.nix"));
+        let bar = state
+            .vfs
+            .read()
+            .unwrap()
+            .file_for_uri(&uri("bar.nix"))
+            .unwrap();
+        assert_eq!(&*state.vfs.read().unwrap().content_for_file(bar), "42");
+
+        // Changes on disk drop the cached content.
+        fs::write(root.join("bar.nix"), "43").unwrap();
+        let change = Notification::new(
+            notif::DidChangeWatchedFiles::METHOD.into(),
+            json!({ "changes": [{ "uri": uri("bar.nix"), "type": 2 }] }),
+        );
+        state.dispatch_notification(change).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+        assert!(!is_loaded(&state, "bar.nix"));
+    }
+
+    #[test]
+    fn on_demand_scan() {
+        let root = std::env::temp_dir().join(format!("nil-on-demand-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for dir in ["a/sub", "b", "c"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        fs::write(root.join("a/sibling.nix"), "1").unwrap();
+        fs::write(root.join("a/sub/foo.nix"), "2").unwrap();
+        fs::write(root.join("b/bar.nix"), "3").unwrap();
+        fs::write(root.join("c/baz.nix"), "4").unwrap();
+        let root = root.canonicalize().unwrap();
+        let uri = |name: &str| Url::from_file_path(root.join(name)).unwrap();
+        let (mut state, receiver) = new_state(Some(root.clone()), Default::default());
+        state.config = Arc::new(Config {
+            scan: ScanConfig {
+                on_demand: true,
+                ..ScanConfig::default()
+            },
+            ..Config::default()
+        });
+        let notif = Notification::new(notif::Initialized::METHOD.into(), json!({}));
+        state.dispatch_notification(notif).unwrap();
+        assert!(!state.is_indexing());
+
+        let is_indexed =
+            |state: &State, name: &str| state.vfs.read().unwrap().file_for_uri(&uri(name)).is_ok();
+        // The directory of the opened file, and directories of its imports.
+        open(
+            &mut state,
+            uri("a/default.nix").as_str(),
+            "import ./sub/foo.nix",
+        );
+        published(&mut state, &receiver);
+        for name in ["a/sibling.nix", "a/sub/foo.nix"] {
+            assert!(is_indexed(&state, name));
+        }
+        for name in ["b/bar.nix", "c/baz.nix"] {
+            assert!(!is_indexed(&state, name));
+        }
+
+        // Referencing another directory indexes it on the next request.
+        let change = Notification::new(
+            notif::DidChangeTextDocument::METHOD.into(),
+            json!({
+                "textDocument": { "uri": uri("a/default.nix"), "version": 1 },
+                "contentChanges": [{
+                    "range": { "start": { "line": 0, "character": 0 }, "end": { "line": 0, "character": 20 } },
+                    "text": "import ../b/bar.nix",
+                }],
+            }),
+        );
+        state.dispatch_notification(change).unwrap();
+        let params = json!({
+            "textDocument": { "uri": uri("a/default.nix") },
+            "position": { "line": 0, "character": 10 },
+        });
+        let req = Request::new(
+            RequestId::from(1),
+            req::GotoDefinition::METHOD.into(),
+            params,
+        );
+        state.dispatch_request(req);
+        let resp = receiver
+            .try_iter()
+            .find_map(|msg| match msg {
+                Message::Response(resp) => Some(resp.result.expect("No error")),
+                _ => None,
+            })
+            .unwrap();
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(resp[0]["uri"], json!(uri("b/bar.nix")));
+        assert!(is_indexed(&state, "b/bar.nix"));
+        assert!(!is_indexed(&state, "c/baz.nix"));
+    }
+
+    #[test]
+    fn on_demand_scan_root_dir() {
+        let root = std::env::temp_dir().join(format!("nil-on-demand-root-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let root = root.canonicalize().unwrap();
+        let uri = Url::from_file_path(root.join("default.nix")).unwrap();
+        let (mut state, receiver) = new_state(Some(root.clone()), Default::default());
+        state.config = Arc::new(Config {
+            scan: ScanConfig {
+                on_demand: true,
+                ..ScanConfig::default()
+            },
+            ..Config::default()
+        });
+        let notif = Notification::new(notif::Initialized::METHOD.into(), json!({}));
+        state.dispatch_notification(notif).unwrap();
+
+        // `./.` in a root file references the root directory itself.
+        open(&mut state, uri.as_str(), "{ src = ./.; }");
+        let got = published(&mut state, &receiver);
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].uri, uri);
+    }
+
+    #[test]
+    fn reset_after_repeated_panics() {
+        let (mut state, receiver) = new_state(None, Default::default());
+        open(&mut state, "file:///default.nix", "let a = 1; in a");
+        published(&mut state, &receiver);
+
+        let goto_params = json!({
+            "textDocument": { "uri": "file:///default.nix" },
+            "position": { "line": 0, "character": 14 },
+        });
+        let panicking = |state: &mut State| {
+            let req = Request::new(
+                RequestId::from(0),
+                req::GotoDefinition::METHOD.into(),
+                goto_params.clone(),
+            );
+            RequestDispatcher(state, Some(req))
+                .on::<req::GotoDefinition>(|_, _| panic!("Injected panic"))
+                .finish();
+            match receiver.try_recv().unwrap() {
+                Message::Response(resp) => assert!(resp.error.is_some()),
+                msg => panic!("Unexpected message: {:?}", msg),
+            }
+            match receiver.try_recv().unwrap() {
+                Message::Notification(notif) => {
+                    assert_eq!(notif.method, notif::ShowMessage::METHOD);
+                }
+                msg => panic!("Unexpected message: {:?}", msg),
+            }
+        };
+
+        panicking(&mut state);
+        panicking(&mut state);
+        assert_eq!(
+            state.consecutive_panics,
+            Some((req::GotoDefinition::METHOD, 2))
+        );
+        panicking(&mut state);
+        assert_eq!(state.consecutive_panics, None);
+
+        // The rebuilt analysis state works as before.
+        state.dispatch_request(Request::new(
+            RequestId::from(1),
+            req::GotoDefinition::METHOD.into(),
+            goto_params.clone(),
+        ));
+        let resp = match receiver.try_recv().unwrap() {
+            Message::Response(resp) => resp.result.expect("No error"),
+            msg => panic!("Unexpected message: {:?}", msg),
+        };
+        assert_eq!(
+            resp[0]["range"]["start"],
+            json!({ "line": 0, "character": 4 })
+        );
+    }
+
+    #[test]
+    fn report_panics() {
+        let (mut state, receiver) = new_state(None, Default::default());
+        open(&mut state, "file:///default.nix", "a");
+        published(&mut state, &receiver);
+
+        let panicking = |state: &mut State| {
+            let params = json!({
+                "textDocument": { "uri": "file:///default.nix" },
+                "position": { "line": 0, "character": 0 },
+            });
+            let req = Request::new(RequestId::from(0), req::HoverRequest::METHOD.into(), params);
+            RequestDispatcher(state, Some(req))
+                .on::<req::HoverRequest>(|_, _| panic!("Injected panic"))
+                .finish();
+            receiver
+                .try_iter()
+                .filter_map(|msg| match msg {
+                    Message::Notification(notif) if notif.method == notif::ShowMessage::METHOD => {
+                        Some(serde_json::from_value::<ShowMessageParams>(notif.params).unwrap())
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let got = panicking(&mut state);
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].typ, MessageType::ERROR);
+        assert!(got[0]
+            .message
+            .starts_with("Request handler of textDocument/hover panicked at "));
+        assert!(got[0].message.contains("state.rs"));
+        assert!(got[0].message.contains("Injected panic"));
+
+        state.update_config(json!({ "reportPanics": false }));
+        assert_eq!(panicking(&mut state), []);
+    }
 }
@@ -1,18 +1,32 @@
 use crate::Result;
 use ide::{Change, FileId, FileSet, SourceRoot, VfsPath};
 use lsp_types::Url;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::{fmt, mem};
+use std::{fmt, fs, mem};
 use text_size::{TextRange, TextSize};
 
+/// Files marking the directory containing them as a separate source root.
+const ROOT_MARKERS: &[&str] = &["flake.nix"];
+/// Candidates of the entry file of a source root, in the order of priority.
+const ROOT_ENTRIES: &[&str] = &["flake.nix", "default.nix"];
+/// URI schemes of documents not backed by disk which are analyzed in memory.
+/// Others, like `git:` or `vscode-notebook-cell:`, are views of something else and are ignored.
+const OVERLAY_SCHEMES: &[&str] = &["untitled"];
+
 pub struct Vfs {
     // FIXME: Currently this list is append-only.
     files: Vec<(Arc<str>, Arc<LineMap>)>,
     /// The root directory, which must be absolute.
     local_root: PathBuf,
     local_file_set: FileSet,
+    /// Files whose content is not read from disk yet. They are empty until loaded.
+    unloaded: HashSet<FileId>,
+    /// Documents not backed by disk, like `untitled:` ones, keyed by their URIs.
+    /// Each of them lives alone in its own source root under a synthetic path,
+    /// so relative paths in them resolve to nothing.
+    overlays: HashMap<Url, (FileId, VfsPath)>,
     root_changed: bool,
     change: Change,
 }
@@ -33,12 +47,18 @@ impl Vfs {
             files: Vec::new(),
             local_root,
             local_file_set: FileSet::default(),
+            unloaded: HashSet::new(),
+            overlays: HashMap::new(),
             root_changed: false,
             change: Change::default(),
         }
     }
 
     fn uri_to_vpath(&self, uri: &Url) -> Result<VfsPath> {
+        // `to_file_path` doesn't check the scheme.
+        if uri.scheme() != "file" {
+            return Err(format!("Non-file URI: {}", uri).into());
+        }
         let path = uri
             .to_file_path()
             .map_err(|_| format!("Non-file URI: {}", uri))?;
@@ -49,6 +69,10 @@ impl Vfs {
     }
 
     pub fn set_uri_content(&mut self, uri: &Url, text: String) -> Result<()> {
+        if OVERLAY_SCHEMES.contains(&uri.scheme()) {
+            self.set_overlay_content(uri, text);
+            return Ok(());
+        }
         let vpath = self.uri_to_vpath(uri)?;
         self.set_path_content(vpath, text);
         Ok(())
@@ -64,10 +88,12 @@ impl Vfs {
         let line_map = Arc::new(line_map);
         match self.local_file_set.file_for_path(&path) {
             Some(file) => {
+                self.unloaded.remove(&file);
                 self.files[file.0 as usize] = (text.clone(), line_map);
                 self.change.change_file(file, text);
                 if !is_valid {
                     self.local_file_set.remove_file(file);
+                    self.root_changed = true;
                 }
             }
             None => {
@@ -76,12 +102,86 @@ impl Vfs {
                 }
                 let file = FileId(u32::try_from(self.files.len()).expect("Length overflow"));
                 self.local_file_set.insert(file, path);
+                self.root_changed = true;
                 self.files.push((text.clone(), line_map));
                 self.change.change_file(file, text);
             }
         };
     }
 
+    fn set_overlay_content(&mut self, uri: &Url, text: String) {
+        // Too large files are analyzed as empty.
+        let (text, line_map) = LineMap::normalize(text).unwrap_or_default();
+        let text = <Arc<str>>::from(text);
+        let line_map = Arc::new(line_map);
+        let file = match self.overlays.get(uri) {
+            Some(&(file, _)) => {
+                self.files[file.0 as usize] = (text.clone(), line_map);
+                file
+            }
+            None => {
+                let file = FileId(u32::try_from(self.files.len()).expect("Length overflow"));
+                self.files.push((text.clone(), line_map));
+                self.overlays.insert(uri.clone(), (file, overlay_path(uri)));
+                self.root_changed = true;
+                file
+            }
+        };
+        self.change.change_file(file, text);
+    }
+
+    /// Forget a document not backed by disk, eg. when it is closed.
+    /// Returns whether `uri` was such a document.
+    pub fn remove_overlay(&mut self, uri: &Url) -> bool {
+        let file = match self.overlays.remove(uri) {
+            Some((file, _)) => file,
+            None => return false,
+        };
+        // File ids are never reused. It is unreachable once out of the roots,
+        // so just release our copy of the content.
+        self.files[file.0 as usize] = Default::default();
+        self.root_changed = true;
+        true
+    }
+
+    /// Whether the file is a document not backed by disk.
+    pub fn is_overlay(&self, file: FileId) -> bool {
+        self.overlays.values().any(|&(overlay, _)| overlay == file)
+    }
+
+    /// Register a file without reading its content, which is read by [`Vfs::load_file`]
+    /// on its first use. Any content already read is dropped.
+    pub fn set_path_unloaded(&mut self, path: VfsPath) {
+        self.set_path_content(path.clone(), String::new());
+        if let Some(file) = self.local_file_set.file_for_path(&path) {
+            self.unloaded.insert(file);
+        }
+    }
+
+    pub fn set_uri_unloaded(&mut self, uri: &Url) -> Result<()> {
+        let vpath = self.uri_to_vpath(uri)?;
+        self.set_path_unloaded(vpath);
+        Ok(())
+    }
+
+    /// Read the content of a file registered by [`Vfs::set_path_unloaded`] from disk.
+    /// Returns `false` if it is already loaded.
+    pub fn load_file(&mut self, file: FileId) -> bool {
+        if !self.unloaded.contains(&file) {
+            return false;
+        }
+        let vpath = self.local_file_set.path_for_file(file).clone();
+        // Unreadable files are analyzed as empty, the same as during the workspace scan.
+        let text = fs::read_to_string(self.local_path(&vpath)).unwrap_or_default();
+        log::debug!("Lazily loaded {:?}", vpath);
+        self.set_path_content(vpath, text);
+        true
+    }
+
+    pub fn is_loaded(&self, file: FileId) -> bool {
+        !self.unloaded.contains(&file)
+    }
+
     pub fn change_file_content(
         &mut self,
         file: FileId,
@@ -111,41 +211,148 @@ impl Vfs {
     }
 
     pub fn file_for_uri(&self, uri: &Url) -> Result<FileId> {
+        if let Some(&(file, _)) = self.overlays.get(uri) {
+            return Ok(file);
+        }
         let vpath = self.uri_to_vpath(uri)?;
         self.local_file_set
             .file_for_path(&vpath)
             .ok_or_else(|| format!("URI not found: {}", uri).into())
     }
 
+    /// All files which can be analyzed.
+    pub fn files(&self) -> impl Iterator<Item = FileId> + '_ {
+        self.local_file_set
+            .iter()
+            .map(|(file, _)| file)
+            .chain(self.overlays.values().map(|&(file, _)| file))
+    }
+
     pub fn uri_for_file(&self, file: FileId) -> Url {
-        let vpath = self.local_file_set.path_for_file(file).as_str();
-        assert!(!vpath.is_empty(), "Root is a directory");
-        let path = self.local_root.join(vpath.strip_prefix('/').unwrap());
-        Url::from_file_path(path).expect("Root is absolute")
+        if let Some((uri, _)) = self
+            .overlays
+            .iter()
+            .find(|(_, &(overlay, _))| overlay == file)
+        {
+            return uri.clone();
+        }
+        self.uri_for_path(self.local_file_set.path_for_file(file))
+    }
+
+    pub fn uri_for_path(&self, vpath: &VfsPath) -> Url {
+        assert!(!vpath.as_str().is_empty(), "Root is a directory");
+        Url::from_file_path(self.local_path(vpath)).expect("Root is absolute")
+    }
+
+    /// The path on disk. The empty `vpath` is the root directory itself, like `./.` in root files.
+    pub fn local_path(&self, vpath: &VfsPath) -> PathBuf {
+        match vpath.as_str().strip_prefix('/') {
+            Some(relative) => self.local_root.join(relative),
+            None => self.local_root.clone(),
+        }
+    }
+
+    /// The path on disk of a relative path literal like `./foo.nix` in `file`.
+    /// Returns `None` for documents not backed by disk, or paths going beyond the root.
+    pub fn local_path_relative_to(&self, file: FileId, relative: &str) -> Option<PathBuf> {
+        if self.is_overlay(file) {
+            return None;
+        }
+        let mut dir = self.local_file_set.path_for_file(file).clone();
+        dir.pop()?;
+        let vpath = VfsPath::new(format!("{}/{}", dir.as_str(), relative))?.normalize()?;
+        Some(self.local_path(&vpath))
+    }
+
+    /// A change setting up all files and source roots from scratch, for a fresh database.
+    pub fn full_change(&self) -> Change {
+        let mut change = Change::default();
+        for (i, (text, _)) in self.files.iter().enumerate() {
+            change.change_file(FileId(i as u32), text.clone());
+        }
+        change.set_roots(self.source_roots());
+        change
     }
 
     pub fn take_change(&mut self) -> Change {
         let mut change = mem::take(&mut self.change);
         if self.root_changed {
             self.root_changed = false;
-            // TODO: Configurable.
-            let entry = ["/flake.nix", "/default.nix"].iter().find_map(|&path| {
-                let path = VfsPath::new(path).unwrap();
-                self.local_file_set.file_for_path(&path)
-            });
-            change.set_roots(vec![SourceRoot::new_local(
-                self.local_file_set.clone(),
-                entry,
-            )]);
+            change.set_roots(self.source_roots());
         }
         change
     }
 
+    /// Partition files into source roots by their nearest ancestor directory containing
+    /// any of [`ROOT_MARKERS`], so that each flake in a monorepo is analyzed independently.
+    /// Files outside of them belong to the root of the workspace.
+    fn source_roots(&self) -> Vec<SourceRoot> {
+        let mut dirs = vec![VfsPath::root()];
+        dirs.extend(self.local_file_set.iter().filter_map(|(_, path)| {
+            let (dir, name) = path.as_str().rsplit_once('/')?;
+            if !ROOT_MARKERS.contains(&name) || dir.is_empty() {
+                return None;
+            }
+            VfsPath::new(dir)
+        }));
+        dirs.sort_by(|lhs, rhs| lhs.as_str().cmp(rhs.as_str()));
+        dirs.dedup();
+
+        let mut file_sets = vec![FileSet::default(); dirs.len()];
+        for (file, path) in self.local_file_set.iter() {
+            // Paths of nested directories are longer, thus sorted after their parents.
+            let idx = dirs
+                .iter()
+                .rposition(|dir| path.is_under(dir))
+                .expect("Every path is under the root");
+            file_sets[idx].insert(file, path.clone());
+        }
+
+        let mut roots = dirs
+            .into_iter()
+            .zip(file_sets)
+            .map(|(dir, file_set)| {
+                // TODO: Configurable.
+                let entry = ROOT_ENTRIES.iter().find_map(|&name| {
+                    let mut path = dir.clone();
+                    path.push_segment(name)?;
+                    file_set.file_for_path(&path)
+                });
+                SourceRoot::new_local(file_set, entry)
+            })
+            .collect::<Vec<_>>();
+
+        let mut overlays = self.overlays.values().collect::<Vec<_>>();
+        overlays.sort_by_key(|(file, _)| file.0);
+        roots.extend(overlays.into_iter().map(|(file, path)| {
+            let mut file_set = FileSet::default();
+            file_set.insert(*file, path.clone());
+            SourceRoot::new_local(file_set, None)
+        }));
+        roots
+    }
+
+    pub fn content_for_file(&self, file_id: FileId) -> Arc<str> {
+        self.files[file_id.0 as usize].0.clone()
+    }
+
     pub fn line_map_for_file(&self, file_id: FileId) -> Arc<LineMap> {
         self.files[file_id.0 as usize].1.clone()
     }
 }
 
+/// The synthetic path of a document not backed by disk, from the last segment of its URI.
+fn overlay_path(uri: &Url) -> VfsPath {
+    let name = match uri.path().rsplit('/').next() {
+        Some(name) if !name.is_empty() => name,
+        _ => "untitled",
+    };
+    let mut path = VfsPath::root();
+    path.push_segment(name)
+        .expect("No slash in the last segment");
+    path
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct LineMap {
     line_starts: Vec<u32>,
@@ -260,8 +467,24 @@ impl LineMap {
 
 #[cfg(test)]
 mod tests {
-    use super::{CodeUnitsDiff, LineMap};
+    use super::{CodeUnitsDiff, LineMap, Vfs};
+    use ide::VfsPath;
     use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    #[test]
+    fn root_changed_on_new_file() {
+        let mut vfs = Vfs::new(PathBuf::from("/root"));
+        let path = VfsPath::new("/default.nix").unwrap();
+        vfs.set_path_content(path.clone(), "1".into());
+        let roots = vfs.take_change().roots.expect("Root changed");
+        let file = roots[0].file_for_path(&path).unwrap();
+        assert_eq!(roots[0].entry(), Some(file));
+
+        // Changing contents of a known file keeps the root.
+        vfs.set_path_content(path, "2".into());
+        assert_eq!(vfs.take_change().roots, None);
+    }
 
     #[test]
     fn line_map_ascii() {
@@ -345,4 +568,94 @@ mod tests {
         assert_eq!(map.end_col_for_line(2), 0);
         assert_eq!(map.end_col_for_line(3), 3);
     }
+
+    #[test]
+    fn flake_source_roots() {
+        let mut vfs = Vfs::new(PathBuf::from("/"));
+        for path in [
+            "/default.nix",
+            "/a/flake.nix",
+            "/a/lib.nix",
+            "/a/sub/foo.nix",
+            "/b/flake.nix",
+            "/b/default.nix",
+            "/b-c/foo.nix",
+        ] {
+            vfs.set_path_content(VfsPath::new(path).unwrap(), String::new());
+        }
+        let roots = vfs.take_change().roots.unwrap();
+        let got = roots
+            .iter()
+            .map(|root| {
+                let mut paths = root
+                    .iter()
+                    .map(|(_, path)| path.as_str())
+                    .collect::<Vec<_>>();
+                paths.sort();
+                let entry = root.entry().map(|file| root.path_for_file(file).as_str());
+                (paths, entry)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            got,
+            [
+                (vec!["/b-c/foo.nix", "/default.nix"], Some("/default.nix")),
+                (
+                    vec!["/a/flake.nix", "/a/lib.nix", "/a/sub/foo.nix"],
+                    Some("/a/flake.nix"),
+                ),
+                (vec!["/b/default.nix", "/b/flake.nix"], Some("/b/flake.nix")),
+            ],
+        );
+
+        // Roots are only recomputed when files are added.
+        assert_eq!(vfs.take_change().roots, None);
+    }
+
+    #[test]
+    fn overlay_files() {
+        let mut vfs = Vfs::new(PathBuf::from("/"));
+        vfs.set_path_content(VfsPath::new("/default.nix").unwrap(), String::new());
+        vfs.take_change();
+
+        let uri = "untitled:Untitled-1".parse().unwrap();
+        vfs.set_uri_content(&uri, "1".into()).unwrap();
+        let file = vfs.file_for_uri(&uri).unwrap();
+        assert_eq!(vfs.uri_for_file(file), uri);
+        assert!(vfs.is_overlay(file));
+        assert_eq!(&*vfs.content_for_file(file), "1");
+        assert!(vfs.files().any(|f| f == file));
+
+        // It lives alone in its own source root.
+        let roots = vfs.take_change().roots.unwrap();
+        assert_eq!(roots.len(), 2);
+        let got = roots[1]
+            .iter()
+            .map(|(file, path)| (file, path.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(got, [(file, "/Untitled-1")]);
+
+        // Updating the content keeps the file and the roots.
+        vfs.set_uri_content(&uri, "2".into()).unwrap();
+        assert_eq!(vfs.file_for_uri(&uri).unwrap(), file);
+        assert_eq!(&*vfs.content_for_file(file), "2");
+        assert_eq!(vfs.take_change().roots, None);
+
+        let other = "untitled:Untitled-2".parse().unwrap();
+        assert!(vfs.file_for_uri(&other).is_err());
+
+        // Closed documents are dropped along with their roots.
+        assert!(vfs.remove_overlay(&uri));
+        assert!(!vfs.remove_overlay(&uri));
+        assert!(vfs.file_for_uri(&uri).is_err());
+        assert!(!vfs.is_overlay(file));
+        assert!(vfs.files().all(|f| f != file));
+        assert_eq!(vfs.take_change().roots.unwrap().len(), 1);
+
+        // Other schemes are not analyzed.
+        let git = "git:/default.nix?%7B%7D".parse().unwrap();
+        assert!(vfs.set_uri_content(&git, "1".into()).is_err());
+        assert!(vfs.file_for_uri(&git).is_err());
+        assert_eq!(vfs.take_change().roots, None);
+    }
 }